@@ -0,0 +1,23 @@
+//! Feeds the same bytes from the card into the parser split up in different ways, to make sure the result doesn't depend on the transfer sizes.
+//! Run with `cargo fuzz run parser_chunking`.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use spi_sd_card::fuzzing::parse_read_chunked;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&chunk_count, data)) = data.split_first() else {
+        return;
+    };
+    let Some((chunk_lens, miso)) = data.split_at_checked(usize::from(chunk_count % 16)) else {
+        return;
+    };
+    let (Some(whole), Some(chunked)) = (
+        parse_read_chunked(miso, &[]),
+        parse_read_chunked(miso, chunk_lens),
+    ) else {
+        return;
+    };
+    assert_eq!(format!("{:?}", whole.0), format!("{:?}", chunked.0));
+    assert_eq!(whole.1, chunked.1);
+});
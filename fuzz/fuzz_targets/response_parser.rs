@@ -0,0 +1,88 @@
+//! Feeds arbitrary bytes from the card into the response and data parser, to make sure it never panics.
+//! Run with `cargo fuzz run response_parser`.
+#![no_main]
+
+use core::{
+    convert::Infallible,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use embedded_hal_async::spi::{ErrorType, SpiBus};
+use libfuzzer_sys::fuzz_target;
+use spi_sd_card::{
+    Instant, SdCardConfig,
+    fuzzing::{ParseOptions, parse_responses},
+};
+
+/// A card that sends the fuzzer's bytes, and then `0xFF` forever
+struct FuzzCard<'a> {
+    miso: &'a [u8],
+}
+
+impl FuzzCard<'_> {
+    fn receive(&mut self, words: &mut [u8]) {
+        let len = words.len().min(self.miso.len());
+        words[..len].copy_from_slice(&self.miso[..len]);
+        words[len..].fill(0xFF);
+        self.miso = &self.miso[len..];
+    }
+}
+
+impl ErrorType for FuzzCard<'_> {
+    type Error = Infallible;
+}
+
+impl SpiBus for FuzzCard<'_> {
+    async fn read(&mut self, words: &mut [u8]) -> Result<(), Infallible> {
+        self.receive(words);
+        Ok(())
+    }
+
+    async fn write(&mut self, words: &[u8]) -> Result<(), Infallible> {
+        let len = words.len().min(self.miso.len());
+        self.miso = &self.miso[len..];
+        Ok(())
+    }
+
+    async fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Infallible> {
+        self.receive(read);
+        let extra = write.len().saturating_sub(read.len()).min(self.miso.len());
+        self.miso = &self.miso[extra..];
+        Ok(())
+    }
+
+    async fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Infallible> {
+        self.receive(words);
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+/// Every call advances 1 ms, so timeouts are reached without actually waiting
+fn clock() -> Instant {
+    static NOW: AtomicU64 = AtomicU64::new(0);
+    Instant::from_micros(NOW.fetch_add(1_000, Ordering::Relaxed))
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Some((options, miso)) = data.split_first_chunk::<6>() else {
+        return;
+    };
+    let options = ParseOptions {
+        max_transfer_len: usize::from(options[0]) * 8,
+        buffer_len: usize::from(options[1]) * 8,
+        expected_bytes_until_response: usize::from(options[2] % 16),
+        expected_bytes_until_data: usize::from(options[3] % 16),
+        parts: usize::from(options[4] % 5),
+        skip_bytes: usize::from(options[5]) * 2,
+    };
+    let mut card = FuzzCard { miso };
+    embassy_futures::block_on(parse_responses(
+        &mut card,
+        SdCardConfig::new(clock),
+        options,
+    ));
+});
@@ -0,0 +1,86 @@
+use core::fmt::Debug;
+
+use embassy_embedded_hal::SetConfig;
+use embedded_hal_async::{delay::DelayNs, spi::SpiBus};
+
+use crate::{BLOCK_SIZE, CardDetect, ChipSelect, Error, R1, SdCardDisk, SharedSpiBus};
+
+/// The error for a read command that the card rejected with `r1`
+pub(crate) fn read_response_error<Bus, CsError>(r1: R1) -> Error<Bus, CsError>
+where
+    Bus: SpiBus + SetConfig,
+    <Bus as SetConfig>::ConfigError: Debug,
+{
+    if r1.contains(R1::ADDRESS_ERROR) {
+        Error::ReadAddressError
+    } else {
+        Error::ReadResponseError
+    }
+}
+
+/// The error for a write command that the card rejected with `r1`
+pub(crate) fn write_response_error<Bus, CsError>(r1: R1) -> Error<Bus, CsError>
+where
+    Bus: SpiBus + SetConfig,
+    <Bus as SetConfig>::ConfigError: Debug,
+{
+    if r1.contains(R1::ADDRESS_ERROR) {
+        Error::WriteAddressError
+    } else {
+        Error::WriteResponseError
+    }
+}
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect> SdCardDisk<'_, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// After an `ADDRESS_ERROR`, reads the `CCS` bit of the OCR again to check if the card uses block or byte addresses.
+    /// Returns `true` if the card was using the wrong addressing, so the command is worth trying again with the right one.
+    ///
+    /// Only the OCR decides the addressing. Trying the other addressing without it could write to the wrong place.
+    pub(crate) async fn recheck_addressing<T>(
+        &mut self,
+        result: &Result<T, Error<Spi::Bus, Cs::Error>>,
+    ) -> bool {
+        if !self.sd_card.config.recheck_addressing
+            || !matches!(
+                result,
+                Err(Error::ReadAddressError | Error::WriteAddressError)
+            )
+        {
+            return false;
+        }
+        let block_addressed = match self.ocr_inner().await {
+            Ok(ocr) => match ocr.supports_sdhc_or_sdxc() {
+                Some(block_addressed) => block_addressed,
+                None => return false,
+            },
+            Err(_) => {
+                error!("failed to read the OCR after an address error");
+                return false;
+            }
+        };
+        if block_addressed == self.card_type.is_block_addressed() {
+            return false;
+        }
+        let card_type = self.card_type.with_block_addressing(block_addressed);
+        warn!(
+            "card reported an address error, and its CCS bit says it is {:?}. Using that from now on",
+            card_type
+        );
+        if !block_addressed
+            && self
+                .set_block_length_inner(BLOCK_SIZE as u32)
+                .await
+                .is_err()
+        {
+            error!("failed to set the block length after switching to byte addresses");
+            return false;
+        }
+        self.card_type = card_type;
+        true
+    }
+}
@@ -0,0 +1,248 @@
+use crc::{CRC_32_ISO_HDLC, Crc, Digest};
+
+use crate::{BLOCK_SIZE, Disk};
+
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+const MAGIC: [u8; 4] = *b"ALOG";
+/// Magic, sequence number, length, and CRC
+const HEADER_LEN: usize = 16;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AppendLogError<E> {
+    Disk(E),
+    /// The record doesn't fit in the rest of the region
+    Full,
+    /// The buffer is shorter than [`AppendLog::buffer_len`] for an append, or than the record for a read
+    BufferTooSmall,
+}
+
+/// Where a record is, and what it has
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LogRecord {
+    /// Starts at 0 for the first record, and counts up
+    pub seq: u32,
+    pub len: usize,
+    /// The block after the record, where the next one starts
+    pub next_block: u64,
+}
+
+/// An append-only log of records in a region of a disk, for black-box event logging without a filesystem.
+///
+/// Every record has a sequence number and a CRC, and takes whole blocks.
+/// An append writes the record and a zeroed block after it in a single write (a multi-block write on an SD card),
+/// so the zeroed block marks the end of the log, and the next append writes over it.
+/// Records are never changed after they're written, and when the region is full, appends fail with [`AppendLogError::Full`].
+///
+/// [`AppendLog::mount`] scans the records from the start of the region to find the end.
+/// A record that was cut off by losing power fails its CRC, so the log ends before it.
+pub struct AppendLog<D> {
+    disk: D,
+    start_block: u64,
+    end_block: u64,
+    next_block: u64,
+    next_seq: u32,
+}
+
+impl<D: Disk<Address = u64>> AppendLog<D> {
+    /// Starts an empty log in the `blocks` blocks from `start_block`, forgetting any records that were there
+    pub async fn format(
+        mut disk: D,
+        start_block: u64,
+        blocks: u64,
+    ) -> Result<Self, AppendLogError<D::Error>> {
+        if blocks > 0 {
+            disk.write(start_block * BLOCK_SIZE as u64, &[0; BLOCK_SIZE])
+                .await
+                .map_err(AppendLogError::Disk)?;
+        }
+        Ok(Self {
+            disk,
+            start_block,
+            end_block: start_block + blocks,
+            next_block: start_block,
+            next_seq: 0,
+        })
+    }
+
+    /// Opens the log in the `blocks` blocks from `start_block`, reading every record to find where the next one goes.
+    /// The region must have been [formatted](Self::format) once.
+    pub async fn mount(
+        disk: D,
+        start_block: u64,
+        blocks: u64,
+    ) -> Result<Self, AppendLogError<D::Error>> {
+        let mut log = Self {
+            disk,
+            start_block,
+            end_block: start_block + blocks,
+            next_block: start_block,
+            next_seq: 0,
+        };
+        while let Some(record) = log.read_record(log.next_block, None).await? {
+            log.next_block = record.next_block;
+            log.next_seq = record.seq.wrapping_add(1);
+        }
+        info!(
+            "mounted append log with {} records, using {} blocks",
+            log.next_seq,
+            log.next_block - log.start_block
+        );
+        Ok(log)
+    }
+
+    pub fn into_inner(self) -> D {
+        self.disk
+    }
+
+    /// The block of the first record, for reading the log with [`Self::read`]
+    pub fn first_block(&self) -> u64 {
+        self.start_block
+    }
+
+    /// The block where the next record will be written
+    pub fn next_block(&self) -> u64 {
+        self.next_block
+    }
+
+    /// The sequence number of the next record, which is also the number of records
+    pub fn next_seq(&self) -> u32 {
+        self.next_seq
+    }
+
+    /// The number of blocks a record of `len` bytes takes
+    pub const fn record_blocks(len: usize) -> u64 {
+        len.saturating_add(HEADER_LEN).div_ceil(BLOCK_SIZE) as u64
+    }
+
+    /// The length of the buffer needed to append a record of `len` bytes, including the block that marks the end of the log
+    pub const fn buffer_len(len: usize) -> usize {
+        (Self::record_blocks(len) as usize + 1) * BLOCK_SIZE
+    }
+
+    /// Writes a record after the last one, and returns its sequence number.
+    /// `buffer` is used to put the record together, so that it's written all at once. See [`Self::buffer_len`].
+    pub async fn append(
+        &mut self,
+        record: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<u32, AppendLogError<D::Error>> {
+        let record_blocks = Self::record_blocks(record.len());
+        let next_block = self.next_block + record_blocks;
+        if next_block > self.end_block {
+            return Err(AppendLogError::Full);
+        }
+        // The end of the region ends the log too
+        let blocks = if next_block < self.end_block {
+            record_blocks + 1
+        } else {
+            record_blocks
+        };
+        let buffer = buffer
+            .get_mut(..blocks as usize * BLOCK_SIZE)
+            .ok_or(AppendLogError::BufferTooSmall)?;
+        let seq = self.next_seq;
+        let len = record.len() as u32;
+        let mut digest = CRC.digest();
+        digest.update(&seq.to_le_bytes());
+        digest.update(&len.to_le_bytes());
+        digest.update(record);
+        buffer[..4].copy_from_slice(&MAGIC);
+        buffer[4..8].copy_from_slice(&seq.to_le_bytes());
+        buffer[8..12].copy_from_slice(&len.to_le_bytes());
+        buffer[12..16].copy_from_slice(&digest.finalize().to_le_bytes());
+        buffer[HEADER_LEN..HEADER_LEN + record.len()].copy_from_slice(record);
+        buffer[HEADER_LEN + record.len()..].fill(0);
+        self.disk
+            .write(self.next_block * BLOCK_SIZE as u64, buffer)
+            .await
+            .map_err(AppendLogError::Disk)?;
+        self.next_block = next_block;
+        self.next_seq = seq.wrapping_add(1);
+        Ok(seq)
+    }
+
+    /// Reads the record at `block` into the start of `buffer`.
+    /// Start at [`Self::first_block`], and continue at [`LogRecord::next_block`]. Returns `None` at the end of the log.
+    pub async fn read(
+        &mut self,
+        block: u64,
+        buffer: &mut [u8],
+    ) -> Result<Option<LogRecord>, AppendLogError<D::Error>> {
+        if block >= self.next_block {
+            return Ok(None);
+        }
+        self.read_record(block, Some(buffer)).await
+    }
+
+    /// Checks the record at `block`, and copies it to `buffer` if there is one
+    async fn read_record(
+        &mut self,
+        block: u64,
+        mut buffer: Option<&mut [u8]>,
+    ) -> Result<Option<LogRecord>, AppendLogError<D::Error>> {
+        if block < self.start_block || block >= self.end_block {
+            return Ok(None);
+        }
+        let mut block_bytes = [0; BLOCK_SIZE];
+        self.read_block(block, &mut block_bytes).await?;
+        if block_bytes[..4] != MAGIC {
+            return Ok(None);
+        }
+        let seq = u32::from_le_bytes(block_bytes[4..8].try_into().unwrap());
+        let len = u32::from_le_bytes(block_bytes[8..12].try_into().unwrap());
+        let crc = u32::from_le_bytes(block_bytes[12..16].try_into().unwrap());
+        // While scanning in `mount`, a record with the wrong sequence number was left over from before the log was formatted
+        if buffer.is_none() && seq != self.next_seq {
+            return Ok(None);
+        }
+        let record = LogRecord {
+            seq,
+            len: len as usize,
+            next_block: block + Self::record_blocks(len as usize),
+        };
+        if record.next_block > self.end_block {
+            return Ok(None);
+        }
+        if let Some(buffer) = &buffer
+            && buffer.len() < record.len
+        {
+            return Err(AppendLogError::BufferTooSmall);
+        }
+
+        let mut digest = CRC.digest();
+        digest.update(&seq.to_le_bytes());
+        digest.update(&len.to_le_bytes());
+        let mut copied = 0;
+        let mut copy = |digest: &mut Digest<'_, u32>, bytes: &[u8]| {
+            let bytes = &bytes[..bytes.len().min(record.len - copied)];
+            digest.update(bytes);
+            if let Some(buffer) = buffer.as_deref_mut() {
+                buffer[copied..copied + bytes.len()].copy_from_slice(bytes);
+            }
+            copied += bytes.len();
+        };
+        copy(&mut digest, &block_bytes[HEADER_LEN..]);
+        for block in block + 1..record.next_block {
+            self.read_block(block, &mut block_bytes).await?;
+            copy(&mut digest, &block_bytes);
+        }
+        if digest.finalize() != crc {
+            warn!("append log record at block {} has a bad CRC", block);
+            return Ok(None);
+        }
+        Ok(Some(record))
+    }
+
+    async fn read_block(
+        &mut self,
+        block: u64,
+        buffer: &mut [u8; BLOCK_SIZE],
+    ) -> Result<(), AppendLogError<D::Error>> {
+        self.disk
+            .read(block * BLOCK_SIZE as u64, buffer)
+            .await
+            .map_err(AppendLogError::Disk)
+    }
+}
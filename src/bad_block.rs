@@ -0,0 +1,80 @@
+use core::fmt::Debug;
+
+use embassy_embedded_hal::SetConfig;
+use embedded_hal_async::delay::DelayNs;
+
+use crate::{BLOCK_SIZE, CardDetect, ChipSelect, Error, SdCardDisk, SharedSpiBus};
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect> SdCardDisk<'_, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// Finds the first block from `start_block` to `end_block` (exclusive) that can't be read, for mapping the damaged areas of a card.
+    /// The range is read with `CMD18` in chunks the size of `buffer`, and a chunk that fails is bisected to find the bad block in it.
+    /// This is much faster than reading every block on its own, since most of the card is usually readable.
+    ///
+    /// A block is bad if reading it fails with a data error (bad CRC, data error token, or no data) even after the [`crate::RetryPolicy`].
+    /// Any other error is returned, since it means that the card isn't working at all.
+    /// `buffer` must fit at least 1 block, and its contents afterwards are unspecified.
+    pub async fn first_bad_block(
+        &mut self,
+        start_block: u32,
+        end_block: u32,
+        buffer: &mut [u8],
+    ) -> Result<Option<u32>, Error<Spi::Bus, Cs::Error>> {
+        let chunk_blocks = (buffer.len() / BLOCK_SIZE) as u32;
+        if chunk_blocks == 0 {
+            return Err(Error::ReadUnaligned);
+        }
+        let mut block = start_block;
+        while block < end_block {
+            let chunk_end = end_block.min(block.saturating_add(chunk_blocks));
+            if self.blocks_readable(block, chunk_end, buffer).await? {
+                block = chunk_end;
+                continue;
+            }
+            // The first bad block is somewhere in low..high
+            let (mut low, mut high) = (block, chunk_end);
+            while high - low > 1 {
+                let middle = low + (high - low) / 2;
+                if self.blocks_readable(low, middle, buffer).await? {
+                    low = middle;
+                } else {
+                    high = middle;
+                }
+            }
+            info!("first bad block: {}", low);
+            return Ok(Some(low));
+        }
+        Ok(None)
+    }
+
+    /// Reads the blocks into `buffer`, and returns `false` if the data couldn't be read
+    async fn blocks_readable(
+        &mut self,
+        start_block: u32,
+        end_block: u32,
+        buffer: &mut [u8],
+    ) -> Result<bool, Error<Spi::Bus, Cs::Error>> {
+        let len = (end_block - start_block) as usize * BLOCK_SIZE;
+        match self
+            .read_measured(
+                u64::from(start_block) * BLOCK_SIZE as u64,
+                &mut buffer[..len],
+                None,
+            )
+            .await
+        {
+            Ok(()) => Ok(true),
+            Err(
+                Error::ReadInvalidCrc
+                | Error::ReadDataError(_)
+                | Error::ReadUnexpectedData
+                | Error::ReadReceiveDataTimeout,
+            ) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
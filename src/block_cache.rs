@@ -0,0 +1,288 @@
+use crate::{BLOCK_SIZE, Disk};
+
+#[derive(Clone, Copy)]
+struct Slot {
+    /// `None` if the slot is empty
+    block: Option<u64>,
+    /// The block was written to, and the disk doesn't have the new data yet
+    dirty: bool,
+    /// When the slot was last read or written, to evict the least recently used block
+    used: u64,
+}
+
+impl Slot {
+    const EMPTY: Self = Self {
+        block: None,
+        dirty: false,
+        used: 0,
+    };
+}
+
+/// Counts of what [`BlockCache`] did, for choosing how many slots it needs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CacheStats {
+    /// Blocks that were read or written in the cache without reading them from the disk
+    pub hits: u64,
+    /// Blocks that had to be read from the disk
+    pub misses: u64,
+    /// Blocks that were removed from the cache to make room for another block
+    pub evictions: u64,
+    /// Dirty blocks that were written to the disk, when evicted or flushed
+    pub write_backs: u64,
+}
+
+/// A least recently used cache of `SLOTS` blocks, used for both reads and writes.
+///
+/// Reads of cached blocks don't touch the disk, and writes only change the cached block and mark it dirty.
+/// A dirty block is written to the disk when it's evicted, or on [`BlockCache::flush`].
+/// Writing part of a block that isn't cached reads the rest of it first.
+/// Reads and writes that touch more than `SLOTS` blocks go straight to the disk, so a big transfer doesn't evict everything.
+///
+/// The cached blocks are the disk's [`Disk::BLOCK_SIZE`], which can be at most [`BLOCK_SIZE`], the size of each slot.
+///
+/// Call [`BlockCache::flush`] before dropping this or removing the card, or the dirty blocks are lost.
+pub struct BlockCache<D, const SLOTS: usize> {
+    disk: D,
+    slots: [Slot; SLOTS],
+    data: [[u8; BLOCK_SIZE]; SLOTS],
+    /// Counts up on every access, for [`Slot::used`]
+    accesses: u64,
+    stats: CacheStats,
+    /// If `false`, reads go to the disk and only see the cached blocks, and blocks are only cached when they are written, like [`WriteBackDisk`](crate::WriteBackDisk) does
+    cache_reads: bool,
+}
+
+impl<D: Disk<Address = u64>, const SLOTS: usize> BlockCache<D, SLOTS> {
+    pub const fn new(disk: D) -> Self {
+        Self::with_cache_reads(disk, true)
+    }
+
+    /// A cache of only the blocks that are written, for [`WriteBackDisk`](crate::WriteBackDisk)
+    pub(crate) const fn write_only(disk: D) -> Self {
+        Self::with_cache_reads(disk, false)
+    }
+
+    const fn with_cache_reads(disk: D, cache_reads: bool) -> Self {
+        const {
+            assert!(
+                D::BLOCK_SIZE <= BLOCK_SIZE,
+                "the disk's blocks don't fit in a slot"
+            )
+        };
+        Self {
+            disk,
+            slots: [Slot::EMPTY; SLOTS],
+            data: [[0; BLOCK_SIZE]; SLOTS],
+            accesses: 0,
+            stats: CacheStats {
+                hits: 0,
+                misses: 0,
+                evictions: 0,
+                write_backs: 0,
+            },
+            cache_reads,
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = CacheStats::default();
+    }
+
+    /// How many cached blocks were written to and not written to the disk yet
+    pub fn dirty_blocks(&self) -> usize {
+        self.slots.iter().filter(|slot| slot.dirty).count()
+    }
+
+    /// Only call this after [`BlockCache::flush`] succeeded, or the dirty blocks are lost
+    pub fn into_inner(self) -> D {
+        self.disk
+    }
+
+    /// Writes the block in `slot` to the disk if it's dirty
+    async fn write_back(&mut self, slot: usize) -> Result<(), D::Error> {
+        if let Slot {
+            block: Some(block),
+            dirty: true,
+            ..
+        } = self.slots[slot]
+        {
+            self.disk
+                .write(
+                    block * D::BLOCK_SIZE as u64,
+                    &self.data[slot][..D::BLOCK_SIZE],
+                )
+                .await?;
+            self.slots[slot].dirty = false;
+            self.stats.write_backs += 1;
+        }
+        Ok(())
+    }
+
+    /// Gets the slot of `block`, reading it from the disk if it isn't cached, unless all of it is about to be written.
+    /// The least recently used block is evicted if every slot is used.
+    async fn slot(&mut self, block: u64, whole_write: bool) -> Result<usize, D::Error> {
+        self.accesses += 1;
+        if let Some(slot) = self.slots.iter().position(|slot| slot.block == Some(block)) {
+            self.stats.hits += 1;
+            self.slots[slot].used = self.accesses;
+            return Ok(slot);
+        }
+        let slot = match self.slots.iter().position(|slot| slot.block.is_none()) {
+            Some(slot) => slot,
+            None => {
+                let (slot, _) = self
+                    .slots
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, slot)| slot.used)
+                    .unwrap();
+                self.write_back(slot).await?;
+                self.slots[slot] = Slot::EMPTY;
+                self.stats.evictions += 1;
+                slot
+            }
+        };
+        if whole_write {
+            self.stats.hits += 1;
+        } else {
+            self.stats.misses += 1;
+            self.disk
+                .read(
+                    block * D::BLOCK_SIZE as u64,
+                    &mut self.data[slot][..D::BLOCK_SIZE],
+                )
+                .await?;
+        }
+        self.slots[slot] = Slot {
+            block: Some(block),
+            dirty: false,
+            used: self.accesses,
+        };
+        Ok(slot)
+    }
+
+    /// Writes back and forgets the cached blocks from `first_block` to `end_block` (exclusive), before a transfer that bypasses the cache
+    async fn evict_range(&mut self, first_block: u64, end_block: u64) -> Result<(), D::Error> {
+        for slot in 0..SLOTS {
+            if self.slots[slot]
+                .block
+                .is_some_and(|block| (first_block..end_block).contains(&block))
+            {
+                self.write_back(slot).await?;
+                self.slots[slot] = Slot::EMPTY;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads from the disk, and then copies the cached blocks over what was read, since they can be newer
+    async fn read_through(&mut self, start: u64, buffer: &mut [u8]) -> Result<(), D::Error> {
+        self.disk.read(start, buffer).await?;
+        let end = start + buffer.len() as u64;
+        for (slot, data) in self.slots.iter().zip(&self.data) {
+            let Some(block) = slot.block else {
+                continue;
+            };
+            let block_start = block * D::BLOCK_SIZE as u64;
+            let overlap_start = start.max(block_start);
+            let overlap_end = end.min(block_start + D::BLOCK_SIZE as u64);
+            if overlap_start < overlap_end {
+                buffer[(overlap_start - start) as usize..(overlap_end - start) as usize]
+                    .copy_from_slice(
+                        &data[(overlap_start - block_start) as usize
+                            ..(overlap_end - block_start) as usize],
+                    );
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<D: Disk<Address = u64>, const SLOTS: usize> Disk for BlockCache<D, SLOTS> {
+    type Address = u64;
+    type Error = D::Error;
+    const BLOCK_SIZE: usize = D::BLOCK_SIZE;
+
+    fn block_size(&self) -> usize {
+        self.disk.block_size()
+    }
+
+    fn erase_unit_size(&self) -> Option<usize> {
+        self.disk.erase_unit_size()
+    }
+
+    async fn read(&mut self, start: u64, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        if !self.cache_reads {
+            return self.read_through(start, buffer).await;
+        }
+        let block_size = D::BLOCK_SIZE as u64;
+        let end = start + buffer.len() as u64;
+        let first_block = start / block_size;
+        let end_block = end.div_ceil(block_size);
+        if end_block - first_block > SLOTS as u64 {
+            self.evict_range(first_block, end_block).await?;
+            return self.disk.read(start, buffer).await;
+        }
+        let mut address = start;
+        while address < end {
+            let offset = (address % block_size) as usize;
+            let len = (D::BLOCK_SIZE - offset).min((end - address) as usize);
+            let slot = self.slot(address / block_size, false).await?;
+            let buffer_offset = (address - start) as usize;
+            buffer[buffer_offset..buffer_offset + len]
+                .copy_from_slice(&self.data[slot][offset..offset + len]);
+            address += len as u64;
+        }
+        Ok(())
+    }
+
+    async fn write(&mut self, start: u64, buffer: &[u8]) -> Result<(), Self::Error> {
+        let block_size = D::BLOCK_SIZE as u64;
+        let end = start + buffer.len() as u64;
+        let first_block = start / block_size;
+        let end_block = end.div_ceil(block_size);
+        if end_block - first_block > SLOTS as u64 {
+            self.evict_range(first_block, end_block).await?;
+            return self.disk.write(start, buffer).await;
+        }
+        let mut address = start;
+        while address < end {
+            let offset = (address % block_size) as usize;
+            let len = (D::BLOCK_SIZE - offset).min((end - address) as usize);
+            let slot = self
+                .slot(address / block_size, len == D::BLOCK_SIZE)
+                .await?;
+            let buffer_offset = (address - start) as usize;
+            self.data[slot][offset..offset + len]
+                .copy_from_slice(&buffer[buffer_offset..buffer_offset + len]);
+            self.slots[slot].dirty = true;
+            address += len as u64;
+        }
+        Ok(())
+    }
+
+    async fn erase(&mut self, start: u64, end: u64) -> Result<(), Self::Error> {
+        let block_size = D::BLOCK_SIZE as u64;
+        // Blocks that are only partly erased still have data that's needed
+        let erased = start.div_ceil(block_size)..end / block_size;
+        for slot in &mut self.slots {
+            if slot.block.is_some_and(|block| erased.contains(&block)) {
+                *slot = Slot::EMPTY;
+            }
+        }
+        self.disk.erase(start, end).await
+    }
+
+    /// Writes every dirty block to the disk, and then flushes the disk. The blocks stay cached.
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        for slot in 0..SLOTS {
+            self.write_back(slot).await?;
+        }
+        self.disk.flush().await
+    }
+}
@@ -0,0 +1,52 @@
+use core::fmt::Debug;
+
+use aligned::{A1, Aligned};
+use block_device_driver::{BlockDevice, blocks_to_slice, blocks_to_slice_mut};
+use embassy_embedded_hal::SetConfig;
+use embedded_hal_async::delay::DelayNs;
+
+use crate::{BLOCK_SIZE, CardDetect, ChipSelect, Disk, Error, SdCardDisk, SharedSpiBus};
+
+/// Lets the card be used directly with `embedded-fatfs` and the `block-device-adapters` crate.
+/// Blocks don't need any alignment, since the data is transferred with the CPU or the SPI driver's own DMA buffers.
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect> BlockDevice<BLOCK_SIZE>
+    for SdCardDisk<'_, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+    Error<Spi::Bus, Cs::Error>: Debug,
+{
+    type Error = Error<Spi::Bus, Cs::Error>;
+    type Align = A1;
+
+    async fn read(
+        &mut self,
+        block_address: u32,
+        data: &mut [Aligned<A1, [u8; BLOCK_SIZE]>],
+    ) -> Result<(), Self::Error> {
+        Disk::read(
+            self,
+            u64::from(block_address) * BLOCK_SIZE as u64,
+            blocks_to_slice_mut(data),
+        )
+        .await
+    }
+
+    async fn write(
+        &mut self,
+        block_address: u32,
+        data: &[Aligned<A1, [u8; BLOCK_SIZE]>],
+    ) -> Result<(), Self::Error> {
+        Disk::write(
+            self,
+            u64::from(block_address) * BLOCK_SIZE as u64,
+            blocks_to_slice(data),
+        )
+        .await
+    }
+
+    async fn size(&mut self) -> Result<u64, Self::Error> {
+        self.capacity().await
+    }
+}
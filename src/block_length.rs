@@ -0,0 +1,89 @@
+use core::{fmt::Debug, ops::DerefMut};
+
+use embassy_embedded_hal::SetConfig;
+use embedded_hal_async::delay::DelayNs;
+
+use crate::{
+    BLOCK_SIZE, CardCommand3Error, CardDetect, ChipSelect, EXPECTED_BYTES_UNTIL_RESPONSE, Error,
+    ErrorContext, R1, SdCardDisk, SdCommand, SharedSpiBus, card_command, report_error,
+};
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect> SdCardDisk<'_, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// The block length last set with `CMD16`, which is 512 after init
+    pub fn block_length(&self) -> u32 {
+        self.sd_card.block_length
+    }
+
+    /// Sets the block length with `CMD16`, such as for the data block of a command that doesn't transfer 512 bytes.
+    /// Standard capacity cards read and write blocks of this length, so the driver sets it back to 512 before their next block transfer.
+    /// High capacity cards always transfer 512 byte blocks, and only use it for `CMD42` (`LOCK_UNLOCK`).
+    pub async fn set_block_length(
+        &mut self,
+        block_length: u32,
+    ) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        let result = self.set_block_length_inner(block_length).await;
+        report_error(
+            self.sd_card.on_error,
+            ErrorContext::SetBlockLength { block_length },
+            result,
+        )
+    }
+
+    pub(crate) async fn set_block_length_inner(
+        &mut self,
+        block_length: u32,
+    ) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        let mut spi = self.begin_operation().await?;
+        self.send_block_length(spi.deref_mut(), block_length)
+            .await?;
+        self.end_operation(spi).await?;
+        Ok(())
+    }
+
+    /// Like [`Self::begin_operation`], for commands that transfer [`BLOCK_SIZE`] blocks
+    pub(crate) async fn begin_transfer(
+        &mut self,
+    ) -> Result<Spi::Guard, Error<Spi::Bus, Cs::Error>> {
+        let mut spi = self.begin_operation().await?;
+        if self.sd_card.block_length != BLOCK_SIZE as u32 && !self.card_type.is_block_addressed() {
+            self.send_block_length(spi.deref_mut(), BLOCK_SIZE as u32)
+                .await?;
+        }
+        Ok(spi)
+    }
+
+    async fn send_block_length(
+        &mut self,
+        spi: &mut Spi::Bus,
+        block_length: u32,
+    ) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        let response = card_command(
+            spi,
+            &mut self.sd_card.scratch,
+            &self.sd_card.config,
+            SdCommand::SetBlockLen { block_length },
+            EXPECTED_BYTES_UNTIL_RESPONSE,
+            self.sd_card.config.timeouts.command,
+            None,
+        )
+        .await
+        .map_err(|e| match e {
+            CardCommand3Error::Spi(e) => Error::SpiBus(e),
+            CardCommand3Error::ReceiveResponseTimeout(_) => Error::SetBlockLengthFailed,
+            CardCommand3Error::NothingToTransfer => Error::NothingToTransfer,
+            CardCommand3Error::UnexpectedTrailingData(byte) => Error::UnexpectedTrailingData(byte),
+            _ => unreachable!(),
+        })?;
+        let r1 = R1::from_bits_retain(response[0]);
+        if !r1.is_empty() {
+            return Err(Error::SetBlockLengthFailed);
+        }
+        self.sd_card.block_length = block_length;
+        Ok(())
+    }
+}
@@ -0,0 +1,409 @@
+use crc::{CRC_32_ISO_HDLC, Crc};
+use embedded_hal::{digital::OutputPin, spi::SpiBus};
+
+use crate::{
+    BLOCK_SIZE, BusyOperation, CardCommand3Error, CardCommandOperation, CardType, Command,
+    CommandParser, Duration, EXPECTED_BYTES_UNTIL_RESPONSE, MAX_RESPONSE_LEN, Ocr, ParserStep, R1,
+    ResponseType, START_BLOCK_TOKEN, SdCardConfig, SdCommand, WriteOperation,
+};
+
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+/// Big enough for a command and a single block write
+const BUFFER_LEN: usize = size_of::<Command>()
+    + EXPECTED_BYTES_UNTIL_RESPONSE
+    + ResponseType::R1.size()
+    + 1
+    + 1
+    + BLOCK_SIZE
+    + size_of::<u16>();
+
+#[derive(Debug)]
+pub enum BlockingError<SpiError, CsError> {
+    Spi(SpiError),
+    CsPin(CsError),
+    /// The card didn't respond to a command during init, or responded with an error
+    InitFailed,
+    /// The card did not switch from idle to ready, or its OCR didn't say it was powered up, before the timeout
+    ReadyTimeout,
+    /// The card didn't respond to a write, rejected it, or was still busy after the timeout
+    WriteFailed,
+    /// The length of the data to write was not a multiple of the block size
+    WriteUnaligned,
+    /// The log doesn't fit in the [`CrashLogRegion`]
+    LogTooLong,
+    /// This is a bug in this crate, like [`crate::Error::NothingToTransfer`]
+    NothingToTransfer,
+}
+
+/// Where [`SpiSdCardBlocking::write_crash_log`] puts the log.
+/// The first block is the [`CrashLogHeader`], and the log is in the blocks after it.
+/// Keep this region out of any partition, so that nothing else writes to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CrashLogRegion {
+    pub start_block: u32,
+    /// Including the header block
+    pub blocks: u32,
+}
+
+impl CrashLogRegion {
+    /// The longest log that fits
+    pub fn max_log_len(&self) -> usize {
+        self.blocks.saturating_sub(1) as usize * BLOCK_SIZE
+    }
+}
+
+/// The first block of a [`CrashLogRegion`].
+/// After booting, read it with the async driver to check if there is a crash log to report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CrashLogHeader {
+    pub len: u32,
+    /// CRC-32 of the log
+    pub crc: u32,
+}
+
+impl CrashLogHeader {
+    const MAGIC: [u8; 8] = *b"SDCRASH1";
+
+    pub fn new(log: &[u8]) -> Self {
+        Self {
+            len: log.len() as u32,
+            crc: CRC.checksum(log),
+        }
+    }
+
+    /// Returns `None` if the block isn't a crash log header, such as after the region was erased
+    pub fn from_block(block: &[u8; BLOCK_SIZE]) -> Option<Self> {
+        if block[..8] != Self::MAGIC {
+            return None;
+        }
+        Some(Self {
+            len: u32::from_le_bytes(block[8..12].try_into().unwrap()),
+            crc: u32::from_le_bytes(block[12..16].try_into().unwrap()),
+        })
+    }
+
+    pub fn to_block(&self) -> [u8; BLOCK_SIZE] {
+        let mut block = [0; BLOCK_SIZE];
+        block[..8].copy_from_slice(&Self::MAGIC);
+        block[8..12].copy_from_slice(&self.len.to_le_bytes());
+        block[12..16].copy_from_slice(&self.crc.to_le_bytes());
+        block
+    }
+
+    /// `true` if `log` is the log that this header was written for, so it wasn't cut off or corrupted
+    pub fn matches(&self, log: &[u8]) -> bool {
+        log.len() == self.len as usize && CRC.checksum(log) == self.crc
+    }
+}
+
+/// A minimal blocking driver, for writing a crash log from a panic or hard fault handler when the async executor can't run anymore.
+/// It doesn't allocate, and drives the same [`CommandParser`] as the async driver.
+///
+/// It only initializes the card and writes single blocks. CRC isn't turned on, so the card doesn't check the data it receives.
+/// It sends the commands at whatever speed `spi` is at, which should be at most 400 kHz for init.
+/// The clock in `config` must keep running without interrupts (for example, a cycle counter), or a card that doesn't respond makes it wait forever.
+#[derive(Debug)]
+pub struct SpiSdCardBlocking<Spi, Cs> {
+    spi: Spi,
+    cs: Cs,
+    config: SdCardConfig,
+    card_type: Option<CardType>,
+}
+
+impl<Spi: SpiBus, Cs: OutputPin> SpiSdCardBlocking<Spi, Cs> {
+    pub fn new(spi: Spi, cs: Cs, config: SdCardConfig) -> Self {
+        Self {
+            spi,
+            cs,
+            config,
+            card_type: None,
+        }
+    }
+
+    pub fn into_inner(self) -> (Spi, Cs) {
+        (self.spi, self.cs)
+    }
+
+    /// Initializes the card, even if the async driver already did, since the card could be in the middle of anything.
+    /// The writes do this first if it wasn't done yet.
+    pub fn init_card(&mut self) -> Result<CardType, BlockingError<Spi::Error, Cs::Error>> {
+        self.cs.set_high().map_err(BlockingError::CsPin)?;
+        // At least 74 clock cycles with CS high
+        self.spi.write(&[0xFF; 10]).map_err(BlockingError::Spi)?;
+        self.cs.set_low().map_err(BlockingError::CsPin)?;
+        let result = self.init_card_selected();
+        let deselect_result = self.deselect();
+        let card_type = result?;
+        deselect_result?;
+        self.card_type = Some(card_type);
+        Ok(card_type)
+    }
+
+    fn init_card_selected(&mut self) -> Result<CardType, BlockingError<Spi::Error, Cs::Error>> {
+        let mut attempt_number = 1;
+        loop {
+            // The card might not respond until it stops whatever it was doing, so timeouts are retried too
+            match self.init_command(SdCommand::GoIdleState) {
+                Ok(R1::IN_IDLE_STATE) => break,
+                Err(e @ (BlockingError::Spi(_) | BlockingError::NothingToTransfer)) => {
+                    return Err(e);
+                }
+                _ => {}
+            }
+            if attempt_number == self.config.cmd0_retry.max_attempts {
+                return Err(BlockingError::InitFailed);
+            }
+            attempt_number += 1;
+        }
+
+        let check_pattern = 0xAA;
+        let response = self.command(SdCommand::SendIfCond { check_pattern }, None)?;
+        let r1 = R1::from_bits_retain(response[0]);
+        let version_2 = if r1.contains(R1::ILLEGAL_COMMAND) {
+            false
+        } else if r1 == R1::IN_IDLE_STATE && response[4] == check_pattern {
+            true
+        } else {
+            return Err(BlockingError::InitFailed);
+        };
+
+        let start_time = (self.config.clock)();
+        let mut attempt_number = 0;
+        // MMC cards reject CMD55 or ACMD41, and are initialized with CMD1 instead
+        let mut mmc = false;
+        loop {
+            // The attempt limit is a backstop in case the clock doesn't advance
+            if attempt_number == self.config.acmd41_retry.max_attempts
+                || start_time.elapsed(self.config.clock) > self.config.timeouts.init
+            {
+                return Err(BlockingError::ReadyTimeout);
+            }
+            if !mmc {
+                let r1 = self.init_command(SdCommand::AppCmd)?;
+                if !version_2 && r1.contains(R1::ILLEGAL_COMMAND) {
+                    mmc = true;
+                } else if !(r1 == R1::IN_IDLE_STATE || r1.is_empty()) {
+                    return Err(BlockingError::InitFailed);
+                }
+            }
+            if !mmc {
+                let r1 = self.init_command(SdCommand::SdSendOpCond {
+                    high_capacity_support: version_2,
+                })?;
+                if r1.is_empty() {
+                    break;
+                } else if !version_2 && r1.contains(R1::ILLEGAL_COMMAND) {
+                    mmc = true;
+                } else if r1 != R1::IN_IDLE_STATE {
+                    return Err(BlockingError::InitFailed);
+                }
+            }
+            if mmc {
+                let r1 = self.init_command(SdCommand::SendOpCond {
+                    high_capacity_support: true,
+                })?;
+                if r1.is_empty() {
+                    break;
+                } else if r1 != R1::IN_IDLE_STATE {
+                    return Err(BlockingError::InitFailed);
+                }
+            }
+            attempt_number += 1;
+        }
+
+        let card_type = if version_2 || mmc {
+            // The CCS bit is only valid once the card is powered up
+            let ocr = loop {
+                let response = self.command(SdCommand::ReadOcr, None)?;
+                if response[0] != 0 {
+                    return Err(BlockingError::InitFailed);
+                }
+                let ocr =
+                    Ocr::from_bits_retain(u32::from_be_bytes(response[1..5].try_into().unwrap()));
+                if ocr.is_powered_up() {
+                    break ocr;
+                }
+                attempt_number += 1;
+                if attempt_number == self.config.acmd41_retry.max_attempts
+                    || start_time.elapsed(self.config.clock) > self.config.timeouts.init
+                {
+                    return Err(BlockingError::ReadyTimeout);
+                }
+            };
+            match (mmc, ocr.supports_sdhc_or_sdxc() == Some(true)) {
+                (true, true) => CardType::MmcHc,
+                (true, false) => CardType::Mmc,
+                (false, true) => CardType::SdV2Hc,
+                (false, false) => CardType::SdV2Sc,
+            }
+        } else {
+            CardType::SdV1
+        };
+        if !card_type.is_block_addressed() {
+            let r1 = self.init_command(SdCommand::SetBlockLen {
+                block_length: BLOCK_SIZE as u32,
+            })?;
+            if !r1.is_empty() {
+                return Err(BlockingError::InitFailed);
+            }
+        }
+        Ok(card_type)
+    }
+
+    /// Writes whole blocks, one `CMD24` at a time
+    pub fn write_blocks(
+        &mut self,
+        start_block: u32,
+        data: &[u8],
+    ) -> Result<(), BlockingError<Spi::Error, Cs::Error>> {
+        if !data.len().is_multiple_of(BLOCK_SIZE) {
+            return Err(BlockingError::WriteUnaligned);
+        }
+        let card_type = match self.card_type {
+            Some(card_type) => card_type,
+            None => self.init_card()?,
+        };
+        self.cs.set_low().map_err(BlockingError::CsPin)?;
+        let result = (start_block..)
+            .zip(data.chunks_exact(BLOCK_SIZE))
+            .try_for_each(|(block, data)| self.write_block(card_type, block, data));
+        let deselect_result = self.deselect();
+        result?;
+        deselect_result
+    }
+
+    /// Writes `log` to `region`. The log is written first, and the header last,
+    /// so if this is interrupted (for example, by a watchdog reset), the region doesn't look like it has a complete log.
+    pub fn write_crash_log(
+        &mut self,
+        region: CrashLogRegion,
+        log: &[u8],
+    ) -> Result<(), BlockingError<Spi::Error, Cs::Error>> {
+        if log.len() > region.max_log_len() {
+            return Err(BlockingError::LogTooLong);
+        }
+        let whole_blocks_len = log.len() / BLOCK_SIZE * BLOCK_SIZE;
+        let (whole_blocks, rest) = log.split_at(whole_blocks_len);
+        let log_start = region.start_block + 1;
+        self.write_blocks(log_start, whole_blocks)?;
+        if !rest.is_empty() {
+            let mut block = [0; BLOCK_SIZE];
+            block[..rest.len()].copy_from_slice(rest);
+            self.write_blocks(log_start + (whole_blocks_len / BLOCK_SIZE) as u32, &block)?;
+        }
+        self.write_blocks(region.start_block, &CrashLogHeader::new(log).to_block())
+    }
+
+    fn write_block(
+        &mut self,
+        card_type: CardType,
+        block: u32,
+        data: &[u8],
+    ) -> Result<(), BlockingError<Spi::Error, Cs::Error>> {
+        let address = if card_type.is_block_addressed() {
+            block
+        } else {
+            block * BLOCK_SIZE as u32
+        };
+        let response = self
+            .command(
+                SdCommand::WriteBlock { address },
+                Some(CardCommandOperation::Write(WriteOperation {
+                    buffer: data,
+                    part_size: BLOCK_SIZE,
+                    start_token: START_BLOCK_TOKEN,
+                    busy: BusyOperation {
+                        expected_bytes_until_not_busy: self.config.gaps.not_busy,
+                        timeout: self.config.timeouts.busy,
+                    },
+                })),
+            )
+            .map_err(|e| match e {
+                BlockingError::InitFailed => BlockingError::WriteFailed,
+                e => e,
+            })?;
+        if response[0] != 0 {
+            return Err(BlockingError::WriteFailed);
+        }
+        Ok(())
+    }
+
+    fn init_command(
+        &mut self,
+        command: SdCommand,
+    ) -> Result<R1, BlockingError<Spi::Error, Cs::Error>> {
+        Ok(R1::from_bits_retain(self.command(command, None)?[0]))
+    }
+
+    /// Timeouts and rejected data are [`BlockingError::InitFailed`], which writes turn into [`BlockingError::WriteFailed`]
+    fn command(
+        &mut self,
+        command: SdCommand,
+        operation: Option<CardCommandOperation<'_>>,
+    ) -> Result<[u8; MAX_RESPONSE_LEN], BlockingError<Spi::Error, Cs::Error>> {
+        let mut buffer = [0xFF; BUFFER_LEN];
+        card_command_blocking(
+            &mut self.spi,
+            &mut buffer,
+            &self.config,
+            command,
+            self.config.timeouts.command,
+            operation,
+        )
+        .map_err(|e| match e {
+            CardCommand3Error::Spi(e) => BlockingError::Spi(e),
+            CardCommand3Error::NothingToTransfer => BlockingError::NothingToTransfer,
+            _ => BlockingError::InitFailed,
+        })
+    }
+
+    fn deselect(&mut self) -> Result<(), BlockingError<Spi::Error, Cs::Error>> {
+        self.spi.flush().map_err(BlockingError::Spi)?;
+        self.cs.set_high().map_err(BlockingError::CsPin)?;
+        self.spi.write(&[0xFF]).map_err(BlockingError::Spi)?;
+        self.spi.flush().map_err(BlockingError::Spi)
+    }
+}
+
+/// Like [`crate::card_command`], but with a blocking bus. Only for commands without a read data phase.
+fn card_command_blocking<S: SpiBus>(
+    spi: &mut S,
+    buffer: &mut [u8],
+    config: &SdCardConfig,
+    command: SdCommand,
+    response_timeout: Duration,
+    operation: Option<CardCommandOperation<'_>>,
+) -> Result<[u8; MAX_RESPONSE_LEN], CardCommand3Error<S::Error>> {
+    let mut parser = CommandParser::new(
+        command,
+        EXPECTED_BYTES_UNTIL_RESPONSE,
+        response_timeout,
+        operation,
+        config.clock,
+    )
+    .with_trailing_bytes(config.trailing_bytes);
+    let mut received = 0;
+    loop {
+        match parser.feed(&buffer[..received])? {
+            ParserStep::Transfer => {}
+            ParserStep::Done => return Ok(parser.response()),
+            ParserStep::DiscardBlock { byte, .. } => {
+                return Err(CardCommand3Error::ExpectedStartBlockToken(byte));
+            }
+        }
+        let len = parser.fill_transfer(buffer)?;
+        if config.split_transfers {
+            let (send, rest) = buffer[..len].split_at_mut(parser.bytes_to_send(len));
+            spi.write(send).map_err(CardCommand3Error::Spi)?;
+            send.fill(0xFF);
+            spi.read(rest).map_err(CardCommand3Error::Spi)?;
+        } else {
+            spi.transfer_in_place(&mut buffer[..len])
+                .map_err(CardCommand3Error::Spi)?;
+        }
+        received = len;
+    }
+}
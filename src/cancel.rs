@@ -0,0 +1,104 @@
+use core::{
+    fmt::Debug,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use embassy_embedded_hal::SetConfig;
+use embedded_hal_async::delay::DelayNs;
+
+use crate::{BLOCK_SIZE, CardDetect, ChipSelect, Disk, Error, SdCardDisk, SharedSpiBus};
+
+/// Long reads and writes are split into transfers of this many blocks, so that cancelling doesn't have to wait for the whole thing
+const CANCEL_CHUNK_BYTES: u64 = 16 * BLOCK_SIZE as u64;
+
+/// Asks a long operation to stop, such as from a cancel button in another task.
+///
+/// Unlike dropping the operation's future, the operation stops at a point where the card is ready for the next command,
+/// and returns how much it did.
+#[derive(Debug, Default)]
+pub struct CancelToken {
+    cancelled: AtomicBool,
+}
+
+impl CancelToken {
+    pub const fn new() -> Self {
+        Self {
+            cancelled: AtomicBool::new(false),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Lets the token be used for another operation
+    pub fn reset(&self) {
+        self.cancelled.store(false, Ordering::Relaxed);
+    }
+}
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect> SdCardDisk<'_, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// Like [`Disk::read`], but stops early if `cancel` is cancelled.
+    /// The read is done in chunks of 16 blocks, and the chunk being read when it is cancelled is finished first.
+    ///
+    /// Returns the number of bytes that were read into the start of `buffer`.
+    pub async fn read_cancellable(
+        &mut self,
+        start: u64,
+        buffer: &mut [u8],
+        cancel: &CancelToken,
+    ) -> Result<usize, Error<Spi::Bus, Cs::Error>> {
+        let mut bytes_done = 0;
+        while bytes_done < buffer.len() {
+            if cancel.is_cancelled() {
+                info!("read cancelled after {} bytes", bytes_done);
+                break;
+            }
+            let address = start + bytes_done as u64;
+            let len = chunk_len(address, buffer.len() - bytes_done);
+            self.read_measured(address, &mut buffer[bytes_done..bytes_done + len], None)
+                .await?;
+            bytes_done += len;
+        }
+        Ok(bytes_done)
+    }
+
+    /// Like [`Disk::write`], but stops early if `cancel` is cancelled.
+    /// The write is done in chunks of 16 blocks, and the chunk being written when it is cancelled is finished first.
+    ///
+    /// Returns the number of bytes from the start of `buffer` that were written.
+    pub async fn write_cancellable(
+        &mut self,
+        start: u64,
+        buffer: &[u8],
+        cancel: &CancelToken,
+    ) -> Result<usize, Error<Spi::Bus, Cs::Error>> {
+        let mut bytes_done = 0;
+        while bytes_done < buffer.len() {
+            if cancel.is_cancelled() {
+                info!("write cancelled after {} bytes", bytes_done);
+                break;
+            }
+            let address = start + bytes_done as u64;
+            let len = chunk_len(address, buffer.len() - bytes_done);
+            Disk::write(self, address, &buffer[bytes_done..bytes_done + len]).await?;
+            bytes_done += len;
+        }
+        Ok(bytes_done)
+    }
+}
+
+/// The length of the next chunk, which ends at a chunk boundary so that every chunk after the first is aligned
+fn chunk_len(address: u64, bytes_left: usize) -> usize {
+    let len = CANCEL_CHUNK_BYTES - address % CANCEL_CHUNK_BYTES;
+    usize::try_from(len).unwrap().min(bytes_left)
+}
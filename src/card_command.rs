@@ -1,37 +1,111 @@
-use core::cmp::min;
+use core::{cmp::min, ops::Range};
 
 use crc::{CRC_16_XMODEM, Crc, Digest};
-use embassy_time::{Duration, Instant};
 use embedded_hal_async::spi::SpiBus;
 
-use crate::{Command, R1, START_BLOCK_TOKEN};
+use crate::{
+    Clock, Command, DataErrorToken, DataPhase, DataResponseToken, Duration, Instant,
+    MAX_RESPONSE_LEN, R1, START_BLOCK_TOKEN, STOP_TRAN_TOKEN, SdCardConfig, SdCommand,
+    TrailingBytes, fmt::Bytes,
+};
 
+#[derive(Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct ReadOperation<'a> {
     pub buffer: &'a mut [u8],
     pub expected_bytes_until_data: usize,
     pub timeout: Duration,
-    /// Lets you read multiple, so buffer will be N * 512 bytes and parts will be N
+    /// Lets you read multiple, so buffer will be N * 512 bytes and parts will be N.
+    /// If this is 0, only the command is sent, and the data blocks can be received later with [`receive_data_block`].
     pub parts: usize,
     pub part_size: usize,
     pub crc_enabled: bool,
     /// Lets you skip the first bytes to read into a buffer that wants data starting at an address that is not a multiple of 512
     pub skip_bytes: usize,
+    /// If set, the number of bytes between the end of the response (or the previous part) and the start block token of each part is written here
+    pub bytes_until_data: Option<&'a mut [usize]>,
+}
+
+impl ReadOperation<'_> {
+    /// The number of bytes we expect to transfer for each part, including the start block token and CRC
+    fn bytes_per_part(&self) -> usize {
+        self.expected_bytes_until_data + 1 + self.part_size + size_of::<u16>()
+    }
+
+    /// Where the data of `part` goes in `buffer`, if all of it goes there, so that it can be read straight into `buffer`
+    fn direct_range(&self, part: usize) -> Option<Range<usize>> {
+        let start = (part * self.part_size).checked_sub(self.skip_bytes)?;
+        let end = start + self.part_size;
+        (end <= self.buffer.len()).then_some(start..end)
+    }
+
+    /// The number of bytes we expect to transfer for the parts from `part` on.
+    /// This stops at the start block token of the first part that can be read straight into `buffer`, so that its data isn't received into the transfer buffer.
+    fn bytes_from_part(&self, part: usize) -> usize {
+        let until_data = self.expected_bytes_until_data + 1;
+        if part >= self.parts {
+            0
+        } else if self.direct_range(part).is_some() {
+            until_data
+        } else if part + 1 < self.parts && self.direct_range(part + 1).is_some() {
+            // Only the first part and the parts at the end can miss `buffer`, so the part after the first is the one to stop at
+            self.bytes_per_part() + until_data
+        } else {
+            self.bytes_per_part() * (self.parts - part)
+        }
+    }
 }
 
+#[derive(Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct WriteOperation<'a> {
+    /// Lets you write multiple blocks with `CMD25`, so buffer will be N * 512 bytes
     pub buffer: &'a [u8],
-    pub expected_bytes_until_data: usize,
+    pub part_size: usize,
+    /// Sent right before the data of each part
+    pub start_token: u8,
+    /// How long the card can be busy programming the data for
+    pub busy: BusyOperation,
+}
+
+impl WriteOperation<'_> {
+    /// The 1 byte gap, start token, data, and CRC
+    fn packet_len(&self) -> usize {
+        1 + 1 + self.part_size + size_of::<u16>()
+    }
+
+    fn parts(&self) -> usize {
+        self.buffer.len() / self.part_size
+    }
+
+    fn part(&self, part: usize) -> &[u8] {
+        &self.buffer[part * self.part_size..(part + 1) * self.part_size]
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BusyOperation {
+    pub expected_bytes_until_not_busy: usize,
     pub timeout: Duration,
 }
 
+#[derive(Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum CardCommandOperation<'a> {
     Read(ReadOperation<'a>),
     Write(WriteOperation<'a>),
-    /// Expected bytes until not busy
-    BusySignal(usize),
+    BusySignal(BusyOperation),
+}
+
+impl CardCommandOperation<'_> {
+    fn busy_operation(&self) -> &BusyOperation {
+        match self {
+            Self::BusySignal(operation) => operation,
+            Self::Write(operation) => &operation.busy,
+            Self::Read(_) => unreachable!(),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -39,68 +113,139 @@ pub enum CardCommand3Error<SpiError> {
     Spi(SpiError),
     /// `true` if any data that was not `0xFF` was received
     ReceiveResponseTimeout(bool),
-    /// Expected a start block token, but got something else
-    ExpectedStartBlockToken,
+    /// Expected a start block token, but got something else. Contains the byte that was received instead.
+    /// If it isn't a [`DataErrorToken`], the rest of the block was clocked out so that the card is ready for the next command.
+    ExpectedStartBlockToken(u8),
     InvalidCrc,
     /// Returns the number of data successfully read before the timeout
     ReceiveDataTimeout(usize),
+    /// The card was still busy after the timeout
+    BusyTimeout,
+    /// Did not receive a data response token after writing data
+    DataResponseTimeout,
+    /// The card did not accept the written data. Contains the status from the data response token.
+    DataRejected(u8),
+    /// The card sent this byte after the response, and [`SdCardConfig::trailing_bytes`] is [`TrailingBytes::Error`]
+    UnexpectedTrailingData(u8),
+    /// There was nothing left to transfer even though the command wasn't done.
+    /// This is a bug, so with the `debug-assert` feature it panics instead.
+    NothingToTransfer,
 }
 
-/// Supports all commands except for multi block read and write.
-pub async fn card_command<S: SpiBus>(
-    spi: &mut S,
-    buffer: &mut [u8],
-    command: &[u8; 6],
+const CRC: Crc<u16> = Crc::<u16>::new(&CRC_16_XMODEM);
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug)]
+enum Phase {
+    /// Bytes sent
+    SendCommand(usize),
+    ReceiveResponseStart((Instant, bool)),
+    /// Number of bytes of the response received so far
+    ReceiveResponse(usize),
+    /// Start time, number of busy bytes received so far, number of parts written
+    WaitUntilNotBusy((Instant, usize, usize)),
+    /// Start time, parts read, bytes received while waiting for the token
+    ReceiveStartBlockToken((Instant, usize, usize)),
+    /// Digest, Number of parts, number of bytes of the data received so far
+    ReceiveData((Digest<'static, u16>, usize, usize)),
+    /// Expected crc, Number of parts read, The byte of the partial CRC received, if any
+    ReceiveCrc((u16, usize, Option<u8>)),
+    /// CRC of the data, number of parts written, bytes of the packet sent so far
+    WriteData((u16, usize, usize)),
+    /// Start time, number of parts written
+    ReceiveDataResponse((Instant, usize)),
+}
+
+/// What to do after [`CommandParser::feed`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ParserStep {
+    /// Transfer the bytes from [`CommandParser::fill_transfer`], and feed what the card sent back
+    Transfer,
+    /// The command is done, and [`CommandParser::response`] has the response
+    Done,
+    /// Expected a start block token, but got `byte`, which isn't a [`DataErrorToken`].
+    /// The card could be sending a corrupted block, so clock out `len` more bytes before the next command.
+    DiscardBlock { byte: u8, len: usize },
+}
+
+/// The bytes of a command and everything the card sends back, without doing any SPI transfers.
+/// [`card_command`] drives this by transferring what [`CommandParser::fill_transfer`] says to, and feeding the received bytes to [`CommandParser::feed`].
+/// Data that goes straight into the read buffer is received with [`CommandParser::direct_read`] instead.
+/// Since it's separate from the bus, it can be fuzzed with the received bytes split up in any way.
+#[derive(Debug)]
+pub struct CommandParser<'a> {
+    command: Command,
+    response: [u8; MAX_RESPONSE_LEN],
+    response_len: usize,
     expected_bytes_until_response: usize,
-    response: &mut [u8],
     response_timeout: Duration,
-    mut operation: Option<CardCommandOperation<'_>>,
-) -> Result<(), CardCommand3Error<S::Error>> {
-    defmt::trace!("Operations: {:#?}", operation);
-    const CRC: Crc<u16> = Crc::<u16>::new(&CRC_16_XMODEM);
-    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-    #[derive(Debug)]
-    enum Phase<'a> {
-        /// Bytes sent
-        SendCommand(usize),
-        ReceiveResponseStart((Instant, bool)),
-        /// Number of bytes of the response received so far
-        ReceiveResponse(usize),
-        /// Records number of busy bytes
-        WaitUntilNotBusy(usize),
-        /// Data: parts read
-        ReceiveStartBlockToken((Instant, usize)),
-        /// Digest, Number of parts, number of bytes of the data received so far
-        ReceiveData((Digest<'a, u16>, usize, usize)),
-        /// Expected crc, Number of parts read, The byte of the partial CRC received, if any
-        ReceiveCrc((u16, usize, Option<u8>)),
-        WriteData(usize),
+    operation: Option<CardCommandOperation<'a>>,
+    clock: Clock,
+    trailing_bytes: TrailingBytes,
+    phase: Phase,
+}
+
+impl<'a> CommandParser<'a> {
+    /// `operation` must match the [`DataPhase`] of the command, and a write must have at least 1 part
+    pub fn new(
+        command: SdCommand,
+        expected_bytes_until_response: usize,
+        response_timeout: Duration,
+        operation: Option<CardCommandOperation<'a>>,
+        clock: Clock,
+    ) -> Self {
+        Self {
+            command: command.format(),
+            response: [0xFF; MAX_RESPONSE_LEN],
+            response_len: command.response_type().size(),
+            expected_bytes_until_response,
+            response_timeout,
+            operation,
+            clock,
+            trailing_bytes: TrailingBytes::Ignore,
+            phase: Phase::SendCommand(0),
+        }
     }
-    let mut phase = Phase::SendCommand(0);
-    let mut buffer_valid_bytes = 0;
-    'spi: loop {
-        defmt::trace!("number of bytes to process: {}", buffer_valid_bytes);
+
+    /// What to do with bytes after a response that ends the command. [`TrailingBytes::Ignore`] by default.
+    pub fn with_trailing_bytes(mut self, trailing_bytes: TrailingBytes) -> Self {
+        self.trailing_bytes = trailing_bytes;
+        self
+    }
+
+    /// Only the first [`crate::ResponseType::size`] bytes are valid, once the response was received
+    pub fn response(&self) -> [u8; MAX_RESPONSE_LEN] {
+        self.response
+    }
+
+    /// Processes the bytes received in the last transfer, which was set up by [`Self::fill_transfer`]
+    pub fn feed<E>(&mut self, received: &[u8]) -> Result<ParserStep, CardCommand3Error<E>> {
+        let clock = self.clock;
+        let response_timeout = self.response_timeout;
+        let trailing_bytes = self.trailing_bytes;
+        let response = &mut self.response[..self.response_len];
+        let mut phase = core::mem::replace(&mut self.phase, Phase::SendCommand(0));
+        trace!("number of bytes to process: {}", received.len());
         let mut bytes_processed = 0;
-        let before = Instant::now();
-        while buffer_valid_bytes > bytes_processed {
-            defmt::trace!("processing: {:?}", phase);
-            let bytes_to_process = &mut buffer[bytes_processed..buffer_valid_bytes];
+        while received.len() > bytes_processed {
+            trace!("processing: {:?}", phase);
+            let bytes_to_process = &received[bytes_processed..];
             match phase {
                 Phase::SendCommand(bytes_sent) => {
-                    defmt::trace!("send command phase: {}", bytes_sent);
+                    trace!("send command phase: {}", bytes_sent);
                     let bytes_to_send = size_of::<Command>() - bytes_sent;
                     let command_bytes_sent = min(bytes_to_send, bytes_to_process.len());
                     bytes_processed += command_bytes_sent;
                     let new_bytes_sent = bytes_sent + command_bytes_sent;
                     if new_bytes_sent == size_of::<Command>() {
-                        phase = Phase::ReceiveResponseStart((Instant::now(), false));
+                        phase = Phase::ReceiveResponseStart((clock(), false));
                     } else {
                         phase = Phase::SendCommand(new_bytes_sent)
                     }
-                    defmt::trace!(
+                    trace!(
                         "send command phase: {}. new bytes sent: {}",
-                        bytes_sent,
-                        new_bytes_sent
+                        bytes_sent, new_bytes_sent
                     );
                 }
                 Phase::ReceiveResponseStart((start_time, data_received)) => {
@@ -109,7 +254,7 @@ pub async fn card_command<S: SpiBus>(
                     let mut data_received = data_received;
                     let r1_index = loop {
                         if let Some(&byte) = bytes_to_process.get(i) {
-                            defmt::trace!("Byte: 0x{:02X}", byte);
+                            trace!("Byte: 0x{:02X}", byte);
                             if byte != 0xFF {
                                 data_received = true;
                                 if !R1::from_bits_retain(byte).contains(R1::BIT_7) {
@@ -121,29 +266,27 @@ pub async fn card_command<S: SpiBus>(
                         }
                         i += 1;
                     };
-                    defmt::trace!(
-                        "receive response start phase: {}, {}. r1 index: {}",
-                        start_time,
-                        data_received,
-                        r1_index
+                    trace!(
+                        "receive response start phase: {:?}, {}. r1 index: {:?}",
+                        start_time, data_received, r1_index
                     );
                     if let Some(r1_index) = r1_index {
                         bytes_processed += r1_index;
                         phase = Phase::ReceiveResponse(0);
-                    } else if start_time.elapsed() >= response_timeout {
+                    } else if start_time.elapsed(clock) >= response_timeout {
                         return Err(CardCommand3Error::ReceiveResponseTimeout(data_received));
                     } else {
-                        bytes_processed = buffer_valid_bytes;
+                        bytes_processed = received.len();
                         phase = Phase::ReceiveResponseStart((start_time, data_received));
                     }
                 }
                 Phase::ReceiveResponse(bytes_received) => {
                     let bytes_to_receive = response.len() - bytes_received;
                     let copy_len = min(bytes_to_receive, bytes_to_process.len());
-                    defmt::trace!(
-                        "receive response phase: {}. processing bytes: {:02X}. copy len: {}",
+                    trace!(
+                        "receive response phase: {}. processing bytes: {:?}. copy len: {}",
                         bytes_received,
-                        bytes_to_process,
+                        Bytes(bytes_to_process),
                         copy_len
                     );
                     response[bytes_received..bytes_received + copy_len]
@@ -151,64 +294,124 @@ pub async fn card_command<S: SpiBus>(
                     bytes_processed += copy_len;
                     let new_bytes_received = bytes_received + copy_len;
                     if new_bytes_received == response.len() {
-                        match &operation {
-                            None => break 'spi,
+                        // The card doesn't send or accept any data after rejecting a command
+                        let rejected = !R1::from_bits_retain(response[0])
+                            .difference(R1::IN_IDLE_STATE)
+                            .is_empty();
+                        match &self.operation {
+                            None => {
+                                check_trailing_bytes(
+                                    trailing_bytes,
+                                    &bytes_to_process[copy_len..],
+                                )?;
+                                return Ok(ParserStep::Done);
+                            }
+                            Some(
+                                CardCommandOperation::Read(_) | CardCommandOperation::Write(_),
+                            ) if rejected => {
+                                check_trailing_bytes(
+                                    trailing_bytes,
+                                    &bytes_to_process[copy_len..],
+                                )?;
+                                return Ok(ParserStep::Done);
+                            }
+                            Some(CardCommandOperation::Read(operation)) if operation.parts == 0 => {
+                                return Ok(ParserStep::Done);
+                            }
                             Some(CardCommandOperation::Read(_)) => {
-                                phase = Phase::ReceiveStartBlockToken((Instant::now(), 0));
+                                phase = Phase::ReceiveStartBlockToken((clock(), 0, 0));
                             }
-                            Some(CardCommandOperation::Write(_)) => {
-                                phase = Phase::WriteData(0);
+                            Some(CardCommandOperation::Write(operation)) => {
+                                phase = Phase::WriteData((CRC.checksum(operation.part(0)), 0, 0));
+                                // Any bytes after the response were sent before the data packet
+                                bytes_processed = received.len();
                             }
                             Some(CardCommandOperation::BusySignal(_)) => {
-                                phase = Phase::WaitUntilNotBusy(0)
+                                phase = Phase::WaitUntilNotBusy((clock(), 0, 0))
                             }
                         }
                     } else {
                         phase = Phase::ReceiveResponse(new_bytes_received);
                     }
                 }
-                Phase::WaitUntilNotBusy(busy_bytes) => {
-                    let mut i = 0;
-                    while let Some(&byte) = bytes_to_process.get(i) {
-                        if byte != 0 {
-                            defmt::trace!("{} bytes until not busy", busy_bytes + i);
-                            break 'spi;
-                        }
-                        i += 1;
-                    }
-                    phase = Phase::WaitUntilNotBusy(i);
-                }
-                Phase::ReceiveStartBlockToken((start_time, parts_read)) => {
-                    defmt::trace!("receive start block token phase");
-                    for &mut byte in bytes_to_process {
-                        bytes_processed += 1;
-                        if byte != 0xFF {
-                            if byte == START_BLOCK_TOKEN {
-                                phase = Phase::ReceiveData((CRC.digest(), parts_read, 0));
-                                break;
-                            } else {
-                                defmt::error!(
-                                    "expected start block token, but got 0x{:02X} instead",
-                                    byte
-                                );
-                                return Err(CardCommand3Error::ExpectedStartBlockToken);
+                Phase::WaitUntilNotBusy((start_time, busy_bytes, parts_written)) => {
+                    if let Some(i) = bytes_to_process.iter().position(|&byte| byte != 0) {
+                        trace!("{} bytes until not busy", busy_bytes + i);
+                        match &self.operation {
+                            Some(CardCommandOperation::Write(operation))
+                                if parts_written < operation.parts() =>
+                            {
+                                phase = Phase::WriteData((
+                                    CRC.checksum(operation.part(parts_written)),
+                                    parts_written,
+                                    0,
+                                ));
+                                // Any bytes after the busy signal were sent before the next data packet
+                                bytes_processed = received.len();
+                                continue;
                             }
+                            _ => return Ok(ParserStep::Done),
                         }
                     }
+                    bytes_processed = received.len();
+                    let operation = self.operation.as_ref().unwrap().busy_operation();
+                    if start_time.elapsed(clock) > operation.timeout {
+                        return Err(CardCommand3Error::BusyTimeout);
+                    }
+                    phase = Phase::WaitUntilNotBusy((
+                        start_time,
+                        busy_bytes + bytes_to_process.len(),
+                        parts_written,
+                    ));
+                }
+                Phase::ReceiveStartBlockToken((start_time, parts_read, bytes_waited)) => {
+                    trace!("receive start block token phase");
                     let operation =
-                        if let Some(CardCommandOperation::Read(operation)) = &mut operation {
+                        if let Some(CardCommandOperation::Read(operation)) = &mut self.operation {
                             operation
                         } else {
                             unreachable!()
                         };
-                    if start_time.elapsed() > operation.timeout {
+                    if let Some(i) = bytes_to_process.iter().position(|&byte| byte != 0xFF) {
+                        bytes_processed += i + 1;
+                        let byte = bytes_to_process[i];
+                        if byte == START_BLOCK_TOKEN {
+                            if let Some(bytes_until_data) = operation
+                                .bytes_until_data
+                                .as_deref_mut()
+                                .and_then(|bytes_until_data| bytes_until_data.get_mut(parts_read))
+                            {
+                                *bytes_until_data = bytes_waited + i;
+                            }
+                            phase = Phase::ReceiveData((CRC.digest(), parts_read, 0));
+                        } else {
+                            error!("expected start block token, but got 0x{:02X} instead", byte);
+                            if DataErrorToken::from_byte(byte).is_none() {
+                                let bytes_received = received.len() - bytes_processed;
+                                return Ok(ParserStep::DiscardBlock {
+                                    byte,
+                                    len: (operation.part_size + size_of::<u16>())
+                                        .saturating_sub(bytes_received),
+                                });
+                            }
+                            return Err(CardCommand3Error::ExpectedStartBlockToken(byte));
+                        }
+                    } else {
+                        bytes_processed = received.len();
+                        phase = Phase::ReceiveStartBlockToken((
+                            start_time,
+                            parts_read,
+                            bytes_waited + bytes_to_process.len(),
+                        ));
+                    }
+                    if start_time.elapsed(clock) > operation.timeout {
                         return Err(CardCommand3Error::ReceiveDataTimeout(parts_read));
                     }
                 }
                 Phase::ReceiveData((mut digest, parts_read, bytes_received)) => {
-                    defmt::trace!("receive data phase: {}", bytes_received);
+                    trace!("receive data phase: {}", bytes_received);
                     let operation =
-                        if let Some(CardCommandOperation::Read(operation)) = &mut operation {
+                        if let Some(CardCommandOperation::Read(operation)) = &mut self.operation {
                             operation
                         } else {
                             unreachable!()
@@ -236,7 +439,7 @@ pub async fn card_command<S: SpiBus>(
                                 (0, 0, 0)
                             }
                         };
-                        defmt::trace!("copy_len: {}", copy_len);
+                        trace!("copy_len: {}", copy_len);
                         // check for end
                         let copy_len = if dest_start + copy_len > operation.buffer.len() {
                             let skip_end = dest_start + copy_len - operation.buffer.len();
@@ -244,7 +447,7 @@ pub async fn card_command<S: SpiBus>(
                         } else {
                             copy_len
                         };
-                        defmt::trace!(
+                        trace!(
                             "start: {}. dest_start: {}. src_start: {}. copy_len: {}. parts_read: {}. bytes_received: {}. read_len: {}",
                             start,
                             dest_start,
@@ -258,27 +461,29 @@ pub async fn card_command<S: SpiBus>(
                         let src = &bytes_to_read[src_start..src_start + copy_len];
                         dest.copy_from_slice(src);
                     }
-                    digest.update(&bytes_to_read);
+                    if operation.crc_enabled {
+                        digest.update(bytes_to_read);
+                    }
                     bytes_processed += read_len;
                     let new_bytes_received = bytes_received + read_len;
                     if new_bytes_received == operation.part_size {
                         phase = Phase::ReceiveCrc((digest.finalize(), parts_read, None));
                     } else {
                         phase = Phase::ReceiveData((digest, parts_read, new_bytes_received));
-                        defmt::trace!(
+                        trace!(
                             "did not receive all data in a single transfer (missing {} bytes)",
                             operation.part_size - new_bytes_received
                         );
                     }
                 }
                 Phase::ReceiveCrc((expected_crc, parts_read, byte_0)) => {
-                    defmt::trace!("receive CRC phase: {}", byte_0);
+                    trace!("receive CRC phase: {:?}", byte_0);
                     if let Some(byte_0) = byte_0 {
                         let byte_1 = bytes_to_process[0];
                         bytes_processed += 1;
                         let crc = u16::from_be_bytes([byte_0, byte_1]);
                         let operation =
-                            if let Some(CardCommandOperation::Read(operation)) = &mut operation {
+                            if let Some(CardCommandOperation::Read(operation)) = &self.operation {
                                 operation
                             } else {
                                 unreachable!()
@@ -287,10 +492,9 @@ pub async fn card_command<S: SpiBus>(
                         if crc == expected_crc || !operation.crc_enabled {
                             let new_parts_read = parts_read + 1;
                             if new_parts_read == operation.parts {
-                                break 'spi;
+                                return Ok(ParserStep::Done);
                             } else {
-                                phase =
-                                    Phase::ReceiveStartBlockToken((Instant::now(), new_parts_read))
+                                phase = Phase::ReceiveStartBlockToken((clock(), new_parts_read, 0))
                             }
                         } else {
                             return Err(CardCommand3Error::InvalidCrc);
@@ -301,31 +505,121 @@ pub async fn card_command<S: SpiBus>(
                         phase = Phase::ReceiveCrc((expected_crc, parts_read, Some(byte_0)));
                     };
                 }
-                Phase::WriteData(_) => todo!(),
+                Phase::WriteData((crc, parts_written, bytes_sent)) => {
+                    let operation =
+                        if let Some(CardCommandOperation::Write(operation)) = &self.operation {
+                            operation
+                        } else {
+                            unreachable!()
+                        };
+                    // The card doesn't send anything while we send the data packet
+                    let packet_bytes_sent =
+                        min(operation.packet_len() - bytes_sent, bytes_to_process.len());
+                    bytes_processed += packet_bytes_sent;
+                    let new_bytes_sent = bytes_sent + packet_bytes_sent;
+                    if new_bytes_sent == operation.packet_len() {
+                        phase = Phase::ReceiveDataResponse((clock(), parts_written));
+                    } else {
+                        phase = Phase::WriteData((crc, parts_written, new_bytes_sent));
+                    }
+                }
+                Phase::ReceiveDataResponse((start_time, parts_written)) => {
+                    if let Some(i) = bytes_to_process.iter().position(|&byte| byte != 0xFF) {
+                        let token = DataResponseToken(bytes_to_process[i]);
+                        trace!("data response token: 0x{:02X}", token.0);
+                        bytes_processed += i + 1;
+                        // 0b010 means the data was accepted
+                        if token.get_status() == 0b010 {
+                            phase = Phase::WaitUntilNotBusy((clock(), 0, parts_written + 1));
+                        } else {
+                            return Err(CardCommand3Error::DataRejected(token.get_status()));
+                        }
+                    } else if start_time.elapsed(clock) >= response_timeout {
+                        return Err(CardCommand3Error::DataResponseTimeout);
+                    } else {
+                        bytes_processed = received.len();
+                        phase = Phase::ReceiveDataResponse((start_time, parts_written));
+                    }
+                }
             }
         }
-        defmt::trace!("procesing time: {} us", before.elapsed().as_micros());
+        self.phase = phase;
+        Ok(ParserStep::Transfer)
+    }
+
+    /// If the rest of the data of the current part goes straight into the read buffer, the part of the buffer to receive next, up to `max_len` bytes.
+    /// The card only needs `0xFF` to be sent while it sends data, so it can be received without [`Self::fill_transfer`], and then passed to [`Self::feed_direct`].
+    pub fn direct_read(&mut self, max_len: usize) -> Option<&mut [u8]> {
+        let Phase::ReceiveData((_, parts_read, bytes_received)) = self.phase else {
+            return None;
+        };
+        let Some(CardCommandOperation::Read(operation)) = &mut self.operation else {
+            unreachable!()
+        };
+        let range = operation.direct_range(parts_read)?;
+        let start = range.start + bytes_received;
+        let end = range.end.min(start + max_len);
+        Some(&mut operation.buffer[start..end])
+    }
+
+    /// Processes the `len` bytes that were received into the buffer from [`Self::direct_read`]
+    pub fn feed_direct(&mut self, len: usize) {
+        let Phase::ReceiveData((mut digest, parts_read, bytes_received)) =
+            core::mem::replace(&mut self.phase, Phase::SendCommand(0))
+        else {
+            unreachable!()
+        };
+        let Some(CardCommandOperation::Read(operation)) = &self.operation else {
+            unreachable!()
+        };
+        let start = operation.direct_range(parts_read).unwrap().start + bytes_received;
+        if operation.crc_enabled {
+            digest.update(&operation.buffer[start..start + len]);
+        }
+        let new_bytes_received = bytes_received + len;
+        self.phase = if new_bytes_received == operation.part_size {
+            Phase::ReceiveCrc((digest.finalize(), parts_read, None))
+        } else {
+            Phase::ReceiveData((digest, parts_read, new_bytes_received))
+        };
+    }
+
+    /// How many of the `bytes_to_transfer` bytes from [`Self::fill_transfer`] have to be sent.
+    /// The rest are `0xFF`, and nothing the card sends back while they are sent is used, so they can be received with [`SpiBus::read`] after sending these with [`SpiBus::write`].
+    pub fn bytes_to_send(&self, bytes_to_transfer: usize) -> usize {
+        let bytes_to_send = match &self.phase {
+            Phase::SendCommand(bytes_sent) => size_of::<Command>() - bytes_sent,
+            Phase::WriteData((_, _, bytes_sent)) => match &self.operation {
+                Some(CardCommandOperation::Write(operation)) => operation.packet_len() - bytes_sent,
+                _ => unreachable!(),
+            },
+            _ => 0,
+        };
+        bytes_to_send.min(bytes_to_transfer)
+    }
 
-        // Set up buffer
-        let bytes_to_transfer = match &phase {
+    /// Fills `buffer` with the bytes to send next, and returns how many of them to transfer.
+    /// The length of `buffer` bounds the transfer.
+    pub fn fill_transfer<E>(&self, buffer: &mut [u8]) -> Result<usize, CardCommand3Error<E>> {
+        let operation = &self.operation;
+        let response_len = self.response_len;
+        let expected_bytes_until_response = self.expected_bytes_until_response;
+        let bytes_to_transfer = match &self.phase {
             Phase::SendCommand(bytes_sent) => {
                 let bytes_sent = *bytes_sent;
                 let copy_len = min(size_of::<Command>() - bytes_sent, buffer.len());
-                buffer[..copy_len].copy_from_slice(&command[bytes_sent..bytes_sent + copy_len]);
+                buffer[..copy_len]
+                    .copy_from_slice(&self.command[bytes_sent..bytes_sent + copy_len]);
                 let bytes_to_transfer = (copy_len
                     + expected_bytes_until_response
-                    + response.len()
-                    + match &operation {
+                    + response_len
+                    + match operation {
                         None => 0,
-                        Some(CardCommandOperation::Read(op)) => {
-                            (op.expected_bytes_until_data + op.buffer.len() + size_of::<u16>())
-                                * op.parts
-                        }
-                        Some(CardCommandOperation::Write(_)) => {
-                            todo!()
-                        }
-                        Some(CardCommandOperation::BusySignal(expected_bytes_until_not_busy)) => {
-                            *expected_bytes_until_not_busy
+                        Some(CardCommandOperation::Read(op)) => op.bytes_from_part(0),
+                        // We can't send the data until we know when the response ended
+                        Some(CardCommandOperation::Write(_)) => 0,
+                        Some(CardCommandOperation::BusySignal(op)) => {
+                            op.expected_bytes_until_not_busy
                         }
                     })
                 .min(buffer.len());
@@ -334,18 +628,14 @@ pub async fn card_command<S: SpiBus>(
             }
             Phase::ReceiveResponseStart(_) => {
                 let bytes_to_transfer = (expected_bytes_until_response
-                    + response.len()
-                    + match &operation {
+                    + response_len
+                    + match operation {
                         None => 0,
-                        Some(CardCommandOperation::Read(op)) => {
-                            (op.expected_bytes_until_data + op.buffer.len() + size_of::<u16>())
-                                * op.parts
-                        }
-                        Some(CardCommandOperation::Write(_)) => {
-                            todo!()
-                        }
-                        Some(CardCommandOperation::BusySignal(expected_bytes_until_not_busy)) => {
-                            *expected_bytes_until_not_busy
+                        Some(CardCommandOperation::Read(op)) => op.bytes_from_part(0),
+                        // We can't send the data until we know when the response ended
+                        Some(CardCommandOperation::Write(_)) => 0,
+                        Some(CardCommandOperation::BusySignal(op)) => {
+                            op.expected_bytes_until_not_busy
                         }
                     })
                 .min(buffer.len());
@@ -353,28 +643,23 @@ pub async fn card_command<S: SpiBus>(
                 bytes_to_transfer
             }
             Phase::ReceiveResponse(bytes_received) => {
-                let bytes_to_transfer = (response.len() - bytes_received
-                    + match &operation {
+                let bytes_to_transfer = (response_len - bytes_received
+                    + match operation {
                         None => 0,
-                        Some(CardCommandOperation::Read(op)) => {
-                            (op.expected_bytes_until_data + op.buffer.len() + size_of::<u16>())
-                                * op.parts
-                        }
-                        Some(CardCommandOperation::Write(_)) => {
-                            todo!()
-                        }
-                        Some(CardCommandOperation::BusySignal(expected_bytes_until_not_busy)) => {
-                            *expected_bytes_until_not_busy
+                        Some(CardCommandOperation::Read(op)) => op.bytes_from_part(0),
+                        // We can't send the data until we know when the response ended
+                        Some(CardCommandOperation::Write(_)) => 0,
+                        Some(CardCommandOperation::BusySignal(op)) => {
+                            op.expected_bytes_until_not_busy
                         }
                     })
                 .min(buffer.len());
                 buffer[..bytes_to_transfer].fill(0xFF);
                 bytes_to_transfer
             }
-            Phase::ReceiveStartBlockToken((_, parts_read)) => {
-                let bytes_to_transfer = (if let Some(CardCommandOperation::Read(op)) = &operation {
-                    (op.expected_bytes_until_data + op.buffer.len() + size_of::<u16>())
-                        * (op.parts - parts_read)
+            Phase::ReceiveStartBlockToken((_, parts_read, _)) => {
+                let bytes_to_transfer = (if let Some(CardCommandOperation::Read(op)) = operation {
+                    op.bytes_from_part(*parts_read)
                 } else {
                     unreachable!()
                 })
@@ -383,11 +668,10 @@ pub async fn card_command<S: SpiBus>(
                 bytes_to_transfer
             }
             Phase::ReceiveData((_digest, parts_read, bytes_received)) => {
-                let bytes_to_transfer = (if let Some(CardCommandOperation::Read(op)) = &operation {
-                    op.buffer.len() - bytes_received
+                let bytes_to_transfer = (if let Some(CardCommandOperation::Read(op)) = operation {
+                    op.part_size - bytes_received
                         + size_of::<u16>()
-                        + (op.expected_bytes_until_data + op.buffer.len() + size_of::<u16>())
-                            * (op.parts - (parts_read + 1))
+                        + op.bytes_from_part(parts_read + 1)
                 } else {
                     unreachable!()
                 })
@@ -396,10 +680,9 @@ pub async fn card_command<S: SpiBus>(
                 bytes_to_transfer
             }
             Phase::ReceiveCrc((_expected_crc, parts_read, byte_0)) => {
-                let bytes_to_transfer = (if let Some(CardCommandOperation::Read(op)) = &operation {
+                let bytes_to_transfer = (if let Some(CardCommandOperation::Read(op)) = operation {
                     size_of::<u16>() - if byte_0.is_some() { 1 } else { 0 }
-                        + (op.expected_bytes_until_data + op.buffer.len() + size_of::<u16>())
-                            * (op.parts - (parts_read + 1))
+                        + op.bytes_from_part(parts_read + 1)
                 } else {
                     unreachable!()
                 })
@@ -408,33 +691,472 @@ pub async fn card_command<S: SpiBus>(
                 bytes_to_transfer
             }
             Phase::WaitUntilNotBusy(_) => {
+                let bytes_to_transfer = operation
+                    .as_ref()
+                    .unwrap()
+                    .busy_operation()
+                    .expected_bytes_until_not_busy
+                    .max(1)
+                    .min(buffer.len());
+                buffer[..bytes_to_transfer].fill(0xFF);
+                bytes_to_transfer
+            }
+            Phase::WriteData((crc, parts_written, bytes_sent)) => {
+                let operation = if let Some(CardCommandOperation::Write(operation)) = operation {
+                    operation
+                } else {
+                    unreachable!()
+                };
+                let packet_len = operation.packet_len();
+                let part = operation.part(*parts_written);
                 let bytes_to_transfer =
-                    (if let Some(CardCommandOperation::BusySignal(expected_bytes_until_not_busy)) =
-                        &operation
-                    {
-                        *expected_bytes_until_not_busy
-                    } else {
-                        unreachable!()
-                    })
+                    (packet_len - bytes_sent + 1 + operation.busy.expected_bytes_until_not_busy)
+                        .min(buffer.len());
+                let crc = crc.to_be_bytes();
+                for (i, byte) in buffer[..bytes_to_transfer].iter_mut().enumerate() {
+                    let packet_index = bytes_sent + i;
+                    *byte = match packet_index {
+                        0 => 0xFF,
+                        1 => operation.start_token,
+                        i if i < packet_len - size_of::<u16>() => part[i - 2],
+                        i if i < packet_len => crc[i - (packet_len - size_of::<u16>())],
+                        _ => 0xFF,
+                    };
+                }
+                bytes_to_transfer
+            }
+            Phase::ReceiveDataResponse(_) => {
+                let bytes_to_transfer = (1 + operation
+                    .as_ref()
+                    .unwrap()
+                    .busy_operation()
+                    .expected_bytes_until_not_busy)
                     .min(buffer.len());
                 buffer[..bytes_to_transfer].fill(0xFF);
                 bytes_to_transfer
             }
-            Phase::WriteData(_) => todo!(),
         };
-        assert_ne!(bytes_to_transfer, 0, "{:#?}", phase);
-        defmt::trace!("transferring...");
-        let before = Instant::now();
-        spi.transfer_in_place(&mut buffer[..bytes_to_transfer])
-            .await
-            .map_err(CardCommand3Error::Spi)?;
-        defmt::trace!(
+        check!(
+            bytes_to_transfer != 0,
+            CardCommand3Error::NothingToTransfer,
+            "nothing to transfer in phase {:?}",
+            self.phase
+        );
+        Ok(bytes_to_transfer)
+    }
+}
+
+/// The card should send `0xFF` after a response when there's no data phase
+fn check_trailing_bytes<E>(
+    policy: TrailingBytes,
+    trailing: &[u8],
+) -> Result<(), CardCommand3Error<E>> {
+    if let Some(&byte) = trailing.iter().find(|&&byte| byte != 0xFF) {
+        match policy {
+            TrailingBytes::Ignore => {}
+            TrailingBytes::Warn => warn!("unexpected byte after the response: 0x{:02X}", byte),
+            TrailingBytes::Error => return Err(CardCommand3Error::UnexpectedTrailingData(byte)),
+        }
+    }
+    Ok(())
+}
+
+/// Supports all commands. For multi block write, use [`stop_multiple_block_write`] afterwards.
+/// Returns the response. Only the first [`crate::ResponseType::size`] bytes of it are valid.
+pub async fn card_command<S: SpiBus>(
+    spi: &mut S,
+    buffer: &mut [u8],
+    config: &SdCardConfig,
+    command: SdCommand,
+    expected_bytes_until_response: usize,
+    response_timeout: Duration,
+    operation: Option<CardCommandOperation<'_>>,
+) -> Result<[u8; MAX_RESPONSE_LEN], CardCommand3Error<S::Error>> {
+    let clock = config.clock;
+    let buffer_len = buffer.len().min(config.max_transfer_len.max(1));
+    let buffer = &mut buffer[..buffer_len];
+    trace!("Command: {:?}. Operations: {:#?}", command, operation);
+    debug_assert!(matches!(
+        (command.data_phase(), &operation),
+        (DataPhase::None, None)
+            | (DataPhase::Busy, Some(CardCommandOperation::BusySignal(_)))
+            | (DataPhase::Read, Some(CardCommandOperation::Read(_)))
+            // The blocks of a write stream are sent afterwards with `send_data_block`
+            | (DataPhase::Write, None | Some(CardCommandOperation::Write(_)))
+    ));
+    if let Some(CardCommandOperation::Write(operation)) = &operation {
+        check!(
+            operation.parts() > 0,
+            CardCommand3Error::NothingToTransfer,
+            "write operation has no blocks to write"
+        );
+    }
+    let mut parser = CommandParser::new(
+        command,
+        expected_bytes_until_response,
+        response_timeout,
+        operation,
+        clock,
+    )
+    .with_trailing_bytes(config.trailing_bytes);
+    let mut buffer_valid_bytes = 0;
+    loop {
+        let before = clock();
+        match parser.feed(&buffer[..buffer_valid_bytes])? {
+            ParserStep::Transfer => {}
+            ParserStep::Done => break,
+            ParserStep::DiscardBlock { byte, len } => {
+                discard_bytes(spi, buffer, len, config.split_transfers).await?;
+                return Err(CardCommand3Error::ExpectedStartBlockToken(byte));
+            }
+        }
+        trace!("procesing time: {} us", before.elapsed(clock).as_micros());
+
+        if let Some(data) = parser.direct_read(buffer_len) {
+            let len = data.len();
+            receive(spi, data, config.split_transfers).await?;
+            trace!("Read {} bytes of data straight into the buffer", len);
+            parser.feed_direct(len);
+            buffer_valid_bytes = 0;
+            continue;
+        }
+
+        let bytes_to_transfer = parser.fill_transfer(buffer)?;
+        trace!("transferring...");
+        let before = clock();
+        if config.split_transfers {
+            let (send, rest) =
+                buffer[..bytes_to_transfer].split_at_mut(parser.bytes_to_send(bytes_to_transfer));
+            if !send.is_empty() {
+                spi.write(send).await.map_err(CardCommand3Error::Spi)?;
+                // The parser doesn't look at what the card sent while we were sending
+                send.fill(0xFF);
+            }
+            receive(spi, rest, true).await?;
+        } else {
+            spi.transfer_in_place(&mut buffer[..bytes_to_transfer])
+                .await
+                .map_err(CardCommand3Error::Spi)?;
+        }
+        trace!(
             "Transferred {} bytes in {} us",
             bytes_to_transfer,
-            before.elapsed().as_micros()
+            before.elapsed(clock).as_micros()
         );
-        defmt::trace!("Bytes: {:02X}", &mut buffer[..bytes_to_transfer]);
+        trace!("Bytes: {:?}", Bytes(&buffer[..bytes_to_transfer]));
         buffer_valid_bytes = bytes_to_transfer;
     }
+    Ok(parser.response())
+}
+
+/// Sends the stop transmission token after the last block of a `CMD25` multi block write, and waits until the card is done programming
+pub async fn stop_multiple_block_write<S: SpiBus>(
+    spi: &mut S,
+    buffer: &mut [u8],
+    config: &SdCardConfig,
+    busy: &BusyOperation,
+) -> Result<(), CardCommand3Error<S::Error>> {
+    let buffer_len = buffer.len().min(config.max_transfer_len.max(1));
+    let buffer = &mut buffer[..buffer_len];
+    // The card starts being busy 1 byte after the token
+    let bytes_to_transfer = (1 + 1 + busy.expected_bytes_until_not_busy).min(buffer.len());
+    buffer[0] = STOP_TRAN_TOKEN;
+    if config.split_transfers {
+        spi.write(&buffer[..1])
+            .await
+            .map_err(CardCommand3Error::Spi)?;
+        receive(spi, &mut buffer[1..bytes_to_transfer], true).await?;
+    } else {
+        buffer[1..bytes_to_transfer].fill(0xFF);
+        spi.transfer_in_place(&mut buffer[..bytes_to_transfer])
+            .await
+            .map_err(CardCommand3Error::Spi)?;
+    }
+    wait_until_not_busy(
+        spi,
+        buffer,
+        config,
+        busy,
+        2.min(bytes_to_transfer)..bytes_to_transfer,
+    )
+    .await
+}
+
+/// Sends a single data block without sending a command, such as the next block of a `CMD25` transfer that is kept open,
+/// and waits until the card is done programming it.
+pub async fn send_data_block<S: SpiBus>(
+    spi: &mut S,
+    buffer: &mut [u8],
+    config: &SdCardConfig,
+    data: &[u8],
+    start_token: u8,
+    response_timeout: Duration,
+    busy: &BusyOperation,
+) -> Result<(), CardCommand3Error<S::Error>> {
+    let clock = config.clock;
+    let buffer_len = buffer.len().min(config.max_transfer_len.max(1));
+    let buffer = &mut buffer[..buffer_len];
+    // The card doesn't send anything while we send the data packet
+    for part in [&[start_token][..], data, &CRC.checksum(data).to_be_bytes()] {
+        spi.write(part).await.map_err(CardCommand3Error::Spi)?;
+    }
+    let bytes_to_transfer = (1 + busy.expected_bytes_until_not_busy).min(buffer.len());
+    let start_time = clock();
+    let bytes_after_token = loop {
+        receive(
+            spi,
+            &mut buffer[..bytes_to_transfer],
+            config.split_transfers,
+        )
+        .await?;
+        if let Some(i) = buffer[..bytes_to_transfer]
+            .iter()
+            .position(|&byte| byte != 0xFF)
+        {
+            let token = DataResponseToken(buffer[i]);
+            trace!("data response token: 0x{:02X}", token.0);
+            // 0b010 means the data was accepted
+            if token.get_status() != 0b010 {
+                return Err(CardCommand3Error::DataRejected(token.get_status()));
+            }
+            break i + 1..bytes_to_transfer;
+        }
+        if start_time.elapsed(clock) >= response_timeout {
+            return Err(CardCommand3Error::DataResponseTimeout);
+        }
+    };
+    wait_until_not_busy(spi, buffer, config, busy, bytes_after_token).await
+}
+
+/// Clocks bytes until the card stops holding MISO low. The bytes in `received` were already received into `buffer`.
+async fn wait_until_not_busy<S: SpiBus>(
+    spi: &mut S,
+    buffer: &mut [u8],
+    config: &SdCardConfig,
+    busy: &BusyOperation,
+    received: Range<usize>,
+) -> Result<(), CardCommand3Error<S::Error>> {
+    let clock = config.clock;
+    let mut bytes_to_check = &buffer[received];
+    let start_time = clock();
+    loop {
+        if bytes_to_check.iter().any(|&byte| byte != 0) {
+            return Ok(());
+        }
+        if start_time.elapsed(clock) > busy.timeout {
+            return Err(CardCommand3Error::BusyTimeout);
+        }
+        let bytes_to_transfer = busy.expected_bytes_until_not_busy.max(1).min(buffer.len());
+        receive(
+            spi,
+            &mut buffer[..bytes_to_transfer],
+            config.split_transfers,
+        )
+        .await?;
+        bytes_to_check = &buffer[..bytes_to_transfer];
+    }
+}
+
+/// Receives a single data block without sending a command, such as the next block of a `CMD18` transfer that was started with `parts: 0`.
+/// The data is read straight into `data`, and `buffer` is only used while waiting for the start block token.
+pub async fn receive_data_block<S: SpiBus>(
+    spi: &mut S,
+    buffer: &mut [u8],
+    config: &SdCardConfig,
+    data: &mut [u8],
+    timeout: Duration,
+    crc_enabled: bool,
+) -> Result<(), CardCommand3Error<S::Error>> {
+    let len = data.len();
+    receive_scattered_block(
+        spi,
+        buffer,
+        config,
+        &mut ScatterBuffer::new(&mut [data], 0),
+        len,
+        timeout,
+        crc_enabled,
+    )
+    .await
+}
+
+/// Where the data of a vectored read goes.
+/// The first `skip` bytes are thrown away, then the data fills `buffers` one after another, and anything after that is thrown away.
+pub struct ScatterBuffer<'a, 'b> {
+    buffers: &'a mut [&'b mut [u8]],
+    skip: usize,
+    /// The buffer being filled
+    buffer: usize,
+    /// How much of the buffer being filled is filled
+    offset: usize,
+}
+
+impl<'a, 'b> ScatterBuffer<'a, 'b> {
+    pub fn new(buffers: &'a mut [&'b mut [u8]], skip: usize) -> Self {
+        Self {
+            buffers,
+            skip,
+            buffer: 0,
+            offset: 0,
+        }
+    }
+
+    /// Where the next bytes go, up to `len` of them.
+    /// Returns how many bytes it's for, and `None` if they are thrown away.
+    fn next(&mut self, len: usize) -> (usize, Option<&mut [u8]>) {
+        if self.skip > 0 {
+            let skipped = len.min(self.skip);
+            self.skip -= skipped;
+            return (skipped, None);
+        }
+        while self
+            .buffers
+            .get(self.buffer)
+            .is_some_and(|buffer| self.offset == buffer.len())
+        {
+            self.buffer += 1;
+            self.offset = 0;
+        }
+        let Some(buffer) = self.buffers.get_mut(self.buffer) else {
+            return (len, None);
+        };
+        let start = self.offset;
+        let filled = len.min(buffer.len() - start);
+        self.offset += filled;
+        (filled, Some(&mut buffer[start..start + filled]))
+    }
+}
+
+/// Like [`receive_data_block`], but for a block of `len` bytes whose data goes into `data`.
+/// The parts of the block that are thrown away are received into `buffer`.
+pub async fn receive_scattered_block<S: SpiBus>(
+    spi: &mut S,
+    buffer: &mut [u8],
+    config: &SdCardConfig,
+    data: &mut ScatterBuffer<'_, '_>,
+    len: usize,
+    timeout: Duration,
+    crc_enabled: bool,
+) -> Result<(), CardCommand3Error<S::Error>> {
+    let clock = config.clock;
+    let buffer_len = buffer.len().min(config.max_transfer_len.max(1));
+    let buffer = &mut buffer[..buffer_len];
+    let mut digest = CRC.digest();
+    let mut crc = [0xFF; size_of::<u16>()];
+    let start_time = clock();
+    // We never transfer more than the token, data, and CRC, so we can't accidentally receive part of the next block
+    let bytes_to_transfer = (1 + len + crc.len()).min(buffer.len());
+    let (mut data_received, crc_received) = loop {
+        receive(
+            spi,
+            &mut buffer[..bytes_to_transfer],
+            config.split_transfers,
+        )
+        .await?;
+        if let Some(i) = buffer[..bytes_to_transfer]
+            .iter()
+            .position(|&byte| byte != 0xFF)
+        {
+            let byte = buffer[i];
+            if byte != START_BLOCK_TOKEN {
+                error!("expected start block token, but got 0x{:02X} instead", byte);
+                if DataErrorToken::from_byte(byte).is_none() {
+                    // The card could be sending a corrupted block, so finish clocking it out
+                    let bytes_received = bytes_to_transfer - (i + 1);
+                    discard_bytes(
+                        spi,
+                        buffer,
+                        (len + crc.len()).saturating_sub(bytes_received),
+                        config.split_transfers,
+                    )
+                    .await?;
+                }
+                return Err(CardCommand3Error::ExpectedStartBlockToken(byte));
+            }
+            let received = &buffer[i + 1..bytes_to_transfer];
+            let data_received = received.len().min(len);
+            let mut copied = 0;
+            while copied < data_received {
+                let (copy_len, dest) = data.next(data_received - copied);
+                if let Some(dest) = dest {
+                    dest.copy_from_slice(&received[copied..copied + copy_len]);
+                }
+                copied += copy_len;
+            }
+            if crc_enabled {
+                digest.update(&received[..data_received]);
+            }
+            let crc_received = received.len() - data_received;
+            crc[..crc_received].copy_from_slice(&received[data_received..]);
+            break (data_received, crc_received);
+        }
+        if start_time.elapsed(clock) > timeout {
+            return Err(CardCommand3Error::ReceiveDataTimeout(0));
+        }
+    };
+    while data_received < len {
+        let (receive_len, dest) = data.next(len - data_received);
+        match dest {
+            Some(dest) => {
+                receive(spi, dest, config.split_transfers).await?;
+                if crc_enabled {
+                    digest.update(dest);
+                }
+            }
+            None => {
+                // The CRC still covers the bytes that are thrown away
+                let mut left = receive_len;
+                while left > 0 {
+                    let chunk = &mut buffer[..left.min(buffer_len)];
+                    receive(spi, chunk, config.split_transfers).await?;
+                    if crc_enabled {
+                        digest.update(chunk);
+                    }
+                    left -= chunk.len();
+                }
+            }
+        }
+        data_received += receive_len;
+    }
+    if crc_received < crc.len() {
+        receive(spi, &mut crc[crc_received..], config.split_transfers).await?;
+    }
+    if crc_enabled && u16::from_be_bytes(crc) != digest.finalize() {
+        return Err(CardCommand3Error::InvalidCrc);
+    }
     Ok(())
 }
+
+/// Clocks out `len` bytes, ignoring whatever the card sends
+async fn discard_bytes<S: SpiBus>(
+    spi: &mut S,
+    buffer: &mut [u8],
+    mut len: usize,
+    split_transfers: bool,
+) -> Result<(), CardCommand3Error<S::Error>> {
+    while len > 0 {
+        let bytes_to_transfer = len.min(buffer.len());
+        receive(spi, &mut buffer[..bytes_to_transfer], split_transfers).await?;
+        len -= bytes_to_transfer;
+    }
+    Ok(())
+}
+
+/// Sends `0xFF` while receiving into `buffer`.
+/// With [`SdCardConfig::split_transfers`] this is a [`SpiBus::read`], so `buffer` doesn't have to be filled with `0xFF` first.
+async fn receive<S: SpiBus>(
+    spi: &mut S,
+    buffer: &mut [u8],
+    split_transfers: bool,
+) -> Result<(), CardCommand3Error<S::Error>> {
+    if buffer.is_empty() {
+        return Ok(());
+    }
+    if split_transfers {
+        spi.read(buffer).await
+    } else {
+        buffer.fill(0xFF);
+        spi.transfer_in_place(buffer).await
+    }
+    .map_err(CardCommand3Error::Spi)
+}
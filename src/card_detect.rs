@@ -0,0 +1,100 @@
+use embedded_hal::digital::{InputPin, PinState};
+use embedded_hal_async::digital::Wait;
+
+/// A card detect switch in the SD card socket.
+/// `()` means there is no switch, so a card is always assumed to be inserted.
+pub trait CardDetect {
+    /// Returns `true` if a card is inserted
+    fn is_card_present(&mut self) -> bool;
+    /// Resolves when a card is inserted
+    async fn wait_for_card(&mut self);
+    /// Returns `true` if the socket's write protect switch says the card is read-only
+    fn is_write_protected(&mut self) -> bool {
+        false
+    }
+}
+
+impl CardDetect for () {
+    fn is_card_present(&mut self) -> bool {
+        true
+    }
+
+    async fn wait_for_card(&mut self) {}
+}
+
+/// A card detect switch connected to a pin
+pub struct CardDetectPin<P> {
+    pin: P,
+    present_state: PinState,
+}
+
+impl<P: InputPin + Wait> CardDetectPin<P> {
+    /// `present_state` is the level of the pin when a card is inserted.
+    /// Most sockets connect the switch to ground when a card is inserted, so this is usually [`PinState::Low`].
+    pub fn new(pin: P, present_state: PinState) -> Self {
+        Self { pin, present_state }
+    }
+}
+
+impl<P: InputPin + Wait> CardDetect for CardDetectPin<P> {
+    /// If reading the pin fails, the card is assumed to be inserted, so init can still find out for sure
+    fn is_card_present(&mut self) -> bool {
+        match self.pin.is_high() {
+            Ok(is_high) => PinState::from(is_high) == self.present_state,
+            Err(_) => {
+                warn!("failed to read the card detect pin");
+                true
+            }
+        }
+    }
+
+    async fn wait_for_card(&mut self) {
+        let result = match self.present_state {
+            PinState::High => self.pin.wait_for_high().await,
+            PinState::Low => self.pin.wait_for_low().await,
+        };
+        if result.is_err() {
+            warn!("failed to wait for the card detect pin");
+        }
+    }
+}
+
+/// Adds a write protect switch to a [`CardDetect`].
+/// Use `()` as the card detect if the socket only has a write protect switch.
+pub struct WithWriteProtect<Cd, P> {
+    card_detect: Cd,
+    pin: P,
+    protected_state: PinState,
+}
+
+impl<Cd: CardDetect, P: InputPin> WithWriteProtect<Cd, P> {
+    /// `protected_state` is the level of the pin when the card's write protect tab is in the locked position
+    pub fn new(card_detect: Cd, pin: P, protected_state: PinState) -> Self {
+        Self {
+            card_detect,
+            pin,
+            protected_state,
+        }
+    }
+}
+
+impl<Cd: CardDetect, P: InputPin> CardDetect for WithWriteProtect<Cd, P> {
+    fn is_card_present(&mut self) -> bool {
+        self.card_detect.is_card_present()
+    }
+
+    async fn wait_for_card(&mut self) {
+        self.card_detect.wait_for_card().await
+    }
+
+    /// If reading the pin fails, the card is assumed to be writable, and the card will reject the write if it isn't
+    fn is_write_protected(&mut self) -> bool {
+        match self.pin.is_high() {
+            Ok(is_high) => PinState::from(is_high) == self.protected_state,
+            Err(_) => {
+                warn!("failed to read the write protect pin");
+                false
+            }
+        }
+    }
+}
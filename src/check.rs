@@ -0,0 +1,14 @@
+//! Checks for states that should never happen, but could if the card behaves unexpectedly or there is a bug
+
+/// If the condition is false, panics with the `debug-assert` feature, and otherwise logs the message and returns the error
+macro_rules! check {
+    ($condition:expr, $error:expr, $s:literal $(, $x:expr)* $(,)?) => {
+        if !$condition {
+            if cfg!(feature = "debug-assert") {
+                panic!($s $(, $x)*);
+            }
+            error!($s $(, $x)*);
+            return Err($error);
+        }
+    };
+}
@@ -0,0 +1,185 @@
+use crc::{CRC_32_ISO_HDLC, Crc};
+
+use crate::{BLOCK_SIZE, Disk};
+
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+const CHECKSUM_LEN: usize = size_of::<u32>();
+/// Number of checksums stored in each sidecar block
+const CHECKSUMS_PER_BLOCK: u64 = (BLOCK_SIZE / CHECKSUM_LEN) as u64;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ChecksumError<E> {
+    Disk(E),
+    /// The start and length must be multiples of [`BLOCK_SIZE`]
+    Unaligned,
+    /// The range goes past the end of the data region
+    OutOfRange,
+    /// The block's data doesn't match its checksum, so it was corrupted after it was written
+    Mismatch {
+        block: u64,
+    },
+}
+
+/// Stores a CRC-32 of every block in a sidecar region at the end of the disk, and checks it on every read.
+/// This catches corruption that the card's own CRC can't, such as a firmware bug overwriting the wrong block.
+///
+/// The first `data_blocks` blocks of `disk` are exposed, and the [`ChecksumDisk::sidecar_blocks`] blocks after them store the checksums.
+/// Reads and writes must be whole blocks. Writes also read and write the sidecar blocks they affect, so they are slower.
+/// If power is lost between writing the data and its checksums, those blocks fail their checksum until they are written again.
+///
+/// This is a [`Disk`] itself, so it can be used under a cache such as `ReadLeaseCache`.
+pub struct ChecksumDisk<D> {
+    disk: D,
+    data_blocks: u64,
+}
+
+impl<D: Disk<Address = u64>> ChecksumDisk<D> {
+    pub const fn new(disk: D, data_blocks: u64) -> Self {
+        Self { disk, data_blocks }
+    }
+
+    /// The number of blocks needed after the data region to store the checksums
+    pub const fn sidecar_blocks(data_blocks: u64) -> u64 {
+        data_blocks.div_ceil(CHECKSUMS_PER_BLOCK)
+    }
+
+    pub fn into_inner(self) -> D {
+        self.disk
+    }
+
+    /// Computes and stores the checksum of every block.
+    /// Do this once before using a region that already has data (or garbage) in it, or every read would fail with [`ChecksumError::Mismatch`].
+    pub async fn rebuild(&mut self) -> Result<(), ChecksumError<D::Error>> {
+        let mut sidecar = [0; BLOCK_SIZE];
+        let mut block = [0; BLOCK_SIZE];
+        for sidecar_index in 0..Self::sidecar_blocks(self.data_blocks) {
+            let first_block = sidecar_index * CHECKSUMS_PER_BLOCK;
+            let last_block = (first_block + CHECKSUMS_PER_BLOCK).min(self.data_blocks);
+            for data_block in first_block..last_block {
+                self.disk
+                    .read(data_block * BLOCK_SIZE as u64, &mut block)
+                    .await
+                    .map_err(ChecksumError::Disk)?;
+                let offset = (data_block - first_block) as usize * CHECKSUM_LEN;
+                sidecar[offset..offset + CHECKSUM_LEN]
+                    .copy_from_slice(&CRC.checksum(&block).to_le_bytes());
+            }
+            self.disk
+                .write(self.sidecar_address(sidecar_index), &sidecar)
+                .await
+                .map_err(ChecksumError::Disk)?;
+        }
+        Ok(())
+    }
+
+    fn sidecar_address(&self, sidecar_index: u64) -> u64 {
+        (self.data_blocks + sidecar_index) * BLOCK_SIZE as u64
+    }
+
+    /// Returns the first block of the range
+    fn check_range(&self, start: u64, len: usize) -> Result<u64, ChecksumError<D::Error>> {
+        if !start.is_multiple_of(BLOCK_SIZE as u64) || !len.is_multiple_of(BLOCK_SIZE) {
+            return Err(ChecksumError::Unaligned);
+        }
+        let start_block = start / BLOCK_SIZE as u64;
+        if start_block + (len / BLOCK_SIZE) as u64 > self.data_blocks {
+            return Err(ChecksumError::OutOfRange);
+        }
+        Ok(start_block)
+    }
+}
+
+impl<D: Disk<Address = u64>> Disk for ChecksumDisk<D> {
+    type Address = u64;
+    type Error = ChecksumError<D::Error>;
+    const BLOCK_SIZE: usize = BLOCK_SIZE;
+
+    fn block_size(&self) -> usize {
+        self.disk.block_size()
+    }
+
+    fn erase_unit_size(&self) -> Option<usize> {
+        self.disk.erase_unit_size()
+    }
+
+    async fn read(&mut self, start: u64, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        let start_block = self.check_range(start, buffer.len())?;
+        self.disk
+            .read(start, buffer)
+            .await
+            .map_err(ChecksumError::Disk)?;
+        let mut sidecar = [0; BLOCK_SIZE];
+        let mut loaded_sidecar = None;
+        for (i, block) in buffer.chunks_exact(BLOCK_SIZE).enumerate() {
+            let block_number = start_block + i as u64;
+            let sidecar_index = block_number / CHECKSUMS_PER_BLOCK;
+            if loaded_sidecar != Some(sidecar_index) {
+                self.disk
+                    .read(self.sidecar_address(sidecar_index), &mut sidecar)
+                    .await
+                    .map_err(ChecksumError::Disk)?;
+                loaded_sidecar = Some(sidecar_index);
+            }
+            let offset = (block_number % CHECKSUMS_PER_BLOCK) as usize * CHECKSUM_LEN;
+            let expected =
+                u32::from_le_bytes(sidecar[offset..offset + CHECKSUM_LEN].try_into().unwrap());
+            if CRC.checksum(block) != expected {
+                error!("block {} doesn't match its checksum", block_number);
+                return Err(ChecksumError::Mismatch {
+                    block: block_number,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    async fn write(&mut self, start: u64, buffer: &[u8]) -> Result<(), Self::Error> {
+        let start_block = self.check_range(start, buffer.len())?;
+        self.disk
+            .write(start, buffer)
+            .await
+            .map_err(ChecksumError::Disk)?;
+        let mut sidecar = [0; BLOCK_SIZE];
+        let mut blocks = buffer
+            .chunks_exact(BLOCK_SIZE)
+            .zip(start_block..)
+            .peekable();
+        while let Some(&(_, first_block)) = blocks.peek() {
+            let sidecar_index = first_block / CHECKSUMS_PER_BLOCK;
+            let address = self.sidecar_address(sidecar_index);
+            self.disk
+                .read(address, &mut sidecar)
+                .await
+                .map_err(ChecksumError::Disk)?;
+            while let Some((block, block_number)) = blocks
+                .next_if(|&(_, block_number)| block_number / CHECKSUMS_PER_BLOCK == sidecar_index)
+            {
+                let offset = (block_number % CHECKSUMS_PER_BLOCK) as usize * CHECKSUM_LEN;
+                sidecar[offset..offset + CHECKSUM_LEN]
+                    .copy_from_slice(&CRC.checksum(block).to_le_bytes());
+            }
+            self.disk
+                .write(address, &sidecar)
+                .await
+                .map_err(ChecksumError::Disk)?;
+        }
+        Ok(())
+    }
+
+    /// Only erases the data region, never the checksums.
+    /// The erased blocks fail their checksum until they are written again.
+    async fn erase(&mut self, start: u64, end: u64) -> Result<(), Self::Error> {
+        if start > end || end > self.data_blocks * BLOCK_SIZE as u64 {
+            return Err(ChecksumError::OutOfRange);
+        }
+        self.disk
+            .erase(start, end)
+            .await
+            .map_err(ChecksumError::Disk)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.disk.flush().await.map_err(ChecksumError::Disk)
+    }
+}
@@ -0,0 +1,25 @@
+use embedded_hal::digital::OutputPin;
+
+/// Drives the card's chip select line.
+/// This is implemented for every [`OutputPin`], with the card selected while the pin is low.
+/// Implement it yourself if CS is driven some other way, such as through an I2C GPIO expander or a shift register.
+pub trait ChipSelect {
+    type Error;
+
+    /// Selects the card, by setting CS low
+    async fn select(&mut self) -> Result<(), Self::Error>;
+    /// Deselects the card, by setting CS high
+    async fn deselect(&mut self) -> Result<(), Self::Error>;
+}
+
+impl<P: OutputPin> ChipSelect for P {
+    type Error = P::Error;
+
+    async fn select(&mut self) -> Result<(), Self::Error> {
+        self.set_low()
+    }
+
+    async fn deselect(&mut self) -> Result<(), Self::Error> {
+        self.set_high()
+    }
+}
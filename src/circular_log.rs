@@ -0,0 +1,532 @@
+use crc::{CRC_32_ISO_HDLC, Crc};
+
+use crate::{BLOCK_SIZE, Disk};
+
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+const RECORD_MAGIC: [u8; 4] = *b"CLOG";
+const SUPERBLOCK_MAGIC: [u8; 4] = *b"CSUP";
+/// Magic, log id, sequence number, length, time, head block, head sequence number, and CRC
+const HEADER_LEN: usize = 36;
+/// The superblocks are written in turns, so one of them is always complete
+const SUPERBLOCKS: u64 = 2;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CircularLogError<E> {
+    Disk(E),
+    /// Neither superblock is valid, so the region needs to be formatted with [`CircularLog::format`]
+    NotFormatted,
+    /// The region needs at least 1 block for records after the superblocks
+    RegionTooSmall,
+    /// The record is bigger than the whole region
+    TooLong,
+    /// The buffer is shorter than [`CircularLog::buffer_len`] for an append, or than the record for a read
+    BufferTooSmall,
+    /// A record that should still be in the log has a bad CRC or was overwritten by something else
+    Corrupted {
+        block: u64,
+    },
+}
+
+/// A position in a [`CircularLog`], for reading records in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LogCursor {
+    /// Where the record is, unless it wrapped around to the start of the region
+    pub block: u64,
+    pub seq: u32,
+}
+
+/// A record read from a [`CircularLog`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CircularRecord {
+    pub seq: u32,
+    pub time: u64,
+    pub len: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Header {
+    seq: u32,
+    len: u32,
+    time: u64,
+    head: LogCursor,
+    crc: u32,
+}
+
+/// A log that wraps around in a fixed range of blocks, overwriting the oldest records, like for a dashcam or telemetry.
+///
+/// Every record has a time (in any unit that doesn't go backwards, such as microseconds since boot or a Unix time), a sequence number, and a CRC,
+/// and takes whole blocks. The first 2 blocks of the region are superblocks, which store where the oldest record (the head) and the next record (the tail) are.
+/// They are written in turns every [`CircularLog::sync_interval`] appends, before wrapping around to the start of the region, and by [`CircularLog::sync`].
+/// Every record also stores the head, so [`CircularLog::mount`] finds the records written after the last superblock, and continues after them.
+///
+/// Read the records in order by starting at [`CircularLog::oldest`] (or [`CircularLog::seek_time`]) and calling [`CircularLog::read`].
+pub struct CircularLog<D> {
+    disk: D,
+    start_block: u64,
+    data_start: u64,
+    data_end: u64,
+    /// Changes every time the region is formatted, so that records from before aren't mistaken for new ones
+    log_id: u32,
+    generation: u32,
+    head: LogCursor,
+    tail: LogCursor,
+    appends_since_sync: u32,
+    /// Writes a superblock after this many appends. Fewer means less to scan in [`CircularLog::mount`], but more writes.
+    pub sync_interval: u32,
+}
+
+impl<D: Disk<Address = u64>> CircularLog<D> {
+    /// Starts an empty log in the `blocks` blocks from `start_block`
+    pub async fn format(
+        mut disk: D,
+        start_block: u64,
+        blocks: u64,
+    ) -> Result<Self, CircularLogError<D::Error>> {
+        if blocks <= SUPERBLOCKS {
+            return Err(CircularLogError::RegionTooSmall);
+        }
+        let log_id = match Self::newest_superblock(&mut disk, start_block).await? {
+            Some(superblock) => superblock.log_id.wrapping_add(1),
+            None => 0,
+        };
+        let data_start = start_block + SUPERBLOCKS;
+        let empty = LogCursor {
+            block: data_start,
+            seq: 0,
+        };
+        let mut log = Self {
+            disk,
+            start_block,
+            data_start,
+            data_end: start_block + blocks,
+            log_id,
+            generation: 0,
+            head: empty,
+            tail: empty,
+            appends_since_sync: 0,
+            sync_interval: 16,
+        };
+        // Both, so that the old log's superblock isn't newer
+        log.sync().await?;
+        log.sync().await?;
+        Ok(log)
+    }
+
+    /// Opens the log in the `blocks` blocks from `start_block`, scanning the records written after the newest superblock
+    pub async fn mount(
+        mut disk: D,
+        start_block: u64,
+        blocks: u64,
+    ) -> Result<Self, CircularLogError<D::Error>> {
+        if blocks <= SUPERBLOCKS {
+            return Err(CircularLogError::RegionTooSmall);
+        }
+        let superblock = Self::newest_superblock(&mut disk, start_block)
+            .await?
+            .ok_or(CircularLogError::NotFormatted)?;
+        let data_start = start_block + SUPERBLOCKS;
+        let mut log = Self {
+            disk,
+            start_block,
+            data_start,
+            data_end: start_block + blocks,
+            log_id: superblock.log_id,
+            generation: superblock.generation,
+            head: log_cursor(data_start, superblock.head_offset, superblock.head_seq),
+            tail: log_cursor(data_start, superblock.tail_offset, superblock.tail_seq),
+            appends_since_sync: 0,
+            sync_interval: 16,
+        };
+        let mut scanned = 0;
+        while let Some((block, header)) = log.locate(log.tail).await? {
+            if !log.read_payload(block, &header, None).await? {
+                break;
+            }
+            log.tail = LogCursor {
+                block: block + record_blocks(header.len as usize),
+                seq: header.seq.wrapping_add(1),
+            };
+            log.head = header.head;
+            scanned += 1;
+        }
+        info!(
+            "mounted circular log with {} records, {} after the superblock",
+            log.tail.seq.wrapping_sub(log.head.seq),
+            scanned
+        );
+        Ok(log)
+    }
+
+    pub fn into_inner(self) -> D {
+        self.disk
+    }
+
+    /// The oldest record that hasn't been overwritten
+    pub fn oldest(&self) -> LogCursor {
+        self.head
+    }
+
+    /// Where the next record will be written. Reading from here returns `None` until it is.
+    pub fn end(&self) -> LogCursor {
+        self.tail
+    }
+
+    /// The length of the buffer needed to append a record of `len` bytes
+    pub const fn buffer_len(len: usize) -> usize {
+        record_blocks(len) as usize * BLOCK_SIZE
+    }
+
+    /// Writes a record after the newest one, overwriting the oldest records if there isn't enough space, and returns its sequence number.
+    /// `buffer` is used to put the record together, so that it's written all at once. See [`Self::buffer_len`].
+    pub async fn append(
+        &mut self,
+        time: u64,
+        record: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<u32, CircularLogError<D::Error>> {
+        let blocks = record_blocks(record.len());
+        if blocks > self.data_end - self.data_start {
+            return Err(CircularLogError::TooLong);
+        }
+        let buffer = buffer
+            .get_mut(..blocks as usize * BLOCK_SIZE)
+            .ok_or(CircularLogError::BufferTooSmall)?;
+        // Records don't wrap around in the middle
+        let block = if self.tail.block + blocks <= self.data_end {
+            self.tail.block
+        } else {
+            self.data_start
+        };
+        let wrapped = block != self.tail.block;
+        let seq = self.tail.seq;
+
+        // Drop the oldest records that are about to be overwritten, and the ones skipped at the end of the region when wrapping around
+        while self.head.seq != self.tail.seq {
+            let (head_block, header) =
+                self.locate(self.head)
+                    .await?
+                    .ok_or(CircularLogError::Corrupted {
+                        block: self.head.block,
+                    })?;
+            let head_end = head_block + record_blocks(header.len as usize);
+            let overwritten = head_block < block + blocks && head_end > block;
+            let skipped = wrapped && head_block >= self.tail.block;
+            if !overwritten && !skipped {
+                break;
+            }
+            self.head = LogCursor {
+                block: head_end,
+                seq: header.seq.wrapping_add(1),
+            };
+        }
+        if self.head.seq == self.tail.seq {
+            self.head = LogCursor { block, seq };
+        }
+        // The record that the superblock points to is about to be overwritten on the next lap, so `mount` needs a newer one
+        if wrapped {
+            self.sync().await?;
+        }
+
+        let header = Header {
+            seq,
+            len: record.len() as u32,
+            time,
+            head: self.head,
+            crc: 0,
+        };
+        self.write_header(&header, buffer);
+        buffer[HEADER_LEN..HEADER_LEN + record.len()].copy_from_slice(record);
+        buffer[HEADER_LEN + record.len()..].fill(0);
+        let mut digest = CRC.digest();
+        digest.update(&buffer[4..HEADER_LEN - 4]);
+        digest.update(record);
+        buffer[HEADER_LEN - 4..HEADER_LEN].copy_from_slice(&digest.finalize().to_le_bytes());
+        self.disk
+            .write(block * BLOCK_SIZE as u64, buffer)
+            .await
+            .map_err(CircularLogError::Disk)?;
+        self.tail = LogCursor {
+            block: block + blocks,
+            seq: seq.wrapping_add(1),
+        };
+
+        self.appends_since_sync += 1;
+        if self.appends_since_sync >= self.sync_interval {
+            self.sync().await?;
+        }
+        Ok(seq)
+    }
+
+    /// Writes the head and tail to a superblock, so that [`Self::mount`] doesn't have to scan the records written since the last one
+    pub async fn sync(&mut self) -> Result<(), CircularLogError<D::Error>> {
+        self.generation = self.generation.wrapping_add(1);
+        let superblock = Superblock {
+            generation: self.generation,
+            log_id: self.log_id,
+            head_offset: (self.head.block - self.data_start) as u32,
+            head_seq: self.head.seq,
+            tail_offset: (self.tail.block - self.data_start) as u32,
+            tail_seq: self.tail.seq,
+        };
+        let address =
+            (self.start_block + u64::from(self.generation) % SUPERBLOCKS) * BLOCK_SIZE as u64;
+        self.disk
+            .write(address, &superblock.to_block())
+            .await
+            .map_err(CircularLogError::Disk)?;
+        self.appends_since_sync = 0;
+        Ok(())
+    }
+
+    /// Reads the record at `cursor` into the start of `buffer`, and moves `cursor` to the next one.
+    /// Returns `None` at the end of the log.
+    /// If the record was already overwritten, this skips to the oldest record. Check [`CircularRecord::seq`] to know if records were skipped.
+    pub async fn read(
+        &mut self,
+        cursor: &mut LogCursor,
+        buffer: &mut [u8],
+    ) -> Result<Option<CircularRecord>, CircularLogError<D::Error>> {
+        if !self.is_live(cursor.seq) {
+            *cursor = self.head;
+        }
+        if cursor.seq == self.tail.seq {
+            return Ok(None);
+        }
+        let (block, header) = self
+            .locate(*cursor)
+            .await?
+            .ok_or(CircularLogError::Corrupted {
+                block: cursor.block,
+            })?;
+        if buffer.len() < header.len as usize {
+            return Err(CircularLogError::BufferTooSmall);
+        }
+        if !self.read_payload(block, &header, Some(buffer)).await? {
+            return Err(CircularLogError::Corrupted { block });
+        }
+        *cursor = LogCursor {
+            block: block + record_blocks(header.len as usize),
+            seq: header.seq.wrapping_add(1),
+        };
+        Ok(Some(CircularRecord {
+            seq: header.seq,
+            time: header.time,
+            len: header.len as usize,
+        }))
+    }
+
+    /// Finds the first record with a time at or after `time`, or [`Self::end`] if there isn't one.
+    /// This only reads the first block of each record, from the oldest one.
+    pub async fn seek_time(&mut self, time: u64) -> Result<LogCursor, CircularLogError<D::Error>> {
+        let mut cursor = self.head;
+        while cursor.seq != self.tail.seq {
+            let (block, header) =
+                self.locate(cursor)
+                    .await?
+                    .ok_or(CircularLogError::Corrupted {
+                        block: cursor.block,
+                    })?;
+            if header.time >= time {
+                return Ok(LogCursor {
+                    block,
+                    seq: header.seq,
+                });
+            }
+            cursor = LogCursor {
+                block: block + record_blocks(header.len as usize),
+                seq: header.seq.wrapping_add(1),
+            };
+        }
+        Ok(cursor)
+    }
+
+    /// `true` if the record with this sequence number is in the log, or is the next one
+    fn is_live(&self, seq: u32) -> bool {
+        seq.wrapping_sub(self.head.seq) <= self.tail.seq.wrapping_sub(self.head.seq)
+    }
+
+    /// Finds the header of the record at `cursor`, which is either at its block, or at the start of the region if it wrapped around
+    async fn locate(
+        &mut self,
+        cursor: LogCursor,
+    ) -> Result<Option<(u64, Header)>, CircularLogError<D::Error>> {
+        for block in [cursor.block, self.data_start] {
+            if block >= self.data_end {
+                continue;
+            }
+            if let Some(header) = self.read_header(block).await?
+                && header.seq == cursor.seq
+                && block + record_blocks(header.len as usize) <= self.data_end
+            {
+                return Ok(Some((block, header)));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn read_header(
+        &mut self,
+        block: u64,
+    ) -> Result<Option<Header>, CircularLogError<D::Error>> {
+        let mut bytes = [0; BLOCK_SIZE];
+        self.read_block(block, &mut bytes).await?;
+        if bytes[..4] != RECORD_MAGIC || le_u32(&bytes[4..8]) != self.log_id {
+            return Ok(None);
+        }
+        Ok(Some(Header {
+            seq: le_u32(&bytes[8..12]),
+            len: le_u32(&bytes[12..16]),
+            time: u64::from_le_bytes(bytes[16..24].try_into().unwrap()),
+            head: log_cursor(
+                self.data_start,
+                le_u32(&bytes[24..28]),
+                le_u32(&bytes[28..32]),
+            ),
+            crc: le_u32(&bytes[32..36]),
+        }))
+    }
+
+    fn write_header(&self, header: &Header, buffer: &mut [u8]) {
+        buffer[..4].copy_from_slice(&RECORD_MAGIC);
+        buffer[4..8].copy_from_slice(&self.log_id.to_le_bytes());
+        buffer[8..12].copy_from_slice(&header.seq.to_le_bytes());
+        buffer[12..16].copy_from_slice(&header.len.to_le_bytes());
+        buffer[16..24].copy_from_slice(&header.time.to_le_bytes());
+        buffer[24..28]
+            .copy_from_slice(&((header.head.block - self.data_start) as u32).to_le_bytes());
+        buffer[28..32].copy_from_slice(&header.head.seq.to_le_bytes());
+        buffer[32..36].copy_from_slice(&header.crc.to_le_bytes());
+    }
+
+    /// Checks the CRC of the record, and copies it to `buffer` if there is one. Returns `false` if the CRC is wrong.
+    async fn read_payload(
+        &mut self,
+        block: u64,
+        header: &Header,
+        mut buffer: Option<&mut [u8]>,
+    ) -> Result<bool, CircularLogError<D::Error>> {
+        let len = header.len as usize;
+        let mut bytes = [0; BLOCK_SIZE];
+        let mut digest = CRC.digest();
+        let mut copied = 0;
+        for (i, block) in (block..block + record_blocks(len)).enumerate() {
+            self.read_block(block, &mut bytes).await?;
+            let bytes = if i == 0 {
+                digest.update(&bytes[4..HEADER_LEN - 4]);
+                &bytes[HEADER_LEN..]
+            } else {
+                &bytes[..]
+            };
+            let bytes = &bytes[..bytes.len().min(len - copied)];
+            digest.update(bytes);
+            if let Some(buffer) = buffer.as_deref_mut() {
+                buffer[copied..copied + bytes.len()].copy_from_slice(bytes);
+            }
+            copied += bytes.len();
+        }
+        if digest.finalize() != header.crc {
+            warn!("circular log record at block {} has a bad CRC", block);
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    async fn read_block(
+        &mut self,
+        block: u64,
+        buffer: &mut [u8; BLOCK_SIZE],
+    ) -> Result<(), CircularLogError<D::Error>> {
+        self.disk
+            .read(block * BLOCK_SIZE as u64, buffer)
+            .await
+            .map_err(CircularLogError::Disk)
+    }
+
+    async fn newest_superblock(
+        disk: &mut D,
+        start_block: u64,
+    ) -> Result<Option<Superblock>, CircularLogError<D::Error>> {
+        let mut newest: Option<Superblock> = None;
+        let mut bytes = [0; BLOCK_SIZE];
+        for block in start_block..start_block + SUPERBLOCKS {
+            disk.read(block * BLOCK_SIZE as u64, &mut bytes)
+                .await
+                .map_err(CircularLogError::Disk)?;
+            if let Some(superblock) = Superblock::from_block(&bytes)
+                && newest.is_none_or(|newest| {
+                    // Newer, even if the generation wrapped around
+                    (superblock.generation.wrapping_sub(newest.generation) as i32) > 0
+                })
+            {
+                newest = Some(superblock);
+            }
+        }
+        Ok(newest)
+    }
+}
+
+/// The number of blocks a record of `len` bytes takes
+const fn record_blocks(len: usize) -> u64 {
+    len.saturating_add(HEADER_LEN).div_ceil(BLOCK_SIZE) as u64
+}
+
+fn log_cursor(data_start: u64, offset: u32, seq: u32) -> LogCursor {
+    LogCursor {
+        block: data_start + u64::from(offset),
+        seq,
+    }
+}
+
+fn le_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes.try_into().unwrap())
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Superblock {
+    generation: u32,
+    log_id: u32,
+    head_offset: u32,
+    head_seq: u32,
+    tail_offset: u32,
+    tail_seq: u32,
+}
+
+impl Superblock {
+    fn from_block(bytes: &[u8; BLOCK_SIZE]) -> Option<Self> {
+        if bytes[..4] != SUPERBLOCK_MAGIC || CRC.checksum(&bytes[4..28]) != le_u32(&bytes[28..32]) {
+            return None;
+        }
+        Some(Self {
+            generation: le_u32(&bytes[4..8]),
+            log_id: le_u32(&bytes[8..12]),
+            head_offset: le_u32(&bytes[12..16]),
+            head_seq: le_u32(&bytes[16..20]),
+            tail_offset: le_u32(&bytes[20..24]),
+            tail_seq: le_u32(&bytes[24..28]),
+        })
+    }
+
+    fn to_block(self) -> [u8; BLOCK_SIZE] {
+        let mut bytes = [0; BLOCK_SIZE];
+        bytes[..4].copy_from_slice(&SUPERBLOCK_MAGIC);
+        for (i, value) in [
+            self.generation,
+            self.log_id,
+            self.head_offset,
+            self.head_seq,
+            self.tail_offset,
+            self.tail_seq,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            bytes[4 + i * 4..8 + i * 4].copy_from_slice(&value.to_le_bytes());
+        }
+        let crc = CRC.checksum(&bytes[4..28]);
+        bytes[28..32].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+}
@@ -0,0 +1,327 @@
+use bitflags::bitflags;
+use embedded_hal_async::delay::DelayNs;
+
+use crate::{
+    BYTES_UNTIL_APP_DATA, BYTES_UNTIL_NOT_BUSY, BYTES_UNTIL_READ_DATA, BYTES_UNTIL_REGISTER, Clock,
+    ClockPolicy, Duration, R1,
+};
+
+/// How to choose the check pattern sent with `CMD8`, which the card echoes back
+#[derive(Debug, Clone, Copy)]
+pub enum CheckPattern {
+    /// Always use the same check pattern
+    Fixed(u8),
+    /// Use a different check pattern every time, by counting
+    Counter,
+    /// Get the check pattern from an entropy source, such as a hardware RNG
+    Entropy(fn() -> u8),
+}
+
+impl CheckPattern {
+    /// `0x00` and `0xFF` would be echoed back by a MISO line that is stuck low or high, so they get replaced
+    pub(crate) fn usable(pattern: u8) -> u8 {
+        match pattern {
+            0x00 | 0xFF => pattern ^ 0xA5,
+            pattern => pattern,
+        }
+    }
+}
+
+/// What to do when the card sends something other than `0xFF` right after the response to a command without data.
+/// Some cards do this without anything being wrong, but it can also mean that the card is out of sync with the driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TrailingBytes {
+    #[default]
+    Ignore,
+    /// Logs a warning, and uses the response anyway
+    Warn,
+    /// Fails the command with [`crate::Error::UnexpectedTrailingData`]
+    Error,
+}
+
+/// How long to wait for the card before giving up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Timeouts {
+    /// Waiting for the response to a command
+    pub command: Duration,
+    /// Waiting for each block of data after a read command
+    pub read: Duration,
+    /// Waiting for the card to finish programming. The spec allows SDXC cards to be busy for up to 500ms after a write.
+    pub busy: Duration,
+    /// Waiting for the data of the CSD or CID register
+    pub register: Duration,
+    /// Waiting for the data of the SD Status or SCR
+    pub app_data: Duration,
+    /// Waiting for the card to finish initializing with `ACMD41`. The spec says this takes at most 1s.
+    pub init: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            command: Duration::from_millis(100),
+            read: Duration::from_millis(100),
+            busy: Duration::from_millis(500),
+            register: Duration::from_millis(100),
+            app_data: Duration::from_millis(100),
+            init: Duration::from_secs(1),
+        }
+    }
+}
+
+/// How many bytes the card is expected to take before it sends something, which are clocked in the same transfer.
+/// Overestimating clocks bytes that aren't needed, and underestimating needs another transfer, so a card that is known to be fast or slow can be tuned for.
+/// Each transfer is still bounded by its buffer and [`SdCardConfig::max_transfer_len`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ExpectedGaps {
+    /// Between the response and each block of data after a read command
+    pub read_data: usize,
+    /// Between the response (or a written block) and the end of the busy signal
+    pub not_busy: usize,
+    /// Between the response and the data of the CSD or CID register
+    pub register: usize,
+    /// Between the response and the data of the SD Status or SCR
+    pub app_data: usize,
+}
+
+impl Default for ExpectedGaps {
+    fn default() -> Self {
+        Self {
+            read_data: BYTES_UNTIL_READ_DATA,
+            not_busy: BYTES_UNTIL_NOT_BUSY,
+            register: BYTES_UNTIL_REGISTER,
+            app_data: BYTES_UNTIL_APP_DATA,
+        }
+    }
+}
+
+bitflags! {
+    /// Kinds of errors that can be retried
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct RetryOn: u8 {
+        /// The card didn't respond to the command
+        const RESPONSE_TIMEOUT = 1 << 0;
+        /// The card responded to the command with an error
+        const RESPONSE_ERROR = 1 << 1;
+        /// The card didn't send the data
+        const DATA_TIMEOUT = 1 << 2;
+        /// The CRC of the data was wrong
+        const INVALID_CRC = 1 << 3;
+        /// Got something other than the data
+        const UNEXPECTED_DATA = 1 << 4;
+    }
+}
+
+/// How many times to try an operation, and which errors are worth trying it again for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Includes the first attempt, so `1` means never retry
+    pub max_attempts: usize,
+    /// How long to wait before trying again
+    pub delay: Duration,
+    pub retry_on: RetryOn,
+}
+
+impl RetryPolicy {
+    /// Only try once
+    pub const NEVER: Self = Self {
+        max_attempts: 1,
+        delay: Duration::from_micros(0),
+        retry_on: RetryOn::empty(),
+    };
+
+    /// `attempt` is the number of the attempt that just failed, starting at 1
+    pub(crate) fn should_retry(&self, attempt: usize, kind: RetryOn) -> bool {
+        attempt < self.max_attempts && self.retry_on.intersects(kind)
+    }
+
+    pub(crate) async fn wait(&self, delayer: &mut impl DelayNs) {
+        if self.delay.as_micros() > 0 {
+            delayer
+                .delay_us(self.delay.as_micros().try_into().unwrap_or(u32::MAX))
+                .await;
+        }
+    }
+}
+
+/// Options for how the driver talks to the card
+#[derive(Debug, Clone)]
+pub struct SdCardConfig {
+    /// A varying check pattern can catch wiring faults (such as a stuck MISO line) that a constant pattern can miss
+    pub check_pattern: CheckPattern,
+    /// Some cards briefly set error bits such as `ERASE_RESET` or `PARAMETER_ERROR` during init, and then work fine.
+    /// Bits in this mask are ignored in the responses during [`crate::SpiSdCard::init_card`].
+    /// `IN_IDLE_STATE` and `ILLEGAL_COMMAND` are needed for init to work, so they are never ignored.
+    pub init_r1_tolerance: R1,
+    /// Used for all timeouts
+    pub clock: Clock,
+    pub timeouts: Timeouts,
+    pub gaps: ExpectedGaps,
+    /// Retries `CMD0` until the card enters the idle state
+    pub cmd0_retry: RetryPolicy,
+    /// `ACMD41` is repeated until the card leaves the idle state (or [`Timeouts::init`] passes), so only `max_attempts` and `delay` are used
+    pub acmd41_retry: RetryPolicy,
+    /// Used by [`crate::Disk::read`] and [`crate::Disk::write`]
+    pub transfer_retry: RetryPolicy,
+    /// The most bytes clocked in one SPI transfer.
+    /// Transfers include padding bytes for the time the card is expected to take to respond, which scales with the number of blocks.
+    /// This bounds how long the bus is held by padding, at the cost of more transfers for big reads.
+    pub max_transfer_len: usize,
+    /// Enables CRC checking with `CMD59` during init. If the card rejects it, init continues without CRC.
+    /// `false` leaves CRC off for the most throughput. It can also be changed later with [`crate::SdCardDisk::set_crc`].
+    pub enable_crc: bool,
+    /// If this many reads in a row fail because of a bad CRC, even after [`Self::transfer_retry`], CRC is turned off.
+    /// Some cards send bad CRCs for good data. `0` never turns it off.
+    pub crc_fallback_after: u8,
+    /// If a read or write fails with `ADDRESS_ERROR`, reads the card's `CCS` bit again, and if the card uses the other addressing (block or byte addresses),
+    /// switches to it and tries once more. This catches a card that was detected as the wrong capacity, instead of failing every operation.
+    pub recheck_addressing: bool,
+    /// Chooses between the 25 MHz config and a derated one from the hints given to [`crate::SpiSdCard::set_environment`],
+    /// so that a low supply voltage or extreme temperature slows the clock before it causes errors. `None` always uses the 25 MHz config.
+    pub clock_policy: Option<ClockPolicy>,
+    /// Only the bytes clocked in the same transfer as the response are checked
+    pub trailing_bytes: TrailingBytes,
+    /// Measures how long the card takes to start sending read data, and once enough blocks were read,
+    /// uses the longest time measured plus a margin instead of [`ExpectedGaps::read_data`] if it's smaller.
+    /// The default gap is very long to work with slow cards, which makes single block reads from fast cards much slower than they need to be.
+    pub learn_read_gap: bool,
+    /// Sends commands and data with [`embedded_hal_async::spi::SpiBus::write`] and receives with [`embedded_hal_async::spi::SpiBus::read`],
+    /// instead of full-duplex transfers of buffers filled with `0xFF`. Some DMA engines do receive-only transfers more efficiently.
+    /// The card needs `0xFF` while it sends, so this relies on the bus's `read` sending `0xFF`, which `embedded-hal` leaves up to the implementation.
+    pub split_transfers: bool,
+    /// Overwrites the driver's buffers with zeros after every operation that moves data, so that no data read from or written to the card stays in RAM after the transfer.
+    /// The caller's buffers are left alone.
+    pub zeroize_buffers: bool,
+    /// At the end of init, sends `CMD13` at the transfer speed to check that the card still understands the bus at that speed.
+    /// If it doesn't, the derated config (or the 400 kHz config) is used until the next init, and if that fails too, init fails with [`crate::Error::SpeedSwitchFailed`].
+    /// This makes a bad signal at 25 MHz show up during init, instead of as CRC errors on later transfers.
+    pub verify_speed_switch: bool,
+}
+
+impl SdCardConfig {
+    pub(crate) fn init_r1(&self, byte: u8) -> R1 {
+        let r1 = R1::from_bits_retain(byte);
+        let ignored = r1
+            & self
+                .init_r1_tolerance
+                .difference(R1::IN_IDLE_STATE | R1::ILLEGAL_COMMAND);
+        if !ignored.is_empty() {
+            warn!("Ignoring R1 bits during init: {:x}", ignored.bits());
+        }
+        r1.difference(ignored)
+    }
+}
+
+impl SdCardConfig {
+    /// The default options, with a custom clock
+    pub fn new(clock: Clock) -> Self {
+        Self {
+            check_pattern: CheckPattern::Counter,
+            init_r1_tolerance: R1::empty(),
+            clock,
+            timeouts: Default::default(),
+            gaps: Default::default(),
+            cmd0_retry: RetryPolicy {
+                max_attempts: 50,
+                delay: Duration::from_micros(10),
+                retry_on: RetryOn::RESPONSE_TIMEOUT.union(RetryOn::RESPONSE_ERROR),
+            },
+            acmd41_retry: RetryPolicy {
+                max_attempts: 10_000,
+                delay: Duration::from_micros(0),
+                retry_on: RetryOn::RESPONSE_ERROR,
+            },
+            transfer_retry: RetryPolicy {
+                max_attempts: 3,
+                delay: Duration::from_micros(0),
+                retry_on: RetryOn::INVALID_CRC,
+            },
+            max_transfer_len: 1024,
+            enable_crc: true,
+            crc_fallback_after: 3,
+            recheck_addressing: true,
+            clock_policy: None,
+            trailing_bytes: TrailingBytes::Ignore,
+            learn_read_gap: true,
+            split_transfers: false,
+            zeroize_buffers: false,
+            verify_speed_switch: false,
+        }
+    }
+
+    pub fn with_check_pattern(mut self, check_pattern: CheckPattern) -> Self {
+        self.check_pattern = check_pattern;
+        self
+    }
+
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    pub fn with_gaps(mut self, gaps: ExpectedGaps) -> Self {
+        self.gaps = gaps;
+        self
+    }
+
+    pub fn with_cmd0_retry(mut self, cmd0_retry: RetryPolicy) -> Self {
+        self.cmd0_retry = cmd0_retry;
+        self
+    }
+
+    pub fn with_acmd41_retry(mut self, acmd41_retry: RetryPolicy) -> Self {
+        self.acmd41_retry = acmd41_retry;
+        self
+    }
+
+    pub fn with_transfer_retry(mut self, transfer_retry: RetryPolicy) -> Self {
+        self.transfer_retry = transfer_retry;
+        self
+    }
+
+    pub fn with_max_transfer_len(mut self, max_transfer_len: usize) -> Self {
+        self.max_transfer_len = max_transfer_len;
+        self
+    }
+
+    pub fn with_crc(mut self, enable_crc: bool) -> Self {
+        self.enable_crc = enable_crc;
+        self
+    }
+
+    pub fn with_clock_policy(mut self, clock_policy: ClockPolicy) -> Self {
+        self.clock_policy = Some(clock_policy);
+        self
+    }
+
+    pub fn with_trailing_bytes(mut self, trailing_bytes: TrailingBytes) -> Self {
+        self.trailing_bytes = trailing_bytes;
+        self
+    }
+
+    pub fn with_split_transfers(mut self, split_transfers: bool) -> Self {
+        self.split_transfers = split_transfers;
+        self
+    }
+
+    pub fn with_zeroize_buffers(mut self, zeroize_buffers: bool) -> Self {
+        self.zeroize_buffers = zeroize_buffers;
+        self
+    }
+
+    pub fn with_verify_speed_switch(mut self, verify_speed_switch: bool) -> Self {
+        self.verify_speed_switch = verify_speed_switch;
+        self
+    }
+}
+
+#[cfg(feature = "embassy-time")]
+impl Default for SdCardConfig {
+    fn default() -> Self {
+        Self::new(crate::embassy_clock)
+    }
+}
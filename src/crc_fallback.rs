@@ -0,0 +1,104 @@
+use core::{fmt::Debug, ops::DerefMut};
+
+use embassy_embedded_hal::SetConfig;
+use embedded_hal_async::delay::DelayNs;
+
+use crate::{
+    CardCommand3Error, CardDetect, ChipSelect, EXPECTED_BYTES_UNTIL_RESPONSE, Error, ErrorContext,
+    R1, SdCardDisk, SdCommand, SharedSpiBus, card_command, report_error,
+};
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect> SdCardDisk<'_, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// `false` if the card rejected `CMD59` during init, CRC was turned off after too many bad CRCs, or [`crate::SdCardConfig::enable_crc`] is `false`.
+    /// Without CRC, corrupted data isn't detected.
+    pub fn crc_enabled(&self) -> bool {
+        self.sd_card.crc_enabled
+    }
+
+    /// Turns CRC checking on or off with `CMD59`.
+    /// With CRC off, the card doesn't check the CRCs of commands and written data, and the CRCs of read data aren't calculated,
+    /// which saves CPU time on short wires where corruption is unlikely.
+    /// To never turn it on, set [`crate::SdCardConfig::enable_crc`] to `false` instead.
+    pub async fn set_crc(&mut self, enabled: bool) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        let result = self.crc_on_off(enabled).await;
+        if result.is_ok() {
+            self.sd_card.crc_enabled = enabled;
+            self.consecutive_crc_errors = 0;
+        }
+        report_error(self.sd_card.on_error, ErrorContext::SetCrc, result)
+    }
+
+    /// Turns CRC off once [`crate::SdCardConfig::crc_fallback_after`] reads in a row failed because of a bad CRC
+    pub(crate) async fn track_crc_errors<T>(
+        &mut self,
+        result: &Result<T, Error<Spi::Bus, Cs::Error>>,
+    ) {
+        match result {
+            Err(Error::ReadInvalidCrc) if self.sd_card.crc_enabled => {
+                self.consecutive_crc_errors = self.consecutive_crc_errors.saturating_add(1);
+            }
+            Err(_) => return,
+            Ok(_) => {
+                self.consecutive_crc_errors = 0;
+                return;
+            }
+        }
+        let fallback_after = self.sd_card.config.crc_fallback_after;
+        if fallback_after == 0 || self.consecutive_crc_errors < fallback_after {
+            return;
+        }
+        warn!(
+            "{} reads in a row had a bad CRC, turning CRC off",
+            self.consecutive_crc_errors
+        );
+        match self.crc_on_off(false).await {
+            Ok(()) => {
+                self.sd_card.crc_enabled = false;
+                self.consecutive_crc_errors = 0;
+            }
+            Err(_) => error!("failed to turn CRC off"),
+        }
+    }
+
+    async fn crc_on_off(&mut self, crc_on: bool) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        let failed = || {
+            if crc_on {
+                Error::EnableCrcFailed
+            } else {
+                Error::DisableCrcFailed
+            }
+        };
+        let mut spi = self.begin_operation().await?;
+
+        let response = card_command(
+            spi.deref_mut(),
+            &mut self.sd_card.scratch,
+            &self.sd_card.config,
+            SdCommand::CrcOnOff { crc_on },
+            EXPECTED_BYTES_UNTIL_RESPONSE,
+            self.sd_card.config.timeouts.command,
+            None,
+        )
+        .await
+        .map_err(|e| match e {
+            CardCommand3Error::Spi(e) => Error::SpiBus(e),
+            CardCommand3Error::ReceiveResponseTimeout(_) => failed(),
+            CardCommand3Error::NothingToTransfer => Error::NothingToTransfer,
+            CardCommand3Error::UnexpectedTrailingData(byte) => Error::UnexpectedTrailingData(byte),
+            _ => unreachable!(),
+        })?;
+        let r1 = R1::from_bits_retain(response[0]);
+        if !r1.is_empty() {
+            return Err(failed());
+        }
+
+        self.end_operation(spi).await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+use core::{fmt::Debug, ops::DerefMut};
+
+use embassy_embedded_hal::SetConfig;
+use embedded_hal_async::delay::DelayNs;
+
+use crate::{
+    BLOCK_SIZE, BusyOperation, CardCommand3Error, CardCommandOperation, CardDetect, ChipSelect,
+    DataErrorToken, EXPECTED_BYTES_UNTIL_RESPONSE, Error, ErrorContext, R1, ReadOperation,
+    SdCardDisk, SdCommand, SharedSpiBus, card_command, read_response_error, report_error,
+};
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect> SdCardDisk<'_, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// Reads `count` blocks starting at `start_block` and checks their CRCs, without keeping the data.
+    /// The data only goes through a small buffer, so this can check that a whole image is intact with almost no RAM.
+    ///
+    /// The CRCs are checked even if CRC is off, since the card always sends them with the data.
+    /// Fails with [`Error::ReadInvalidCrc`] if any block has a bad CRC.
+    pub async fn read_crc_only(
+        &mut self,
+        start_block: u32,
+        count: u32,
+    ) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        let result = self.read_crc_only_inner(start_block, count).await;
+        self.sd_card.zeroize_scratch();
+        self.check_card_lost(&result);
+        report_error(
+            self.sd_card.on_error,
+            ErrorContext::CrcScan { start_block, count },
+            result,
+        )
+    }
+
+    async fn read_crc_only_inner(
+        &mut self,
+        start_block: u32,
+        count: u32,
+    ) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        if count == 0 {
+            return Ok(());
+        }
+        self.reinit_if_lost().await?;
+        let address = self
+            .block_argument(start_block)
+            .ok_or(Error::ReadAddressError)?;
+        let mut spi = self.begin_transfer().await?;
+        let expected_bytes_until_data = self.read_data_gap();
+        let result = card_command(
+            spi.deref_mut(),
+            &mut self.sd_card.scratch,
+            &self.sd_card.config,
+            SdCommand::ReadMultipleBlock { address },
+            EXPECTED_BYTES_UNTIL_RESPONSE,
+            self.sd_card.config.timeouts.command,
+            Some(CardCommandOperation::Read(ReadOperation {
+                expected_bytes_until_data,
+                timeout: self.sd_card.config.timeouts.read,
+                parts: count as usize,
+                part_size: BLOCK_SIZE,
+                buffer: &mut [],
+                crc_enabled: true,
+                skip_bytes: 0,
+                bytes_until_data: None,
+            })),
+        )
+        .await;
+        // The card keeps sending blocks until it's stopped
+        if let Err(
+            CardCommand3Error::ExpectedStartBlockToken(_)
+            | CardCommand3Error::InvalidCrc
+            | CardCommand3Error::ReceiveDataTimeout(_),
+        ) = result
+        {
+            self.recover_read(spi.deref_mut(), true).await;
+        }
+        let response = result.map_err(|e| match e {
+            CardCommand3Error::Spi(e) => Error::SpiBus(e),
+            CardCommand3Error::ReceiveResponseTimeout(_) => Error::ReadReceiveResponseTimeout,
+            CardCommand3Error::ExpectedStartBlockToken(byte) => DataErrorToken::from_byte(byte)
+                .map_or(Error::ReadUnexpectedData, Error::ReadDataError),
+            CardCommand3Error::InvalidCrc => Error::ReadInvalidCrc,
+            CardCommand3Error::ReceiveDataTimeout(_) => Error::ReadReceiveDataTimeout,
+            CardCommand3Error::NothingToTransfer => Error::NothingToTransfer,
+            CardCommand3Error::UnexpectedTrailingData(byte) => Error::UnexpectedTrailingData(byte),
+            _ => unreachable!(),
+        })?;
+        let r1 = R1::from_bits_retain(response[0]);
+        if !r1.is_empty() {
+            return Err(read_response_error(r1));
+        }
+
+        let response = card_command(
+            spi.deref_mut(),
+            &mut self.sd_card.scratch,
+            &self.sd_card.config,
+            SdCommand::StopTransmission,
+            EXPECTED_BYTES_UNTIL_RESPONSE,
+            self.sd_card.config.timeouts.command,
+            Some(CardCommandOperation::BusySignal(BusyOperation {
+                expected_bytes_until_not_busy: self.sd_card.config.gaps.not_busy,
+                timeout: self.sd_card.config.timeouts.busy,
+            })),
+        )
+        .await
+        .map_err(|e| match e {
+            CardCommand3Error::Spi(e) => Error::SpiBus(e),
+            CardCommand3Error::ReceiveResponseTimeout(_) => Error::StopTransmissionResponseTimeout,
+            CardCommand3Error::BusyTimeout => Error::StopTransmissionBusyTimeout,
+            CardCommand3Error::NothingToTransfer => Error::NothingToTransfer,
+            CardCommand3Error::UnexpectedTrailingData(byte) => Error::UnexpectedTrailingData(byte),
+            _ => unreachable!(),
+        })?;
+        let r1 = R1::from_bits_retain(response[0]);
+        if !r1.is_empty() {
+            return Err(Error::StopTransmissionResponseError);
+        }
+
+        self.end_operation(spi).await
+    }
+}
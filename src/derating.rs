@@ -0,0 +1,91 @@
+use core::fmt::Debug;
+
+use embassy_embedded_hal::SetConfig;
+use embedded_hal_async::delay::DelayNs;
+
+use crate::{CardDetect, ChipSelect, SdCardDisk, SharedSpiBus, SpiSdCard};
+
+/// Conditions measured by the application, which can make the card unreliable at full speed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Environment {
+    /// The card's supply voltage, in millivolts
+    pub vdd_mv: Option<u16>,
+    /// In degrees Celsius
+    pub temperature_c: Option<i16>,
+}
+
+/// Which SPI config to use for operations after init
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ClockChoice {
+    /// The 25 MHz config
+    Full,
+    /// The config from [`SpiSdCard::with_derated_config`], or the 400 kHz config if there isn't one
+    Derated,
+}
+
+/// Chooses the SPI config from the [`Environment`], before every operation. See [`crate::SdCardConfig::clock_policy`].
+pub type ClockPolicy = fn(Environment) -> ClockChoice;
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect> SpiSdCard<Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// The config used when [`crate::SdCardConfig::clock_policy`] chooses [`ClockChoice::Derated`], such as a few MHz.
+    /// Without one, the 400 kHz config is used, which is always safe but slow.
+    pub fn with_derated_config(mut self, derated_config: <Spi::Bus as SetConfig>::Config) -> Self {
+        self.derated_config = Some(derated_config);
+        self
+    }
+
+    /// Gives the clock policy new measurements. They are used from the next operation.
+    pub fn set_environment(&mut self, environment: Environment) {
+        self.environment = environment;
+    }
+
+    /// The SPI config for operations after init
+    pub(crate) fn transfer_config(&mut self) -> &<Spi::Bus as SetConfig>::Config {
+        let choice = match self.config.clock_policy {
+            _ if self.speed_fallback => ClockChoice::Derated,
+            Some(policy) => policy(self.environment),
+            None => ClockChoice::Full,
+        };
+        let derated = choice == ClockChoice::Derated;
+        if derated != self.derated {
+            if derated {
+                warn!("derating the SPI clock, for {:?}", self.environment);
+            } else {
+                info!("back to the full SPI clock");
+            }
+            self.derated = derated;
+        }
+        match choice {
+            ClockChoice::Full => &self._25_mhz_config,
+            ClockChoice::Derated => self
+                .derated_config
+                .as_ref()
+                .unwrap_or(&self._400_khz_config),
+        }
+    }
+}
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect> SdCardDisk<'_, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// Like [`SpiSdCard::set_environment`], while the card is borrowed by this
+    pub fn set_environment(&mut self, environment: Environment) {
+        self.sd_card.set_environment(environment);
+    }
+
+    /// `true` if the last operation used the derated config
+    pub fn is_derated(&self) -> bool {
+        self.sd_card.derated
+    }
+}
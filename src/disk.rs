@@ -9,6 +9,62 @@ pub trait Disk {
     const BLOCK_SIZE: usize;
 
     // fn len(&self) -> Self::Address;
+    /// The block size of this disk, so that layers such as caches and filesystems can align their reads and writes to it.
+    /// This is [`Disk::BLOCK_SIZE`], unless the disk only knows it at runtime.
+    fn block_size(&self) -> usize {
+        Self::BLOCK_SIZE
+    }
+    /// The size of the units the disk erases in, in bytes, if it's known.
+    /// Erases (and filesystem clusters) that are aligned to whole units are the fastest, and wear the disk the least.
+    fn erase_unit_size(&self) -> Option<usize> {
+        None
+    }
     async fn read(&mut self, start: Self::Address, buffer: &mut [u8]) -> Result<(), Self::Error>;
     async fn write(&mut self, start: Self::Address, buffer: &[u8]) -> Result<(), Self::Error>;
+    /// Tells the disk that the data from `start` to `end` (exclusive) is no longer needed.
+    /// Afterwards, reading that range can return any data.
+    /// Since this is only a hint, the default implementation does nothing.
+    async fn erase(
+        &mut self,
+        _start: Self::Address,
+        _end: Self::Address,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+    /// Writes anything that was buffered to the disk below, so that it's kept after power is lost.
+    /// Call this before power down or removing the card.
+    /// Disks that write straight through, such as the card itself, have nothing to do, which is the default implementation.
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl<D: Disk> Disk for &mut D {
+    type Address = D::Address;
+    type Error = D::Error;
+    const BLOCK_SIZE: usize = D::BLOCK_SIZE;
+
+    fn block_size(&self) -> usize {
+        D::block_size(self)
+    }
+
+    fn erase_unit_size(&self) -> Option<usize> {
+        D::erase_unit_size(self)
+    }
+
+    async fn read(&mut self, start: Self::Address, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        D::read(self, start, buffer).await
+    }
+
+    async fn write(&mut self, start: Self::Address, buffer: &[u8]) -> Result<(), Self::Error> {
+        D::write(self, start, buffer).await
+    }
+
+    async fn erase(&mut self, start: Self::Address, end: Self::Address) -> Result<(), Self::Error> {
+        D::erase(self, start, end).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        D::flush(self).await
+    }
 }
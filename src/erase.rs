@@ -0,0 +1,235 @@
+use core::{fmt::Debug, ops::DerefMut};
+
+use embassy_embedded_hal::SetConfig;
+use embedded_hal_async::delay::DelayNs;
+
+use crate::{
+    BLOCK_SIZE, BusyOperation, CancelToken, CardCommand3Error, CardCommandOperation, CardDetect,
+    ChipSelect, Duration, EXPECTED_BYTES_UNTIL_RESPONSE, Error, ErrorContext, R1, SdCardDisk,
+    SdCommand, SdStatus, SharedSpiBus, card_command, report_error,
+};
+
+/// If the card doesn't specify erase timing in its SD Status, the spec says to allow 250ms per block
+const ERASE_TIMEOUT_PER_BLOCK: Duration = Duration::from_millis(250);
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect> SdCardDisk<'_, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// Erases the blocks from `start_block` to `end_block` (exclusive), using `CMD32`, `CMD33`, and `CMD38`.
+    /// [`Disk::erase`](crate::Disk::erase) does the same with byte addresses.
+    /// MMC cards use `CMD35` and `CMD36` instead of `CMD32` and `CMD33`, and erase whole erase groups.
+    /// After erasing, the blocks will read as all `0x00` or all `0xFF`, depending on the card.
+    ///
+    /// The erase timeout is calculated from the card's SD Status, which gets read first.
+    /// MMC cards don't have an SD Status, so they get the spec's default timeout per block.
+    pub async fn erase_blocks(
+        &mut self,
+        start_block: u32,
+        end_block: u32,
+    ) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        let result = self.erase_inner(start_block, end_block).await;
+        report_error(
+            self.sd_card.on_error,
+            ErrorContext::Erase {
+                start_block,
+                end_block,
+            },
+            result,
+        )
+    }
+
+    async fn erase_inner(
+        &mut self,
+        start_block: u32,
+        end_block: u32,
+    ) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        if start_block >= end_block {
+            return Ok(());
+        }
+        self.check_write_protect().await?;
+        let blocks = u64::from(end_block - start_block);
+        let sd_status = self.erase_sd_status().await?;
+        let timeout = match sd_status.and_then(|sd_status| sd_status.erase_timeout_ms(blocks)) {
+            Some(timeout_ms) => Duration::from_millis(timeout_ms),
+            None => ERASE_TIMEOUT_PER_BLOCK * (end_block - start_block),
+        };
+        self.erase_range(start_block, end_block, timeout).await
+    }
+
+    /// Like [`Self::erase_inner`], but on cards that can't erase single blocks, only erases the erase sectors that are completely inside of the range.
+    /// Those cards erase every sector that the range touches, so this keeps the blocks around the range.
+    pub(crate) async fn erase_sectors_inner(
+        &mut self,
+        start_block: u32,
+        end_block: u32,
+    ) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        if start_block >= end_block {
+            return Ok(());
+        }
+        let csd = self.csd_inner().await?;
+        let sector_blocks = if csd.get_erase_blk_en() {
+            1
+        } else {
+            (csd.erase_sector_size_bytes() / BLOCK_SIZE as u32).max(1)
+        };
+        // Rounding up past the last block address leaves no whole sector to erase
+        let Some(start_block) = start_block
+            .div_ceil(sector_blocks)
+            .checked_mul(sector_blocks)
+        else {
+            return Ok(());
+        };
+        let end_block = end_block / sector_blocks * sector_blocks;
+        self.erase_inner(start_block, end_block).await
+    }
+
+    /// Erases the entire user area of the card, such as for a "factory reset".
+    ///
+    /// The card is erased in chunks of whole erase units (`ERASE_SIZE` allocation units from the SD Status), which is the biggest size the card specifies an erase timeout for.
+    /// MMC cards are erased in chunks of their erase group size from the CSD.
+    /// `progress` is called with the number of blocks erased so far and the total number of blocks, after each chunk.
+    ///
+    /// If `cancel` is cancelled, this stops after the chunk being erased.
+    /// Returns the number of blocks that were erased, which is all of them unless it was cancelled.
+    pub async fn erase_all(
+        &mut self,
+        progress: impl FnMut(u32, u32),
+        cancel: Option<&CancelToken>,
+    ) -> Result<u32, Error<Spi::Bus, Cs::Error>> {
+        let result = self.erase_all_inner(progress, cancel).await;
+        report_error(self.sd_card.on_error, ErrorContext::EraseAll, result)
+    }
+
+    async fn erase_all_inner(
+        &mut self,
+        mut progress: impl FnMut(u32, u32),
+        cancel: Option<&CancelToken>,
+    ) -> Result<u32, Error<Spi::Bus, Cs::Error>> {
+        self.check_write_protect().await?;
+        let csd = self.csd_inner().await?;
+        // Bigger cards than 2 TiB have blocks that a 32-bit block number can't address
+        let Ok(total_blocks) = u32::try_from(csd.card_capacity_bytes() / BLOCK_SIZE as u64) else {
+            return Err(Error::EraseAddressError);
+        };
+        let sd_status = self.erase_sd_status().await?;
+        let chunk_bytes = match sd_status
+            .and_then(|sd_status| Some((sd_status.au_size_bytes()?, sd_status.get_erase_size())))
+        {
+            Some((au_size, erase_size)) if erase_size != 0 => au_size * u32::from(erase_size),
+            // Cards that don't specify erase timing still have an erase sector size in the CSD
+            _ => csd.erase_sector_size_bytes(),
+        };
+        let chunk_blocks = (chunk_bytes / BLOCK_SIZE as u32).max(1);
+        let timeout = match sd_status
+            .and_then(|sd_status| sd_status.erase_timeout_ms(u64::from(chunk_blocks)))
+        {
+            Some(timeout_ms) => Duration::from_millis(timeout_ms),
+            None => ERASE_TIMEOUT_PER_BLOCK * chunk_blocks,
+        };
+        info!(
+            "erasing {} blocks in chunks of {} blocks",
+            total_blocks, chunk_blocks
+        );
+
+        progress(0, total_blocks);
+        let mut start_block = 0;
+        while start_block < total_blocks {
+            if cancel.is_some_and(CancelToken::is_cancelled) {
+                info!("erase cancelled after {} blocks", start_block);
+                break;
+            }
+            let end_block = start_block.saturating_add(chunk_blocks).min(total_blocks);
+            self.erase_range(start_block, end_block, timeout).await?;
+            start_block = end_block;
+            progress(start_block, total_blocks);
+        }
+        Ok(start_block)
+    }
+
+    /// The SD Status, for its erase timing. `None` for MMC cards, which don't have one.
+    async fn erase_sd_status(&mut self) -> Result<Option<SdStatus>, Error<Spi::Bus, Cs::Error>> {
+        if self.card_type.is_mmc() {
+            return Ok(None);
+        }
+        Ok(Some(self.sd_status_inner().await?))
+    }
+
+    async fn erase_range(
+        &mut self,
+        start_block: u32,
+        end_block: u32,
+        timeout: Duration,
+    ) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        // The end address is inclusive
+        let (Some(start_address), Some(end_address)) = (
+            self.block_argument(start_block),
+            self.block_argument(end_block - 1),
+        ) else {
+            return Err(Error::EraseAddressError);
+        };
+        let mut spi = self.begin_operation().await?;
+
+        let (start_command, end_command) = if self.card_type.is_mmc() {
+            (
+                SdCommand::EraseGroupStart {
+                    address: start_address,
+                },
+                SdCommand::EraseGroupEnd {
+                    address: end_address,
+                },
+            )
+        } else {
+            (
+                SdCommand::EraseWrBlkStartAddr {
+                    address: start_address,
+                },
+                SdCommand::EraseWrBlkEndAddr {
+                    address: end_address,
+                },
+            )
+        };
+        for (command, operation) in [
+            (start_command, None),
+            (end_command, None),
+            (
+                SdCommand::Erase,
+                Some(CardCommandOperation::BusySignal(BusyOperation {
+                    expected_bytes_until_not_busy: self.sd_card.config.gaps.not_busy,
+                    timeout,
+                })),
+            ),
+        ] {
+            let response = card_command(
+                spi.deref_mut(),
+                &mut self.sd_card.scratch,
+                &self.sd_card.config,
+                command,
+                EXPECTED_BYTES_UNTIL_RESPONSE,
+                self.sd_card.config.timeouts.command,
+                operation,
+            )
+            .await
+            .map_err(|e| match e {
+                CardCommand3Error::Spi(e) => Error::SpiBus(e),
+                CardCommand3Error::ReceiveResponseTimeout(_) => Error::EraseResponseTimeout,
+                CardCommand3Error::BusyTimeout => Error::EraseBusyTimeout,
+                CardCommand3Error::NothingToTransfer => Error::NothingToTransfer,
+                CardCommand3Error::UnexpectedTrailingData(byte) => {
+                    Error::UnexpectedTrailingData(byte)
+                }
+                _ => unreachable!(),
+            })?;
+            let r1 = R1::from_bits_retain(response[0]);
+            if !r1.is_empty() {
+                return Err(Error::EraseResponseError);
+            }
+        }
+
+        self.end_operation(spi).await?;
+
+        Ok(())
+    }
+}
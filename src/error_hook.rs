@@ -0,0 +1,57 @@
+use crate::Error;
+
+/// What the driver was doing when an error happened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ErrorContext {
+    InitCard,
+    Read {
+        start: u64,
+        len: usize,
+    },
+    ReadExtents {
+        extents: usize,
+    },
+    Write {
+        start: u64,
+        len: usize,
+    },
+    Capacity,
+    Cid,
+    Csd,
+    Ocr,
+    WaitPoweredUp,
+    SdStatus,
+    Scr,
+    Status,
+    Erase {
+        start_block: u32,
+        end_block: u32,
+    },
+    EraseAll,
+    SetCrc,
+    SetBlockLength {
+        block_length: u32,
+    },
+    CrcScan {
+        start_block: u32,
+        count: u32,
+    },
+    /// Deselecting the card at the end of [`crate::SdCardDisk::transaction`]
+    Transaction,
+}
+
+/// Called with every error returned by [`crate::SpiSdCard`] and [`crate::SdCardDisk`], before it is returned.
+/// This is useful for counting or reporting errors through telemetry, without wrapping every call site.
+pub type ErrorHook<Bus, CsError> = fn(ErrorContext, &Error<Bus, CsError>);
+
+pub(crate) fn report_error<T, E>(
+    on_error: Option<fn(ErrorContext, &E)>,
+    context: ErrorContext,
+    result: Result<T, E>,
+) -> Result<T, E> {
+    if let (Some(on_error), Err(e)) = (on_error, &result) {
+        on_error(context, e);
+    }
+    result
+}
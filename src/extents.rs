@@ -0,0 +1,111 @@
+use core::fmt::Debug;
+
+use embassy_embedded_hal::SetConfig;
+use embedded_hal_async::delay::DelayNs;
+
+use crate::{
+    BLOCK_SIZE, CardDetect, ChipSelect, Error, ErrorContext, SdCardDisk, SharedSpiBus,
+    TransferBuffer, report_error,
+};
+
+/// A range of blocks to read with [`SdCardDisk::read_extents`], and where to put them
+#[derive(Debug)]
+pub struct Extent<'a> {
+    pub start_block: u32,
+    /// The number of blocks read is the length of this divided by [`BLOCK_SIZE`]
+    pub buffer: &'a mut [u8],
+}
+
+impl Extent<'_> {
+    fn end_block(&self) -> u32 {
+        self.start_block + (self.buffer.len() / BLOCK_SIZE) as u32
+    }
+}
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect> SdCardDisk<'_, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// Reads many extents, such as the clusters of a FAT cluster chain, with as few commands as possible.
+    /// The extents are sorted by block, and extents that continue where the previous one ended are read with a single `CMD18`.
+    /// Since there is a lot of overhead for each command, this is much faster than reading lots of short extents one at a time.
+    ///
+    /// The length of every buffer must be a multiple of [`BLOCK_SIZE`]. `extents` is left sorted.
+    pub async fn read_extents(
+        &mut self,
+        extents: &mut [Extent<'_>],
+    ) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        let context = ErrorContext::ReadExtents {
+            extents: extents.len(),
+        };
+        let result = self.read_extents_inner(extents).await;
+        report_error(self.sd_card.on_error, context, result)
+    }
+
+    async fn read_extents_inner(
+        &mut self,
+        extents: &mut [Extent<'_>],
+    ) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        if extents
+            .iter()
+            .any(|extent| extent.buffer.len() % BLOCK_SIZE != 0)
+        {
+            return Err(Error::ReadUnaligned);
+        }
+        extents.sort_unstable_by_key(|extent| extent.start_block);
+
+        let max_block_latency = self.sd_card.config.timeouts.read;
+        let mut start = 0;
+        while start < extents.len() {
+            let mut end = start + 1;
+            let mut end_block = extents[start].end_block();
+            while end < extents.len() && extents[end].start_block == end_block {
+                end_block = extents[end].end_block();
+                end += 1;
+            }
+            let run = &mut extents[start..end];
+            start = end;
+
+            match end_block - run[0].start_block {
+                0 => {}
+                // CMD17 is faster when there is only 1 block
+                1 => {
+                    let extent = run
+                        .iter_mut()
+                        .find(|extent| !extent.buffer.is_empty())
+                        .unwrap();
+                    self.read_inner(
+                        u64::from(extent.start_block) * BLOCK_SIZE as u64,
+                        TransferBuffer::Caller(extent.buffer),
+                        None,
+                    )
+                    .await?;
+                }
+                _ => {
+                    let mut stream = self
+                        .open_stream(run[0].start_block, max_block_latency)
+                        .await?;
+                    let mut result = Ok(());
+                    'run: for extent in run.iter_mut() {
+                        for block in extent.buffer.chunks_exact_mut(BLOCK_SIZE) {
+                            result = stream.next_block(block.try_into().unwrap()).await;
+                            if result.is_err() {
+                                break 'run;
+                            }
+                        }
+                    }
+                    let close_result = stream.close().await;
+                    result
+                        .map_err(|e| match e {
+                            Error::StreamDeadlineMissed => Error::ReadReceiveDataTimeout,
+                            e => e,
+                        })
+                        .and(close_result)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+//! Entry points for the fuzz targets in `fuzz/`. This isn't a stable API.
+
+use core::convert::Infallible;
+
+use embedded_hal_async::spi::SpiBus;
+
+use crate::{
+    BLOCK_SIZE, BusyOperation, CardCommand3Error, CardCommandOperation, CommandParser, Duration,
+    Instant, MAX_RESPONSE_LEN, ParserStep, ReadOperation, START_BLOCK_TOKEN_MULTIPLE_WRITE,
+    SdCardConfig, SdCommand, WriteOperation, card_command, receive_data_block,
+    stop_multiple_block_write,
+};
+
+/// How the fuzzer wants the commands to be sent, so that the buffer and transfer sizes are covered too
+#[derive(Debug, Clone, Copy)]
+pub struct ParseOptions {
+    pub max_transfer_len: usize,
+    pub buffer_len: usize,
+    pub expected_bytes_until_response: usize,
+    pub expected_bytes_until_data: usize,
+    pub parts: usize,
+    pub skip_bytes: usize,
+}
+
+/// Sends one command of every kind of data phase, with `spi` returning whatever the card sends.
+/// Errors are expected and ignored. The only thing that matters is that nothing panics.
+pub async fn parse_responses<S: SpiBus>(
+    spi: &mut S,
+    mut config: SdCardConfig,
+    options: ParseOptions,
+) {
+    config.max_transfer_len = options.max_transfer_len;
+    // Keeps each command short, since every byte from the fuzzer is only used once
+    let timeout = Duration::from_millis(10);
+    let mut buffer = [0xFF; 2048];
+    let buffer = &mut buffer[..options.buffer_len.clamp(1, 2048)];
+    let parts = options.parts.min(4);
+    let mut data = [0; 4 * BLOCK_SIZE];
+    let mut bytes_until_data = [0; 4];
+
+    for command in [
+        SdCommand::SendStatus,
+        SdCommand::ReadOcr,
+        SdCommand::SendIfCond {
+            check_pattern: 0xAA,
+        },
+    ] {
+        let _ = card_command(
+            spi,
+            buffer,
+            &config,
+            command,
+            options.expected_bytes_until_response,
+            timeout,
+            None,
+        )
+        .await;
+    }
+
+    let skip_bytes = options.skip_bytes % BLOCK_SIZE;
+    let read_len = (parts * BLOCK_SIZE).saturating_sub(skip_bytes);
+    let _ = card_command(
+        spi,
+        buffer,
+        &config,
+        SdCommand::ReadMultipleBlock { address: 0 },
+        options.expected_bytes_until_response,
+        timeout,
+        Some(CardCommandOperation::Read(ReadOperation {
+            buffer: &mut data[..read_len],
+            expected_bytes_until_data: options.expected_bytes_until_data,
+            timeout,
+            parts,
+            part_size: BLOCK_SIZE,
+            crc_enabled: true,
+            skip_bytes,
+            bytes_until_data: Some(&mut bytes_until_data),
+        })),
+    )
+    .await;
+
+    let mut block = [0; BLOCK_SIZE];
+    let _ = receive_data_block(spi, buffer, &config, &mut block, timeout, true).await;
+
+    let busy = BusyOperation {
+        expected_bytes_until_not_busy: options.expected_bytes_until_data,
+        timeout,
+    };
+    let _ = card_command(
+        spi,
+        buffer,
+        &config,
+        SdCommand::WriteMultipleBlock { address: 0 },
+        options.expected_bytes_until_response,
+        timeout,
+        Some(CardCommandOperation::Write(WriteOperation {
+            buffer: &data[..parts * BLOCK_SIZE],
+            part_size: BLOCK_SIZE,
+            start_token: START_BLOCK_TOKEN_MULTIPLE_WRITE,
+            busy: busy.clone(),
+        })),
+    )
+    .await;
+    let _ = stop_multiple_block_write(spi, buffer, &config, &busy).await;
+
+    let _ = card_command(
+        spi,
+        buffer,
+        &config,
+        SdCommand::StopTransmission,
+        options.expected_bytes_until_response,
+        timeout,
+        Some(CardCommandOperation::BusySignal(busy)),
+    )
+    .await;
+}
+
+/// How [`parse_read_chunked`] ended
+#[derive(Debug)]
+pub enum ChunkedOutcome {
+    Done([u8; MAX_RESPONSE_LEN]),
+    /// The byte that was received instead of the start block token
+    DiscardBlock(u8),
+    Error(CardCommand3Error<Infallible>),
+}
+
+/// Parses a 2 block CMD18 read from `miso`, with each transfer limited to the next length in `chunk_lens`.
+/// The clock never advances, so the outcome only depends on the bytes, not how they were split up.
+/// Returns [`None`] if `miso` ran out before the parser finished.
+pub fn parse_read_chunked(
+    mut miso: &[u8],
+    chunk_lens: &[u8],
+) -> Option<(ChunkedOutcome, [u8; 2 * BLOCK_SIZE])> {
+    let mut data = [0; 2 * BLOCK_SIZE];
+    let timeout = Duration::from_millis(10);
+    let mut parser = CommandParser::new(
+        SdCommand::ReadMultipleBlock { address: 0 },
+        0,
+        timeout,
+        Some(CardCommandOperation::Read(ReadOperation {
+            buffer: &mut data,
+            expected_bytes_until_data: 0,
+            timeout,
+            parts: 2,
+            part_size: BLOCK_SIZE,
+            crc_enabled: true,
+            skip_bytes: 0,
+            bytes_until_data: None,
+        })),
+        || Instant::from_micros(0),
+    );
+    let mut buffer = [0xFF; 2048];
+    let mut received = 0;
+    let mut chunk_lens = chunk_lens.iter().cycle();
+    let outcome = loop {
+        match parser.feed(&buffer[..received]) {
+            Ok(ParserStep::Transfer) => {}
+            Ok(ParserStep::Done) => break ChunkedOutcome::Done(parser.response()),
+            Ok(ParserStep::DiscardBlock { byte, .. }) => break ChunkedOutcome::DiscardBlock(byte),
+            Err(e) => break ChunkedOutcome::Error(e),
+        }
+        let chunk_len = chunk_lens
+            .next()
+            .map_or(buffer.len(), |&len| usize::from(len).max(1));
+        let len = match parser.fill_transfer(&mut buffer[..chunk_len]) {
+            Ok(len) => len,
+            Err(e) => break ChunkedOutcome::Error(e),
+        };
+        if miso.len() < len {
+            return None;
+        }
+        buffer[..len].copy_from_slice(&miso[..len]);
+        miso = &miso[len..];
+        received = len;
+    };
+    Some((outcome, data))
+}
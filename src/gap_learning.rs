@@ -0,0 +1,61 @@
+use core::fmt::Debug;
+
+use embassy_embedded_hal::SetConfig;
+use embedded_hal_async::delay::DelayNs;
+
+use crate::{CardDetect, ChipSelect, SdCardDisk, SharedSpiBus};
+
+/// How many blocks have to be measured before the learned gap is used
+const SAMPLES: u8 = 8;
+
+/// Learns how many bytes the card takes to start sending the data of a read (N<sub>AC</sub>),
+/// so that reads don't clock hundreds of bytes that aren't needed.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct GapLearner {
+    samples: u8,
+    max: usize,
+}
+
+impl GapLearner {
+    pub fn observe(&mut self, bytes: usize) {
+        self.max = self.max.max(bytes);
+        self.samples = self.samples.saturating_add(1);
+    }
+
+    /// The most bytes that were measured, plus a margin, once enough blocks were measured
+    pub fn learned(&self) -> Option<usize> {
+        (self.samples >= SAMPLES).then(|| self.max + self.max / 2 + 2)
+    }
+}
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect> SdCardDisk<'_, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// The gap before read data that was learned from this card, if [`crate::SdCardConfig::learn_read_gap`] is on and enough blocks were read.
+    /// It's used instead of [`crate::ExpectedGaps::read_data`] if it's smaller.
+    pub fn learned_read_gap(&self) -> Option<usize> {
+        self.sd_card
+            .config
+            .learn_read_gap
+            .then(|| self.sd_card.read_gap.learned())
+            .flatten()
+    }
+
+    pub(crate) fn read_data_gap(&self) -> usize {
+        let configured = self.sd_card.config.gaps.read_data;
+        self.learned_read_gap()
+            .map_or(configured, |learned| learned.min(configured))
+    }
+
+    /// `bytes_until_data` has the gaps of the blocks of a read that succeeded
+    pub(crate) fn learn_read_gaps(&mut self, bytes_until_data: &[usize]) {
+        if self.sd_card.config.learn_read_gap {
+            for &bytes in bytes_until_data {
+                self.sd_card.read_gap.observe(bytes);
+            }
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use core::ops::Range;
+
+use embedded_io_async::{Read, Write};
+
+use crate::{BLOCK_SIZE, Disk};
+
+/// How much is read from or written to the disk at a time. More than 1 block, so that reads can use `CMD18` and writes can use `CMD25`.
+const IMAGE_CHUNK_SIZE: usize = 2 * BLOCK_SIZE;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ImageError<E, IoError> {
+    Disk(E),
+    Io(IoError),
+    /// The image would go past the last address of the disk
+    OutOfRange,
+}
+
+/// Streams the raw contents of `range` (in bytes) from the disk to `sink`, which can be anything such as a UART, USB, or network writer.
+/// This is useful for pulling a full card image off a device for offline analysis.
+pub async fn read_image<D: Disk<Address = u64>, W: Write>(
+    disk: &mut D,
+    range: Range<u64>,
+    sink: &mut W,
+) -> Result<(), ImageError<D::Error, W::Error>> {
+    let mut buffer = [0; IMAGE_CHUNK_SIZE];
+    let mut position = range.start;
+    while position < range.end {
+        // Keep the chunks aligned to blocks, even if the range isn't
+        let chunk_end = range
+            .end
+            .min((position / IMAGE_CHUNK_SIZE as u64 + 1) * IMAGE_CHUNK_SIZE as u64);
+        let chunk = &mut buffer[..(chunk_end - position) as usize];
+        disk.read(position, chunk).await.map_err(ImageError::Disk)?;
+        sink.write_all(chunk).await.map_err(ImageError::Io)?;
+        position = chunk_end;
+    }
+    sink.flush().await.map_err(ImageError::Io)
+}
+
+/// Streams an image from `source` onto the disk, starting at block `start_block`, for example to provision cards straight from the device in a factory.
+/// `on_progress` is called with the total number of bytes written so far after every chunk.
+///
+/// If the image is not a multiple of [`BLOCK_SIZE`], the last block is padded with `0x00`.
+/// Returns the number of bytes read from `source`.
+pub async fn write_image<D: Disk<Address = u64>, R: Read>(
+    disk: &mut D,
+    source: &mut R,
+    start_block: u64,
+    mut on_progress: impl FnMut(u64),
+) -> Result<u64, ImageError<D::Error, R::Error>> {
+    let mut buffer = [0; IMAGE_CHUNK_SIZE];
+    let mut position = start_block
+        .checked_mul(BLOCK_SIZE as u64)
+        .ok_or(ImageError::OutOfRange)?;
+    let mut bytes_written = 0;
+    loop {
+        // Fill the whole chunk, unless the source ends
+        let mut chunk_len = 0;
+        while chunk_len < buffer.len() {
+            match source
+                .read(&mut buffer[chunk_len..])
+                .await
+                .map_err(ImageError::Io)?
+            {
+                0 => break,
+                len => chunk_len += len,
+            }
+        }
+        if chunk_len == 0 {
+            break;
+        }
+        let padded_len = chunk_len.next_multiple_of(BLOCK_SIZE);
+        buffer[chunk_len..padded_len].fill(0);
+        disk.write(position, &buffer[..padded_len])
+            .await
+            .map_err(ImageError::Disk)?;
+        position = position
+            .checked_add(padded_len as u64)
+            .ok_or(ImageError::OutOfRange)?;
+        bytes_written += chunk_len as u64;
+        on_progress(bytes_written);
+        if chunk_len < buffer.len() {
+            break;
+        }
+    }
+    Ok(bytes_written)
+}
@@ -0,0 +1,128 @@
+use crate::{Clock, Duration, Instant};
+
+/// A step of [`crate::SpiSdCard::init_card`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum InitStep {
+    /// Waiting 1ms for the card to power up, and clocking at least 74 cycles
+    PowerUp,
+    /// `CMD0`
+    Reset,
+    /// `CMD59`
+    EnableCrc,
+    /// `CMD8`
+    InterfaceCondition,
+    /// `CMD58`, to check the voltage
+    ReadOcr,
+    /// `ACMD41` until the card leaves the idle state
+    Initialize,
+    /// `CMD58` again, to read the capacity
+    ReadCapacity,
+    /// `CMD16`, only for standard capacity cards
+    SetBlockLength,
+    /// `CMD13` at the transfer speed, only with [`crate::SdCardConfig::verify_speed_switch`]
+    VerifySpeed,
+}
+
+impl InitStep {
+    pub const ALL: [Self; 9] = [
+        Self::PowerUp,
+        Self::Reset,
+        Self::EnableCrc,
+        Self::InterfaceCondition,
+        Self::ReadOcr,
+        Self::Initialize,
+        Self::ReadCapacity,
+        Self::SetBlockLength,
+        Self::VerifySpeed,
+    ];
+
+    /// How long the step should take at most.
+    /// Powering up includes clocking about 1000 bytes at 400 kHz, which takes about 20ms, and the spec gives `ACMD41` 1s.
+    /// A single command at 400 kHz takes well under 1ms, so a command step taking longer than its budget
+    /// means that the bus clock is much slower than it should be, or the card is very slow to respond.
+    pub const fn budget(self) -> Duration {
+        match self {
+            Self::PowerUp => Duration::from_millis(50),
+            Self::Reset => Duration::from_millis(100),
+            Self::Initialize => Duration::from_secs(1),
+            _ => Duration::from_millis(10),
+        }
+    }
+}
+
+/// How long each step of the last init took, from [`crate::SpiSdCard::init_timeline`].
+/// If init failed, this shows which step it failed at and how long the steps before it took.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct InitTimeline {
+    /// Indexed like [`InitStep::ALL`]. `None` for steps that didn't run.
+    durations: [Option<Duration>; InitStep::ALL.len()],
+    failed_step: Option<InitStep>,
+    /// The step that is running, and when it started
+    current: Option<(InitStep, Instant)>,
+}
+
+impl InitTimeline {
+    /// `None` if the step didn't run
+    pub fn duration(&self, step: InitStep) -> Option<Duration> {
+        self.durations[step as usize]
+    }
+
+    /// The step that init failed at, if it failed
+    pub fn failed_step(&self) -> Option<InitStep> {
+        self.failed_step
+    }
+
+    /// The steps that took longer than their [budget](InitStep::budget), and how long they took
+    pub fn over_budget(&self) -> impl Iterator<Item = (InitStep, Duration)> + '_ {
+        InitStep::ALL.into_iter().filter_map(|step| {
+            self.duration(step)
+                .filter(|&duration| duration > step.budget())
+                .map(|duration| (step, duration))
+        })
+    }
+
+    /// `true` if the power up step took less than the 1ms that the driver waits, which means that the delayer returns too early
+    pub fn delayer_too_fast(&self) -> bool {
+        self.duration(InitStep::PowerUp)
+            .is_some_and(|duration| duration < Duration::from_millis(1))
+    }
+
+    pub(crate) fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Ends the step that is running, and starts `step`
+    pub(crate) fn start(&mut self, step: InitStep, clock: Clock) {
+        let now = clock();
+        self.end(now);
+        self.current = Some((step, now));
+    }
+
+    /// Ends the step that is running, which is the step that failed if init failed
+    pub(crate) fn finish(&mut self, clock: Clock, failed: bool) {
+        if failed {
+            self.failed_step = self.current.map(|(step, _)| step);
+        }
+        self.end(clock());
+        for (step, duration) in self.over_budget() {
+            warn!(
+                "init step {:?} took {} ms, more than its budget of {} ms",
+                step,
+                duration.as_millis(),
+                step.budget().as_millis()
+            );
+        }
+        if self.delayer_too_fast() {
+            warn!("the delayer waited less than it was asked to during init");
+        }
+    }
+
+    fn end(&mut self, now: Instant) {
+        if let Some((step, start)) = self.current.take() {
+            self.durations[step as usize] = Some(now - start);
+        }
+    }
+}
@@ -1,33 +1,140 @@
-#![no_std]
+#![cfg_attr(not(feature = "std"), no_std)]
 #![allow(async_fn_in_trait)]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+#[macro_use]
+mod fmt;
+#[macro_use]
+mod check;
+
 use core::{
     cmp::{max, min},
     fmt::Debug,
     ops::DerefMut,
+    ptr,
+    sync::atomic::{Ordering, compiler_fence},
 };
 
-use defmt::warn;
 mod shared_spi_bus;
 use embassy_embedded_hal::SetConfig;
 pub use shared_spi_bus::*;
+mod address_error;
+mod append_log;
+mod bad_block;
+mod block_cache;
+#[cfg(feature = "block-device-driver")]
+mod block_device;
+mod block_length;
+mod blocking;
+mod cancel;
 mod card_command;
+mod card_detect;
+mod checksum;
+mod chip_select;
+mod circular_log;
+mod config;
+mod crc_fallback;
+mod crc_scan;
+mod derating;
 mod disk;
+mod erase;
+mod error_hook;
+mod extents;
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzzing;
+mod gap_learning;
+#[cfg(feature = "embedded-io-async")]
+mod image;
+mod init_timeline;
+#[cfg(feature = "embassy-sync")]
+mod manager;
+mod mbr;
+mod partial_write;
+mod pool;
+mod power_up;
+mod qualify;
+mod ram_disk;
+mod read_ahead;
+#[cfg(feature = "embassy-sync")]
+mod read_lease;
+mod reinit;
+#[cfg(feature = "embassy-rp")]
+mod rp;
+mod sd_command;
+#[cfg(feature = "embassy-sync")]
+mod server;
+#[cfg(feature = "std")]
+mod sim_card;
+mod speed_check;
+#[cfg(feature = "embassy-stm32")]
+mod stm32;
+mod stream;
+mod transaction;
+mod transport;
+mod write_stream;
 
 mod structs;
+mod sub_disk;
+mod time;
 mod util;
+mod vectored;
+mod write_back;
+mod write_protect;
+use address_error::*;
+pub use append_log::*;
+pub use block_cache::*;
+pub use blocking::*;
+pub use cancel::*;
 use card_command::*;
+pub use card_detect::*;
+pub use checksum::*;
+pub use chip_select::*;
+pub use circular_log::*;
+pub use config::*;
+pub use derating::*;
 pub use disk::*;
+pub use error_hook::*;
+pub use extents::*;
+use gap_learning::*;
+#[cfg(feature = "embedded-io-async")]
+pub use image::*;
+pub use init_timeline::*;
+#[cfg(feature = "embassy-sync")]
+pub use manager::*;
+pub use mbr::*;
+use partial_write::*;
+pub use pool::*;
+pub use qualify::*;
+pub use ram_disk::*;
+pub use read_ahead::*;
+#[cfg(feature = "embassy-sync")]
+pub use read_lease::*;
+#[cfg(feature = "embassy-rp")]
+pub use rp::*;
+pub use sd_command::*;
+#[cfg(feature = "embassy-sync")]
+pub use server::*;
+#[cfg(feature = "std")]
+pub use sim_card::*;
+#[cfg(feature = "embassy-stm32")]
+pub use stm32::*;
+pub use stream::*;
 pub use util::*;
+pub use write_back::*;
+pub use write_stream::*;
 
 use crc::{CRC_7_MMC, CRC_16_XMODEM, Crc};
-use embassy_time::{Duration, Instant, Timer};
-use embedded_hal::digital::OutputPin;
 use embedded_hal_async::{
     delay::DelayNs,
     spi::{ErrorType, SpiBus},
 };
 pub use structs::*;
+pub use sub_disk::*;
+pub use time::*;
+pub use transport::*;
 
 pub fn format_command(command_index: u8, argument: u32) -> [u8; 6] {
     let mut command: [u8; 6] = Default::default();
@@ -49,6 +156,14 @@ pub fn format_command(command_index: u8, argument: u32) -> [u8; 6] {
     command
 }
 
+fn zeroize(bytes: &mut [u8]) {
+    for byte in bytes {
+        // SAFETY: `byte` is a valid reference. The write is volatile so that it isn't optimized away as a dead store.
+        unsafe { ptr::write_volatile(byte, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
 /// Some errors, such as the SpiBus and CsPin error, can happen from any command
 /// Other errors are command-specific and may never occur in certain commands
 #[derive(Debug)]
@@ -66,12 +181,21 @@ where
     /// Error setting the level of the CS pin
     /// If this happens, the CS pin might still be set low
     CsPin(CsError),
+    /// A command got into a state where it had nothing to transfer.
+    /// This is a bug in this crate. Enable the `debug-assert` feature to panic with more details instead.
+    NothingToTransfer,
+    /// The card sent this byte after a response, where it should have sent `0xFF`.
+    /// Only returned if [`SdCardConfig::trailing_bytes`] is [`TrailingBytes::Error`].
+    UnexpectedTrailingData(u8),
 
     // Init errors
+    /// The card detect switch says that there is no card
+    NoCard,
     /// Got errors doing CMD0, and retrying didn't succeed
     Cmd0Failed {
         card_present: bool,
     },
+    /// The card didn't respond to `CMD59` turning CRC on, during init or with [`SdCardDisk::set_crc`]
     EnableCrcFailed,
     Cmd8Failed,
     /// Command 8 - the SD Card does not support 3.3V
@@ -82,22 +206,71 @@ where
     GetOcrVoltageNotSupported,
     Cmd55Failed,
     Acmd41Failed,
+    /// An MMC card, which rejected `CMD55` or `ACMD41`, didn't accept `CMD1`
+    Cmd1Failed,
     /// The card did not switch from idle to ready before the timeout.
     ReadyTimeout,
+    /// The power up status bit of the OCR wasn't set before the timeout, so the card's capacity status isn't known
+    PowerUpTimeout,
+    /// With automatic re-initialization, the card that was initialized after the old one was lost has a different CID.
+    /// The new card is ready to use, but anything cached about the old card is wrong.
+    CardChanged,
+    /// Setting the block length with `CMD16` failed, during init or with [`SdCardDisk::set_block_length`]
+    SetBlockLengthFailed,
+    /// With [`SdCardConfig::verify_speed_switch`], the card didn't answer `CMD13` correctly after switching to the transfer speed,
+    /// even after falling back to the derated config. This usually means that the wiring can't carry the faster clock.
+    SpeedSwitchFailed,
 
     // Read errors
+    /// The length of a buffer passed to [`SdCardDisk::read_extents`] was not a multiple of the block size
+    ReadUnaligned,
     /// Error receiving a response after sending the read command
     ReadReceiveResponseTimeout,
     /// Got a response from the read command, but it was not ok
     ReadResponseError,
+    /// The card rejected the address of the read command with `ADDRESS_ERROR`, even after checking if it uses block or byte addresses.
+    /// Also returned without sending the command for addresses that the command can't express, such as past 4 GiB on a byte addressed card.
+    ReadAddressError,
     /// Received data that was in an unexpected format when reading
     ReadUnexpectedData,
+    /// The card sent a data error token instead of the data when reading
+    ReadDataError(DataErrorToken),
     /// Got an ok response, but never received the actual data when reading
     ReadReceiveDataTimeout,
     /// Received data, but the CRC was invalid
     ReadInvalidCrc,
     StopTransmissionResponseTimeout,
     StopTransmissionResponseError,
+    /// The card was still busy after stopping the transmission
+    StopTransmissionBusyTimeout,
+    /// In a [`BlockStream`], the next block didn't arrive before the deadline
+    StreamDeadlineMissed,
+    /// The card didn't accept `CMD59` turning CRC off, after too many bad CRCs or with [`SdCardDisk::set_crc`]
+    DisableCrcFailed,
+
+    // Write errors
+    /// The card is read-only, because of the socket's write protect switch or the write protect bits in the CSD
+    WriteProtected,
+    /// A filesystem has mounted the card with [`SdCardManager::mount`], so other handles can only read from it
+    #[cfg(feature = "embassy-sync")]
+    Mounted,
+    /// The start address or length of the data to write was not a multiple of the block size
+    WriteUnaligned,
+    /// Error receiving a response after sending the write command
+    WriteResponseTimeout,
+    /// Got a response from the write command, but it was not ok
+    WriteResponseError,
+    /// The card rejected the address of the write command with `ADDRESS_ERROR`, even after checking if it uses block or byte addresses.
+    /// Also returned without sending the command for addresses that the command can't express, like [`Error::ReadAddressError`].
+    WriteAddressError,
+    /// Sent the data, but didn't get a data response token back
+    WriteDataResponseTimeout,
+    /// The card rejected the data because the CRC was invalid
+    WriteInvalidCrc,
+    /// The card rejected the data because of a write error
+    WriteDataRejected,
+    /// The card was still busy programming the data after the timeout
+    WriteBusyTimeout,
 
     // Send CSD errors
     SendCsdResponseTimeout,
@@ -105,6 +278,80 @@ where
     SendCsdDataTimeout,
     SendCsdUnexpectedData,
     SendCsdInvalidCrc,
+
+    // Send CID errors
+    SendCidResponseTimeout,
+    SendCidResponseError,
+    SendCidDataTimeout,
+    SendCidUnexpectedData,
+    SendCidInvalidCrc,
+
+    // Send SD status errors
+    SendSdStatusResponseTimeout,
+    SendSdStatusResponseError,
+    SendSdStatusDataTimeout,
+    SendSdStatusUnexpectedData,
+    SendSdStatusInvalidCrc,
+
+    // Send SCR errors
+    SendScrResponseTimeout,
+    SendScrResponseError,
+    SendScrDataTimeout,
+    SendScrUnexpectedData,
+    SendScrInvalidCrc,
+
+    // Send status errors
+    SendStatusResponseTimeout,
+
+    // Erase errors
+    /// Error receiving a response after sending one of the erase commands
+    EraseResponseTimeout,
+    /// Got a response from one of the erase commands, but it was not ok
+    EraseResponseError,
+    /// The card was still busy erasing after the timeout
+    EraseBusyTimeout,
+    /// The range to erase ends past the last block that a 32-bit block number, or a byte address on a byte addressed card, can address
+    EraseAddressError,
+}
+
+impl<Bus, CsError> Error<Bus, CsError>
+where
+    Bus: SpiBus + SetConfig,
+    <Bus as SetConfig>::ConfigError: Debug,
+{
+    /// Which kind of error this is, for deciding whether to retry with a [`RetryPolicy`].
+    /// Empty for errors that are never worth retrying.
+    pub fn retry_kind(&self) -> RetryOn {
+        match self {
+            Self::ReadReceiveResponseTimeout
+            | Self::WriteResponseTimeout
+            | Self::WriteDataResponseTimeout => RetryOn::RESPONSE_TIMEOUT,
+            Self::ReadResponseError
+            | Self::ReadAddressError
+            | Self::WriteResponseError
+            | Self::WriteAddressError => RetryOn::RESPONSE_ERROR,
+            Self::ReadReceiveDataTimeout => RetryOn::DATA_TIMEOUT,
+            Self::ReadInvalidCrc | Self::WriteInvalidCrc => RetryOn::INVALID_CRC,
+            Self::ReadUnexpectedData => RetryOn::UNEXPECTED_DATA,
+            _ => RetryOn::empty(),
+        }
+    }
+
+    /// The card didn't respond to a command, which usually means that it was removed
+    pub fn is_no_response(&self) -> bool {
+        matches!(
+            self,
+            Self::ReadReceiveResponseTimeout
+                | Self::StopTransmissionResponseTimeout
+                | Self::WriteResponseTimeout
+                | Self::SendCsdResponseTimeout
+                | Self::SendCidResponseTimeout
+                | Self::SendSdStatusResponseTimeout
+                | Self::SendScrResponseTimeout
+                | Self::SendStatusResponseTimeout
+                | Self::EraseResponseTimeout
+        )
+    }
 }
 
 type Command = [u8; 6];
@@ -115,32 +362,70 @@ type Command = [u8; 6];
 /// But if other people have MicroSD cards that take longer, we can increase this const.
 /// If the bytes vary by command, we can use a separate value for different commands.
 const EXPECTED_BYTES_UNTIL_RESPONSE: usize = 2;
-const COMMAND_TIMEOUT: Duration = Duration::from_millis(100);
-/// This is just a guess
-const BYTES_UNTIL_CSD: usize = 2;
-const CSD_TIMEOUT: Duration = Duration::from_millis(100);
+// The defaults of `ExpectedGaps`. A bigger gap in the config than fits in `SCRATCH_LEN` takes more than one transfer.
+/// Bytes until the data of the CSD or CID register. This is just a guess
+const BYTES_UNTIL_REGISTER: usize = 2;
 /// In my experience this is up to 2
 /// Note that if we make this super big it will reduce performance
 /// With `670` we are basically guaranteeing that the transfer speed will be <0.5x of the SPI transfer speed
 const BYTES_UNTIL_READ_DATA: usize = 670;
-const READ_TIMEOUT: Duration = Duration::from_millis(100);
 /// In the SD card I tested, it always had 1 busy byte
 const BYTES_UNTIL_NOT_BUSY: usize = 1;
-const MAX_ACMD_41_ATTEMPTS: usize = 10_000;
+/// Bytes until the data of the SD Status or SCR. This is just a guess
+const BYTES_UNTIL_APP_DATA: usize = 2;
+/// Fits a whole single block read, with the default gaps, in one transfer.
+/// Multiple block reads and writes also use all of it, since a bigger buffer means fewer transfers.
+const SCRATCH_LEN: usize = size_of::<Command>()
+    + EXPECTED_BYTES_UNTIL_RESPONSE
+    + ResponseType::R1.size()
+    + BYTES_UNTIL_READ_DATA
+    + 1
+    + BLOCK_SIZE
+    + size_of::<u16>();
 
-pub struct SpiSdCard<Spi, Cs, Delayer>
+/// Has buffers of about 1.7 KiB that the commands reuse, so it's best kept in a `static` rather than on a small stack.
+pub struct SpiSdCard<Spi, Cs, Delayer, Cd = ()>
 where
     Spi: SharedSpiBus<u8>,
     Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+    Cs: ChipSelect,
 {
     spi: Spi,
     cs: Cs,
     delayer: Delayer,
     _400_khz_config: <Spi::Bus as SetConfig>::Config,
     _25_mhz_config: <Spi::Bus as SetConfig>::Config,
+    on_error: Option<ErrorHook<Spi::Bus, Cs::Error>>,
+    /// Used for [`CheckPattern::Counter`]
+    check_pattern_counter: u8,
+    card_detect: Cd,
+    /// If the card checks CRCs, and we check the CRCs of the data it sends
+    crc_enabled: bool,
+    derated_config: Option<<Spi::Bus as SetConfig>::Config>,
+    environment: Environment,
+    /// The last choice of [`SdCardConfig::clock_policy`] was [`ClockChoice::Derated`]
+    derated: bool,
+    /// The card failed [`SdCardConfig::verify_speed_switch`] at full speed, so the derated config is used until the next init
+    speed_fallback: bool,
+    init_timeline: InitTimeline,
+    /// Learned from the reads since the last init
+    read_gap: GapLearner,
+    /// The block length last set with `CMD16`
+    block_length: u32,
+    /// The last state sent to [`ChipSelect`], so that selecting an already selected card doesn't cost a transaction.
+    /// It's kept with the card rather than the [`SdCardDisk`], since the card can still be selected after a disk is dropped.
+    cs_selected: bool,
+    /// A stream was dropped without being closed, so the card is still in the middle of its transfer
+    stream_left_open: Option<OpenTransfer>,
+    /// Reused by every command, so that commands and data transfers don't need buffers on the stack
+    scratch: [u8; SCRATCH_LEN],
+    /// The block that [`Disk::write`] reads, changes, and writes back when a write only covers part of it
+    block: [u8; BLOCK_SIZE],
+    pub config: SdCardConfig,
 }
 
-impl<Spi, Cs: OutputPin, Delayer: DelayNs> SpiSdCard<Spi, Cs, Delayer>
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs> SpiSdCard<Spi, Cs, Delayer>
 where
     Spi: SharedSpiBus<u8>,
     Spi::Bus: SetConfig,
@@ -152,12 +437,32 @@ where
     ///
     /// Before the SD card's initialization is complete, a 400 kHz SPI speed is used. After that, a 25 MHz SPI speed can be used.
     /// Provide the correct SPI speeds.
+    #[cfg(feature = "embassy-time")]
     pub fn new(
         spi: Spi,
         cs: Cs,
         delayer: Delayer,
         _400_khz_config: <Spi::Bus as SetConfig>::Config,
         _25_mhz_config: <Spi::Bus as SetConfig>::Config,
+    ) -> Self {
+        Self::new_with_config(
+            spi,
+            cs,
+            delayer,
+            _400_khz_config,
+            _25_mhz_config,
+            Default::default(),
+        )
+    }
+
+    /// Use this without the `embassy-time` feature, to provide a [`Clock`] through [`SdCardConfig::new`]
+    pub fn new_with_config(
+        spi: Spi,
+        cs: Cs,
+        delayer: Delayer,
+        _400_khz_config: <Spi::Bus as SetConfig>::Config,
+        _25_mhz_config: <Spi::Bus as SetConfig>::Config,
+        config: SdCardConfig,
     ) -> Self {
         Self {
             spi,
@@ -165,13 +470,167 @@ where
             delayer,
             _400_khz_config,
             _25_mhz_config,
+            on_error: None,
+            check_pattern_counter: 0xE2,
+            card_detect: (),
+            crc_enabled: false,
+            derated_config: None,
+            environment: Environment::default(),
+            derated: false,
+            speed_fallback: false,
+            init_timeline: InitTimeline::default(),
+            read_gap: GapLearner::default(),
+            block_length: BLOCK_SIZE as u32,
+            cs_selected: false,
+            stream_left_open: None,
+            scratch: [0; SCRATCH_LEN],
+            block: [0; BLOCK_SIZE],
+            config,
+        }
+    }
+
+    /// Uses a card detect switch, so that [`SpiSdCard::init_card`] fails fast with [`Error::NoCard`] when there is no card,
+    /// and [`SpiSdCard::wait_for_card`] can wait for one to be inserted.
+    pub fn with_card_detect<Cd: CardDetect>(
+        self,
+        card_detect: Cd,
+    ) -> SpiSdCard<Spi, Cs, Delayer, Cd> {
+        SpiSdCard {
+            spi: self.spi,
+            cs: self.cs,
+            delayer: self.delayer,
+            _400_khz_config: self._400_khz_config,
+            _25_mhz_config: self._25_mhz_config,
+            on_error: self.on_error,
+            check_pattern_counter: self.check_pattern_counter,
+            card_detect,
+            crc_enabled: self.crc_enabled,
+            derated_config: self.derated_config,
+            environment: self.environment,
+            derated: self.derated,
+            speed_fallback: self.speed_fallback,
+            init_timeline: self.init_timeline,
+            read_gap: self.read_gap,
+            block_length: self.block_length,
+            cs_selected: self.cs_selected,
+            stream_left_open: self.stream_left_open,
+            scratch: self.scratch,
+            block: self.block,
+            config: self.config,
         }
     }
+}
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect> SpiSdCard<Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// Set a function that gets called with every error this driver returns.
+    pub fn set_error_hook(&mut self, on_error: Option<ErrorHook<Spi::Bus, Cs::Error>>) {
+        self.on_error = on_error;
+    }
 
     pub async fn init_card(
         &mut self,
-    ) -> Result<SdCardDisk<'_, Spi, Cs, Delayer>, Error<Spi::Bus, Cs::Error>> {
+    ) -> Result<SdCardDisk<'_, Spi, Cs, Delayer, Cd>, Error<Spi::Bus, Cs::Error>> {
+        let on_error = self.on_error;
+        let result = self.init_card_inner().await;
+        let card_type = report_error(on_error, ErrorContext::InitCard, result)?;
+        Ok(SdCardDisk::new(self, card_type))
+    }
+
+    /// Like [`Self::init_card`], but the card stays selected after init and `f` runs in a [transaction](SdCardDisk::transaction) with it.
+    /// Reading the capacity, the CID, and the first blocks while mounting then costs one select and one switch to the transfer speed,
+    /// instead of a select, deselect, and config switch for init and for every operation.
+    ///
+    /// Returns the disk, for using the card after `f`, along with what `f` returned.
+    pub async fn init_card_in_transaction<R>(
+        &mut self,
+        f: impl AsyncFnOnce(&mut SdCardDisk<'_, Spi, Cs, Delayer, Cd>) -> R,
+    ) -> Result<(SdCardDisk<'_, Spi, Cs, Delayer, Cd>, R), Error<Spi::Bus, Cs::Error>> {
+        let on_error = self.on_error;
+        let result = self.init_card_selected().await;
+        let (card_type, mut spi) = report_error(on_error, ErrorContext::InitCard, result)?;
+        let result = spi
+            .set_config(self.transfer_config())
+            .map_err(Error::SpiSetConfig);
+        report_error(on_error, ErrorContext::InitCard, result)?;
+        let mut disk = SdCardDisk::new(self, card_type);
+        disk.continue_selected(spi);
+        let output = disk.transaction(f).await?;
+        Ok((disk, output))
+    }
+
+    /// Overwrites the scratch buffer with zeros, if [`SdCardConfig::zeroize_buffers`] is on
+    pub(crate) fn zeroize_scratch(&mut self) {
+        if self.config.zeroize_buffers {
+            zeroize(&mut self.scratch);
+        }
+    }
+
+    /// Overwrites the block used by partial writes with zeros, if [`SdCardConfig::zeroize_buffers`] is on
+    pub(crate) fn zeroize_block(&mut self) {
+        if self.config.zeroize_buffers {
+            zeroize(&mut self.block);
+        }
+    }
+
+    /// Resolves when a card is inserted. Without a card detect switch, this resolves immediately.
+    pub async fn wait_for_card(&mut self) {
+        self.card_detect.wait_for_card().await
+    }
+
+    /// How long each step of the last init took, and which step it failed at.
+    /// Steps that take longer than their [budget](InitStep::budget) are also logged as warnings.
+    pub fn init_timeline(&self) -> &InitTimeline {
+        &self.init_timeline
+    }
+
+    async fn init_card_inner(&mut self) -> Result<CardType, Error<Spi::Bus, Cs::Error>> {
+        let (card_type, mut spi) = self.init_card_selected().await?;
+        spi.flush().await.map_err(Error::SpiBus)?;
+        self.cs.deselect().await.map_err(Error::CsPin)?;
+        self.cs_selected = false;
+        spi.write(&[0xFF]).await.map_err(Error::SpiBus)?;
+        spi.flush().await.map_err(Error::SpiBus)?;
+        Ok(card_type)
+    }
+
+    /// Returns the bus, still locked, with the card still selected.
+    /// The bus is at the transfer speed if the switch was verified, and at the init speed otherwise.
+    async fn init_card_selected(
+        &mut self,
+    ) -> Result<(CardType, Spi::Guard), Error<Spi::Bus, Cs::Error>> {
+        self.init_timeline.clear();
+        // The card could have been swapped
+        self.read_gap = GapLearner::default();
+        self.speed_fallback = false;
+        // CMD0 resets it, and init sets it to 512 if it matters
+        self.block_length = BLOCK_SIZE as u32;
+        // CMD0 also ends any transfer that a stream left open
+        self.stream_left_open = None;
+        let result = match self.init_steps().await {
+            Ok((card_type, mut spi)) if self.config.verify_speed_switch => self
+                .verify_speed_switch(spi.deref_mut())
+                .await
+                .map(|()| (card_type, spi)),
+            result => result,
+        };
+        self.init_timeline
+            .finish(self.config.clock, result.is_err());
+        result
+    }
+
+    async fn init_steps(&mut self) -> Result<(CardType, Spi::Guard), Error<Spi::Bus, Cs::Error>> {
+        if !self.card_detect.is_card_present() {
+            return Err(Error::NoCard);
+        }
+
         // Wait at least 1ms
+        self.init_timeline
+            .start(InitStep::PowerUp, self.config.clock);
         self.delayer.delay_ms(1).await;
 
         let mut spi = self.spi.lock().await;
@@ -180,156 +639,177 @@ where
 
         // Send 0xFF for at least 74 clock cycles according to the spec
         // So 9 bytes
-        spi.write(&[0xFF; 9]).await.unwrap();
+        spi.write(&[0xFF; 9]).await.map_err(Error::SpiBus)?;
 
-        self.cs.set_low().map_err(Error::CsPin)?;
+        self.cs.select().await.map_err(Error::CsPin)?;
 
         // This might help if the card was previously in the middle of something
         // TODO: Is this needed?
-        spi.write(&[0xFF; 1000]).await.unwrap();
+        spi.write(&[0xFF; 1000]).await.map_err(Error::SpiBus)?;
 
         let mut got_response = false;
         // TODO: Gracefully handle failures (remember to set CS to high and write a 0xFF byte);
         // Do CMD0
+        self.init_timeline.start(InitStep::Reset, self.config.clock);
         {
-            let mut buffer = [Default::default();
-                size_of::<Command>() + EXPECTED_BYTES_UNTIL_RESPONSE + size_of::<R1>()];
-            let mut response = [Default::default(); 1];
-            let mut attempt_number = 0;
-            let max_attempts = 50;
+            let mut attempt_number = 1;
             loop {
-                if attempt_number == max_attempts {
-                    break Err(Error::Cmd0Failed {
-                        card_present: got_response,
-                    });
-                }
                 let result = card_command(
                     spi.deref_mut(),
-                    &mut buffer,
-                    &format_command(0, 0),
+                    &mut self.scratch,
+                    &self.config,
+                    SdCommand::GoIdleState,
                     EXPECTED_BYTES_UNTIL_RESPONSE,
-                    &mut response,
-                    COMMAND_TIMEOUT,
+                    self.config.timeouts.command,
                     None,
                 )
                 .await;
-                match result {
-                    Ok(_) => {
+                let kind = match result {
+                    Ok(response) => {
                         got_response = true;
+                        let r1 = self.config.init_r1(response[0]);
+                        if r1 == R1::IN_IDLE_STATE {
+                            break Ok(());
+                        } else {
+                            warn!("Got response: {:x}, trying again..", r1.bits());
+                        }
+                        RetryOn::RESPONSE_ERROR
                     }
                     Err(CardCommand3Error::ReceiveResponseTimeout(data_received)) => {
                         got_response |= data_received;
+                        RetryOn::RESPONSE_TIMEOUT
                     }
-                    _ => {}
-                }
-                if result.is_ok() {
-                    let r1 = R1::from_bits_retain(response[0]);
-                    if r1 == R1::IN_IDLE_STATE {
-                        break Ok(());
-                    } else {
-                        warn!("Got response: {:x}, trying again..", r1.bits());
+                    Err(CardCommand3Error::Spi(e)) => break Err(Error::SpiBus(e)),
+                    Err(CardCommand3Error::UnexpectedTrailingData(byte)) => {
+                        break Err(Error::UnexpectedTrailingData(byte));
                     }
+                    Err(_) => RetryOn::empty(),
+                };
+                if !self.config.cmd0_retry.should_retry(attempt_number, kind) {
+                    break Err(Error::Cmd0Failed {
+                        card_present: got_response,
+                    });
                 }
                 // TODO: Release SPI lock?
-                self.delayer.delay_us(10).await;
+                self.config.cmd0_retry.wait(&mut self.delayer).await;
                 attempt_number += 1;
             }
         }?;
 
         // Enable CRC
-        {
-            let mut buffer = [Default::default();
-                size_of::<Command>() + EXPECTED_BYTES_UNTIL_RESPONSE + size_of::<R1>()];
-            let mut response = [Default::default(); 1];
-            card_command(
+        self.crc_enabled = false;
+        if self.config.enable_crc {
+            self.init_timeline
+                .start(InitStep::EnableCrc, self.config.clock);
+            let response = card_command(
                 spi.deref_mut(),
-                &mut buffer,
-                &format_command(59, Command59Argument::CRC_ON.bits()),
+                &mut self.scratch,
+                &self.config,
+                SdCommand::CrcOnOff { crc_on: true },
                 EXPECTED_BYTES_UNTIL_RESPONSE,
-                &mut response,
-                COMMAND_TIMEOUT,
+                self.config.timeouts.command,
                 None,
             )
             .await
             .map_err(|e| match e {
                 CardCommand3Error::Spi(e) => Error::SpiBus(e),
                 CardCommand3Error::ReceiveResponseTimeout(_) => Error::EnableCrcFailed,
+                CardCommand3Error::NothingToTransfer => Error::NothingToTransfer,
+                CardCommand3Error::UnexpectedTrailingData(byte) => {
+                    Error::UnexpectedTrailingData(byte)
+                }
                 _ => unreachable!(),
             })?;
-            let r1 = R1::from_bits_retain(response[0]);
-            if r1 != R1::IN_IDLE_STATE {
-                return Err(Error::EnableCrcFailed);
+            let r1 = self.config.init_r1(response[0]);
+            if r1 == R1::IN_IDLE_STATE {
+                self.crc_enabled = true;
+            } else {
+                // Some cards reject CMD59 but work fine without CRC
+                warn!(
+                    "card rejected enabling CRC with R1 0x{:02X}, continuing without CRC",
+                    r1.bits()
+                );
             }
         }
 
         // Do CMD8
-        {
-            let mut buffer = [Default::default();
-                size_of::<Command>() + EXPECTED_BYTES_UNTIL_RESPONSE + size_of::<R7>()];
-            let mut response = [Default::default(); size_of::<R7>()];
+        self.init_timeline
+            .start(InitStep::InterfaceCondition, self.config.clock);
+        let version_2 = {
             // The check pattern can be anything we want
-            let check_pattern = 0xE2;
-            card_command(
+            let check_pattern = match self.config.check_pattern {
+                CheckPattern::Fixed(check_pattern) => check_pattern,
+                CheckPattern::Counter => {
+                    self.check_pattern_counter = self.check_pattern_counter.wrapping_add(1);
+                    CheckPattern::usable(self.check_pattern_counter)
+                }
+                CheckPattern::Entropy(get_random) => CheckPattern::usable(get_random()),
+            };
+            trace!("CMD8 check pattern: 0x{:02X}", check_pattern);
+            let response = card_command(
                 spi.deref_mut(),
-                &mut buffer,
-                &format_command(8, {
-                    let mut argument = Command8Argument(Default::default());
-                    argument.set_pcie1_2v_support(false);
-                    argument.set_pcie_availability(false);
-                    argument.set_voltage_accepted(VoltageAccpted::_2_7V_3_6V.bits());
-                    argument.set_check_pattern(check_pattern);
-                    argument.0
-                }),
+                &mut self.scratch,
+                &self.config,
+                SdCommand::SendIfCond { check_pattern },
                 EXPECTED_BYTES_UNTIL_RESPONSE,
-                &mut response,
-                COMMAND_TIMEOUT,
+                self.config.timeouts.command,
                 None,
             )
             .await
             .map_err(|e| match e {
                 CardCommand3Error::Spi(e) => Error::SpiBus(e),
                 CardCommand3Error::ReceiveResponseTimeout(_) => Error::Cmd8Failed,
+                CardCommand3Error::NothingToTransfer => Error::NothingToTransfer,
+                CardCommand3Error::UnexpectedTrailingData(byte) => {
+                    Error::UnexpectedTrailingData(byte)
+                }
                 _ => unreachable!(),
             })?;
-            let r1 = R1::from_bits_retain(response[0]);
-            if r1 == R1::ILLEGAL_COMMAND {
-                todo!("Handle version 1")
+            let r1 = self.config.init_r1(response[0]);
+            if r1.contains(R1::ILLEGAL_COMMAND) {
+                // SD version 1 cards don't support CMD8
+                false
             } else if r1 != R1::IN_IDLE_STATE {
                 return Err(Error::Cmd8Failed);
+            } else {
+                let byte_3 = R7Byte3(response[3]);
+                if !byte_3
+                    .get_voltage_accepted()
+                    .contains(VoltageAccpted::_2_7V_3_6V)
+                {
+                    return Err(Error::Cmd8VoltageNotSupported);
+                }
+                if response[4] != check_pattern {
+                    return Err(Error::Cmd8InvalidCheckPattern);
+                }
+                true
             }
-            let byte_3 = R7Byte3(response[3]);
-            if !byte_3
-                .get_voltage_accepted()
-                .contains(VoltageAccpted::_2_7V_3_6V)
-            {
-                return Err(Error::Cmd8VoltageNotSupported);
-            }
-            if response[4] != check_pattern {
-                return Err(Error::Cmd8InvalidCheckPattern);
-            }
-        }
+        };
 
         // Get OCR to make sure voltage is compatible
+        self.init_timeline
+            .start(InitStep::ReadOcr, self.config.clock);
         {
-            let mut buffer = [Default::default();
-                size_of::<Command>() + EXPECTED_BYTES_UNTIL_RESPONSE + size_of::<R3>()];
-            let mut response = [Default::default(); size_of::<R3>()];
-            card_command(
+            let response = card_command(
                 spi.deref_mut(),
-                &mut buffer,
-                &format_command(58, 0),
+                &mut self.scratch,
+                &self.config,
+                SdCommand::ReadOcr,
                 EXPECTED_BYTES_UNTIL_RESPONSE,
-                &mut response,
-                COMMAND_TIMEOUT,
+                self.config.timeouts.command,
                 None,
             )
             .await
             .map_err(|e| match e {
                 CardCommand3Error::Spi(e) => Error::SpiBus(e),
                 CardCommand3Error::ReceiveResponseTimeout(_) => Error::GetOcrFailed,
+                CardCommand3Error::NothingToTransfer => Error::NothingToTransfer,
+                CardCommand3Error::UnexpectedTrailingData(byte) => {
+                    Error::UnexpectedTrailingData(byte)
+                }
                 _ => unreachable!(),
             })?;
-            let r1 = R1::from_bits_retain(response[0]);
+            let r1 = self.config.init_r1(response[0]);
             if r1 != R1::IN_IDLE_STATE {
                 return Err(Error::GetOcrFailed);
             }
@@ -340,111 +820,191 @@ where
         }
 
         // Initialize card
+        self.init_timeline
+            .start(InitStep::Initialize, self.config.clock);
+        // MMC cards don't support CMD8 or application commands, and are initialized with CMD1 instead
+        let mut mmc = false;
         {
             let mut attempt_number = 0;
-            let mut buffer = [Default::default();
-                size_of::<Command>() + EXPECTED_BYTES_UNTIL_RESPONSE + size_of::<R1>()];
-            let mut response = [Default::default(); size_of::<R1>()];
+            let start_time = (self.config.clock)();
             loop {
-                if attempt_number == MAX_ACMD_41_ATTEMPTS {
+                // The attempt limit is a backstop in case the clock doesn't advance
+                if attempt_number == self.config.acmd41_retry.max_attempts
+                    || start_time.elapsed(self.config.clock) > self.config.timeouts.init
+                {
                     return Err(Error::ReadyTimeout);
                 }
-                // CMD55 - next command is an "A" command
-                card_command(
-                    spi.deref_mut(),
-                    &mut buffer,
-                    &format_command(55, 0),
-                    EXPECTED_BYTES_UNTIL_RESPONSE,
-                    &mut response,
-                    COMMAND_TIMEOUT,
-                    None,
-                )
-                .await
-                .map_err(|e| match e {
-                    CardCommand3Error::Spi(e) => Error::SpiBus(e),
-                    CardCommand3Error::ReceiveResponseTimeout(_) => Error::Cmd55Failed,
-                    _ => unreachable!(),
-                })?;
-                let r1 = R1::from_bits_retain(response[0]);
-                if !(r1 == R1::IN_IDLE_STATE || r1 == R1::empty()) {
-                    return Err(Error::Cmd55Failed);
+                if attempt_number > 0 {
+                    self.config.acmd41_retry.wait(&mut self.delayer).await;
+                }
+                if !mmc {
+                    // CMD55 - next command is an "A" command
+                    let response = card_command(
+                        spi.deref_mut(),
+                        &mut self.scratch,
+                        &self.config,
+                        SdCommand::AppCmd,
+                        EXPECTED_BYTES_UNTIL_RESPONSE,
+                        self.config.timeouts.command,
+                        None,
+                    )
+                    .await
+                    .map_err(|e| match e {
+                        CardCommand3Error::Spi(e) => Error::SpiBus(e),
+                        CardCommand3Error::ReceiveResponseTimeout(_) => Error::Cmd55Failed,
+                        CardCommand3Error::NothingToTransfer => Error::NothingToTransfer,
+                        CardCommand3Error::UnexpectedTrailingData(byte) => {
+                            Error::UnexpectedTrailingData(byte)
+                        }
+                        _ => unreachable!(),
+                    })?;
+                    let r1 = self.config.init_r1(response[0]);
+                    if !version_2 && r1.contains(R1::ILLEGAL_COMMAND) {
+                        mmc = true;
+                    } else if !(r1 == R1::IN_IDLE_STATE || r1 == R1::empty()) {
+                        return Err(Error::Cmd55Failed);
+                    }
                 }
 
-                // ACMD41
-                card_command(
-                    spi.deref_mut(),
-                    &mut buffer,
-                    &format_command(41, CommandA41Argument::HCS.bits()),
-                    EXPECTED_BYTES_UNTIL_RESPONSE,
-                    &mut response,
-                    COMMAND_TIMEOUT,
-                    None,
-                )
-                .await
-                .map_err(|e| match e {
-                    CardCommand3Error::Spi(e) => Error::SpiBus(e),
-                    CardCommand3Error::ReceiveResponseTimeout(_) => Error::Acmd41Failed,
-                    _ => unreachable!(),
-                })?;
-                let r1 = R1::from_bits_retain(response[0]);
-                if r1 == R1::empty() {
-                    break;
-                } else if r1 != R1::IN_IDLE_STATE {
-                    return Err(Error::Acmd41Failed);
+                if !mmc {
+                    // ACMD41
+                    let response = card_command(
+                        spi.deref_mut(),
+                        &mut self.scratch,
+                        &self.config,
+                        SdCommand::SdSendOpCond {
+                            // Version 1 cards don't support high capacity
+                            high_capacity_support: version_2,
+                        },
+                        EXPECTED_BYTES_UNTIL_RESPONSE,
+                        self.config.timeouts.command,
+                        None,
+                    )
+                    .await
+                    .map_err(|e| match e {
+                        CardCommand3Error::Spi(e) => Error::SpiBus(e),
+                        CardCommand3Error::ReceiveResponseTimeout(_) => Error::Acmd41Failed,
+                        CardCommand3Error::NothingToTransfer => Error::NothingToTransfer,
+                        CardCommand3Error::UnexpectedTrailingData(byte) => {
+                            Error::UnexpectedTrailingData(byte)
+                        }
+                        _ => unreachable!(),
+                    })?;
+                    let r1 = self.config.init_r1(response[0]);
+                    if r1 == R1::empty() {
+                        break;
+                    } else if !version_2 && r1.contains(R1::ILLEGAL_COMMAND) {
+                        mmc = true;
+                    } else if r1 != R1::IN_IDLE_STATE {
+                        return Err(Error::Acmd41Failed);
+                    }
+                }
+
+                if mmc {
+                    let response = card_command(
+                        spi.deref_mut(),
+                        &mut self.scratch,
+                        &self.config,
+                        // Cards of more than 2 GB only switch to sector addresses if we support them
+                        SdCommand::SendOpCond {
+                            high_capacity_support: true,
+                        },
+                        EXPECTED_BYTES_UNTIL_RESPONSE,
+                        self.config.timeouts.command,
+                        None,
+                    )
+                    .await
+                    .map_err(|e| match e {
+                        CardCommand3Error::Spi(e) => Error::SpiBus(e),
+                        CardCommand3Error::ReceiveResponseTimeout(_) => Error::Cmd1Failed,
+                        CardCommand3Error::NothingToTransfer => Error::NothingToTransfer,
+                        CardCommand3Error::UnexpectedTrailingData(byte) => {
+                            Error::UnexpectedTrailingData(byte)
+                        }
+                        _ => unreachable!(),
+                    })?;
+                    let r1 = self.config.init_r1(response[0]);
+                    if r1 == R1::empty() {
+                        break;
+                    } else if r1 != R1::IN_IDLE_STATE {
+                        return Err(Error::Cmd1Failed);
+                    }
                 }
                 attempt_number += 1;
             }
         }
 
-        defmt::info!("Reading OCR again");
+        info!("Reading OCR again");
+        self.init_timeline
+            .start(InitStep::ReadCapacity, self.config.clock);
 
-        // Get OCR
-        let ocr = {
-            let mut buffer = [Default::default();
-                size_of::<Command>() + EXPECTED_BYTES_UNTIL_RESPONSE + size_of::<R3>()];
-            let mut response = [Default::default(); size_of::<R3>()];
-            card_command(
+        // The CCS bit is only valid once the card is powered up. Version 1 cards don't have it.
+        let ocr = if version_2 || mmc {
+            Some(self.read_ocr_powered_up(spi.deref_mut()).await?)
+        } else {
+            None
+        };
+        let block_addressed = ocr.and_then(|ocr| ocr.supports_sdhc_or_sdxc()) == Some(true);
+        let card_type = if mmc {
+            // For MMC cards, this bit of the OCR means that the card uses sector addresses
+            if block_addressed {
+                CardType::MmcHc
+            } else {
+                CardType::Mmc
+            }
+        } else if !version_2 {
+            CardType::SdV1
+        } else if block_addressed {
+            CardType::SdV2Hc
+        } else {
+            CardType::SdV2Sc
+        };
+        info!("card type: {:?}", card_type);
+
+        // Standard capacity cards can have a different default block length, so make sure it's 512
+        if !card_type.is_block_addressed() {
+            self.init_timeline
+                .start(InitStep::SetBlockLength, self.config.clock);
+            let response = card_command(
                 spi.deref_mut(),
-                &mut buffer,
-                &format_command(58, 0),
+                &mut self.scratch,
+                &self.config,
+                SdCommand::SetBlockLen {
+                    block_length: BLOCK_SIZE as u32,
+                },
                 EXPECTED_BYTES_UNTIL_RESPONSE,
-                &mut response,
-                COMMAND_TIMEOUT,
+                self.config.timeouts.command,
                 None,
             )
             .await
             .map_err(|e| match e {
                 CardCommand3Error::Spi(e) => Error::SpiBus(e),
-                CardCommand3Error::ReceiveResponseTimeout(_) => Error::GetOcrFailed,
+                CardCommand3Error::ReceiveResponseTimeout(_) => Error::SetBlockLengthFailed,
+                CardCommand3Error::NothingToTransfer => Error::NothingToTransfer,
+                CardCommand3Error::UnexpectedTrailingData(byte) => {
+                    Error::UnexpectedTrailingData(byte)
+                }
                 _ => unreachable!(),
             })?;
-            let r1 = R1::from_bits_retain(response[0]);
+            let r1 = self.config.init_r1(response[0]);
             if !r1.is_empty() {
-                return Err(Error::GetOcrFailed);
+                return Err(Error::SetBlockLengthFailed);
             }
-            Ocr::from_bits_retain(u32::from_be_bytes(response[1..5].try_into().unwrap()))
-        };
-
-        spi.flush().await.map_err(Error::SpiBus)?;
-        self.cs.set_high().map_err(Error::CsPin)?;
-        spi.write(&[0xFF]).await.map_err(Error::SpiBus)?;
-        spi.flush().await.map_err(Error::SpiBus)?;
-
-        defmt::info!("is SDHC or SDXC?: {}", ocr.supports_sdhc_or_sdxc().unwrap());
+        }
 
-        Ok(SdCardDisk {
-            sd_card: self,
-            enable_read_multiple: true,
-        })
+        Ok((card_type, spi))
     }
 }
 
-pub struct SdCardDisk<'a, Spi, Cs, Delayer>
+pub struct SdCardDisk<'a, Spi, Cs, Delayer, Cd = ()>
 where
     Spi: SharedSpiBus<u8>,
     Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+    Cs: ChipSelect,
 {
-    sd_card: &'a mut SpiSdCard<Spi, Cs, Delayer>,
+    sd_card: &'a mut SpiSdCard<Spi, Cs, Delayer, Cd>,
+    card_type: CardType,
     /// When reading data from SD cards, data is read as 512 B (aligned) blocks.
     /// To read a single block, we can use `CMD17` (`READ_SINGLE_BLOCK`).
     /// To achieve faster speeds when reading consequtive blocks, we can use `CMD18` (`READ_MULTIPLE_BLOCK`).
@@ -452,11 +1012,69 @@ where
     /// They give a bad CRC.
     /// So you can disable this to always read using CMD17, even when reading consecutive blocks.
     pub enable_read_multiple: bool,
+    /// Like [`Self::enable_read_multiple`], but for writing consecutive blocks with `CMD25` (`WRITE_MULTIPLE_BLOCK`) instead of `CMD24`.
+    pub enable_write_multiple: bool,
+    /// The priority used when locking the shared bus, for buses that support it such as `PrioritySpiBus`.
+    /// Set this to [`BusPriority::High`] before latency-critical reads, and back afterwards.
+    pub bus_priority: BusPriority,
+    /// The CID of the card, if automatic re-initialization is enabled
+    known_cid: Option<Cid>,
+    /// The card stopped responding, so it needs to be initialized again before the next operation
+    card_lost: bool,
+    /// The write protect bits of the CSD, which are read before the first write
+    csd_write_protected: Option<bool>,
+    /// The allocation unit size from the SD Status, once it was read
+    erase_unit: Option<usize>,
+    /// Inside of [`Self::transaction`], operations leave the card selected and keep the bus locked
+    in_transaction: bool,
+    /// The bus, kept locked between operations of a transaction
+    transaction_bus: Option<Spi::Guard>,
+    /// Reads in a row that failed because of a bad CRC, even after retrying
+    consecutive_crc_errors: u8,
 }
 
 pub const BLOCK_SIZE: usize = 512;
 
-impl<Spi, Cs: OutputPin, Delayer: DelayNs> Disk for SdCardDisk<'_, Spi, Cs, Delayer>
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CardType {
+    /// SD version 1.x. These are always standard capacity.
+    SdV1,
+    /// SD version 2.0 or later, standard capacity (SDSC)
+    SdV2Sc,
+    /// SD version 2.0 or later, high or extended capacity (SDHC or SDXC)
+    SdV2Hc,
+    /// An MMC or eMMC of up to 2 GB, which uses byte addresses
+    Mmc,
+    /// An MMC or eMMC of more than 2 GB, which uses sector addresses
+    MmcHc,
+}
+
+impl CardType {
+    /// High capacity cards use block addresses in commands. Standard capacity cards use byte addresses.
+    pub fn is_block_addressed(&self) -> bool {
+        matches!(self, Self::SdV2Hc | Self::MmcHc)
+    }
+
+    /// MMC cards are initialized with `CMD1`, don't have application commands, and have a different CSD
+    pub fn is_mmc(&self) -> bool {
+        matches!(self, Self::Mmc | Self::MmcHc)
+    }
+
+    /// The type with the same kind of card and the given addressing, after the `CCS` bit of the OCR was read again
+    pub(crate) fn with_block_addressing(self, block_addressed: bool) -> Self {
+        match (self.is_mmc(), block_addressed) {
+            (true, true) => Self::MmcHc,
+            (true, false) => Self::Mmc,
+            (false, true) => Self::SdV2Hc,
+            (false, false) => Self::SdV2Sc,
+        }
+    }
+}
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect> Disk
+    for SdCardDisk<'_, Spi, Cs, Delayer, Cd>
 where
     Spi: SharedSpiBus<u8>,
     Spi::Bus: SetConfig,
@@ -467,59 +1085,231 @@ where
     const BLOCK_SIZE: usize = BLOCK_SIZE;
 
     async fn read(&mut self, start: Self::Address, buffer: &mut [u8]) -> Result<(), Self::Error> {
-        let mut spi = self.sd_card.spi.lock().await;
-        spi.set_config(&self.sd_card._25_mhz_config)
-            .map_err(Error::SpiSetConfig)?;
+        self.read_measured(start, buffer, None).await
+    }
+
+    /// The allocation unit size from the SD Status, which is what the SD spec recommends aligning filesystems to.
+    /// This is `None` until the SD Status is read, by [`SdCardDisk::sd_status`] or an erase, since reading it takes a command.
+    fn erase_unit_size(&self) -> Option<usize> {
+        self.erase_unit
+    }
+
+    /// Blocks that are only partly written are read, changed, and written back, like [`SdCardDisk::write_partial`] does,
+    /// but using a block kept in the [`SpiSdCard`].
+    async fn write(&mut self, start: Self::Address, buffer: &[u8]) -> Result<(), Self::Error> {
+        self.write_unaligned(start, buffer, TransferBuffer::CardBlock)
+            .await
+    }
+
+    /// Erases all of the blocks that are completely inside of the range.
+    /// MMC cards, and SDSC cards without `ERASE_BLK_EN` in their CSD, can only erase whole erase sectors,
+    /// so only the sectors that are completely inside of the range are erased.
+    async fn erase(&mut self, start: Self::Address, end: Self::Address) -> Result<(), Self::Error> {
+        let (Ok(start_block), Ok(end_block)) =
+            (u32::try_from(start.div_ceil(512)), u32::try_from(end / 512))
+        else {
+            return Err(Error::EraseAddressError);
+        };
+        let result = self.erase_sectors_inner(start_block, end_block).await;
+        report_error(
+            self.sd_card.on_error,
+            ErrorContext::Erase {
+                start_block,
+                end_block,
+            },
+            result,
+        )
+    }
+}
 
-        self.sd_card.cs.set_low().map_err(Error::CsPin)?;
+impl<'a, Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect> SdCardDisk<'a, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// `sd_card` must already be initialized as `card_type`
+    pub(crate) fn new(
+        sd_card: &'a mut SpiSdCard<Spi, Cs, Delayer, Cd>,
+        card_type: CardType,
+    ) -> Self {
+        Self {
+            sd_card,
+            card_type,
+            enable_read_multiple: true,
+            enable_write_multiple: true,
+            bus_priority: BusPriority::Normal,
+            known_cid: None,
+            card_lost: false,
+            csd_write_protected: None,
+            erase_unit: None,
+            in_transaction: false,
+            transaction_bus: None,
+            consecutive_crc_errors: 0,
+        }
+    }
 
-        let start_block = u32::try_from(start / 512).unwrap();
+    pub fn card_type(&self) -> CardType {
+        self.card_type
+    }
+
+    /// Converts a block number to the address argument of a read, write, or erase command.
+    /// Returns `None` if the card uses byte addresses and the block starts past what 32 bits can address.
+    fn block_argument(&self, block: u32) -> Option<u32> {
+        if self.card_type.is_block_addressed() {
+            Some(block)
+        } else {
+            block.checked_mul(BLOCK_SIZE as u32)
+        }
+    }
+
+    /// Like [`Disk::read`], but also measures how long the card took to start sending each block.
+    /// `bytes_until_data[i]` is set to the number of bytes clocked between the end of the response (or the previous block) and the start of the `i`th block.
+    /// Blocks past the end of `bytes_until_data` aren't measured.
+    ///
+    /// This is useful for modeling a card's latency, such as for scheduling reads.
+    pub async fn read_measured(
+        &mut self,
+        start: u64,
+        buffer: &mut [u8],
+        bytes_until_data: Option<&mut [usize]>,
+    ) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        self.read_into(start, TransferBuffer::Caller(buffer), bytes_until_data)
+            .await
+    }
+
+    pub(crate) async fn read_into(
+        &mut self,
+        start: u64,
+        mut buffer: TransferBuffer<&mut [u8]>,
+        bytes_until_data: Option<&mut [usize]>,
+    ) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        let context = ErrorContext::Read {
+            start,
+            len: buffer.len(),
+        };
+        if let Err(e) = self.reinit_if_lost().await {
+            return report_error(self.sd_card.on_error, context, Err(e));
+        }
+        // Measures the first blocks for learning the gap, if the caller doesn't measure them
+        let mut measured = [0; 4];
+        let mut bytes_until_data = bytes_until_data.or_else(|| {
+            self.sd_card
+                .config
+                .learn_read_gap
+                .then_some(&mut measured[..])
+        });
+        let policy = self.sd_card.config.transfer_retry;
+        let mut attempt_number = 1;
+        let result = loop {
+            let result = self
+                .read_inner(start, buffer.reborrow(), bytes_until_data.as_deref_mut())
+                .await;
+            match &result {
+                Err(e) if policy.should_retry(attempt_number, e.retry_kind()) => {
+                    warn!("read failed on attempt {}, trying again", attempt_number);
+                    policy.wait(&mut self.sd_card.delayer).await;
+                    attempt_number += 1;
+                }
+                _ => break result,
+            }
+        };
+        let result = if self.recheck_addressing(&result).await {
+            self.read_inner(start, buffer.reborrow(), bytes_until_data.as_deref_mut())
+                .await
+        } else {
+            result
+        };
+        self.sd_card.zeroize_scratch();
+        if let (Ok(()), Some(bytes_until_data)) = (&result, bytes_until_data) {
+            let blocks = (start + buffer.len() as u64).div_ceil(512) - start / 512;
+            let measured = bytes_until_data.len().min(blocks as usize);
+            self.learn_read_gaps(&bytes_until_data[..measured]);
+        }
+        self.check_card_lost(&result);
+        self.track_crc_errors(&result).await;
+        report_error(self.sd_card.on_error, context, result)
+    }
+
+    async fn read_inner(
+        &mut self,
+        start: u64,
+        mut buffer: TransferBuffer<&mut [u8]>,
+        mut bytes_until_data: Option<&mut [usize]>,
+    ) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        let (Ok(start_block), Ok(end_block)) = (
+            u32::try_from(start / 512),
+            u32::try_from((start + buffer.len() as u64).div_ceil(512)),
+        ) else {
+            return Err(Error::ReadAddressError);
+        };
+        let mut spi = self.begin_transfer().await?;
 
-        let before = Instant::now();
-        if buffer.len() > 512 && self.enable_read_multiple {
-            // for block_address in start_block..end_block {
-            // The bigger this is, the better
+        let before = (self.sd_card.config.clock)();
+        // CMD18 is worth it as soon as the buffer spans more than 1 block, even if it's not more than 512 bytes
+        if end_block - start_block > 1 && self.enable_read_multiple {
+            // The bigger the buffer, the better
             // from my testing, 1024 can achieve super fast speeds and there is no need for larger than that
-            let mut spi_buffer = [Default::default(); 1024];
-            let mut response = [Default::default(); size_of::<R1>()];
-            // let mut block_bytes = [Default::default(); 512];
-            card_command(
+            let address = self
+                .block_argument(start_block)
+                .ok_or(Error::ReadAddressError)?;
+            let expected_bytes_until_data = self.read_data_gap();
+            let result = card_command(
                 spi.deref_mut(),
-                &mut spi_buffer,
-                &format_command(18, start_block),
+                &mut self.sd_card.scratch,
+                &self.sd_card.config,
+                SdCommand::ReadMultipleBlock { address },
                 EXPECTED_BYTES_UNTIL_RESPONSE,
-                &mut response,
-                COMMAND_TIMEOUT,
+                self.sd_card.config.timeouts.command,
                 Some(CardCommandOperation::Read(ReadOperation {
-                    expected_bytes_until_data: BYTES_UNTIL_READ_DATA,
-                    timeout: READ_TIMEOUT,
-                    parts: (start as usize + buffer.len()).div_ceil(512) - start as usize / 512,
+                    expected_bytes_until_data,
+                    timeout: self.sd_card.config.timeouts.read,
+                    parts: (end_block - start_block) as usize,
                     part_size: 512,
-                    buffer: buffer,
-                    crc_enabled: true,
+                    buffer: buffer.get(&mut self.sd_card.block),
+                    crc_enabled: self.sd_card.crc_enabled,
                     skip_bytes: start as usize % 512,
+                    bytes_until_data,
                 })),
             )
-            .await
-            .map_err(|e| match e {
+            .await;
+            // The card keeps sending blocks until it's stopped
+            if let Err(
+                CardCommand3Error::ExpectedStartBlockToken(_)
+                | CardCommand3Error::InvalidCrc
+                | CardCommand3Error::ReceiveDataTimeout(_),
+            ) = result
+            {
+                self.recover_read(spi.deref_mut(), true).await;
+            }
+            let response = result.map_err(|e| match e {
                 CardCommand3Error::Spi(e) => Error::SpiBus(e),
                 CardCommand3Error::ReceiveResponseTimeout(_) => Error::ReadReceiveResponseTimeout,
-                CardCommand3Error::ExpectedStartBlockToken => Error::ReadUnexpectedData,
+                CardCommand3Error::ExpectedStartBlockToken(byte) => DataErrorToken::from_byte(byte)
+                    .map_or(Error::ReadUnexpectedData, Error::ReadDataError),
                 CardCommand3Error::InvalidCrc => Error::ReadInvalidCrc,
-                CardCommand3Error::ReceiveDataTimeout(_) => Error::ReadReceiveResponseTimeout,
+                CardCommand3Error::ReceiveDataTimeout(_) => Error::ReadReceiveDataTimeout,
+                CardCommand3Error::NothingToTransfer => Error::NothingToTransfer,
+                CardCommand3Error::UnexpectedTrailingData(byte) => {
+                    Error::UnexpectedTrailingData(byte)
+                }
+                _ => unreachable!(),
             })?;
             let r1 = R1::from_bits_retain(response[0]);
             if !r1.is_empty() {
-                return Err(Error::ReadResponseError);
+                return Err(read_response_error(r1));
             }
-            card_command(
+            let response = card_command(
                 spi.deref_mut(),
-                &mut spi_buffer,
-                &format_command(12, 0),
+                &mut self.sd_card.scratch,
+                &self.sd_card.config,
+                SdCommand::StopTransmission,
                 EXPECTED_BYTES_UNTIL_RESPONSE,
-                &mut response,
-                COMMAND_TIMEOUT,
-                Some(CardCommandOperation::BusySignal(BYTES_UNTIL_NOT_BUSY)),
+                self.sd_card.config.timeouts.command,
+                Some(CardCommandOperation::BusySignal(BusyOperation {
+                    expected_bytes_until_not_busy: self.sd_card.config.gaps.not_busy,
+                    timeout: self.sd_card.config.timeouts.busy,
+                })),
             )
             .await
             .map_err(|e| match e {
@@ -527,34 +1317,34 @@ where
                 CardCommand3Error::ReceiveResponseTimeout(_) => {
                     Error::StopTransmissionResponseTimeout
                 }
+                CardCommand3Error::BusyTimeout => Error::StopTransmissionBusyTimeout,
+                CardCommand3Error::NothingToTransfer => Error::NothingToTransfer,
+                CardCommand3Error::UnexpectedTrailingData(byte) => {
+                    Error::UnexpectedTrailingData(byte)
+                }
                 _ => unreachable!(),
             })?;
+            let r1 = R1::from_bits_retain(response[0]);
             if !r1.is_empty() {
                 return Err(Error::StopTransmissionResponseError);
             }
         } else {
-            let mut spi_buffer = [Default::default();
-                size_of::<Command>()
-                    + EXPECTED_BYTES_UNTIL_RESPONSE
-                    + size_of::<R1>()
-                    + BYTES_UNTIL_READ_DATA
-                    + 1
-                    + 512
-                    + size_of::<u16>()];
-            let mut response = [Default::default(); size_of::<R1>()];
-            let end_block = u32::try_from((start + buffer.len() as u64).div_ceil(512)).unwrap();
             for block_address in start_block..end_block {
-                defmt::info!("Reading single block at 0x{:X}", block_address * 512);
-                card_command(
+                info!("Reading single block at 0x{:X}", block_address * 512);
+                let address = self
+                    .block_argument(block_address)
+                    .ok_or(Error::ReadAddressError)?;
+                let expected_bytes_until_data = self.read_data_gap();
+                let result = card_command(
                     spi.deref_mut(),
-                    &mut spi_buffer,
-                    &format_command(17, block_address),
+                    &mut self.sd_card.scratch,
+                    &self.sd_card.config,
+                    SdCommand::ReadSingleBlock { address },
                     EXPECTED_BYTES_UNTIL_RESPONSE,
-                    &mut response,
-                    COMMAND_TIMEOUT,
+                    self.sd_card.config.timeouts.command,
                     Some(CardCommandOperation::Read(ReadOperation {
-                        expected_bytes_until_data: BYTES_UNTIL_READ_DATA,
-                        timeout: READ_TIMEOUT,
+                        expected_bytes_until_data,
+                        timeout: self.sd_card.config.timeouts.read,
                         parts: 1,
                         part_size: 512,
                         buffer: {
@@ -563,27 +1353,48 @@ where
                                 (block_address as u64 + 1) * 512,
                                 start + buffer.len() as u64,
                             );
-                            &mut buffer
+                            &mut buffer.get(&mut self.sd_card.block)
                                 [(start_address - start) as usize..(end_address - start) as usize]
                         },
-                        crc_enabled: true,
+                        crc_enabled: self.sd_card.crc_enabled,
                         skip_bytes: if block_address == start_block {
                             start as usize % 512
                         } else {
                             0
                         },
+                        bytes_until_data: bytes_until_data.as_deref_mut().and_then(
+                            |bytes_until_data| {
+                                let i = (block_address - start_block) as usize;
+                                bytes_until_data.get_mut(i..i + 1)
+                            },
+                        ),
                     })),
                 )
-                .await
-                .map_err(|e| match e {
+                .await;
+                if let Err(CardCommand3Error::ExpectedStartBlockToken(_)) = result {
+                    self.recover_read(spi.deref_mut(), false).await;
+                }
+                let response = result.map_err(|e| match e {
                     CardCommand3Error::Spi(e) => Error::SpiBus(e),
                     CardCommand3Error::ReceiveResponseTimeout(_) => {
                         Error::ReadReceiveResponseTimeout
                     }
-                    CardCommand3Error::ExpectedStartBlockToken => Error::ReadUnexpectedData,
+                    CardCommand3Error::ExpectedStartBlockToken(byte) => {
+                        DataErrorToken::from_byte(byte)
+                            .map_or(Error::ReadUnexpectedData, Error::ReadDataError)
+                    }
                     CardCommand3Error::InvalidCrc => Error::ReadInvalidCrc,
                     CardCommand3Error::ReceiveDataTimeout(_) => Error::ReadReceiveDataTimeout,
+                    CardCommand3Error::NothingToTransfer => Error::NothingToTransfer,
+                    CardCommand3Error::UnexpectedTrailingData(byte) => {
+                        Error::UnexpectedTrailingData(byte)
+                    }
+                    _ => unreachable!(),
                 })?;
+                let r1 = R1::from_bits_retain(response[0]);
+                if !r1.is_empty() {
+                    return Err(read_response_error(r1));
+                }
             }
         }
 
@@ -592,100 +1403,549 @@ where
         // if block_address == start_block {
         //     let start_offset = start as usize % 512;
         //     let copy_len = min(512 - start_offset, buffer.len());
-        //     defmt::trace!("copying {} bytes", copy_len);
+        //     trace!("copying {} bytes", copy_len);
         //     buffer[..copy_len]
         //         .copy_from_slice(&block_bytes[start_offset..start_offset + copy_len]);
         // } else if block_address == end_block {
         //     let buffer_start = ((block_address - start_block) * 512) as usize;
         //     let copy_len = min((start as usize + buffer.len()) % 512, buffer.len());
-        //     defmt::trace!("copying {} bytes", copy_len);
+        //     trace!("copying {} bytes", copy_len);
         //     buffer[buffer_start..].copy_from_slice(&block_bytes[..copy_len]);
         // } else {
         //     let buffer_start = ((block_address - start_block) * 512) as usize;
-        //     defmt::trace!("copying 512 bytes");
+        //     trace!("copying 512 bytes");
         //     buffer[buffer_start..buffer_start + 512].copy_from_slice(&block_bytes)
         // }
         // }
 
         spi.flush().await.map_err(Error::SpiBus)?;
-        defmt::trace!(
+        trace!(
             "[spi_sd_card] read {} B / {} us @ {:X}",
             buffer.len(),
-            before.elapsed().as_micros(),
+            before.elapsed(self.sd_card.config.clock).as_micros(),
             start
         );
-        self.sd_card.cs.set_high().map_err(Error::CsPin)?;
-        spi.write(&[0xFF]).await.map_err(Error::SpiBus)?;
-        spi.flush().await.map_err(Error::SpiBus)?;
+        self.end_operation(spi).await?;
 
         Ok(())
     }
 
-    async fn write(&mut self, start: Self::Address, buffer: &[u8]) -> Result<(), Self::Error> {
-        todo!()
+    /// After a read failed partway through, gets the card ready for the next command.
+    /// Any partial block was already clocked out by [`card_command`].
+    /// A multiple block read still needs to be stopped, and reading the status clears any error bits it set.
+    /// Errors are only logged, since the original error is more useful to the caller.
+    async fn recover_read(&mut self, spi: &mut Spi::Bus, multiple_block: bool) {
+        if multiple_block {
+            match card_command(
+                spi,
+                &mut self.sd_card.scratch,
+                &self.sd_card.config,
+                SdCommand::StopTransmission,
+                EXPECTED_BYTES_UNTIL_RESPONSE,
+                self.sd_card.config.timeouts.command,
+                Some(CardCommandOperation::BusySignal(BusyOperation {
+                    expected_bytes_until_not_busy: self.sd_card.config.gaps.not_busy,
+                    timeout: self.sd_card.config.timeouts.busy,
+                })),
+            )
+            .await
+            {
+                Ok(response) => {
+                    // The card may report the error that stopped the transfer here too
+                    info!("R1 after stopping failed read: 0x{:02X}", response[0]);
+                }
+                Err(_) => {
+                    warn!("failed to stop the multiple block read after a bad block");
+                }
+            }
+        }
+        match card_command(
+            spi,
+            &mut self.sd_card.scratch,
+            &self.sd_card.config,
+            SdCommand::SendStatus,
+            EXPECTED_BYTES_UNTIL_RESPONSE,
+            self.sd_card.config.timeouts.command,
+            None,
+        )
+        .await
+        {
+            Ok(response) => {
+                let status = CardStatus::from_bytes([response[0], response[1]]);
+                if !status.is_ok() {
+                    warn!(
+                        "card status after bad block: R1 0x{:02X}, R2 0x{:02X}",
+                        status.r1.bits(),
+                        status.r2.bits()
+                    );
+                }
+            }
+            Err(_) => {
+                warn!("failed to get the card status after a bad block");
+            }
+        }
+    }
+
+    /// `start` and the length of `buffer` must be multiples of [`BLOCK_SIZE`]
+    pub(crate) async fn write_blocks(
+        &mut self,
+        start: u64,
+        buffer: TransferBuffer<&[u8]>,
+    ) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        let context = ErrorContext::Write {
+            start,
+            len: buffer.len(),
+        };
+        if let Err(e) = self.reinit_if_lost().await {
+            return report_error(self.sd_card.on_error, context, Err(e));
+        }
+        if let Err(e) = self.check_write_protect().await {
+            return report_error(self.sd_card.on_error, context, Err(e));
+        }
+        let policy = self.sd_card.config.transfer_retry;
+        let mut attempt_number = 1;
+        let result = loop {
+            let result = self.write_inner(start, buffer).await;
+            match &result {
+                Err(e) if policy.should_retry(attempt_number, e.retry_kind()) => {
+                    warn!("write failed on attempt {}, trying again", attempt_number);
+                    policy.wait(&mut self.sd_card.delayer).await;
+                    attempt_number += 1;
+                }
+                _ => break result,
+            }
+        };
+        let result = if self.recheck_addressing(&result).await {
+            self.write_inner(start, buffer).await
+        } else {
+            result
+        };
+        self.sd_card.zeroize_scratch();
+        self.check_card_lost(&result);
+        report_error(self.sd_card.on_error, context, result)
+    }
+
+    async fn write_inner(
+        &mut self,
+        start: u64,
+        buffer: TransferBuffer<&[u8]>,
+    ) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        if !start.is_multiple_of(512) || !buffer.len().is_multiple_of(512) {
+            return Err(Error::WriteUnaligned);
+        }
+
+        let Ok(start_block) = u32::try_from(start / 512) else {
+            return Err(Error::WriteAddressError);
+        };
+        let mut spi = self.begin_transfer().await?;
+        let buffer = buffer.get(&self.sd_card.block);
+
+        let before = (self.sd_card.config.clock)();
+        let busy = BusyOperation {
+            expected_bytes_until_not_busy: self.sd_card.config.gaps.not_busy,
+            timeout: self.sd_card.config.timeouts.busy,
+        };
+        let map_err = |e: CardCommand3Error<<Spi::Bus as ErrorType>::Error>| match e {
+            CardCommand3Error::Spi(e) => Error::SpiBus(e),
+            CardCommand3Error::ReceiveResponseTimeout(_) => Error::WriteResponseTimeout,
+            CardCommand3Error::DataResponseTimeout => Error::WriteDataResponseTimeout,
+            CardCommand3Error::DataRejected(0b101) => Error::WriteInvalidCrc,
+            CardCommand3Error::DataRejected(_) => Error::WriteDataRejected,
+            CardCommand3Error::BusyTimeout => Error::WriteBusyTimeout,
+            CardCommand3Error::NothingToTransfer => Error::NothingToTransfer,
+            CardCommand3Error::UnexpectedTrailingData(byte) => Error::UnexpectedTrailingData(byte),
+            _ => unreachable!(),
+        };
+        if buffer.len() > 512 && self.enable_write_multiple {
+            let address = self
+                .block_argument(start_block)
+                .ok_or(Error::WriteAddressError)?;
+            let response = card_command(
+                spi.deref_mut(),
+                &mut self.sd_card.scratch,
+                &self.sd_card.config,
+                SdCommand::WriteMultipleBlock { address },
+                EXPECTED_BYTES_UNTIL_RESPONSE,
+                self.sd_card.config.timeouts.command,
+                Some(CardCommandOperation::Write(WriteOperation {
+                    buffer,
+                    part_size: 512,
+                    start_token: START_BLOCK_TOKEN_MULTIPLE_WRITE,
+                    busy: busy.clone(),
+                })),
+            )
+            .await
+            .map_err(map_err)?;
+            let r1 = R1::from_bits_retain(response[0]);
+            if !r1.is_empty() {
+                return Err(write_response_error(r1));
+            }
+            stop_multiple_block_write(
+                spi.deref_mut(),
+                &mut self.sd_card.scratch,
+                &self.sd_card.config,
+                &busy,
+            )
+            .await
+            .map_err(map_err)?;
+        } else {
+            for (block_address, block) in (start_block..).zip(buffer.chunks(512)) {
+                let address = self
+                    .block_argument(block_address)
+                    .ok_or(Error::WriteAddressError)?;
+                let response = card_command(
+                    spi.deref_mut(),
+                    &mut self.sd_card.scratch,
+                    &self.sd_card.config,
+                    SdCommand::WriteBlock { address },
+                    EXPECTED_BYTES_UNTIL_RESPONSE,
+                    self.sd_card.config.timeouts.command,
+                    Some(CardCommandOperation::Write(WriteOperation {
+                        buffer: block,
+                        part_size: 512,
+                        start_token: START_BLOCK_TOKEN,
+                        busy: busy.clone(),
+                    })),
+                )
+                .await
+                .map_err(map_err)?;
+                let r1 = R1::from_bits_retain(response[0]);
+                if !r1.is_empty() {
+                    return Err(write_response_error(r1));
+                }
+            }
+        }
+
+        trace!(
+            "[spi_sd_card] wrote {} B / {} us @ {:X}",
+            buffer.len(),
+            before.elapsed(self.sd_card.config.clock).as_micros(),
+            start
+        );
+        self.end_operation(spi).await?;
+
+        Ok(())
     }
-}
 
-impl<Spi, Cs: OutputPin, Delayer: DelayNs> SdCardDisk<'_, Spi, Cs, Delayer>
-where
-    Spi: SharedSpiBus<u8>,
-    Spi::Bus: SetConfig,
-    <Spi::Bus as SetConfig>::ConfigError: Debug,
-{
     /// Returns the card capacity in bytes
     pub async fn capacity(&mut self) -> Result<u64, Error<Spi::Bus, Cs::Error>> {
-        let mut spi = self.sd_card.spi.lock().await;
-        spi.set_config(&self.sd_card._25_mhz_config)
-            .map_err(Error::SpiSetConfig)?;
+        let result = self.capacity_inner().await;
+        report_error(self.sd_card.on_error, ErrorContext::Capacity, result)
+    }
+
+    async fn capacity_inner(&mut self) -> Result<u64, Error<Spi::Bus, Cs::Error>> {
+        Ok(self.csd_inner().await?.card_capacity_bytes())
+    }
+
+    /// Reads the CSD register, which has info such as the capacity and timing of the card
+    pub async fn csd(&mut self) -> Result<Csd, Error<Spi::Bus, Cs::Error>> {
+        let result = self.csd_inner().await;
+        report_error(self.sd_card.on_error, ErrorContext::Csd, result)
+    }
+
+    async fn csd_inner(&mut self) -> Result<Csd, Error<Spi::Bus, Cs::Error>> {
+        let csd = self
+            .read_register(
+                SdCommand::SendCsd,
+                |e| match e {
+                    CardCommand3Error::Spi(e) => Error::SpiBus(e),
+                    CardCommand3Error::ReceiveResponseTimeout(_) => Error::SendCsdResponseTimeout,
+                    CardCommand3Error::ExpectedStartBlockToken(_) => Error::SendCsdUnexpectedData,
+                    CardCommand3Error::ReceiveDataTimeout(_) => Error::SendCsdDataTimeout,
+                    CardCommand3Error::InvalidCrc => Error::SendCsdInvalidCrc,
+                    CardCommand3Error::NothingToTransfer => Error::NothingToTransfer,
+                    CardCommand3Error::UnexpectedTrailingData(byte) => {
+                        Error::UnexpectedTrailingData(byte)
+                    }
+                    _ => unreachable!(),
+                },
+                Error::SendCsdResponseError,
+            )
+            .await?;
+        if self.card_type.is_mmc() {
+            return Ok(Csd::from_mmc_bits(csd));
+        }
+        Csd::from_bits(csd).ok_or(Error::SendCsdUnexpectedData)
+    }
+
+    /// Reads the OCR register, which has the supported voltages and the card capacity status
+    pub async fn ocr(&mut self) -> Result<Ocr, Error<Spi::Bus, Cs::Error>> {
+        let result = self.ocr_inner().await;
+        report_error(self.sd_card.on_error, ErrorContext::Ocr, result)
+    }
+
+    async fn ocr_inner(&mut self) -> Result<Ocr, Error<Spi::Bus, Cs::Error>> {
+        let mut spi = self.begin_operation().await?;
+
+        let ocr = {
+            let response = card_command(
+                spi.deref_mut(),
+                &mut self.sd_card.scratch,
+                &self.sd_card.config,
+                SdCommand::ReadOcr,
+                EXPECTED_BYTES_UNTIL_RESPONSE,
+                self.sd_card.config.timeouts.command,
+                None,
+            )
+            .await
+            .map_err(|e| match e {
+                CardCommand3Error::Spi(e) => Error::SpiBus(e),
+                CardCommand3Error::ReceiveResponseTimeout(_) => Error::GetOcrFailed,
+                CardCommand3Error::NothingToTransfer => Error::NothingToTransfer,
+                CardCommand3Error::UnexpectedTrailingData(byte) => {
+                    Error::UnexpectedTrailingData(byte)
+                }
+                _ => unreachable!(),
+            })?;
+            let r1 = R1::from_bits_retain(response[0]);
+            if !r1.is_empty() {
+                return Err(Error::GetOcrFailed);
+            }
+            Ocr::from_bits_retain(u32::from_be_bytes(response[1..5].try_into().unwrap()))
+        };
+
+        self.end_operation(spi).await?;
+
+        Ok(ocr)
+    }
 
-        self.sd_card.cs.set_low().map_err(Error::CsPin)?;
-
-        let csd = {
-            let mut buffer = [Default::default();
-                size_of::<Command>()
-                    + EXPECTED_BYTES_UNTIL_RESPONSE
-                    + size_of::<R1>()
-                    + BYTES_UNTIL_CSD
-                    + size_of::<CsdV2>()];
-            let mut response = [Default::default(); size_of::<R1>()];
-            let mut csd_bytes = [Default::default(); size_of::<CsdV2>()];
-            card_command(
+    /// Reads the CID register, which identifies the card
+    pub async fn cid(&mut self) -> Result<Cid, Error<Spi::Bus, Cs::Error>> {
+        let result = self.cid_inner().await;
+        report_error(self.sd_card.on_error, ErrorContext::Cid, result)
+    }
+
+    async fn cid_inner(&mut self) -> Result<Cid, Error<Spi::Bus, Cs::Error>> {
+        self.read_register(
+            SdCommand::SendCid,
+            |e| match e {
+                CardCommand3Error::Spi(e) => Error::SpiBus(e),
+                CardCommand3Error::ReceiveResponseTimeout(_) => Error::SendCidResponseTimeout,
+                CardCommand3Error::ExpectedStartBlockToken(_) => Error::SendCidUnexpectedData,
+                CardCommand3Error::ReceiveDataTimeout(_) => Error::SendCidDataTimeout,
+                CardCommand3Error::InvalidCrc => Error::SendCidInvalidCrc,
+                CardCommand3Error::NothingToTransfer => Error::NothingToTransfer,
+                CardCommand3Error::UnexpectedTrailingData(byte) => {
+                    Error::UnexpectedTrailingData(byte)
+                }
+                _ => unreachable!(),
+            },
+            Error::SendCidResponseError,
+        )
+        .await
+        .map(Cid)
+    }
+
+    /// Reads a 16 byte register (CSD or CID), which the card sends as a data block
+    async fn read_register(
+        &mut self,
+        command: SdCommand,
+        map_err: impl FnOnce(
+            CardCommand3Error<<Spi::Bus as ErrorType>::Error>,
+        ) -> Error<Spi::Bus, Cs::Error>,
+        response_error: Error<Spi::Bus, Cs::Error>,
+    ) -> Result<u128, Error<Spi::Bus, Cs::Error>> {
+        let mut spi = self.begin_operation().await?;
+
+        let register = {
+            let mut register_bytes = [Default::default(); size_of::<u128>()];
+            let response = card_command(
                 spi.deref_mut(),
-                &mut buffer,
-                &format_command(9, 0),
+                &mut self.sd_card.scratch,
+                &self.sd_card.config,
+                command,
                 EXPECTED_BYTES_UNTIL_RESPONSE,
-                &mut response,
-                COMMAND_TIMEOUT,
+                self.sd_card.config.timeouts.command,
                 Some(CardCommandOperation::Read(ReadOperation {
                     parts: 1,
-                    part_size: csd_bytes.len(),
-                    buffer: &mut csd_bytes,
-                    expected_bytes_until_data: BYTES_UNTIL_CSD,
-                    timeout: CSD_TIMEOUT,
-                    crc_enabled: true,
+                    part_size: register_bytes.len(),
+                    buffer: &mut register_bytes,
+                    expected_bytes_until_data: self.sd_card.config.gaps.register,
+                    timeout: self.sd_card.config.timeouts.register,
+                    crc_enabled: self.sd_card.crc_enabled,
                     skip_bytes: 0,
+                    bytes_until_data: None,
                 })),
             )
             .await
+            .map_err(map_err)?;
+            let r1 = R1::from_bits_retain(response[0]);
+            if !r1.is_empty() {
+                return Err(response_error);
+            }
+            u128::from_be_bytes(register_bytes)
+        };
+
+        self.end_operation(spi).await?;
+
+        Ok(register)
+    }
+
+    /// Reads the 512-bit SD Status register, which has info such as the speed class and erase timing
+    pub async fn sd_status(&mut self) -> Result<SdStatus, Error<Spi::Bus, Cs::Error>> {
+        let result = self.sd_status_inner().await;
+        report_error(self.sd_card.on_error, ErrorContext::SdStatus, result)
+    }
+
+    async fn sd_status_inner(&mut self) -> Result<SdStatus, Error<Spi::Bus, Cs::Error>> {
+        let sd_status = self
+            .read_app_data(
+                SdCommand::SdStatus,
+                |e| match e {
+                    CardCommand3Error::Spi(e) => Error::SpiBus(e),
+                    CardCommand3Error::ReceiveResponseTimeout(_) => {
+                        Error::SendSdStatusResponseTimeout
+                    }
+                    CardCommand3Error::ExpectedStartBlockToken(_) => {
+                        Error::SendSdStatusUnexpectedData
+                    }
+                    CardCommand3Error::ReceiveDataTimeout(_) => Error::SendSdStatusDataTimeout,
+                    CardCommand3Error::InvalidCrc => Error::SendSdStatusInvalidCrc,
+                    CardCommand3Error::NothingToTransfer => Error::NothingToTransfer,
+                    CardCommand3Error::UnexpectedTrailingData(byte) => {
+                        Error::UnexpectedTrailingData(byte)
+                    }
+                    _ => unreachable!(),
+                },
+                Error::SendSdStatusResponseError,
+            )
+            .await
+            .map(SdStatus)?;
+        self.erase_unit = sd_status.au_size_bytes().map(|au_size| au_size as usize);
+        Ok(sd_status)
+    }
+
+    /// Reads the card status with `CMD13`, which is useful for diagnosing errors after writing or erasing
+    pub async fn status(&mut self) -> Result<CardStatus, Error<Spi::Bus, Cs::Error>> {
+        let result = self.status_inner().await;
+        report_error(self.sd_card.on_error, ErrorContext::Status, result)
+    }
+
+    async fn status_inner(&mut self) -> Result<CardStatus, Error<Spi::Bus, Cs::Error>> {
+        let mut spi = self.begin_operation().await?;
+
+        let status = {
+            let response = card_command(
+                spi.deref_mut(),
+                &mut self.sd_card.scratch,
+                &self.sd_card.config,
+                SdCommand::SendStatus,
+                EXPECTED_BYTES_UNTIL_RESPONSE,
+                self.sd_card.config.timeouts.command,
+                None,
+            )
+            .await
             .map_err(|e| match e {
                 CardCommand3Error::Spi(e) => Error::SpiBus(e),
-                CardCommand3Error::ReceiveResponseTimeout(_) => Error::SendCsdResponseTimeout,
-                CardCommand3Error::ExpectedStartBlockToken => Error::SendCsdUnexpectedData,
-                CardCommand3Error::ReceiveDataTimeout(_) => Error::SendCsdDataTimeout,
-                CardCommand3Error::InvalidCrc => Error::SendCsdInvalidCrc,
+                CardCommand3Error::ReceiveResponseTimeout(_) => Error::SendStatusResponseTimeout,
+                CardCommand3Error::NothingToTransfer => Error::NothingToTransfer,
+                CardCommand3Error::UnexpectedTrailingData(byte) => {
+                    Error::UnexpectedTrailingData(byte)
+                }
+                _ => unreachable!(),
+            })?;
+            CardStatus::from_bytes([response[0], response[1]])
+        };
+
+        self.end_operation(spi).await?;
+
+        Ok(status)
+    }
+
+    /// Reads the SCR register, which has info such as the spec version and which optional commands the card supports
+    pub async fn scr(&mut self) -> Result<Scr, Error<Spi::Bus, Cs::Error>> {
+        let result = self
+            .read_app_data(
+                SdCommand::SendScr,
+                |e| match e {
+                    CardCommand3Error::Spi(e) => Error::SpiBus(e),
+                    CardCommand3Error::ReceiveResponseTimeout(_) => Error::SendScrResponseTimeout,
+                    CardCommand3Error::ExpectedStartBlockToken(_) => Error::SendScrUnexpectedData,
+                    CardCommand3Error::ReceiveDataTimeout(_) => Error::SendScrDataTimeout,
+                    CardCommand3Error::InvalidCrc => Error::SendScrInvalidCrc,
+                    CardCommand3Error::NothingToTransfer => Error::NothingToTransfer,
+                    CardCommand3Error::UnexpectedTrailingData(byte) => {
+                        Error::UnexpectedTrailingData(byte)
+                    }
+                    _ => unreachable!(),
+                },
+                Error::SendScrResponseError,
+            )
+            .await
+            .map(|bytes| Scr(u64::from_be_bytes(bytes)));
+        report_error(self.sd_card.on_error, ErrorContext::Scr, result)
+    }
+
+    /// Sends `CMD55` and then an application specific command which responds with a data block, such as the SD Status or SCR
+    async fn read_app_data<const N: usize>(
+        &mut self,
+        command: SdCommand,
+        map_err: impl FnOnce(
+            CardCommand3Error<<Spi::Bus as ErrorType>::Error>,
+        ) -> Error<Spi::Bus, Cs::Error>,
+        response_error: Error<Spi::Bus, Cs::Error>,
+    ) -> Result<[u8; N], Error<Spi::Bus, Cs::Error>> {
+        let mut spi = self.begin_operation().await?;
+
+        let data = {
+            // CMD55 - next command is an "A" command
+            let response = card_command(
+                spi.deref_mut(),
+                &mut self.sd_card.scratch,
+                &self.sd_card.config,
+                SdCommand::AppCmd,
+                EXPECTED_BYTES_UNTIL_RESPONSE,
+                self.sd_card.config.timeouts.command,
+                None,
+            )
+            .await
+            .map_err(|e| match e {
+                CardCommand3Error::Spi(e) => Error::SpiBus(e),
+                CardCommand3Error::ReceiveResponseTimeout(_) => Error::Cmd55Failed,
+                CardCommand3Error::NothingToTransfer => Error::NothingToTransfer,
+                CardCommand3Error::UnexpectedTrailingData(byte) => {
+                    Error::UnexpectedTrailingData(byte)
+                }
+                _ => unreachable!(),
             })?;
             let r1 = R1::from_bits_retain(response[0]);
             if !r1.is_empty() {
-                return Err(Error::SendCsdResponseError);
+                return Err(Error::Cmd55Failed);
             }
-            CsdV2(u128::from_be_bytes(csd_bytes))
+
+            let mut data = [Default::default(); N];
+            let response = card_command(
+                spi.deref_mut(),
+                &mut self.sd_card.scratch,
+                &self.sd_card.config,
+                command,
+                EXPECTED_BYTES_UNTIL_RESPONSE,
+                self.sd_card.config.timeouts.command,
+                Some(CardCommandOperation::Read(ReadOperation {
+                    parts: 1,
+                    part_size: data.len(),
+                    buffer: &mut data,
+                    expected_bytes_until_data: self.sd_card.config.gaps.app_data,
+                    timeout: self.sd_card.config.timeouts.app_data,
+                    crc_enabled: self.sd_card.crc_enabled,
+                    skip_bytes: 0,
+                    bytes_until_data: None,
+                })),
+            )
+            .await
+            .map_err(map_err)?;
+            // For an R2 response, the second byte has more error bits
+            if response[..command.response_type().size()]
+                .iter()
+                .any(|&byte| byte != 0)
+            {
+                return Err(response_error);
+            }
+            data
         };
 
-        spi.flush().await.map_err(Error::SpiBus)?;
-        self.sd_card.cs.set_high().map_err(Error::CsPin)?;
-        spi.write(&[0xFF]).await.map_err(Error::SpiBus)?;
-        spi.flush().await.map_err(Error::SpiBus)?;
+        self.end_operation(spi).await?;
 
-        Ok(csd.card_capacity_bytes())
+        Ok(data)
     }
 }
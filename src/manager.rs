@@ -0,0 +1,305 @@
+use core::{
+    fmt::Debug,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use embassy_embedded_hal::SetConfig;
+use embassy_sync::{
+    blocking_mutex::raw::RawMutex,
+    channel::{Channel, Receiver},
+    mutex::Mutex,
+};
+use embedded_hal_async::delay::DelayNs;
+
+use crate::{
+    BLOCK_SIZE, CardDetect, CardType, ChipSelect, Disk, Duration, Error, SdCardDisk, SharedSpiBus,
+    SpiSdCard,
+};
+
+/// Something that happened to the card, published by [`SdCardManager::run`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CardEvent {
+    /// A card was inserted and initialized
+    Inserted(CardType),
+    /// The card was removed, or stopped responding
+    Removed,
+}
+
+struct State<Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+    Cs: ChipSelect,
+{
+    sd_card: SpiSdCard<Spi, Cs, Delayer, Cd>,
+    /// The type of the initialized card, and its generation
+    card: Option<(CardType, u32)>,
+    /// Incremented every time a card is initialized, so that handles to a removed card can't use the next one
+    generation: u32,
+}
+
+/// Owns a [`SpiSdCard`] and handles inserting and removing cards, so that application code doesn't have to.
+///
+/// [`SdCardManager::run`] checks the card every `poll_interval`.
+/// Without a card detect switch, it tries to initialize a card, and once there is one, it checks that it still responds with `CMD13` (`SEND_STATUS`).
+/// Inserts and removals are published to [`SdCardManager::events`], and [`SdCardManager::disk`] gives a [`Disk`] while a card is initialized.
+///
+/// A filesystem should use the handle from [`SdCardManager::mount`], so that other tasks can read the card at the same time,
+/// such as to back it up, but can't write to it behind the filesystem's back.
+///
+/// `EVENTS` is the capacity of the event channel. Events are dropped if nothing receives them before it fills up.
+pub struct SdCardManager<M: RawMutex, Spi, Cs, Delayer, Cd, const EVENTS: usize>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+    Cs: ChipSelect,
+{
+    state: Mutex<M, State<Spi, Cs, Delayer, Cd>>,
+    events: Channel<M, CardEvent, EVENTS>,
+    poll_interval: Duration,
+    /// If there is a handle from [`SdCardManager::mount`]
+    mounted: AtomicBool,
+}
+
+impl<M: RawMutex, Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect, const EVENTS: usize>
+    SdCardManager<M, Spi, Cs, Delayer, Cd, EVENTS>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    pub const fn new(sd_card: SpiSdCard<Spi, Cs, Delayer, Cd>, poll_interval: Duration) -> Self {
+        Self {
+            state: Mutex::new(State {
+                sd_card,
+                card: None,
+                generation: 0,
+            }),
+            events: Channel::new(),
+            poll_interval,
+            mounted: AtomicBool::new(false),
+        }
+    }
+
+    pub fn events(&self) -> Receiver<'_, M, CardEvent, EVENTS> {
+        self.events.receiver()
+    }
+
+    /// Returns a handle to the card if one is initialized.
+    /// If the card is removed, the handle's operations fail with [`Error::NoCard`], and with [`Error::CardChanged`] once another card is inserted.
+    pub async fn disk(&self) -> Option<ManagedDisk<'_, M, Spi, Cs, Delayer, Cd, EVENTS>> {
+        let (card_type, generation) = self.state.lock().await.card?;
+        Some(ManagedDisk {
+            manager: self,
+            card_type,
+            generation,
+            mount: false,
+        })
+    }
+
+    /// Returns a handle for a filesystem to use. Until it is dropped, it is the only handle that can write to the card.
+    /// Handles from [`SdCardManager::disk`] can still read, but writing or erasing with them fails with [`Error::Mounted`].
+    ///
+    /// Fails with [`Error::Mounted`] if the card is already mounted, and with [`Error::NoCard`] if no card is initialized.
+    pub async fn mount(
+        &self,
+    ) -> Result<ManagedDisk<'_, M, Spi, Cs, Delayer, Cd, EVENTS>, Error<Spi::Bus, Cs::Error>> {
+        let (card_type, generation) = self.state.lock().await.card.ok_or(Error::NoCard)?;
+        if self.mounted.swap(true, Ordering::Acquire) {
+            return Err(Error::Mounted);
+        }
+        Ok(ManagedDisk {
+            manager: self,
+            card_type,
+            generation,
+            mount: true,
+        })
+    }
+
+    pub fn is_mounted(&self) -> bool {
+        self.mounted.load(Ordering::Relaxed)
+    }
+
+    /// Watches for the card being inserted and removed. Run this in its own task.
+    ///
+    /// `delayer` is only used to wait between checks, so that the bus isn't locked while waiting.
+    pub async fn run(&self, mut delayer: impl DelayNs) -> ! {
+        loop {
+            self.poll().await;
+            delayer
+                .delay_us(
+                    self.poll_interval
+                        .as_micros()
+                        .try_into()
+                        .unwrap_or(u32::MAX),
+                )
+                .await;
+        }
+    }
+
+    async fn poll(&self) {
+        let mut state = self.state.lock().await;
+        let state = &mut *state;
+        match state.card {
+            None => {
+                if !state.sd_card.card_detect.is_card_present() {
+                    return;
+                }
+                // Errors are already reported through the error hook, and it will be tried again next time
+                if let Ok(disk) = state.sd_card.init_card().await {
+                    let card_type = disk.card_type();
+                    state.generation = state.generation.wrapping_add(1);
+                    state.card = Some((card_type, state.generation));
+                    info!("card inserted: {:?}", card_type);
+                    self.publish(CardEvent::Inserted(card_type));
+                }
+            }
+            Some((card_type, _)) => {
+                let present = state.sd_card.card_detect.is_card_present()
+                    && match SdCardDisk::new(&mut state.sd_card, card_type)
+                        .status()
+                        .await
+                    {
+                        Ok(_) => true,
+                        // If the bus is having problems, the card is probably still there
+                        Err(e) => !e.is_no_response() || !state.sd_card.spi.is_healthy(),
+                    };
+                if !present {
+                    state.card = None;
+                    info!("card removed");
+                    self.publish(CardEvent::Removed);
+                }
+            }
+        }
+    }
+
+    fn publish(&self, event: CardEvent) {
+        if self.events.try_send(event).is_err() {
+            warn!("card event channel is full, dropping {:?}", event);
+        }
+    }
+}
+
+/// A [`Disk`] for the card that was initialized when it was returned by [`SdCardManager::disk`]
+pub struct ManagedDisk<'m, M: RawMutex, Spi, Cs, Delayer, Cd, const EVENTS: usize>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+    Cs: ChipSelect,
+{
+    manager: &'m SdCardManager<M, Spi, Cs, Delayer, Cd, EVENTS>,
+    card_type: CardType,
+    generation: u32,
+    /// If this handle is from [`SdCardManager::mount`]
+    mount: bool,
+}
+
+impl<M: RawMutex, Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect, const EVENTS: usize>
+    ManagedDisk<'_, M, Spi, Cs, Delayer, Cd, EVENTS>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    pub fn card_type(&self) -> CardType {
+        self.card_type
+    }
+
+    /// If this handle is from [`SdCardManager::mount`]
+    pub fn is_mount(&self) -> bool {
+        self.mount
+    }
+
+    /// Runs `f` with the card, for operations that aren't part of [`Disk`], such as [`SdCardDisk::cid`].
+    /// The card is locked for the whole time, so [`SdCardManager::run`] can't check it until `f` returns.
+    ///
+    /// Since `f` could write to the card, this fails with [`Error::Mounted`] while another handle has the card mounted.
+    /// Other handles to the card can't be used inside of `f`, because they would wait for the card forever. Use the [`SdCardDisk`] passed to `f` instead.
+    pub async fn with_disk<R>(
+        &mut self,
+        f: impl AsyncFnOnce(&mut SdCardDisk<'_, Spi, Cs, Delayer, Cd>) -> R,
+    ) -> Result<R, Error<Spi::Bus, Cs::Error>> {
+        let mut state = self.manager.state.lock().await;
+        self.check(&state)?;
+        self.check_write()?;
+        Ok(f(&mut SdCardDisk::new(&mut state.sd_card, self.card_type)).await)
+    }
+
+    /// Fails if the card this handle was for isn't initialized anymore
+    fn check(&self, state: &State<Spi, Cs, Delayer, Cd>) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        match state.card {
+            Some((_, generation)) if generation == self.generation => Ok(()),
+            Some(_) => Err(Error::CardChanged),
+            None => Err(Error::NoCard),
+        }
+    }
+
+    /// Fails if another handle has the card mounted
+    fn check_write(&self) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        if !self.mount && self.manager.mounted.load(Ordering::Acquire) {
+            return Err(Error::Mounted);
+        }
+        Ok(())
+    }
+}
+
+impl<M: RawMutex, Spi, Cs, Delayer, Cd, const EVENTS: usize> Drop
+    for ManagedDisk<'_, M, Spi, Cs, Delayer, Cd, EVENTS>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+    Cs: ChipSelect,
+{
+    fn drop(&mut self) {
+        if self.mount {
+            self.manager.mounted.store(false, Ordering::Release);
+        }
+    }
+}
+
+impl<M: RawMutex, Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect, const EVENTS: usize> Disk
+    for ManagedDisk<'_, M, Spi, Cs, Delayer, Cd, EVENTS>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    type Address = u64;
+    type Error = Error<Spi::Bus, Cs::Error>;
+    const BLOCK_SIZE: usize = BLOCK_SIZE;
+
+    async fn read(&mut self, start: Self::Address, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        let mut state = self.manager.state.lock().await;
+        self.check(&state)?;
+        SdCardDisk::new(&mut state.sd_card, self.card_type)
+            .read(start, buffer)
+            .await
+    }
+
+    async fn write(&mut self, start: Self::Address, buffer: &[u8]) -> Result<(), Self::Error> {
+        let mut state = self.manager.state.lock().await;
+        self.check(&state)?;
+        self.check_write()?;
+        SdCardDisk::new(&mut state.sd_card, self.card_type)
+            .write(start, buffer)
+            .await
+    }
+
+    async fn erase(&mut self, start: Self::Address, end: Self::Address) -> Result<(), Self::Error> {
+        let mut state = self.manager.state.lock().await;
+        self.check(&state)?;
+        self.check_write()?;
+        Disk::erase(
+            &mut SdCardDisk::new(&mut state.sd_card, self.card_type),
+            start,
+            end,
+        )
+        .await
+    }
+}
@@ -0,0 +1,80 @@
+use crate::{BLOCK_SIZE, Disk, SubDisk};
+
+/// Offset of the first partition entry in the MBR
+const PARTITION_TABLE_OFFSET: usize = 446;
+const PARTITION_ENTRY_LEN: usize = 16;
+const SIGNATURE: [u8; 2] = [0x55, 0xAA];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PartitionEntry {
+    pub bootable: bool,
+    /// For example, `0x0C` for FAT32 with LBA addressing, or `0x07` for exFAT
+    pub partition_type: u8,
+    pub start_lba: u32,
+    /// Length of the partition, in sectors
+    pub sectors: u32,
+}
+
+impl PartitionEntry {
+    /// Returns a [`Disk`] that can only access this partition, with addresses starting at the start of the partition
+    pub fn open<'a, D: Disk<Address = u64>>(&self, disk: &'a mut D) -> PartitionDisk<'a, D> {
+        SubDisk::new(
+            disk,
+            u64::from(self.start_lba) * BLOCK_SIZE as u64,
+            u64::from(self.sectors) * BLOCK_SIZE as u64,
+        )
+    }
+}
+
+/// The primary partition table from a Master Boot Record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Mbr {
+    /// Unused entries are `None`
+    pub partitions: [Option<PartitionEntry>; 4],
+}
+
+impl Mbr {
+    /// Parses the first sector of a disk. Returns `None` if it doesn't end with the MBR signature.
+    pub fn parse(sector: &[u8; BLOCK_SIZE]) -> Option<Self> {
+        if sector[BLOCK_SIZE - SIGNATURE.len()..] != SIGNATURE {
+            return None;
+        }
+        Some(Self {
+            partitions: core::array::from_fn(|i| {
+                let offset = PARTITION_TABLE_OFFSET + i * PARTITION_ENTRY_LEN;
+                let entry = &sector[offset..offset + PARTITION_ENTRY_LEN];
+                let partition_type = entry[4];
+                let sectors = u32::from_le_bytes(entry[12..16].try_into().unwrap());
+                if partition_type == 0 || sectors == 0 {
+                    return None;
+                }
+                Some(PartitionEntry {
+                    bootable: entry[0] & 0x80 != 0,
+                    partition_type,
+                    start_lba: u32::from_le_bytes(entry[8..12].try_into().unwrap()),
+                    sectors,
+                })
+            }),
+        })
+    }
+
+    /// Reads and parses LBA 0
+    pub async fn read<D: Disk<Address = u64>>(disk: &mut D) -> Result<Self, MbrError<D::Error>> {
+        let mut sector = [0; BLOCK_SIZE];
+        disk.read(0, &mut sector).await.map_err(MbrError::Disk)?;
+        Self::parse(&sector).ok_or(MbrError::NoMbr)
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MbrError<E> {
+    Disk(E),
+    /// The first sector doesn't have the `0x55 0xAA` signature, so the disk probably isn't partitioned
+    NoMbr,
+}
+
+/// A partition of a [`Disk`], from [`PartitionEntry::open`]
+pub type PartitionDisk<'a, D> = SubDisk<&'a mut D>;
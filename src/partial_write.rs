@@ -0,0 +1,125 @@
+use core::fmt::Debug;
+
+use embassy_embedded_hal::SetConfig;
+use embedded_hal_async::delay::DelayNs;
+
+use crate::{BLOCK_SIZE, CardDetect, ChipSelect, Error, SdCardDisk, SharedSpiBus};
+
+/// Where the data of a transfer is.
+/// The card's block can't be passed as a slice, since the card is borrowed for the transfer.
+#[derive(Clone, Copy)]
+pub(crate) enum TransferBuffer<B> {
+    Caller(B),
+    /// The block kept in the [`crate::SpiSdCard`] for partial writes
+    CardBlock,
+}
+
+impl<B: AsRef<[u8]>> TransferBuffer<B> {
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Caller(buffer) => buffer.as_ref().len(),
+            Self::CardBlock => BLOCK_SIZE,
+        }
+    }
+}
+
+impl TransferBuffer<&mut [u8]> {
+    pub fn reborrow(&mut self) -> TransferBuffer<&mut [u8]> {
+        match self {
+            Self::Caller(buffer) => TransferBuffer::Caller(buffer),
+            Self::CardBlock => TransferBuffer::CardBlock,
+        }
+    }
+
+    pub fn shared(&self) -> TransferBuffer<&[u8]> {
+        match self {
+            Self::Caller(buffer) => TransferBuffer::Caller(buffer),
+            Self::CardBlock => TransferBuffer::CardBlock,
+        }
+    }
+
+    pub fn get<'s>(&'s mut self, card_block: &'s mut [u8; BLOCK_SIZE]) -> &'s mut [u8] {
+        match self {
+            Self::Caller(buffer) => buffer,
+            Self::CardBlock => card_block,
+        }
+    }
+}
+
+impl<'b> TransferBuffer<&'b [u8]> {
+    pub fn get(&self, card_block: &'b [u8; BLOCK_SIZE]) -> &'b [u8] {
+        match self {
+            Self::Caller(buffer) => buffer,
+            Self::CardBlock => card_block,
+        }
+    }
+}
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect> SdCardDisk<'_, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// Writes `buffer` at any byte address, like [`crate::Disk::write`].
+    /// The blocks at the start and end that are only partly written are read into `scratch`, changed, and written back.
+    /// The blocks in between are written directly from `buffer`.
+    ///
+    /// [`crate::Disk::write`] does the same with a block kept in the [`crate::SpiSdCard`].
+    /// A partly written block isn't written atomically: if the write fails, the rest of the block keeps its old data.
+    pub async fn write_partial(
+        &mut self,
+        start: u64,
+        buffer: &[u8],
+        scratch: &mut [u8; BLOCK_SIZE],
+    ) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        self.write_unaligned(start, buffer, TransferBuffer::Caller(scratch))
+            .await
+    }
+
+    /// Writes `buffer` at any byte address, reading, changing, and writing back the partly written blocks in `block`
+    pub(crate) async fn write_unaligned(
+        &mut self,
+        start: u64,
+        buffer: &[u8],
+        mut block: TransferBuffer<&mut [u8]>,
+    ) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        let block_size = BLOCK_SIZE as u64;
+        let end = start + buffer.len() as u64;
+        let mut address = start;
+        let mut result = Ok(());
+        while address < end {
+            let block_start = address - address % block_size;
+            let offset = (address - block_start) as usize;
+            let buffer_offset = (address - start) as usize;
+            if offset == 0 && end - address >= block_size {
+                // Write every whole block at once
+                let len = ((end - address) / block_size * block_size) as usize;
+                result = self
+                    .write_blocks(
+                        address,
+                        TransferBuffer::Caller(&buffer[buffer_offset..buffer_offset + len]),
+                    )
+                    .await;
+                address += len as u64;
+            } else {
+                let len = (BLOCK_SIZE - offset).min((end - address) as usize);
+                result = self.read_into(block_start, block.reborrow(), None).await;
+                if result.is_err() {
+                    break;
+                }
+                block.get(&mut self.sd_card.block)[offset..offset + len]
+                    .copy_from_slice(&buffer[buffer_offset..buffer_offset + len]);
+                result = self.write_blocks(block_start, block.shared()).await;
+                address += len as u64;
+            }
+            if result.is_err() {
+                break;
+            }
+        }
+        if let TransferBuffer::CardBlock = block {
+            self.sd_card.zeroize_block();
+        }
+        result
+    }
+}
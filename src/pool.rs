@@ -0,0 +1,95 @@
+use core::fmt::Debug;
+
+use embassy_embedded_hal::SetConfig;
+use embedded_hal_async::delay::DelayNs;
+
+use crate::{CardDetect, CardType, ChipSelect, Error, SdCardDisk, SharedSpiBus, SpiSdCard};
+
+/// Several cards with their own CS pins on one [`SharedSpiBus`], such as a logger with 2 card slots.
+///
+/// A card that was never initialized can still be in SD mode, where it doesn't release MISO properly, and it treats anything on the bus while its CS is low as a command.
+/// So [`SdCardPool::init_all`] deselects every card first, and then initializes them one at a time.
+/// After that, every operation of a [`SdCardDisk`] locks the bus from the command until the response and data, so the disks can be used from different tasks at the same time.
+pub struct SdCardPool<Spi, Cs, Delayer, Cd, const N: usize>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+    Cs: ChipSelect,
+{
+    cards: [SpiSdCard<Spi, Cs, Delayer, Cd>; N],
+    card_types: [Option<CardType>; N],
+}
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect, const N: usize>
+    SdCardPool<Spi, Cs, Delayer, Cd, N>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// The cards must all be on the same bus
+    pub fn new(cards: [SpiSdCard<Spi, Cs, Delayer, Cd>; N]) -> Self {
+        Self {
+            cards,
+            card_types: [None; N],
+        }
+    }
+
+    pub fn into_inner(self) -> [SpiSdCard<Spi, Cs, Delayer, Cd>; N] {
+        self.cards
+    }
+
+    /// Deselects every card, and then initializes them in order.
+    /// A card that fails to initialize (including a missing one) is `None`, and its error is reported to its [error hook](SpiSdCard::set_error_hook).
+    /// Fails if a CS pin can't be set, because then no card can be used safely.
+    pub async fn init_all(&mut self) -> Result<[Option<CardType>; N], Error<Spi::Bus, Cs::Error>> {
+        self.deselect_all().await?;
+        for (sd_card, card_type) in self.cards.iter_mut().zip(&mut self.card_types) {
+            *card_type = sd_card.init_card().await.ok().map(|disk| disk.card_type());
+        }
+        info!(
+            "initialized {} of {} cards",
+            self.card_types.iter().flatten().count(),
+            N
+        );
+        Ok(self.card_types)
+    }
+
+    /// Initializes one card again, such as after it was swapped, after deselecting the others
+    pub async fn init(
+        &mut self,
+        index: usize,
+    ) -> Result<SdCardDisk<'_, Spi, Cs, Delayer, Cd>, Error<Spi::Bus, Cs::Error>> {
+        self.card_types[index] = None;
+        self.deselect_all().await?;
+        let disk = self.cards[index].init_card().await?;
+        self.card_types[index] = Some(disk.card_type());
+        Ok(disk)
+    }
+
+    /// The card at `index`, if it was initialized
+    pub fn disk(&mut self, index: usize) -> Option<SdCardDisk<'_, Spi, Cs, Delayer, Cd>> {
+        let card_type = self.card_types[index]?;
+        Some(SdCardDisk::new(&mut self.cards[index], card_type))
+    }
+
+    /// Every card that was initialized, to use them at the same time
+    pub fn disks(&mut self) -> [Option<SdCardDisk<'_, Spi, Cs, Delayer, Cd>>; N] {
+        let mut card_types = self.card_types.into_iter();
+        self.cards.each_mut().map(|sd_card| {
+            card_types
+                .next()
+                .flatten()
+                .map(|card_type| SdCardDisk::new(sd_card, card_type))
+        })
+    }
+
+    async fn deselect_all(&mut self) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        for sd_card in &mut self.cards {
+            sd_card.cs.deselect().await.map_err(Error::CsPin)?;
+            sd_card.cs_selected = false;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,88 @@
+use core::{fmt::Debug, ops::DerefMut};
+
+use embassy_embedded_hal::SetConfig;
+use embedded_hal_async::delay::DelayNs;
+
+use crate::{
+    CardCommand3Error, CardDetect, ChipSelect, EXPECTED_BYTES_UNTIL_RESPONSE, Error, ErrorContext,
+    Ocr, SdCardDisk, SdCommand, SharedSpiBus, SpiSdCard, card_command, report_error,
+};
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect> SpiSdCard<Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// Reads the OCR with `CMD58` until its power up status bit is set, since the `CCS` bit isn't valid before that.
+    /// Polls as often as `ACMD41` does, for up to [`crate::Timeouts::init`]. The card must be selected.
+    pub(crate) async fn read_ocr_powered_up(
+        &mut self,
+        spi: &mut Spi::Bus,
+    ) -> Result<Ocr, Error<Spi::Bus, Cs::Error>> {
+        let mut attempt_number = 0;
+        let start_time = (self.config.clock)();
+        loop {
+            let response = card_command(
+                spi,
+                &mut self.scratch,
+                &self.config,
+                SdCommand::ReadOcr,
+                EXPECTED_BYTES_UNTIL_RESPONSE,
+                self.config.timeouts.command,
+                None,
+            )
+            .await
+            .map_err(|e| match e {
+                CardCommand3Error::Spi(e) => Error::SpiBus(e),
+                CardCommand3Error::ReceiveResponseTimeout(_) => Error::GetOcrFailed,
+                CardCommand3Error::NothingToTransfer => Error::NothingToTransfer,
+                CardCommand3Error::UnexpectedTrailingData(byte) => {
+                    Error::UnexpectedTrailingData(byte)
+                }
+                _ => unreachable!(),
+            })?;
+            let r1 = self.config.init_r1(response[0]);
+            if !r1.is_empty() {
+                return Err(Error::GetOcrFailed);
+            }
+            let ocr = Ocr::from_bits_retain(u32::from_be_bytes(response[1..5].try_into().unwrap()));
+            if ocr.is_powered_up() {
+                return Ok(ocr);
+            }
+            attempt_number += 1;
+            // The attempt limit is a backstop in case the clock doesn't advance
+            if attempt_number == self.config.acmd41_retry.max_attempts
+                || start_time.elapsed(self.config.clock) > self.config.timeouts.init
+            {
+                warn!(
+                    "card is not powered up after initializing. OCR: 0x{:08X}",
+                    ocr.bits()
+                );
+                return Err(Error::PowerUpTimeout);
+            }
+            self.config.acmd41_retry.wait(&mut self.delayer).await;
+        }
+    }
+}
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect> SdCardDisk<'_, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// Reads the OCR until the card says that it finished powering up, and returns it.
+    /// Only then is [`Ocr::supports_sdhc_or_sdxc`] `Some`. Init already waits for this, so an initialized card returns right away.
+    pub async fn wait_powered_up(&mut self) -> Result<Ocr, Error<Spi::Bus, Cs::Error>> {
+        let result = self.wait_powered_up_inner().await;
+        report_error(self.sd_card.on_error, ErrorContext::WaitPoweredUp, result)
+    }
+
+    async fn wait_powered_up_inner(&mut self) -> Result<Ocr, Error<Spi::Bus, Cs::Error>> {
+        let mut spi = self.begin_operation().await?;
+        let result = self.sd_card.read_ocr_powered_up(spi.deref_mut()).await;
+        self.end_operation(spi).await?;
+        result
+    }
+}
@@ -0,0 +1,154 @@
+use core::fmt::Debug;
+
+use embassy_embedded_hal::SetConfig;
+use embedded_hal_async::delay::DelayNs;
+
+use crate::{BLOCK_SIZE, CardDetect, ChipSelect, Disk, Error, SdCardDisk, SharedSpiBus};
+
+/// What a card needs to have to be accepted by [`SdCardDisk::qualify_card`]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct QualificationRequirements {
+    /// Minimum capacity in bytes
+    pub min_capacity: u64,
+    /// Minimum speed class, from the SD Status (0, 2, 4, 6, or 10).
+    /// MMC cards don't have a speed class, so they only pass if this is 0.
+    pub min_speed_class: u8,
+    /// Minimum measured sequential write speed, in bytes per second
+    pub min_write_speed: u64,
+    /// Maximum number of blocks that can fail their CRC when reading back the written data
+    pub max_crc_errors: u32,
+    /// The first block of the region used for the write test.
+    /// Everything in the region will be overwritten!
+    pub scratch_start_block: u32,
+    /// Number of blocks in the region used for the write test
+    pub scratch_blocks: u32,
+}
+
+/// The measurements from [`SdCardDisk::qualify_card`]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct QualificationReport {
+    /// Capacity in bytes
+    pub capacity: u64,
+    /// `None` if the card reported a reserved speed class, or is an MMC card without an SD Status
+    pub speed_class: Option<u8>,
+    /// Measured sequential write speed, in bytes per second.
+    /// The scratch region is written with a single `CMD25`, or one `CMD24` per block if [`SdCardDisk::enable_write_multiple`] is off.
+    pub write_speed: u64,
+    /// Number of blocks that failed their CRC when reading back the written data
+    pub crc_errors: u32,
+    /// Number of blocks that were read back with different data than what was written
+    pub mismatched_blocks: u32,
+    /// `true` if the card meets all of the requirements
+    pub passed: bool,
+}
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect> SdCardDisk<'_, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// Checks if the card is suitable for your product, for example to reject cards at boot.
+    /// This measures the sequential write speed by writing to the scratch region, so **the data in the scratch region will be lost**.
+    ///
+    /// Errors other than CRC errors while reading back the scratch region are returned as errors, not as a failed qualification.
+    pub async fn qualify_card(
+        &mut self,
+        requirements: &QualificationRequirements,
+    ) -> Result<QualificationReport, Error<Spi::Bus, Cs::Error>> {
+        let capacity = self.capacity().await?;
+        let speed_class = if self.card_type.is_mmc() {
+            None
+        } else {
+            self.sd_status().await?.speed_class()
+        };
+
+        let scratch_start = u64::from(requirements.scratch_start_block) * BLOCK_SIZE as u64;
+        let mut block = [0; BLOCK_SIZE];
+        let before = (self.sd_card.config.clock)();
+        self.write_test_pattern(
+            requirements.scratch_start_block,
+            requirements.scratch_blocks,
+        )
+        .await?;
+        let elapsed_us = before.elapsed(self.sd_card.config.clock).as_micros().max(1);
+        let write_speed =
+            u64::from(requirements.scratch_blocks) * BLOCK_SIZE as u64 * 1_000_000 / elapsed_us;
+
+        let mut crc_errors = 0;
+        let mut mismatched_blocks = 0;
+        let mut expected = [0; BLOCK_SIZE];
+        for i in 0..requirements.scratch_blocks {
+            fill_test_pattern(&mut expected, i);
+            match self
+                .read(scratch_start + u64::from(i) * BLOCK_SIZE as u64, &mut block)
+                .await
+            {
+                Ok(()) => {
+                    if block != expected {
+                        mismatched_blocks += 1;
+                    }
+                }
+                Err(Error::ReadInvalidCrc) => crc_errors += 1,
+                Err(e) => return Err(e),
+            }
+        }
+
+        let passed = capacity >= requirements.min_capacity
+            && (requirements.min_speed_class == 0
+                || speed_class
+                    .is_some_and(|speed_class| speed_class >= requirements.min_speed_class))
+            && write_speed >= requirements.min_write_speed
+            && crc_errors <= requirements.max_crc_errors
+            && mismatched_blocks == 0;
+        Ok(QualificationReport {
+            capacity,
+            speed_class,
+            write_speed,
+            crc_errors,
+            mismatched_blocks,
+            passed,
+        })
+    }
+
+    /// Writes the test pattern to `blocks` blocks from `start_block`, as one multiple block write unless it's disabled
+    async fn write_test_pattern(
+        &mut self,
+        start_block: u32,
+        blocks: u32,
+    ) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        let mut block = [0; BLOCK_SIZE];
+        if !self.enable_write_multiple || blocks <= 1 {
+            for i in 0..blocks {
+                fill_test_pattern(&mut block, i);
+                self.write(u64::from(start_block + i) * BLOCK_SIZE as u64, &block)
+                    .await?;
+            }
+            return Ok(());
+        }
+
+        let mut stream = self.open_write_stream(start_block).await?;
+        let mut result = Ok(());
+        for i in 0..blocks {
+            fill_test_pattern(&mut block, i);
+            result = stream.write_block(&block).await;
+            if result.is_err() {
+                break;
+            }
+        }
+        // The stream has to be closed even if a block was rejected
+        let closed = stream.close().await;
+        result.and(closed)
+    }
+}
+
+/// A pattern that is different for every block, so that we can detect blocks being written to the wrong address
+fn fill_test_pattern(block: &mut [u8; BLOCK_SIZE], block_index: u32) {
+    let seed = block_index.to_le_bytes();
+    for (i, byte) in block.iter_mut().enumerate() {
+        *byte = (i as u8) ^ seed[i % seed.len()];
+    }
+}
@@ -0,0 +1,139 @@
+use core::ops::Range;
+
+use crate::{BLOCK_SIZE, Disk};
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RamDiskError {
+    /// The range goes past the end of the disk
+    OutOfRange,
+}
+
+/// Returns the range of `data` that a read or write at `start` covers
+fn range(data: &[u8], start: u64, len: usize) -> Result<Range<usize>, RamDiskError> {
+    let start = usize::try_from(start).map_err(|_| RamDiskError::OutOfRange)?;
+    let end = start.checked_add(len).ok_or(RamDiskError::OutOfRange)?;
+    if end > data.len() {
+        return Err(RamDiskError::OutOfRange);
+    }
+    Ok(start..end)
+}
+
+fn read(data: &[u8], start: u64, buffer: &mut [u8]) -> Result<(), RamDiskError> {
+    buffer.copy_from_slice(&data[range(data, start, buffer.len())?]);
+    Ok(())
+}
+
+fn write(data: &mut [u8], start: u64, buffer: &[u8]) -> Result<(), RamDiskError> {
+    let range = range(data, start, buffer.len())?;
+    data[range].copy_from_slice(buffer);
+    Ok(())
+}
+
+fn erase(data: &mut [u8], start: u64, end: u64) -> Result<(), RamDiskError> {
+    let len = usize::try_from(end.saturating_sub(start)).map_err(|_| RamDiskError::OutOfRange)?;
+    let range = range(data, start, len)?;
+    // Erased flash reads as 1s, and filling it makes code that reads erased data stand out
+    data[range].fill(0xFF);
+    Ok(())
+}
+
+/// A [`Disk`] stored in memory, so that code written for [`Disk`] (partition tables, filesystems, caches) can be tested without a card.
+/// It uses the same [`BLOCK_SIZE`] as an SD card. Erasing fills the range with `0xFF`.
+pub struct RamDisk<const N: usize> {
+    data: [u8; N],
+}
+
+impl<const N: usize> RamDisk<N> {
+    /// Creates a disk filled with 0s
+    pub const fn new() -> Self {
+        Self { data: [0; N] }
+    }
+
+    pub const fn from_bytes(data: [u8; N]) -> Self {
+        Self { data }
+    }
+
+    pub fn as_bytes(&self) -> &[u8; N] {
+        &self.data
+    }
+
+    pub fn as_bytes_mut(&mut self) -> &mut [u8; N] {
+        &mut self.data
+    }
+}
+
+impl<const N: usize> Default for RamDisk<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Disk for RamDisk<N> {
+    type Address = u64;
+    type Error = RamDiskError;
+    const BLOCK_SIZE: usize = BLOCK_SIZE;
+
+    async fn read(&mut self, start: u64, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        read(&self.data, start, buffer)
+    }
+
+    async fn write(&mut self, start: u64, buffer: &[u8]) -> Result<(), Self::Error> {
+        write(&mut self.data, start, buffer)
+    }
+
+    async fn erase(&mut self, start: u64, end: u64) -> Result<(), Self::Error> {
+        erase(&mut self.data, start, end)
+    }
+}
+
+/// A [`RamDisk`] on the heap, for disks that are too big for the stack or whose size is only known at runtime
+#[cfg(feature = "alloc")]
+pub struct VecRamDisk {
+    data: alloc::vec::Vec<u8>,
+}
+
+#[cfg(feature = "alloc")]
+impl VecRamDisk {
+    /// Creates a disk of `len` bytes filled with 0s
+    pub fn new(len: usize) -> Self {
+        Self {
+            data: alloc::vec![0; len],
+        }
+    }
+
+    pub fn from_vec(data: alloc::vec::Vec<u8>) -> Self {
+        Self { data }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    pub fn into_vec(self) -> alloc::vec::Vec<u8> {
+        self.data
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Disk for VecRamDisk {
+    type Address = u64;
+    type Error = RamDiskError;
+    const BLOCK_SIZE: usize = BLOCK_SIZE;
+
+    async fn read(&mut self, start: u64, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        read(&self.data, start, buffer)
+    }
+
+    async fn write(&mut self, start: u64, buffer: &[u8]) -> Result<(), Self::Error> {
+        write(&mut self.data, start, buffer)
+    }
+
+    async fn erase(&mut self, start: u64, end: u64) -> Result<(), Self::Error> {
+        erase(&mut self.data, start, end)
+    }
+}
@@ -0,0 +1,142 @@
+use crate::{BLOCK_SIZE, Disk};
+
+/// Reads ahead after sequential reads, and serves the reads after them from RAM.
+/// Walking a FAT filesystem does many small reads of consecutive blocks, and without this each one costs a whole command.
+///
+/// When a read isn't cached, the blocks it needs are read. If it starts where the last read ended (or in its last block),
+/// `BLOCKS` blocks are read from its first block instead, so the next reads are already cached.
+/// Reads that need more than `BLOCKS` blocks go straight to the disk.
+///
+/// Writes go straight to the disk, and also update the blocks that are cached, so the cache never has stale data
+/// as long as the disk isn't written to except through this.
+pub struct CachedDisk<D, const BLOCKS: usize> {
+    disk: D,
+    blocks: [[u8; BLOCK_SIZE]; BLOCKS],
+    /// The first block that is cached
+    first_block: u64,
+    /// How many blocks from `first_block` are cached
+    cached: usize,
+    /// The block after the end of the last read, to tell if the next read is sequential
+    next_block: u64,
+}
+
+impl<D: Disk<Address = u64>, const BLOCKS: usize> CachedDisk<D, BLOCKS> {
+    pub const fn new(disk: D) -> Self {
+        Self {
+            disk,
+            blocks: [[0; BLOCK_SIZE]; BLOCKS],
+            first_block: 0,
+            cached: 0,
+            next_block: 0,
+        }
+    }
+
+    /// Forgets the cached blocks, for example after the disk was written to without going through this
+    pub fn invalidate(&mut self) {
+        self.cached = 0;
+    }
+
+    pub fn into_inner(self) -> D {
+        self.disk
+    }
+
+    /// `true` if all of the blocks from `first_block` to `end_block` (exclusive) are cached
+    fn contains(&self, first_block: u64, end_block: u64) -> bool {
+        first_block >= self.first_block && end_block <= self.first_block + self.cached as u64
+    }
+
+    /// Reads `count` blocks from `first_block` into the cache, of which only the first `needed` are needed now
+    async fn fill(
+        &mut self,
+        first_block: u64,
+        count: usize,
+        needed: usize,
+    ) -> Result<(), D::Error> {
+        self.cached = 0;
+        self.first_block = first_block;
+        let address = first_block * BLOCK_SIZE as u64;
+        let data = self.blocks.as_flattened_mut();
+        if count > needed
+            && self
+                .disk
+                .read(address, &mut data[..count * BLOCK_SIZE])
+                .await
+                .is_ok()
+        {
+            self.cached = count;
+            return Ok(());
+        }
+        // Reading ahead can go past the end of the disk, so only read what's needed
+        self.disk
+            .read(address, &mut data[..needed * BLOCK_SIZE])
+            .await?;
+        self.cached = needed;
+        Ok(())
+    }
+}
+
+impl<D: Disk<Address = u64>, const BLOCKS: usize> Disk for CachedDisk<D, BLOCKS> {
+    type Address = u64;
+    type Error = D::Error;
+    const BLOCK_SIZE: usize = D::BLOCK_SIZE;
+
+    fn block_size(&self) -> usize {
+        self.disk.block_size()
+    }
+
+    fn erase_unit_size(&self) -> Option<usize> {
+        self.disk.erase_unit_size()
+    }
+
+    async fn read(&mut self, start: u64, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        let block_size = BLOCK_SIZE as u64;
+        let first_block = start / block_size;
+        let end_block = (start + buffer.len() as u64).div_ceil(block_size);
+        let sequential = first_block == self.next_block || first_block + 1 == self.next_block;
+        self.next_block = end_block;
+        if !self.contains(first_block, end_block) {
+            let needed = (end_block - first_block) as usize;
+            if needed > BLOCKS {
+                return self.disk.read(start, buffer).await;
+            }
+            let count = if sequential { BLOCKS } else { needed };
+            self.fill(first_block, count, needed).await?;
+        }
+        let offset = (start - self.first_block * block_size) as usize;
+        buffer.copy_from_slice(&self.blocks.as_flattened()[offset..offset + buffer.len()]);
+        Ok(())
+    }
+
+    async fn write(&mut self, start: u64, buffer: &[u8]) -> Result<(), Self::Error> {
+        if let Err(e) = self.disk.write(start, buffer).await {
+            // Some of the blocks could have been written
+            self.invalidate();
+            return Err(e);
+        }
+        let cache_start = self.first_block * BLOCK_SIZE as u64;
+        let cache_end = cache_start + (self.cached * BLOCK_SIZE) as u64;
+        let overlap_start = start.max(cache_start);
+        let overlap_end = (start + buffer.len() as u64).min(cache_end);
+        if overlap_start < overlap_end {
+            self.blocks.as_flattened_mut()
+                [(overlap_start - cache_start) as usize..(overlap_end - cache_start) as usize]
+                .copy_from_slice(
+                    &buffer[(overlap_start - start) as usize..(overlap_end - start) as usize],
+                );
+        }
+        Ok(())
+    }
+
+    async fn erase(&mut self, start: u64, end: u64) -> Result<(), Self::Error> {
+        // Erased data can read as anything
+        self.invalidate();
+        self.disk.erase(start, end).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.disk.flush().await
+    }
+}
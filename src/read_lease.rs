@@ -0,0 +1,213 @@
+use core::{cell::RefCell, cell::UnsafeCell, ops::Deref};
+
+use embassy_sync::{
+    blocking_mutex::{self, raw::RawMutex},
+    mutex::Mutex,
+};
+
+use crate::{BLOCK_SIZE, Disk};
+
+#[derive(Clone, Copy)]
+struct Slot {
+    /// `None` if the slot is empty, or if the data is currently being read into it
+    block: Option<u64>,
+    /// Number of leases referencing this slot
+    pins: usize,
+}
+
+impl Slot {
+    const EMPTY: Self = Self {
+        block: None,
+        pins: 0,
+    };
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ReadLeaseError<E> {
+    Disk(E),
+    /// The range is bigger than the number of slots in the cache
+    TooManyBlocks,
+    /// There is no run of consecutive slots that are not pinned by other leases
+    NoFreeSlots,
+}
+
+/// A block cache that can be shared between tasks.
+/// Tasks lease a range of blocks, which pins them in the cache and gives a `&[u8]` view of them.
+/// The blocks stay in the cache until every lease referencing them is dropped,
+/// so multiple tasks can parse the same data (for example a directory) without each copying it.
+///
+/// The cache only reads from the disk. Don't write to the disk while the cache is being used, or leases will return stale data.
+pub struct ReadLeaseCache<M: RawMutex, D, const SLOTS: usize> {
+    disk: Mutex<M, D>,
+    slots: blocking_mutex::Mutex<M, RefCell<[Slot; SLOTS]>>,
+    /// Slot `i`'s data is only written while the slot is pinned and has no block, so no lease can be reading it
+    data: UnsafeCell<[[u8; BLOCK_SIZE]; SLOTS]>,
+}
+
+// Safety: slot data is only mutated by the task that pinned an empty slot, and only shared after it is loaded
+unsafe impl<M: RawMutex + Sync, D: Send, const SLOTS: usize> Sync for ReadLeaseCache<M, D, SLOTS> {}
+
+impl<M: RawMutex, D: Disk<Address = u64>, const SLOTS: usize> ReadLeaseCache<M, D, SLOTS> {
+    pub const fn new(disk: D) -> Self {
+        Self {
+            disk: Mutex::new(disk),
+            slots: blocking_mutex::Mutex::new(RefCell::new([Slot::EMPTY; SLOTS])),
+            data: UnsafeCell::new([[0; BLOCK_SIZE]; SLOTS]),
+        }
+    }
+
+    /// Pins `count` blocks starting at `start_block` (in units of [`BLOCK_SIZE`]), reading them from the disk if they aren't cached yet.
+    pub async fn lease(
+        &self,
+        start_block: u64,
+        count: usize,
+    ) -> Result<ReadLease<'_, M, D, SLOTS>, ReadLeaseError<D::Error>> {
+        if count > SLOTS {
+            return Err(ReadLeaseError::TooManyBlocks);
+        }
+        if count == 0 {
+            return Ok(ReadLease {
+                cache: self,
+                first_slot: 0,
+                count: 0,
+            });
+        }
+        if let Some(first_slot) = self.pin_cached(start_block, count) {
+            return Ok(ReadLease {
+                cache: self,
+                first_slot,
+                count,
+            });
+        }
+
+        let mut disk = self.disk.lock().await;
+        // Another task could have read the blocks while we were waiting for the disk
+        if let Some(first_slot) = self.pin_cached(start_block, count) {
+            return Ok(ReadLease {
+                cache: self,
+                first_slot,
+                count,
+            });
+        }
+        let first_slot = self.slots.lock(|slots| {
+            let mut slots = slots.borrow_mut();
+            let first_slot = (0..=SLOTS - count)
+                .find(|&i| slots[i..i + count].iter().all(|slot| slot.pins == 0))?;
+            for slot in &mut slots[first_slot..first_slot + count] {
+                *slot = Slot {
+                    block: None,
+                    pins: 1,
+                };
+            }
+            Some(first_slot)
+        });
+        let first_slot = first_slot.ok_or(ReadLeaseError::NoFreeSlots)?;
+        // Empties the slots again if the read fails, or if this future is dropped before it finishes
+        let loading = LoadingSlots {
+            cache: self,
+            first_slot,
+            count,
+        };
+
+        // Safety: we pinned these slots while they had no block, so nothing else can access them
+        let buffer = unsafe {
+            core::slice::from_raw_parts_mut(
+                (self.data.get() as *mut [u8; BLOCK_SIZE]).add(first_slot) as *mut u8,
+                count * BLOCK_SIZE,
+            )
+        };
+        disk.read(start_block * BLOCK_SIZE as u64, buffer)
+            .await
+            .map_err(ReadLeaseError::Disk)?;
+        loading.loaded(start_block);
+
+        Ok(ReadLease {
+            cache: self,
+            first_slot,
+            count,
+        })
+    }
+
+    /// Pins the slots if the range is already in consecutive slots
+    fn pin_cached(&self, start_block: u64, count: usize) -> Option<usize> {
+        self.slots.lock(|slots| {
+            let mut slots = slots.borrow_mut();
+            let first_slot = (0..=SLOTS - count).find(|&i| {
+                slots[i..i + count]
+                    .iter()
+                    .zip(start_block..)
+                    .all(|(slot, block)| slot.block == Some(block))
+            })?;
+            for slot in &mut slots[first_slot..first_slot + count] {
+                slot.pins += 1;
+            }
+            Some(first_slot)
+        })
+    }
+}
+
+/// Slots that are pinned without a block while data is read into them
+struct LoadingSlots<'a, M: RawMutex, D, const SLOTS: usize> {
+    cache: &'a ReadLeaseCache<M, D, SLOTS>,
+    first_slot: usize,
+    count: usize,
+}
+
+impl<M: RawMutex, D, const SLOTS: usize> LoadingSlots<'_, M, D, SLOTS> {
+    /// Gives the slots their blocks, keeping them pinned for the lease
+    fn loaded(self, start_block: u64) {
+        self.cache.slots.lock(|slots| {
+            let mut slots = slots.borrow_mut();
+            for (slot, block) in slots[self.first_slot..self.first_slot + self.count]
+                .iter_mut()
+                .zip(start_block..)
+            {
+                slot.block = Some(block);
+            }
+        });
+        core::mem::forget(self);
+    }
+}
+
+impl<M: RawMutex, D, const SLOTS: usize> Drop for LoadingSlots<'_, M, D, SLOTS> {
+    fn drop(&mut self) {
+        self.cache.slots.lock(|slots| {
+            let mut slots = slots.borrow_mut();
+            slots[self.first_slot..self.first_slot + self.count].fill(Slot::EMPTY);
+        });
+    }
+}
+
+/// A view of cached blocks. The blocks cannot be evicted until this is dropped.
+pub struct ReadLease<'a, M: RawMutex, D, const SLOTS: usize> {
+    cache: &'a ReadLeaseCache<M, D, SLOTS>,
+    first_slot: usize,
+    count: usize,
+}
+
+impl<M: RawMutex, D, const SLOTS: usize> Deref for ReadLease<'_, M, D, SLOTS> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // Safety: the slots are loaded and pinned, so they won't be written to while we exist
+        unsafe {
+            core::slice::from_raw_parts(
+                (self.cache.data.get() as *const [u8; BLOCK_SIZE]).add(self.first_slot)
+                    as *const u8,
+                self.count * BLOCK_SIZE,
+            )
+        }
+    }
+}
+
+impl<M: RawMutex, D, const SLOTS: usize> Drop for ReadLease<'_, M, D, SLOTS> {
+    fn drop(&mut self) {
+        self.cache.slots.lock(|slots| {
+            let mut slots = slots.borrow_mut();
+            for slot in &mut slots[self.first_slot..self.first_slot + self.count] {
+                slot.pins -= 1;
+            }
+        });
+    }
+}
@@ -0,0 +1,73 @@
+use core::fmt::Debug;
+
+use embassy_embedded_hal::SetConfig;
+use embedded_hal_async::delay::DelayNs;
+
+use crate::{CardDetect, ChipSelect, Error, ErrorContext, SdCardDisk, SharedSpiBus, report_error};
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect> SdCardDisk<'_, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// Enables automatic re-initialization, which reads the card's CID to remember which card it is.
+    ///
+    /// After that, if the card stops responding during a read or write (usually because it was removed),
+    /// the next read or write initializes the card again before doing anything else.
+    /// If a card with a different CID was inserted, that operation fails with [`Error::CardChanged`], and the ones after it use the new card.
+    pub async fn enable_auto_reinit(&mut self) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        self.known_cid = Some(self.cid().await?);
+        self.card_lost = false;
+        Ok(())
+    }
+
+    pub fn disable_auto_reinit(&mut self) {
+        self.known_cid = None;
+        self.card_lost = false;
+    }
+
+    /// `true` if the card stopped responding and will be initialized again before the next read or write
+    pub fn is_card_lost(&self) -> bool {
+        self.card_lost
+    }
+
+    pub(crate) fn check_card_lost<T>(&mut self, result: &Result<T, Error<Spi::Bus, Cs::Error>>) {
+        if self.known_cid.is_some()
+            && let Err(e) = result
+            && e.is_no_response()
+        {
+            if !self.sd_card.spi.is_healthy() {
+                warn!(
+                    "card stopped responding, but other devices on the bus are having errors too"
+                );
+                return;
+            }
+            warn!(
+                "card stopped responding, it will be initialized again before the next operation"
+            );
+            self.card_lost = true;
+        }
+    }
+
+    pub(crate) async fn reinit_if_lost(&mut self) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        if !self.card_lost {
+            return Ok(());
+        }
+        // Initialization locks the bus and drives CS itself, and leaves the card deselected
+        self.transaction_bus = None;
+        // Starts over at 400 kHz, like the first time
+        let result = self.sd_card.init_card_inner().await;
+        self.card_type = report_error(self.sd_card.on_error, ErrorContext::InitCard, result)?;
+        self.csd_write_protected = None;
+        self.erase_unit = None;
+        let cid = self.cid().await?;
+        self.card_lost = false;
+        if self.known_cid.replace(cid) != Some(cid) {
+            info!("a different card was inserted");
+            return Err(Error::CardChanged);
+        }
+        info!("the same card was inserted again");
+        Ok(())
+    }
+}
@@ -0,0 +1,35 @@
+use core::fmt::Debug;
+
+use embassy_embedded_hal::SetConfig;
+use embassy_rp::spi::Config;
+use embedded_hal_async::delay::DelayNs;
+
+use crate::{ChipSelect, SdCardConfig, SharedSpiBus, SpiSdCard};
+
+/// The `embassy_rp::spi::Config` for initializing the card, at 400 kHz.
+/// embassy-rp picks the fastest clock that isn't faster than the config's frequency, so it never goes over.
+pub fn rp_init_config() -> Config {
+    let mut config = Config::default();
+    config.frequency = 400_000;
+    config
+}
+
+/// The `embassy_rp::spi::Config` for after the card is initialized, at 25 MHz
+pub fn rp_run_config() -> Config {
+    let mut config = Config::default();
+    config.frequency = 25_000_000;
+    config
+}
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs> SpiSdCard<Spi, Cs, Delayer>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig<Config = Config>,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// For an `embassy_rp::spi::Spi` on an RP2040 or RP2350, using [`rp_init_config`] and [`rp_run_config`] for the speeds.
+    /// Create the `Spi` with [`rp_init_config`], so the bus starts out slow enough.
+    pub fn new_rp(spi: Spi, cs: Cs, delayer: Delayer, config: SdCardConfig) -> Self {
+        Self::new_with_config(spi, cs, delayer, rp_init_config(), rp_run_config(), config)
+    }
+}
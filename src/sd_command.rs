@@ -0,0 +1,216 @@
+use crate::{
+    Command8Argument, Command59Argument, CommandA41Argument, Ocr, R1, R2, R7, VoltageAccpted,
+    format_command,
+};
+
+/// The format of the response the card sends after a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ResponseType {
+    R1,
+    /// R1, followed by a busy signal
+    R1b,
+    R2,
+    R3,
+    R7,
+}
+
+impl ResponseType {
+    /// The number of bytes in the response
+    pub const fn size(&self) -> usize {
+        match self {
+            Self::R1 | Self::R1b => size_of::<R1>(),
+            Self::R2 => size_of::<R2>(),
+            // `R3` has padding between the R1 and the OCR
+            Self::R3 => size_of::<R1>() + size_of::<Ocr>(),
+            Self::R7 => size_of::<R7>(),
+        }
+    }
+}
+
+/// The longest response is an R3 or R7
+pub const MAX_RESPONSE_LEN: usize = ResponseType::R7.size();
+
+/// What happens after the response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DataPhase {
+    None,
+    /// The card is busy until it stops sending `0x00`
+    Busy,
+    /// The card sends one or more data blocks
+    Read,
+    /// We send a data block, and then the card is busy
+    Write,
+}
+
+/// The commands that this driver sends, with their arguments.
+/// Application specific commands (ACMD) must be sent right after [`SdCommand::AppCmd`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SdCommand {
+    /// CMD0
+    GoIdleState,
+    /// CMD1. Only MMC cards use this instead of `ACMD41`.
+    SendOpCond { high_capacity_support: bool },
+    /// CMD8
+    SendIfCond { check_pattern: u8 },
+    /// CMD9
+    SendCsd,
+    /// CMD10
+    SendCid,
+    /// CMD12
+    StopTransmission,
+    /// CMD13
+    SendStatus,
+    /// CMD16
+    SetBlockLen { block_length: u32 },
+    /// CMD17
+    ReadSingleBlock { address: u32 },
+    /// CMD18
+    ReadMultipleBlock { address: u32 },
+    /// CMD24
+    WriteBlock { address: u32 },
+    /// CMD25
+    WriteMultipleBlock { address: u32 },
+    /// CMD32
+    EraseWrBlkStartAddr { address: u32 },
+    /// CMD33
+    EraseWrBlkEndAddr { address: u32 },
+    /// CMD35. MMC cards use this instead of `CMD32`.
+    EraseGroupStart { address: u32 },
+    /// CMD36. MMC cards use this instead of `CMD33`.
+    EraseGroupEnd { address: u32 },
+    /// CMD38
+    Erase,
+    /// CMD55
+    AppCmd,
+    /// CMD58
+    ReadOcr,
+    /// CMD59
+    CrcOnOff { crc_on: bool },
+    /// ACMD13
+    SdStatus,
+    /// ACMD41
+    SdSendOpCond { high_capacity_support: bool },
+    /// ACMD51
+    SendScr,
+}
+
+impl SdCommand {
+    pub fn index(&self) -> u8 {
+        match self {
+            Self::GoIdleState => 0,
+            Self::SendOpCond { .. } => 1,
+            Self::SendIfCond { .. } => 8,
+            Self::SendCsd => 9,
+            Self::SendCid => 10,
+            Self::StopTransmission => 12,
+            Self::SendStatus => 13,
+            Self::SetBlockLen { .. } => 16,
+            Self::ReadSingleBlock { .. } => 17,
+            Self::ReadMultipleBlock { .. } => 18,
+            Self::WriteBlock { .. } => 24,
+            Self::WriteMultipleBlock { .. } => 25,
+            Self::EraseWrBlkStartAddr { .. } => 32,
+            Self::EraseWrBlkEndAddr { .. } => 33,
+            Self::EraseGroupStart { .. } => 35,
+            Self::EraseGroupEnd { .. } => 36,
+            Self::Erase => 38,
+            Self::AppCmd => 55,
+            Self::ReadOcr => 58,
+            Self::CrcOnOff { .. } => 59,
+            Self::SdStatus => 13,
+            Self::SdSendOpCond { .. } => 41,
+            Self::SendScr => 51,
+        }
+    }
+
+    pub fn argument(&self) -> u32 {
+        match *self {
+            Self::SendIfCond { check_pattern } => {
+                let mut argument = Command8Argument(Default::default());
+                argument.set_pcie1_2v_support(false);
+                argument.set_pcie_availability(false);
+                argument.set_voltage_accepted(VoltageAccpted::_2_7V_3_6V.bits());
+                argument.set_check_pattern(check_pattern);
+                argument.0
+            }
+            Self::SetBlockLen { block_length } => block_length,
+            Self::ReadSingleBlock { address }
+            | Self::ReadMultipleBlock { address }
+            | Self::WriteBlock { address }
+            | Self::WriteMultipleBlock { address }
+            | Self::EraseWrBlkStartAddr { address }
+            | Self::EraseWrBlkEndAddr { address }
+            | Self::EraseGroupStart { address }
+            | Self::EraseGroupEnd { address } => address,
+            Self::CrcOnOff { crc_on } => {
+                if crc_on {
+                    Command59Argument::CRC_ON.bits()
+                } else {
+                    Command59Argument::empty().bits()
+                }
+            }
+            Self::SendOpCond {
+                high_capacity_support,
+            }
+            | Self::SdSendOpCond {
+                high_capacity_support,
+            } => {
+                if high_capacity_support {
+                    CommandA41Argument::HCS.bits()
+                } else {
+                    CommandA41Argument::empty().bits()
+                }
+            }
+            Self::GoIdleState
+            | Self::SendCsd
+            | Self::SendCid
+            | Self::StopTransmission
+            | Self::SendStatus
+            | Self::Erase
+            | Self::AppCmd
+            | Self::ReadOcr
+            | Self::SdStatus
+            | Self::SendScr => 0,
+        }
+    }
+
+    pub fn response_type(&self) -> ResponseType {
+        match self {
+            Self::SendIfCond { .. } => ResponseType::R7,
+            Self::ReadOcr => ResponseType::R3,
+            Self::SendStatus | Self::SdStatus => ResponseType::R2,
+            Self::StopTransmission | Self::Erase => ResponseType::R1b,
+            _ => ResponseType::R1,
+        }
+    }
+
+    pub fn data_phase(&self) -> DataPhase {
+        match self {
+            Self::SendCsd
+            | Self::SendCid
+            | Self::ReadSingleBlock { .. }
+            | Self::ReadMultipleBlock { .. }
+            | Self::SdStatus
+            | Self::SendScr => DataPhase::Read,
+            Self::WriteBlock { .. } | Self::WriteMultipleBlock { .. } => DataPhase::Write,
+            Self::StopTransmission | Self::Erase => DataPhase::Busy,
+            _ => DataPhase::None,
+        }
+    }
+
+    /// If this command must be sent right after [`SdCommand::AppCmd`]
+    pub fn is_app_command(&self) -> bool {
+        matches!(
+            self,
+            Self::SdStatus | Self::SdSendOpCond { .. } | Self::SendScr
+        )
+    }
+
+    /// The 6 bytes that get sent to the card
+    pub fn format(&self) -> [u8; 6] {
+        format_command(self.index(), self.argument())
+    }
+}
@@ -0,0 +1,185 @@
+use core::{
+    fmt::Debug,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use embassy_embedded_hal::SetConfig;
+use embassy_sync::{blocking_mutex::raw::RawMutex, channel::Channel, signal::Signal};
+use embedded_hal_async::{delay::DelayNs, spi::SpiBus};
+
+use crate::{BLOCK_SIZE, CardDetect, CardType, ChipSelect, Disk, Error, SdCardDisk, SharedSpiBus};
+
+/// What [`SdCardClient::info`] returns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SdCardInfo {
+    pub card_type: CardType,
+    /// In bytes
+    pub capacity: u64,
+}
+
+// They're in fixed slots either way, so boxing the block wouldn't save anything
+#[allow(clippy::large_enum_variant)]
+enum Request {
+    Read { block: u32 },
+    Write { block: u32, data: [u8; BLOCK_SIZE] },
+    Info,
+}
+
+#[allow(clippy::large_enum_variant)]
+enum Response<Bus, CsError>
+where
+    Bus: SpiBus + SetConfig,
+    <Bus as SetConfig>::ConfigError: Debug,
+{
+    Read(Result<[u8; BLOCK_SIZE], Error<Bus, CsError>>),
+    Write(Result<(), Error<Bus, CsError>>),
+    Info(Result<SdCardInfo, Error<Bus, CsError>>),
+}
+
+/// The id of the request, and the response
+type ResponseSlot<M, Bus, CsError> = Signal<M, (u32, Response<Bus, CsError>)>;
+
+/// Lets many tasks use one card, by sending requests to a task that owns the [`SdCardDisk`] and runs [`SdCardServer::run`].
+///
+/// Every client gets its own slot for the response, so `CLIENTS` is the most tasks that can use the card.
+/// A client waits for the response to its request before sending another one, so the request channel never fills up.
+/// Requests and responses hold a whole block, so each client costs about 2 blocks of RAM.
+pub struct SdCardServer<M: RawMutex, Bus, CsError, const CLIENTS: usize>
+where
+    Bus: SpiBus + SetConfig,
+    <Bus as SetConfig>::ConfigError: Debug,
+{
+    /// The client's slot, the id of the request, and the request
+    requests: Channel<M, (usize, u32, Request), CLIENTS>,
+    responses: [ResponseSlot<M, Bus, CsError>; CLIENTS],
+    clients_taken: AtomicBool,
+}
+
+impl<M: RawMutex, Bus, CsError, const CLIENTS: usize> Default
+    for SdCardServer<M, Bus, CsError, CLIENTS>
+where
+    Bus: SpiBus + SetConfig,
+    <Bus as SetConfig>::ConfigError: Debug,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M: RawMutex, Bus, CsError, const CLIENTS: usize> SdCardServer<M, Bus, CsError, CLIENTS>
+where
+    Bus: SpiBus + SetConfig,
+    <Bus as SetConfig>::ConfigError: Debug,
+{
+    pub const fn new() -> Self {
+        Self {
+            requests: Channel::new(),
+            responses: [const { Signal::new() }; CLIENTS],
+            clients_taken: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns all the clients, to give to the tasks that use the card. `None` if they were already taken.
+    pub fn clients(&self) -> Option<[SdCardClient<'_, M, Bus, CsError, CLIENTS>; CLIENTS]> {
+        if self.clients_taken.swap(true, Ordering::Acquire) {
+            return None;
+        }
+        Some(core::array::from_fn(|slot| SdCardClient {
+            server: self,
+            slot,
+            next_id: 0,
+        }))
+    }
+
+    /// Serves requests from the clients, one at a time. Run this in its own task.
+    pub async fn run<Spi, Cs: ChipSelect<Error = CsError>, Delayer: DelayNs, Cd: CardDetect>(
+        &self,
+        mut disk: SdCardDisk<'_, Spi, Cs, Delayer, Cd>,
+    ) -> !
+    where
+        Spi: SharedSpiBus<u8, Bus = Bus>,
+    {
+        loop {
+            let (slot, id, request) = self.requests.receive().await;
+            let response = match request {
+                Request::Read { block } => {
+                    let mut data = [0; BLOCK_SIZE];
+                    Response::Read(
+                        disk.read(u64::from(block) * BLOCK_SIZE as u64, &mut data)
+                            .await
+                            .map(|()| data),
+                    )
+                }
+                Request::Write { block, data } => Response::Write(
+                    disk.write(u64::from(block) * BLOCK_SIZE as u64, &data)
+                        .await,
+                ),
+                Request::Info => Response::Info(disk.capacity().await.map(|capacity| SdCardInfo {
+                    card_type: disk.card_type(),
+                    capacity,
+                })),
+            };
+            self.responses[slot].signal((id, response));
+        }
+    }
+}
+
+/// A task's handle to a [`SdCardServer`]
+pub struct SdCardClient<'a, M: RawMutex, Bus, CsError, const CLIENTS: usize>
+where
+    Bus: SpiBus + SetConfig,
+    <Bus as SetConfig>::ConfigError: Debug,
+{
+    server: &'a SdCardServer<M, Bus, CsError, CLIENTS>,
+    slot: usize,
+    /// Responses to requests that were cancelled have an older id, so they're skipped
+    next_id: u32,
+}
+
+impl<M: RawMutex, Bus, CsError, const CLIENTS: usize> SdCardClient<'_, M, Bus, CsError, CLIENTS>
+where
+    Bus: SpiBus + SetConfig,
+    <Bus as SetConfig>::ConfigError: Debug,
+{
+    pub async fn read_block(
+        &mut self,
+        block: u32,
+    ) -> Result<[u8; BLOCK_SIZE], Error<Bus, CsError>> {
+        match self.request(Request::Read { block }).await {
+            Response::Read(result) => result,
+            _ => unreachable!(),
+        }
+    }
+
+    pub async fn write_block(
+        &mut self,
+        block: u32,
+        data: &[u8; BLOCK_SIZE],
+    ) -> Result<(), Error<Bus, CsError>> {
+        match self.request(Request::Write { block, data: *data }).await {
+            Response::Write(result) => result,
+            _ => unreachable!(),
+        }
+    }
+
+    pub async fn info(&mut self) -> Result<SdCardInfo, Error<Bus, CsError>> {
+        match self.request(Request::Info).await {
+            Response::Info(result) => result,
+            _ => unreachable!(),
+        }
+    }
+
+    async fn request(&mut self, request: Request) -> Response<Bus, CsError> {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.server.requests.send((self.slot, id, request)).await;
+        loop {
+            let (response_id, response) = self.server.responses[self.slot].wait().await;
+            if response_id == id {
+                return response;
+            }
+        }
+    }
+}
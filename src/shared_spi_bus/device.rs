@@ -0,0 +1,129 @@
+use core::{
+    cell::RefMut,
+    convert::Infallible,
+    fmt::{self, Debug},
+};
+
+use embassy_embedded_hal::SetConfig;
+use embedded_hal::digital::{ErrorType as PinErrorType, OutputPin};
+use embedded_hal_async::{
+    delay::DelayNs,
+    spi::{ErrorType, SpiBus, SpiDevice},
+};
+
+use crate::{ExclusiveSpiBus, SdCardConfig, SharedSpiBus, SpiSdCard};
+
+/// Uses an [`SpiDevice`] as an [`SpiBus`], by doing every transfer in its own transaction.
+/// The device selects the card for each transfer, and deselects it afterwards.
+///
+/// [`SpiDevice`] can't change the speed, so the config does nothing.
+pub struct SpiDeviceBus<D>(D);
+
+/// [`crate::Error`] needs the bus to be [`Debug`], even if the device isn't
+impl<D> Debug for SpiDeviceBus<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SpiDeviceBus").finish_non_exhaustive()
+    }
+}
+
+impl<D> SpiDeviceBus<D> {
+    pub fn into_inner(self) -> D {
+        self.0
+    }
+}
+
+impl<D: SpiDevice> ErrorType for SpiDeviceBus<D> {
+    type Error = D::Error;
+}
+
+impl<D: SpiDevice> SpiBus for SpiDeviceBus<D> {
+    async fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        self.0.read(words).await
+    }
+
+    async fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        self.0.write(words).await
+    }
+
+    async fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+        self.0.transfer(read, write).await
+    }
+
+    async fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        self.0.transfer_in_place(words).await
+    }
+
+    /// Every transaction is already flushed when it ends
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl<D> SetConfig for SpiDeviceBus<D> {
+    type Config = ();
+    type ConfigError = Infallible;
+
+    fn set_config(&mut self, _config: &()) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+/// Holds an [`SpiDevice`] for [`SpiSdCard::new_with_device`].
+/// Like [`ExclusiveSpiBus`], a reference to this is the [`SharedSpiBus`].
+#[derive(Debug)]
+pub struct SpiDeviceCell<D>(ExclusiveSpiBus<SpiDeviceBus<D>>);
+
+impl<D> SpiDeviceCell<D> {
+    pub const fn new(device: D) -> Self {
+        Self(ExclusiveSpiBus::new(SpiDeviceBus(device)))
+    }
+
+    pub fn into_inner(self) -> D {
+        self.0.into_inner().into_inner()
+    }
+}
+
+impl<'a, D: SpiDevice> SharedSpiBus<u8> for &'a SpiDeviceCell<D> {
+    type Bus = SpiDeviceBus<D>;
+    type Guard = RefMut<'a, SpiDeviceBus<D>>;
+
+    async fn lock(&self) -> Self::Guard {
+        (&self.0).lock().await
+    }
+}
+
+/// The CS "pin" for an [`SpiDevice`], which does nothing because the device controls CS itself
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DeviceCs;
+
+impl PinErrorType for DeviceCs {
+    type Error = Infallible;
+}
+
+impl OutputPin for DeviceCs {
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+impl<'a, D: SpiDevice, Delayer: DelayNs> SpiSdCard<&'a SpiDeviceCell<D>, DeviceCs, Delayer> {
+    /// Uses an [`SpiDevice`] that controls CS itself, such as one from a HAL or from `embassy-embedded-hal`'s shared bus, instead of a [`SharedSpiBus`] and a CS pin.
+    ///
+    /// Every transfer is its own transaction, so the card is deselected between the transfers of a command.
+    /// This relies on the card keeping its state while it's deselected, and is slower than holding CS for the whole command.
+    /// Also, the clocks sent before init are sent with CS low instead of high.
+    ///
+    /// The device's speed can't be changed by the driver, so it must be at most 400 kHz during [`SpiSdCard::init_card`].
+    /// After that, the device's bus can be set to a faster speed (up to 25 MHz) outside of the driver.
+    pub fn new_with_device(
+        device: &'a SpiDeviceCell<D>,
+        delayer: Delayer,
+        config: SdCardConfig,
+    ) -> Self {
+        Self::new_with_config(device, DeviceCs, delayer, (), (), config)
+    }
+}
@@ -0,0 +1,35 @@
+use core::cell::{RefCell, RefMut};
+
+use embedded_hal_async::spi::SpiBus;
+
+use crate::SharedSpiBus;
+
+/// For when the card is the only device on the bus, so the bus doesn't need a mutex.
+/// A reference to this is the [`SharedSpiBus`], since the bus is locked through a shared reference.
+/// Locking it only checks that it isn't already locked.
+///
+/// # Panics
+///
+/// Locking panics if the bus is already locked, such as when another card or task uses the same [`ExclusiveSpiBus`].
+/// To share the bus, use [`LocalSharedSpiBus`](crate::LocalSharedSpiBus) instead.
+#[derive(Debug)]
+pub struct ExclusiveSpiBus<B>(RefCell<B>);
+
+impl<B> ExclusiveSpiBus<B> {
+    pub const fn new(bus: B) -> Self {
+        Self(RefCell::new(bus))
+    }
+
+    pub fn into_inner(self) -> B {
+        self.0.into_inner()
+    }
+}
+
+impl<'a, B: SpiBus<Word>, Word: Copy + 'static> SharedSpiBus<Word> for &'a ExclusiveSpiBus<B> {
+    type Bus = B;
+    type Guard = RefMut<'a, B>;
+
+    async fn lock(&self) -> Self::Guard {
+        self.0.borrow_mut()
+    }
+}
@@ -0,0 +1,132 @@
+use core::{
+    convert::Infallible,
+    fmt::{self, Debug},
+    ops::{Deref, DerefMut},
+};
+
+use embassy_embedded_hal::SetConfig;
+use embedded_hal_async::{
+    delay::DelayNs,
+    spi::{ErrorType, SpiBus},
+};
+
+use crate::{BusPriority, ChipSelect, SdCardConfig, SharedSpiBus, SpiSdCard};
+
+/// A bus that stays at the speed it was set up with, for HALs whose buses don't implement [`SetConfig`].
+/// Changing its config does nothing.
+#[repr(transparent)]
+pub struct FixedSpeed<B>(B);
+
+/// [`crate::Error`] needs the bus to be [`Debug`], even if the inner bus isn't
+impl<B> Debug for FixedSpeed<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FixedSpeed").finish_non_exhaustive()
+    }
+}
+
+impl<B> FixedSpeed<B> {
+    fn from_ref(bus: &B) -> &Self {
+        // SAFETY: `FixedSpeed` is `repr(transparent)`, so it has the same layout as `B`
+        unsafe { &*(bus as *const B as *const Self) }
+    }
+
+    fn from_mut(bus: &mut B) -> &mut Self {
+        // SAFETY: `FixedSpeed` is `repr(transparent)`, so it has the same layout as `B`
+        unsafe { &mut *(bus as *mut B as *mut Self) }
+    }
+}
+
+impl<B: ErrorType> ErrorType for FixedSpeed<B> {
+    type Error = B::Error;
+}
+
+impl<B: SpiBus> SpiBus for FixedSpeed<B> {
+    async fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        self.0.read(words).await
+    }
+
+    async fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        self.0.write(words).await
+    }
+
+    async fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+        self.0.transfer(read, write).await
+    }
+
+    async fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        self.0.transfer_in_place(words).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.0.flush().await
+    }
+}
+
+impl<B> SetConfig for FixedSpeed<B> {
+    type Config = ();
+    type ConfigError = Infallible;
+
+    fn set_config(&mut self, _config: &()) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+/// A locked [`FixedSpeedSpiBus`]
+pub struct FixedSpeedGuard<G>(G);
+
+impl<G: DerefMut<Target: Sized>> Deref for FixedSpeedGuard<G> {
+    type Target = FixedSpeed<G::Target>;
+
+    fn deref(&self) -> &Self::Target {
+        FixedSpeed::from_ref(&self.0)
+    }
+}
+
+impl<G: DerefMut<Target: Sized>> DerefMut for FixedSpeedGuard<G> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        FixedSpeed::from_mut(&mut self.0)
+    }
+}
+
+/// Wraps a [`SharedSpiBus`] whose bus doesn't implement [`SetConfig`], for [`SpiSdCard::new_fixed_speed`]
+#[derive(Debug)]
+pub struct FixedSpeedSpiBus<S>(S);
+
+impl<S> FixedSpeedSpiBus<S> {
+    pub const fn new(spi: S) -> Self {
+        Self(spi)
+    }
+
+    pub fn into_inner(self) -> S {
+        self.0
+    }
+}
+
+impl<S: SharedSpiBus<u8>> SharedSpiBus<u8> for FixedSpeedSpiBus<S> {
+    type Bus = FixedSpeed<S::Bus>;
+    type Guard = FixedSpeedGuard<S::Guard>;
+
+    async fn lock(&self) -> Self::Guard {
+        FixedSpeedGuard(self.0.lock().await)
+    }
+
+    async fn lock_with_priority(&self, priority: BusPriority) -> Self::Guard {
+        FixedSpeedGuard(self.0.lock_with_priority(priority).await)
+    }
+
+    fn is_healthy(&self) -> bool {
+        self.0.is_healthy()
+    }
+}
+
+impl<S: SharedSpiBus<u8>, Cs: ChipSelect, Delayer: DelayNs>
+    SpiSdCard<FixedSpeedSpiBus<S>, Cs, Delayer>
+{
+    /// For a bus that doesn't implement [`SetConfig`], so the driver never changes its speed.
+    ///
+    /// The bus must be at most 400 kHz during [`SpiSdCard::init_card`].
+    /// After that, it can be set to a faster speed (up to 25 MHz) outside of the driver, or everything can run at the init speed.
+    pub fn new_fixed_speed(spi: S, cs: Cs, delayer: Delayer, config: SdCardConfig) -> Self {
+        Self::new_with_config(FixedSpeedSpiBus(spi), cs, delayer, (), (), config)
+    }
+}
@@ -0,0 +1,111 @@
+use core::{
+    cell::{Cell, RefCell, RefMut},
+    fmt::{self, Debug},
+    future::poll_fn,
+    ops::{Deref, DerefMut},
+    task::{Poll, Waker},
+};
+
+use embedded_hal_async::spi::SpiBus;
+
+use crate::SharedSpiBus;
+
+/// For sharing a bus between devices that are used by tasks on the same executor, like on single-core firmware.
+/// This doesn't need an async mutex, so it's smaller and faster than one.
+/// A reference to this is the [`SharedSpiBus`], and it isn't [`Sync`], so it can't be shared between executors or interrupts.
+///
+/// Locking it is immediate when the bus is free. If another task has it locked, the task waits until the bus is unlocked.
+/// Only the last task to start waiting is remembered, and the task it replaces is woken to check the bus again,
+/// so with several tasks waiting they are polled more often, but none of them misses the unlock.
+/// If only the card uses the bus, use [`ExclusiveSpiBus`](crate::ExclusiveSpiBus).
+pub struct LocalSharedSpiBus<B> {
+    bus: RefCell<B>,
+    /// The task waiting for the bus to be unlocked
+    waker: Cell<Option<Waker>>,
+}
+
+impl<B: Debug> Debug for LocalSharedSpiBus<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalSharedSpiBus")
+            .field("bus", &self.bus)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<B> LocalSharedSpiBus<B> {
+    pub const fn new(bus: B) -> Self {
+        Self {
+            bus: RefCell::new(bus),
+            waker: Cell::new(None),
+        }
+    }
+
+    pub fn into_inner(self) -> B {
+        self.bus.into_inner()
+    }
+}
+
+/// The bus locked through a [`LocalSharedSpiBus`]. Dropping it unlocks the bus and wakes the task waiting for it.
+pub struct LocalSharedSpiBusGuard<'a, B> {
+    bus: RefMut<'a, B>,
+    waker: &'a Cell<Option<Waker>>,
+}
+
+impl<B> Deref for LocalSharedSpiBusGuard<'_, B> {
+    type Target = B;
+
+    fn deref(&self) -> &B {
+        &self.bus
+    }
+}
+
+impl<B> DerefMut for LocalSharedSpiBusGuard<'_, B> {
+    fn deref_mut(&mut self) -> &mut B {
+        &mut self.bus
+    }
+}
+
+/// The woken task can only be polled after this returns to the executor, which is after the bus is unlocked
+impl<B> Drop for LocalSharedSpiBusGuard<'_, B> {
+    fn drop(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<'a, B: SpiBus<Word>, Word: Copy + 'static> SharedSpiBus<Word> for &'a LocalSharedSpiBus<B> {
+    type Bus = B;
+    type Guard = LocalSharedSpiBusGuard<'a, B>;
+
+    async fn lock(&self) -> Self::Guard {
+        let shared: &'a LocalSharedSpiBus<B> = self;
+        poll_fn(|cx| match shared.bus.try_borrow_mut() {
+            Ok(bus) => {
+                // This task isn't waiting anymore
+                if let Some(waker) = shared.waker.take()
+                    && !waker.will_wake(cx.waker())
+                {
+                    shared.waker.set(Some(waker));
+                }
+                Poll::Ready(LocalSharedSpiBusGuard {
+                    bus,
+                    waker: &shared.waker,
+                })
+            }
+            Err(_) => {
+                match shared.waker.take() {
+                    Some(waker) if waker.will_wake(cx.waker()) => shared.waker.set(Some(waker)),
+                    replaced => {
+                        shared.waker.set(Some(cx.waker().clone()));
+                        if let Some(waker) = replaced {
+                            waker.wake();
+                        }
+                    }
+                }
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
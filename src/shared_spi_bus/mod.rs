@@ -1,9 +1,25 @@
+mod device;
 #[cfg(feature = "embassy-sync")]
 mod embassy;
+mod exclusive;
+mod fixed_speed;
+mod local;
+#[cfg(feature = "embassy-sync")]
+mod priority;
+#[cfg(feature = "std")]
+mod std_mutex;
 use core::ops::DerefMut;
 
+pub use device::*;
 #[cfg(feature = "embassy-sync")]
 pub use embassy::*;
+pub use exclusive::*;
+pub use fixed_speed::*;
+pub use local::*;
+#[cfg(feature = "embassy-sync")]
+pub use priority::*;
+#[cfg(feature = "std")]
+pub use std_mutex::*;
 
 use embedded_hal_async::spi::SpiBus;
 
@@ -14,4 +30,27 @@ pub trait SharedSpiBus<Word: Copy + 'static> {
     type Guard: DerefMut<Target = Self::Bus>;
 
     async fn lock(&self) -> Self::Guard;
+
+    /// Lets latency-critical operations get the bus before bulk transfers from other devices.
+    /// Buses that don't support priorities ignore it.
+    async fn lock_with_priority(&self, _priority: BusPriority) -> Self::Guard {
+        self.lock().await
+    }
+
+    /// `false` if other devices on the bus recently had transfer errors or timeouts.
+    /// Then errors from the card are probably caused by the bus, so the driver doesn't treat the card as removed because of them.
+    /// Buses that don't track this are always healthy.
+    fn is_healthy(&self) -> bool {
+        true
+    }
+}
+
+/// How urgently an operation needs the bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BusPriority {
+    #[default]
+    Normal,
+    /// For example, reading the next audio buffer
+    High,
 }
@@ -0,0 +1,131 @@
+use core::{cell::RefCell, future::poll_fn, task::Poll};
+
+use embassy_sync::{
+    blocking_mutex::{self, raw::RawMutex},
+    mutex::{Mutex, MutexGuard},
+    waitqueue::MultiWakerRegistration,
+};
+use embedded_hal_async::spi::SpiBus;
+
+use crate::{BusPriority, SharedSpiBus};
+
+struct State<const WAITERS: usize> {
+    /// Number of high priority operations waiting for the bus
+    high_priority_waiting: usize,
+    normal_priority_wakers: MultiWakerRegistration<WAITERS>,
+    /// Transfer errors reported by devices since the last successful transfer
+    errors: usize,
+}
+
+/// A bus shared between multiple devices, where [`BusPriority::High`] operations get the bus before [`BusPriority::Normal`] operations that are waiting.
+/// Each device gets a [`PrioritySharedSpiBus`] from [`PrioritySpiBus::device`].
+///
+/// `WAITERS` is the number of normal priority operations that can wait for the bus without extra wake-ups.
+pub struct PrioritySpiBus<M: RawMutex, BUS, const WAITERS: usize> {
+    bus: Mutex<M, BUS>,
+    state: blocking_mutex::Mutex<M, RefCell<State<WAITERS>>>,
+}
+
+impl<M: RawMutex, BUS, const WAITERS: usize> PrioritySpiBus<M, BUS, WAITERS> {
+    pub const fn new(bus: BUS) -> Self {
+        Self {
+            bus: Mutex::new(bus),
+            state: blocking_mutex::Mutex::new(RefCell::new(State {
+                high_priority_waiting: 0,
+                normal_priority_wakers: MultiWakerRegistration::new(),
+                errors: 0,
+            })),
+        }
+    }
+
+    /// A handle for a device, whose operations use `priority` unless they ask for a different one
+    pub fn device(&self, priority: BusPriority) -> PrioritySharedSpiBus<'_, M, BUS, WAITERS> {
+        PrioritySharedSpiBus {
+            bus: self,
+            priority,
+        }
+    }
+
+    /// Other devices should call this when a transfer fails or times out, so that the SD card driver knows that the bus is having problems
+    pub fn report_error(&self) {
+        self.state.lock(|state| state.borrow_mut().errors += 1);
+    }
+
+    /// Other devices should call this after a successful transfer
+    pub fn report_success(&self) {
+        self.state.lock(|state| state.borrow_mut().errors = 0);
+    }
+
+    /// `true` if no errors were reported since the last successful transfer
+    pub fn is_healthy(&self) -> bool {
+        self.state.lock(|state| state.borrow().errors == 0)
+    }
+
+    pub async fn lock(&self, priority: BusPriority) -> MutexGuard<'_, M, BUS> {
+        match priority {
+            BusPriority::Normal => {
+                poll_fn(|cx| {
+                    self.state.lock(|state| {
+                        let mut state = state.borrow_mut();
+                        if state.high_priority_waiting == 0 {
+                            Poll::Ready(())
+                        } else {
+                            state.normal_priority_wakers.register(cx.waker());
+                            Poll::Pending
+                        }
+                    })
+                })
+                .await;
+                self.bus.lock().await
+            }
+            BusPriority::High => {
+                self.state
+                    .lock(|state| state.borrow_mut().high_priority_waiting += 1);
+                // Decrements the count even if the future is dropped while waiting
+                let _waiting = HighPriorityWaiting(self);
+                self.bus.lock().await
+            }
+        }
+    }
+}
+
+struct HighPriorityWaiting<'a, M: RawMutex, BUS, const WAITERS: usize>(
+    &'a PrioritySpiBus<M, BUS, WAITERS>,
+);
+
+impl<M: RawMutex, BUS, const WAITERS: usize> Drop for HighPriorityWaiting<'_, M, BUS, WAITERS> {
+    fn drop(&mut self) {
+        self.0.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            state.high_priority_waiting -= 1;
+            if state.high_priority_waiting == 0 {
+                state.normal_priority_wakers.wake();
+            }
+        });
+    }
+}
+
+/// A device's handle to a [`PrioritySpiBus`]
+pub struct PrioritySharedSpiBus<'a, M: RawMutex, BUS, const WAITERS: usize> {
+    bus: &'a PrioritySpiBus<M, BUS, WAITERS>,
+    priority: BusPriority,
+}
+
+impl<'a, M: RawMutex, BUS: SpiBus<Word>, Word: Copy + 'static, const WAITERS: usize>
+    SharedSpiBus<Word> for PrioritySharedSpiBus<'a, M, BUS, WAITERS>
+{
+    type Bus = BUS;
+    type Guard = MutexGuard<'a, M, BUS>;
+
+    async fn lock(&self) -> MutexGuard<'a, M, BUS> {
+        self.bus.lock(self.priority).await
+    }
+
+    async fn lock_with_priority(&self, priority: BusPriority) -> MutexGuard<'a, M, BUS> {
+        self.bus.lock(priority).await
+    }
+
+    fn is_healthy(&self) -> bool {
+        self.bus.is_healthy()
+    }
+}
@@ -0,0 +1,123 @@
+use core::{
+    fmt::{self, Debug},
+    future::poll_fn,
+    ops::{Deref, DerefMut},
+    task::{Poll, Waker},
+};
+use std::sync::{Mutex, MutexGuard, PoisonError, TryLockError};
+
+use embedded_hal_async::spi::SpiBus;
+
+use crate::SharedSpiBus;
+
+/// Shares a bus through a [`std::sync::Mutex`], for running the driver on embedded Linux, such as with `linux-embedded-hal`'s spidev.
+/// A reference to this is the [`SharedSpiBus`], and it can be shared between threads.
+///
+/// If the bus is locked by another task or thread, this waits for it to be unlocked without blocking the thread,
+/// so it also works when the tasks sharing the bus are on the same thread.
+/// Like [`LocalSharedSpiBus`](crate::LocalSharedSpiBus), only the last task to start waiting is remembered, and the one it replaces is woken to check the bus again.
+/// If a thread panicked while it had the bus locked, the bus is used anyway, because the next command starts with the card deselected.
+pub struct StdSharedSpiBus<BUS> {
+    bus: Mutex<BUS>,
+    /// The task waiting for the bus to be unlocked
+    waker: Mutex<Option<Waker>>,
+}
+
+impl<BUS: Debug> Debug for StdSharedSpiBus<BUS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StdSharedSpiBus")
+            .field("bus", &self.bus)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<BUS> StdSharedSpiBus<BUS> {
+    pub const fn new(bus: BUS) -> Self {
+        Self {
+            bus: Mutex::new(bus),
+            waker: Mutex::new(None),
+        }
+    }
+
+    pub fn into_inner(self) -> BUS {
+        self.bus
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn waker(&self) -> MutexGuard<'_, Option<Waker>> {
+        self.waker.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// The bus locked through a [`StdSharedSpiBus`]. Dropping it unlocks the bus and wakes the task waiting for it.
+pub struct StdSharedSpiBusGuard<'a, BUS> {
+    /// Only `None` while being dropped, so that the bus is unlocked before the waiting task is woken
+    bus: Option<MutexGuard<'a, BUS>>,
+    shared: &'a StdSharedSpiBus<BUS>,
+}
+
+impl<BUS> Deref for StdSharedSpiBusGuard<'_, BUS> {
+    type Target = BUS;
+
+    fn deref(&self) -> &BUS {
+        self.bus.as_ref().unwrap()
+    }
+}
+
+impl<BUS> DerefMut for StdSharedSpiBusGuard<'_, BUS> {
+    fn deref_mut(&mut self) -> &mut BUS {
+        self.bus.as_mut().unwrap()
+    }
+}
+
+impl<BUS> Drop for StdSharedSpiBusGuard<'_, BUS> {
+    fn drop(&mut self) {
+        // A task on another thread could otherwise be polled before the bus is unlocked, and wait again without being woken
+        drop(self.bus.take());
+        let waker = self.shared.waker().take();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<'a, BUS: SpiBus<Word>, Word: Copy + 'static> SharedSpiBus<Word> for &'a StdSharedSpiBus<BUS> {
+    type Bus = BUS;
+    type Guard = StdSharedSpiBusGuard<'a, BUS>;
+
+    async fn lock(&self) -> Self::Guard {
+        let shared: &'a StdSharedSpiBus<BUS> = self;
+        poll_fn(|cx| {
+            // Registered before trying the lock, so that an unlock right after the attempt still wakes this task
+            let replaced = {
+                let mut waker = shared.waker();
+                match waker.as_ref() {
+                    Some(registered) if registered.will_wake(cx.waker()) => None,
+                    _ => waker.replace(cx.waker().clone()),
+                }
+            };
+            if let Some(replaced) = replaced {
+                replaced.wake();
+            }
+            let bus = match shared.bus.try_lock() {
+                Ok(bus) => bus,
+                Err(TryLockError::Poisoned(poisoned)) => PoisonError::into_inner(poisoned),
+                Err(TryLockError::WouldBlock) => return Poll::Pending,
+            };
+            // This task isn't waiting anymore
+            let mut waker = shared.waker();
+            if waker
+                .as_ref()
+                .is_some_and(|registered| registered.will_wake(cx.waker()))
+            {
+                *waker = None;
+            }
+            Poll::Ready(StdSharedSpiBusGuard {
+                bus: Some(bus),
+                shared,
+            })
+        })
+        .await
+    }
+}
@@ -0,0 +1,80 @@
+use core::fmt::Debug;
+
+use embassy_embedded_hal::SetConfig;
+use embedded_hal_async::delay::DelayNs;
+
+use crate::{
+    CardCommand3Error, CardDetect, ChipSelect, EXPECTED_BYTES_UNTIL_RESPONSE, Error, InitStep, R1,
+    SdCardDisk, SdCommand, SharedSpiBus, SpiSdCard, card_command,
+};
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect> SpiSdCard<Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// Switches `spi` to the transfer speed and checks that the card still responds, falling back to the derated config if it doesn't.
+    /// The card must be selected.
+    pub(crate) async fn verify_speed_switch(
+        &mut self,
+        spi: &mut Spi::Bus,
+    ) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        self.init_timeline
+            .start(InitStep::VerifySpeed, self.config.clock);
+        spi.set_config(self.transfer_config())
+            .map_err(Error::SpiSetConfig)?;
+        if self.status_ok(spi).await? {
+            return Ok(());
+        }
+        if self.derated {
+            // The clock policy already chose the slower config
+            return Err(Error::SpeedSwitchFailed);
+        }
+        warn!(
+            "the card didn't respond correctly at the full SPI clock, falling back to the derated config"
+        );
+        self.speed_fallback = true;
+        spi.set_config(self.transfer_config())
+            .map_err(Error::SpiSetConfig)?;
+        if self.status_ok(spi).await? {
+            Ok(())
+        } else {
+            Err(Error::SpeedSwitchFailed)
+        }
+    }
+
+    /// Sends `CMD13`, which is cheap and has a 2 byte response. With CRC on, the card also checks the command's CRC.
+    /// A response that doesn't arrive or has any bit of R1 set means that the bus is unreliable at its current speed.
+    async fn status_ok(&mut self, spi: &mut Spi::Bus) -> Result<bool, Error<Spi::Bus, Cs::Error>> {
+        let result = card_command(
+            spi,
+            &mut self.scratch,
+            &self.config,
+            SdCommand::SendStatus,
+            EXPECTED_BYTES_UNTIL_RESPONSE,
+            self.config.timeouts.command,
+            None,
+        )
+        .await;
+        match result {
+            Ok(response) => Ok(R1::from_bits_retain(response[0]).is_empty()),
+            Err(CardCommand3Error::Spi(e)) => Err(Error::SpiBus(e)),
+            Err(CardCommand3Error::NothingToTransfer) => Err(Error::NothingToTransfer),
+            Err(_) => Ok(false),
+        }
+    }
+}
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect> SdCardDisk<'_, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// `true` if [`crate::SdCardConfig::verify_speed_switch`] found that the card doesn't work at the full speed,
+    /// so the derated config is used until the card is initialized again
+    pub fn speed_fell_back(&self) -> bool {
+        self.sd_card.speed_fallback
+    }
+}
@@ -0,0 +1,67 @@
+use core::fmt::Debug;
+
+use embassy_embedded_hal::SetConfig;
+use embassy_stm32::{spi::Config, time::Hertz};
+use embedded_hal_async::delay::DelayNs;
+
+use crate::{ChipSelect, SdCardConfig, SharedSpiBus, SpiSdCard};
+
+/// The slowest SPI clock of an STM32 is its kernel clock divided by this
+const MAX_DIVIDER: u32 = 256;
+
+/// The fastest SPI clock that is at most `max`.
+/// The SPI peripheral divides its kernel clock by a power of two from 2 to 256, and embassy-stm32 picks the divider closest to the requested frequency,
+/// which can be faster than it, so this asks for a frequency that a divider makes exactly.
+fn frequency_at_most(kernel_clock: Hertz, max: Hertz) -> Hertz {
+    let divider = kernel_clock
+        .0
+        .div_ceil(max.0)
+        .next_power_of_two()
+        .clamp(2, MAX_DIVIDER);
+    Hertz(kernel_clock.0 / divider)
+}
+
+/// The `embassy_stm32::spi::Config` for initializing the card, at 400 kHz or less.
+/// `kernel_clock` is the clock of the SPI peripheral, such as PCLK2 for SPI1 on an STM32F4.
+///
+/// If the kernel clock is faster than 102.4 MHz, even the slowest SPI clock is faster than 400 kHz.
+/// Most cards initialize fine anyway, but if one doesn't, slow down the peripheral's bus clock.
+pub fn stm32_init_config(kernel_clock: Hertz) -> Config {
+    let mut config = Config::default();
+    config.frequency = frequency_at_most(kernel_clock, Hertz(400_000));
+    config
+}
+
+/// The `embassy_stm32::spi::Config` for after the card is initialized, at 25 MHz or less.
+/// `kernel_clock` is the clock of the SPI peripheral, like for [`stm32_init_config`].
+pub fn stm32_run_config(kernel_clock: Hertz) -> Config {
+    let mut config = Config::default();
+    config.frequency = frequency_at_most(kernel_clock, Hertz(25_000_000));
+    config
+}
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs> SpiSdCard<Spi, Cs, Delayer>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig<Config = Config>,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// For an `embassy_stm32::spi::Spi`, using [`stm32_init_config`] and [`stm32_run_config`] for the speeds.
+    /// Create the `Spi` with [`stm32_init_config`], so the bus starts out slow enough.
+    pub fn new_stm32(
+        spi: Spi,
+        cs: Cs,
+        delayer: Delayer,
+        kernel_clock: Hertz,
+        config: SdCardConfig,
+    ) -> Self {
+        Self::new_with_config(
+            spi,
+            cs,
+            delayer,
+            stm32_init_config(kernel_clock),
+            stm32_run_config(kernel_clock),
+            config,
+        )
+    }
+}
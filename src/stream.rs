@@ -0,0 +1,219 @@
+use core::{fmt::Debug, ops::DerefMut};
+
+use embassy_embedded_hal::SetConfig;
+use embedded_hal_async::{
+    delay::DelayNs,
+    spi::{ErrorType, SpiBus},
+};
+
+use crate::{
+    BLOCK_SIZE, BusyOperation, CardCommand3Error, CardCommandOperation, CardDetect, ChipSelect,
+    DataErrorToken, Duration, EXPECTED_BYTES_UNTIL_RESPONSE, Error, R1, ReadOperation,
+    ScatterBuffer, SdCardDisk, SdCommand, SharedSpiBus, card_command, receive_data_block,
+    receive_scattered_block,
+};
+
+/// A `CMD18` transfer that is kept open, so that blocks can be read one at a time without sending a command for each one.
+/// The stream holds the bus and keeps the card selected, so nothing else can delay the next block.
+/// This makes it suitable for things like audio playback, where jitter causes glitches.
+///
+/// Call [`BlockStream::close`] when you are done. If the stream is dropped without closing it, such as when a read is cancelled,
+/// the card stays selected and in the middle of the transfer, with the bus locked, until the next operation of the disk stops it with `CMD12`.
+#[must_use]
+pub struct BlockStream<'s, 'a, Spi, Cs, Delayer, Cd = ()>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+    Cs: ChipSelect,
+{
+    disk: &'s mut SdCardDisk<'a, Spi, Cs, Delayer, Cd>,
+    /// Only `None` after closing
+    spi: Option<Spi::Guard>,
+    max_block_latency: Duration,
+}
+
+/// The transfer that a stream left open when it was dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OpenTransfer {
+    /// `CMD18`
+    Read,
+    /// `CMD25`
+    Write,
+}
+
+/// The error for a block of a stream that wasn't received
+fn next_block_error<Bus, CsError>(
+    e: CardCommand3Error<<Bus as ErrorType>::Error>,
+) -> Error<Bus, CsError>
+where
+    Bus: SpiBus + SetConfig,
+    <Bus as SetConfig>::ConfigError: Debug,
+{
+    match e {
+        CardCommand3Error::Spi(e) => Error::SpiBus(e),
+        CardCommand3Error::ExpectedStartBlockToken(byte) => {
+            DataErrorToken::from_byte(byte).map_or(Error::ReadUnexpectedData, Error::ReadDataError)
+        }
+        CardCommand3Error::ReceiveDataTimeout(_) => Error::StreamDeadlineMissed,
+        CardCommand3Error::InvalidCrc => Error::ReadInvalidCrc,
+        CardCommand3Error::NothingToTransfer => Error::NothingToTransfer,
+        CardCommand3Error::UnexpectedTrailingData(byte) => Error::UnexpectedTrailingData(byte),
+        _ => unreachable!(),
+    }
+}
+
+impl<'a, Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect> SdCardDisk<'a, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// Starts reading consecutive blocks from `start_block`.
+    ///
+    /// Every [`BlockStream::next_block`] either completes within `max_block_latency` plus the time to transfer one block at the configured clock, or fails with [`Error::StreamDeadlineMissed`].
+    pub async fn open_stream(
+        &mut self,
+        start_block: u32,
+        max_block_latency: Duration,
+    ) -> Result<BlockStream<'_, 'a, Spi, Cs, Delayer, Cd>, Error<Spi::Bus, Cs::Error>> {
+        let address = self
+            .block_argument(start_block)
+            .ok_or(Error::ReadAddressError)?;
+        let mut spi = self.begin_transfer().await?;
+        let expected_bytes_until_data = self.read_data_gap();
+        let response = card_command(
+            spi.deref_mut(),
+            &mut self.sd_card.scratch,
+            &self.sd_card.config,
+            SdCommand::ReadMultipleBlock { address },
+            EXPECTED_BYTES_UNTIL_RESPONSE,
+            self.sd_card.config.timeouts.command,
+            Some(CardCommandOperation::Read(ReadOperation {
+                buffer: &mut [],
+                expected_bytes_until_data,
+                timeout: max_block_latency,
+                parts: 0,
+                part_size: BLOCK_SIZE,
+                crc_enabled: self.sd_card.crc_enabled,
+                skip_bytes: 0,
+                bytes_until_data: None,
+            })),
+        )
+        .await
+        .map_err(|e| match e {
+            CardCommand3Error::Spi(e) => Error::SpiBus(e),
+            CardCommand3Error::ReceiveResponseTimeout(_) => Error::ReadReceiveResponseTimeout,
+            CardCommand3Error::NothingToTransfer => Error::NothingToTransfer,
+            CardCommand3Error::UnexpectedTrailingData(byte) => Error::UnexpectedTrailingData(byte),
+            _ => unreachable!(),
+        })?;
+        let r1 = R1::from_bits_retain(response[0]);
+        if !r1.is_empty() {
+            return Err(Error::ReadResponseError);
+        }
+
+        Ok(BlockStream {
+            disk: self,
+            spi: Some(spi),
+            max_block_latency,
+        })
+    }
+}
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect>
+    BlockStream<'_, '_, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// Reads the next block of the stream.
+    /// If the card sends a bad block, the rest of it is clocked out, but the stream should still be closed with [`BlockStream::close`].
+    pub async fn next_block(
+        &mut self,
+        block: &mut [u8; BLOCK_SIZE],
+    ) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        let result = receive_data_block(
+            self.spi.as_deref_mut().unwrap(),
+            &mut self.disk.sd_card.scratch,
+            &self.disk.sd_card.config,
+            block,
+            self.max_block_latency,
+            self.disk.sd_card.crc_enabled,
+        )
+        .await
+        .map_err(next_block_error);
+        self.disk.sd_card.zeroize_scratch();
+        result
+    }
+
+    /// Like [`BlockStream::next_block`], but the block's data goes into `data`
+    pub(crate) async fn next_scattered(
+        &mut self,
+        data: &mut ScatterBuffer<'_, '_>,
+    ) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        let result = receive_scattered_block(
+            self.spi.as_deref_mut().unwrap(),
+            &mut self.disk.sd_card.scratch,
+            &self.disk.sd_card.config,
+            data,
+            BLOCK_SIZE,
+            self.max_block_latency,
+            self.disk.sd_card.crc_enabled,
+        )
+        .await
+        .map_err(next_block_error);
+        self.disk.sd_card.zeroize_scratch();
+        result
+    }
+
+    /// Stops the transfer with `CMD12` and releases the bus
+    pub async fn close(mut self) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        let mut spi = self.spi.take().unwrap();
+        let response = card_command(
+            spi.deref_mut(),
+            &mut self.disk.sd_card.scratch,
+            &self.disk.sd_card.config,
+            SdCommand::StopTransmission,
+            EXPECTED_BYTES_UNTIL_RESPONSE,
+            self.disk.sd_card.config.timeouts.command,
+            Some(CardCommandOperation::BusySignal(BusyOperation {
+                expected_bytes_until_not_busy: self.disk.sd_card.config.gaps.not_busy,
+                timeout: self.disk.sd_card.config.timeouts.busy,
+            })),
+        )
+        .await
+        .map_err(|e| match e {
+            CardCommand3Error::Spi(e) => Error::SpiBus(e),
+            CardCommand3Error::ReceiveResponseTimeout(_) => Error::StopTransmissionResponseTimeout,
+            CardCommand3Error::BusyTimeout => Error::StopTransmissionBusyTimeout,
+            CardCommand3Error::NothingToTransfer => Error::NothingToTransfer,
+            CardCommand3Error::UnexpectedTrailingData(byte) => Error::UnexpectedTrailingData(byte),
+            _ => unreachable!(),
+        })?;
+        let r1 = R1::from_bits_retain(response[0]);
+        if !r1.is_empty() {
+            return Err(Error::StopTransmissionResponseError);
+        }
+
+        self.disk.end_operation(spi).await
+    }
+}
+
+impl<Spi, Cs, Delayer, Cd> Drop for BlockStream<'_, '_, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+    Cs: ChipSelect,
+{
+    fn drop(&mut self) {
+        if let Some(spi) = self.spi.take() {
+            self.disk.sd_card.stream_left_open = Some(OpenTransfer::Read);
+            // The card is still selected, so the bus stays locked until the next operation stops the transfer.
+            // Otherwise, other devices could clock data into the card.
+            self.disk.transaction_bus = Some(spi);
+        }
+    }
+}
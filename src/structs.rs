@@ -18,6 +18,7 @@ bitfield! {
 
 bitflags! {
     #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct R1: u8 {
         const BIT_7 = 1 << 7;
         const PARAMETER_ERROR = 1 << 6;
@@ -32,6 +33,7 @@ bitflags! {
 
 bitflags! {
     #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct R2Byte1: u8 {
         const OUT_OF_RANGE_OR_CSD_OVERWRITE = 1 << 7;
         const ERASE_PARAM = 1 << 6;
@@ -44,6 +46,76 @@ bitflags! {
     }
 }
 
+#[derive(Debug)]
+#[repr(C)]
+pub struct R2 {
+    pub byte_0: R1,
+    pub byte_1: R2Byte1,
+}
+
+/// The card status from `CMD13` (`SEND_STATUS`), which is both bytes of an R2 response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CardStatus {
+    pub r1: R1,
+    pub r2: R2Byte1,
+}
+
+impl CardStatus {
+    pub fn from_bytes(bytes: [u8; 2]) -> Self {
+        Self {
+            r1: R1::from_bits_retain(bytes[0]),
+            r2: R2Byte1::from_bits_retain(bytes[1]),
+        }
+    }
+
+    /// `true` if none of the error bits are set
+    pub fn is_ok(&self) -> bool {
+        self.r1.difference(R1::IN_IDLE_STATE).is_empty()
+            && self.r2.difference(R2Byte1::CARD_IS_LOCKED).is_empty()
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.r2.contains(R2Byte1::CARD_IS_LOCKED)
+    }
+
+    /// A write to a write-protected block was attempted
+    pub fn write_protect_violation(&self) -> bool {
+        self.r2.contains(R2Byte1::WP_VIOLATION)
+    }
+
+    /// The card's internal ECC could not correct the data
+    pub fn ecc_failed(&self) -> bool {
+        self.r2.contains(R2Byte1::CARD_ECC_FAILED)
+    }
+
+    /// The argument was out of the card's range, or the CSD was overwritten with invalid values
+    pub fn out_of_range(&self) -> bool {
+        self.r2.contains(R2Byte1::OUT_OF_RANGE_OR_CSD_OVERWRITE)
+    }
+
+    /// The card's internal controller had an error
+    pub fn card_controller_error(&self) -> bool {
+        self.r2.contains(R2Byte1::CC_ERROR)
+    }
+
+    /// A general or unknown error happened during the operation
+    pub fn error(&self) -> bool {
+        self.r2.contains(R2Byte1::ERROR)
+    }
+
+    /// An invalid selection of blocks was erased
+    pub fn erase_param(&self) -> bool {
+        self.r2.contains(R2Byte1::ERASE_PARAM)
+    }
+
+    /// Some write-protected blocks were skipped while erasing, or locking or unlocking the card failed
+    pub fn wp_erase_skip_or_lock_unlock_failed(&self) -> bool {
+        self.r2
+            .contains(R2Byte1::WP_ERASE_SKIP_OR_LOCK_UNLOCK_CMD_FAILED)
+    }
+}
+
 bitfield! {
     #[derive(Debug)]
     pub struct R7Byte1(u8);
@@ -93,6 +165,7 @@ bitflags! {
 
 bitflags! {
     #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct Ocr: u32 {
         const _2_7V_2_8V = 1 << 15;
         const _2_8V_2_9V = 1 << 16;
@@ -145,6 +218,28 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// Sent by the card instead of a start block token when it can't send the requested data
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct DataErrorToken: u8 {
+        const OUT_OF_RANGE = 1 << 3;
+        const CARD_ECC_FAILED = 1 << 2;
+        const CC_ERROR = 1 << 1;
+        const ERROR = 1 << 0;
+    }
+}
+
+impl DataErrorToken {
+    /// Returns `None` if the byte is not a data error token
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        if byte & 0xF0 == 0 {
+            Some(Self::from_bits_retain(byte))
+        } else {
+            None
+        }
+    }
+}
+
 bitfield! {
     pub struct DataResponseToken(u8);
 
@@ -154,9 +249,92 @@ bitfield! {
 }
 
 bitfield! {
+    /// CSD version 1.0, used by standard capacity cards
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct CsdV1(u128);
+    impl Debug;
+
+    u8; pub get_csd_structure, set_csd_structure: 127, 126;
+    u8; pub get_taac, set_taac: 119, 112;
+    u8; pub get_nsac, set_nsac: 111, 104;
+    u8; pub get_tran_speed, set_tran_speed: 103, 96;
+    u16; pub get_ccc, set_ccc: 95, 84;
+    u8; pub get_read_bl_len, set_read_bl_len: 83, 80;
+    bool; pub get_read_bl_partial, set_read_bl_partial: 79;
+    bool; pub get_write_blk_misalign, set_write_blk_misalign: 78;
+    bool; pub get_read_blk_misalign, set_read_blk_misalign: 77;
+    bool; pub get_dsr_imp, set_dsr_imp: 76;
+    u16; pub get_c_size, set_c_size: 73, 62;
+    u8; pub get_vdd_r_curr_min, set_vdd_r_curr_min: 61, 59;
+    u8; pub get_vdd_r_curr_max, set_vdd_r_curr_max: 58, 56;
+    u8; pub get_vdd_w_curr_min, set_vdd_w_curr_min: 55, 53;
+    u8; pub get_vdd_w_curr_max, set_vdd_w_curr_max: 52, 50;
+    u8; pub get_c_size_mult, set_c_size_mult: 49, 47;
+    bool; pub get_erase_blk_en, set_erase_blk_en: 46;
+    u8; pub get_sector_size, set_sector_size: 45, 39;
+    u8; pub get_wp_grp_size, set_wp_grp_size: 38, 32;
+    bool; pub get_wp_grp_enable, set_wp_grp_enable: 31;
+    u8; pub get_r2w_factor, set_r2w_factor: 28, 26;
+    u8; pub get_write_bl_len, set_write_bl_len: 25, 22;
+    bool; pub get_write_bl_partial, set_write_bl_partial: 21;
+    bool; pub get_file_format_grp, set_file_format_grp: 15;
+    bool; pub get_copy, set_copy: 14;
+    bool; pub get_perm_write_protect, set_perm_write_protect: 13;
+    bool; pub get_tmp_write_protect, set_tmp_write_protect: 12;
+    u8; pub get_file_format, set_file_format: 11, 10;
+    u8; pub get_crc, set_crc: 7, 1;
+}
+
+impl CsdV1 {
+    pub fn card_capacity_bytes(&self) -> u64 {
+        (u64::from(self.get_c_size()) + 1) << (self.get_c_size_mult() + 2 + self.get_read_bl_len())
+    }
+}
+
+bitfield! {
+    /// CSD version 2.0, used by high and extended capacity cards
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct CsdV2(u128);
+    impl Debug;
 
+    u8; pub get_csd_structure, set_csd_structure: 127, 126;
+    u8;
+    /// Always `0x0E` (1 ms), since these cards use fixed timeouts instead
+    pub get_taac, set_taac: 119, 112;
+    u8;
+    /// Always `0`
+    pub get_nsac, set_nsac: 111, 104;
+    u8; pub get_tran_speed, set_tran_speed: 103, 96;
+    u16; pub get_ccc, set_ccc: 95, 84;
+    u8;
+    /// Always `9` (512 bytes)
+    pub get_read_bl_len, set_read_bl_len: 83, 80;
+    bool; pub get_read_bl_partial, set_read_bl_partial: 79;
+    bool; pub get_write_blk_misalign, set_write_blk_misalign: 78;
+    bool; pub get_read_blk_misalign, set_read_blk_misalign: 77;
+    bool; pub get_dsr_imp, set_dsr_imp: 76;
     u32; pub get_c_size, set_c_size: 75, 48;
+    bool;
+    /// Always `true`
+    pub get_erase_blk_en, set_erase_blk_en: 46;
+    u8;
+    /// Always `0x7F` (64 KiB)
+    pub get_sector_size, set_sector_size: 45, 39;
+    u8; pub get_wp_grp_size, set_wp_grp_size: 38, 32;
+    bool; pub get_wp_grp_enable, set_wp_grp_enable: 31;
+    u8; pub get_r2w_factor, set_r2w_factor: 28, 26;
+    u8;
+    /// Always `9` (512 bytes)
+    pub get_write_bl_len, set_write_bl_len: 25, 22;
+    bool; pub get_write_bl_partial, set_write_bl_partial: 21;
+    bool; pub get_file_format_grp, set_file_format_grp: 15;
+    bool; pub get_copy, set_copy: 14;
+    bool; pub get_perm_write_protect, set_perm_write_protect: 13;
+    bool; pub get_tmp_write_protect, set_tmp_write_protect: 12;
+    u8; pub get_file_format, set_file_format: 11, 10;
+    u8; pub get_crc, set_crc: 7, 1;
 }
 
 impl CsdV2 {
@@ -165,6 +343,225 @@ impl CsdV2 {
     }
 }
 
+bitfield! {
+    /// The CSD of MMC and eMMC cards, which is like [`CsdV1`] except for the erase and ECC fields
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct CsdMmc(u128);
+    impl Debug;
+
+    u8;
+    /// `3` means that the version is in the `EXT_CSD`
+    pub get_csd_structure, set_csd_structure: 127, 126;
+    u8; pub get_spec_vers, set_spec_vers: 125, 122;
+    u8; pub get_taac, set_taac: 119, 112;
+    u8; pub get_nsac, set_nsac: 111, 104;
+    u8; pub get_tran_speed, set_tran_speed: 103, 96;
+    u16; pub get_ccc, set_ccc: 95, 84;
+    u8; pub get_read_bl_len, set_read_bl_len: 83, 80;
+    bool; pub get_read_bl_partial, set_read_bl_partial: 79;
+    bool; pub get_write_blk_misalign, set_write_blk_misalign: 78;
+    bool; pub get_read_blk_misalign, set_read_blk_misalign: 77;
+    bool; pub get_dsr_imp, set_dsr_imp: 76;
+    u16;
+    /// `0xFFF` on cards of more than 2 GB, which have their size in the `EXT_CSD` instead
+    pub get_c_size, set_c_size: 73, 62;
+    u8; pub get_vdd_r_curr_min, set_vdd_r_curr_min: 61, 59;
+    u8; pub get_vdd_r_curr_max, set_vdd_r_curr_max: 58, 56;
+    u8; pub get_vdd_w_curr_min, set_vdd_w_curr_min: 55, 53;
+    u8; pub get_vdd_w_curr_max, set_vdd_w_curr_max: 52, 50;
+    u8; pub get_c_size_mult, set_c_size_mult: 49, 47;
+    u8; pub get_erase_grp_size, set_erase_grp_size: 46, 42;
+    u8; pub get_erase_grp_mult, set_erase_grp_mult: 41, 37;
+    u8; pub get_wp_grp_size, set_wp_grp_size: 36, 32;
+    bool; pub get_wp_grp_enable, set_wp_grp_enable: 31;
+    u8; pub get_default_ecc, set_default_ecc: 30, 29;
+    u8; pub get_r2w_factor, set_r2w_factor: 28, 26;
+    u8; pub get_write_bl_len, set_write_bl_len: 25, 22;
+    bool; pub get_write_bl_partial, set_write_bl_partial: 21;
+    bool; pub get_content_prot_app, set_content_prot_app: 16;
+    bool; pub get_file_format_grp, set_file_format_grp: 15;
+    bool; pub get_copy, set_copy: 14;
+    bool; pub get_perm_write_protect, set_perm_write_protect: 13;
+    bool; pub get_tmp_write_protect, set_tmp_write_protect: 12;
+    u8; pub get_file_format, set_file_format: 11, 10;
+    u8; pub get_ecc, set_ecc: 9, 8;
+    u8; pub get_crc, set_crc: 7, 1;
+}
+
+impl CsdMmc {
+    /// Only correct for cards of up to 2 GB. Bigger cards have their size in the `EXT_CSD`.
+    pub fn card_capacity_bytes(&self) -> u64 {
+        (u64::from(self.get_c_size()) + 1) << (self.get_c_size_mult() + 2 + self.get_read_bl_len())
+    }
+
+    /// The erase group size, in bytes
+    pub fn erase_group_size_bytes(&self) -> u32 {
+        let blocks =
+            (u32::from(self.get_erase_grp_size()) + 1) * (u32::from(self.get_erase_grp_mult()) + 1);
+        blocks << self.get_write_bl_len()
+    }
+}
+
+/// The CSD register, which has a different layout depending on the `CSD_STRUCTURE` field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Csd {
+    V1(CsdV1),
+    V2(CsdV2),
+    /// The CSD of an MMC card. The `CSD_STRUCTURE` doesn't tell it apart from an SD card's, so it comes from [`Csd::from_mmc_bits`].
+    Mmc(CsdMmc),
+}
+
+impl Csd {
+    /// Returns `None` if the `CSD_STRUCTURE` is not supported
+    pub fn from_bits(bits: u128) -> Option<Self> {
+        match bits >> 126 {
+            0 => Some(Self::V1(CsdV1(bits))),
+            1 => Some(Self::V2(CsdV2(bits))),
+            _ => None,
+        }
+    }
+
+    /// Parses the register in the order the card sends it
+    pub fn from_bytes(bytes: [u8; 16]) -> Option<Self> {
+        Self::from_bits(u128::from_be_bytes(bytes))
+    }
+
+    /// Parses the CSD of an MMC card. Every `CSD_STRUCTURE` has the same layout.
+    pub fn from_mmc_bits(bits: u128) -> Self {
+        Self::Mmc(CsdMmc(bits))
+    }
+
+    /// Parses the CSD of an MMC card in the order the card sends it
+    pub fn from_mmc_bytes(bytes: [u8; 16]) -> Self {
+        Self::from_mmc_bits(u128::from_be_bytes(bytes))
+    }
+}
+
+/// Generates getters for fields that are at the same place in every CSD layout
+macro_rules! csd_common_fields {
+    ($($(#[$attr:meta])* $getter:ident: $ty:ty;)*) => {
+        impl Csd {
+            $(
+                $(#[$attr])*
+                pub fn $getter(&self) -> $ty {
+                    match self {
+                        Self::V1(csd) => csd.$getter(),
+                        Self::V2(csd) => csd.$getter(),
+                        Self::Mmc(csd) => csd.$getter(),
+                    }
+                }
+            )*
+        }
+    };
+}
+
+csd_common_fields! {
+    /// Data read access time 1, encoded as a time value and unit
+    get_taac: u8;
+    /// Data read access time 2, in units of 100 clock cycles
+    get_nsac: u8;
+    /// The raw `TRAN_SPEED` field
+    get_tran_speed: u8;
+    /// Card command classes, where bit `n` is set if class `n` is supported
+    get_ccc: u16;
+    /// Max read block length, as a power of 2
+    get_read_bl_len: u8;
+    get_read_bl_partial: bool;
+    get_write_blk_misalign: bool;
+    get_read_blk_misalign: bool;
+    get_dsr_imp: bool;
+    /// Write protect group size minus 1, in erase sectors
+    get_wp_grp_size: u8;
+    get_wp_grp_enable: bool;
+    /// Typical write time as a multiple of the read access time, as a power of 2
+    get_r2w_factor: u8;
+    /// Max write block length, as a power of 2
+    get_write_bl_len: u8;
+    get_write_bl_partial: bool;
+    get_file_format_grp: bool;
+    get_copy: bool;
+    get_perm_write_protect: bool;
+    get_tmp_write_protect: bool;
+    get_file_format: u8;
+    get_crc: u8;
+}
+
+impl Csd {
+    /// If `true`, single 512 byte blocks can be erased. Otherwise, only whole sectors can be.
+    /// MMC cards always erase whole erase groups.
+    pub fn get_erase_blk_en(&self) -> bool {
+        match self {
+            Self::V1(csd) => csd.get_erase_blk_en(),
+            Self::V2(csd) => csd.get_erase_blk_en(),
+            Self::Mmc(_) => false,
+        }
+    }
+
+    /// Erase sector size minus 1, in write blocks.
+    /// MMC cards don't have this field, so for them it's `ERASE_GRP_SIZE`, which [`Csd::erase_sector_size_bytes`] multiplies by `ERASE_GRP_MULT`.
+    pub fn get_sector_size(&self) -> u8 {
+        match self {
+            Self::V1(csd) => csd.get_sector_size(),
+            Self::V2(csd) => csd.get_sector_size(),
+            Self::Mmc(csd) => csd.get_erase_grp_size(),
+        }
+    }
+
+    /// The erase sector size, in bytes. For MMC cards, this is the erase group size.
+    pub fn erase_sector_size_bytes(&self) -> u32 {
+        match self {
+            Self::Mmc(csd) => csd.erase_group_size_bytes(),
+            _ => (u32::from(self.get_sector_size()) + 1) << self.get_write_bl_len(),
+        }
+    }
+
+    /// `true` if either the permanent or temporary write protect bit is set
+    pub fn is_write_protected(&self) -> bool {
+        self.get_perm_write_protect() || self.get_tmp_write_protect()
+    }
+
+    /// The maximum clock frequency for a single data line, in Hz.
+    /// Returns `None` if `TRAN_SPEED` has a reserved value.
+    pub fn max_transfer_rate_hz(&self) -> Option<u32> {
+        let tran_speed = self.get_tran_speed();
+        // Multiplied by 10
+        let time_value = match (tran_speed >> 3) & 0xF {
+            0x1 => 10,
+            0x2 => 12,
+            0x3 => 13,
+            0x4 => 15,
+            0x5 => 20,
+            0x6 => 25,
+            0x7 => 30,
+            0x8 => 35,
+            0x9 => 40,
+            0xA => 45,
+            0xB => 50,
+            0xC => 55,
+            0xD => 60,
+            0xE => 70,
+            0xF => 80,
+            _ => return None,
+        };
+        // 100 kbit/s, divided by 10 for the time value
+        let unit: u32 = match tran_speed & 0b111 {
+            unit @ 0..=3 => 10_000 * 10u32.pow(unit.into()),
+            _ => return None,
+        };
+        Some(time_value * unit)
+    }
+
+    pub fn card_capacity_bytes(&self) -> u64 {
+        match self {
+            Self::V1(csd) => csd.card_capacity_bytes(),
+            Self::V2(csd) => csd.card_capacity_bytes(),
+            Self::Mmc(csd) => csd.card_capacity_bytes(),
+        }
+    }
+}
+
 bitflags! {
     #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
     pub struct Command59Argument: u32 {
@@ -173,7 +570,10 @@ bitflags! {
 }
 
 bitfield! {
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct Cid(u128);
+    impl Debug;
 
     u8; pub get_mid, set_mid: 127, 120;
     u16; pub get_oid, set_oid: 119, 104;
@@ -188,14 +588,52 @@ bitfield! {
 }
 
 impl Cid {
+    /// Parses the register in the order the card sends it
+    pub fn from_bytes(bytes: [u8; 16]) -> Self {
+        Self(u128::from_be_bytes(bytes))
+    }
+
     pub fn get_mdt(&self) -> Mdt {
         Mdt(self._get_mdt())
     }
+
+    /// Manufacturer ID, assigned by the SD-3C
+    pub fn manufacturer_id(&self) -> u8 {
+        self.get_mid()
+    }
+
+    /// OEM/Application ID, which is 2 ASCII characters
+    pub fn oem_id(&self) -> [u8; 2] {
+        self.get_oid().to_be_bytes()
+    }
+
+    /// Product name, which is 5 ASCII characters
+    pub fn product_name(&self) -> [u8; 5] {
+        let bytes = self.get_pnm().to_be_bytes();
+        bytes[3..].try_into().unwrap()
+    }
+
+    /// Product revision as (major, minor)
+    pub fn product_revision(&self) -> (u8, u8) {
+        let prv = self.get_prv();
+        (prv >> 4, prv & 0xF)
+    }
+
+    pub fn serial_number(&self) -> u32 {
+        self.get_psn()
+    }
+
+    pub fn manufacturing_date(&self) -> Mdt {
+        self.get_mdt()
+    }
 }
 
 bitfield! {
     /// 12-bit Manufacturing date
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct Mdt(u16);
+    impl Debug;
 
     u8;
     /// January is `1`
@@ -220,4 +658,154 @@ impl Mdt {
     }
 }
 
+/// The 512-bit SD Status register, read with `ACMD13`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SdStatus(pub [u8; 64]);
+
+impl SdStatus {
+    /// Gets the bits `high..=low`, numbered like in the spec, where bit 511 is the first bit received
+    fn bits(&self, high: usize, low: usize) -> u32 {
+        (low..=high).rev().fold(0, |value, bit| {
+            let byte = self.0[(511 - bit) / 8];
+            (value << 1) | u32::from((byte >> (bit % 8)) & 1)
+        })
+    }
+
+    /// The raw `SPEED_CLASS` field
+    pub fn get_speed_class(&self) -> u8 {
+        self.bits(447, 440) as u8
+    }
+
+    /// The speed class (0, 2, 4, 6, or 10). Returns `None` if the field has a reserved value.
+    pub fn speed_class(&self) -> Option<u8> {
+        match self.get_speed_class() {
+            0 => Some(0),
+            1 => Some(2),
+            2 => Some(4),
+            3 => Some(6),
+            4 => Some(10),
+            _ => None,
+        }
+    }
+
+    /// The raw `AU_SIZE` field
+    pub fn get_au_size(&self) -> u8 {
+        self.bits(431, 428) as u8
+    }
+
+    /// The size of an allocation unit in bytes. Returns `None` if it is not defined.
+    pub fn au_size_bytes(&self) -> Option<u32> {
+        const KIB: u32 = 1024;
+        const MIB: u32 = 1024 * KIB;
+        match self.get_au_size() {
+            0 => None,
+            au_size @ 1..=0xA => Some((16 * KIB) << (au_size - 1)),
+            0xB => Some(12 * MIB),
+            0xC => Some(16 * MIB),
+            0xD => Some(24 * MIB),
+            0xE => Some(32 * MIB),
+            _ => Some(64 * MIB),
+        }
+    }
+
+    /// Number of AUs that are erased in `ERASE_TIMEOUT` seconds
+    pub fn get_erase_size(&self) -> u16 {
+        self.bits(423, 408) as u16
+    }
+
+    /// Seconds it takes to erase `ERASE_SIZE` AUs
+    pub fn get_erase_timeout(&self) -> u8 {
+        self.bits(407, 402) as u8
+    }
+
+    /// Seconds that get added to the erase timeout
+    pub fn get_erase_offset(&self) -> u8 {
+        self.bits(401, 400) as u8
+    }
+
+    /// How long erasing `blocks` 512 B blocks can take, in milliseconds.
+    /// Returns `None` if the card doesn't specify the erase timing.
+    pub fn erase_timeout_ms(&self, blocks: u64) -> Option<u64> {
+        let erase_size = u64::from(self.get_erase_size());
+        let erase_timeout = u64::from(self.get_erase_timeout());
+        if erase_size == 0 || erase_timeout == 0 {
+            return None;
+        }
+        let au_size = u64::from(self.au_size_bytes()?);
+        let aus = (blocks * 512).div_ceil(au_size);
+        Some(
+            (erase_timeout * 1000 * aus).div_ceil(erase_size)
+                + u64::from(self.get_erase_offset()) * 1000,
+        )
+    }
+}
+
 pub const START_BLOCK_TOKEN: u8 = 0b1111_1110;
+/// Start block token for each block of `CMD25`
+pub const START_BLOCK_TOKEN_MULTIPLE_WRITE: u8 = 0b1111_1100;
+/// Ends a `CMD25` transfer
+pub const STOP_TRAN_TOKEN: u8 = 0b1111_1101;
+
+bitfield! {
+    /// The 64-bit SD Configuration Register, read with `ACMD51`
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct Scr(u64);
+    impl Debug;
+
+    u8; pub get_scr_structure, set_scr_structure: 63, 60;
+    u8; pub get_sd_spec, set_sd_spec: 59, 56;
+    bool; pub get_data_stat_after_erase, set_data_stat_after_erase: 55;
+    u8; pub get_sd_security, set_sd_security: 54, 52;
+    u8; pub get_sd_bus_widths, set_sd_bus_widths: 51, 48;
+    bool; pub get_sd_spec3, set_sd_spec3: 47;
+    u8; pub get_ex_security, set_ex_security: 46, 43;
+    bool; pub get_sd_spec4, set_sd_spec4: 42;
+    u8; pub get_sd_specx, set_sd_specx: 41, 38;
+    u8; pub get_cmd_support, set_cmd_support: 35, 32;
+}
+
+impl Scr {
+    /// Parses the register in the order the card sends it
+    pub fn from_bytes(bytes: [u8; 8]) -> Self {
+        Self(u64::from_be_bytes(bytes))
+    }
+
+    /// The version of the Physical Layer Specification that the card supports, as (major, minor).
+    /// Returns `None` if the combination of the `SD_SPEC` fields is reserved.
+    pub fn sd_spec_version(&self) -> Option<(u8, u8)> {
+        match (
+            self.get_sd_spec(),
+            self.get_sd_spec3(),
+            self.get_sd_spec4(),
+            self.get_sd_specx(),
+        ) {
+            (0, false, false, 0) => Some((1, 0)),
+            (1, false, false, 0) => Some((1, 10)),
+            (2, false, false, 0) => Some((2, 0)),
+            (2, true, false, 0) => Some((3, 0)),
+            (2, true, true, 0) => Some((4, 0)),
+            (2, true, _, specx @ 1..=5) => Some((specx + 4, 0)),
+            _ => None,
+        }
+    }
+
+    /// All cards support the 1-bit bus
+    pub fn supports_1_bit_bus(&self) -> bool {
+        self.get_sd_bus_widths() & (1 << 0) != 0
+    }
+
+    pub fn supports_4_bit_bus(&self) -> bool {
+        self.get_sd_bus_widths() & (1 << 2) != 0
+    }
+
+    /// If the card supports `CMD23` (`SET_BLOCK_COUNT`)
+    pub fn supports_cmd23(&self) -> bool {
+        self.get_cmd_support() & (1 << 1) != 0
+    }
+
+    /// If the card supports `CMD20` (`SPEED_CLASS_CONTROL`)
+    pub fn supports_cmd20(&self) -> bool {
+        self.get_cmd_support() & (1 << 0) != 0
+    }
+}
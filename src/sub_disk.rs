@@ -0,0 +1,101 @@
+use crate::Disk;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SubDiskError<E> {
+    Disk(E),
+    /// The range goes past the end of the sub disk
+    OutOfRange,
+}
+
+/// A byte range of a [`Disk`], as its own [`Disk`] with addresses starting at 0.
+/// This can be used for partitions, A/B firmware slots, or a reserved area for metadata.
+///
+/// Use `&mut disk` as `D` to keep using the underlying disk afterwards.
+pub struct SubDisk<D> {
+    disk: D,
+    /// In bytes
+    start: u64,
+    /// In bytes
+    len: u64,
+}
+
+impl<D> SubDisk<D> {
+    /// `start` and `len` are in bytes. They should be multiples of the underlying disk's block size.
+    pub const fn new(disk: D, start: u64, len: u64) -> Self {
+        Self { disk, start, len }
+    }
+
+    /// Where the sub disk starts on the underlying disk, in bytes
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// The length of the sub disk, in bytes
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn into_inner(self) -> D {
+        self.disk
+    }
+
+    /// Converts an address in the sub disk to an address on the underlying disk, if the range fits in the sub disk
+    fn disk_address(&self, start: u64, len: u64) -> Option<u64> {
+        let end = start.checked_add(len)?;
+        (end <= self.len).then_some(self.start + start)
+    }
+}
+
+impl<D: Disk<Address = u64>> Disk for SubDisk<D> {
+    type Address = u64;
+    type Error = SubDiskError<D::Error>;
+    const BLOCK_SIZE: usize = D::BLOCK_SIZE;
+
+    fn block_size(&self) -> usize {
+        self.disk.block_size()
+    }
+
+    fn erase_unit_size(&self) -> Option<usize> {
+        self.disk.erase_unit_size()
+    }
+
+    async fn read(&mut self, start: u64, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        let start = self
+            .disk_address(start, buffer.len() as u64)
+            .ok_or(SubDiskError::OutOfRange)?;
+        self.disk
+            .read(start, buffer)
+            .await
+            .map_err(SubDiskError::Disk)
+    }
+
+    async fn write(&mut self, start: u64, buffer: &[u8]) -> Result<(), Self::Error> {
+        let start = self
+            .disk_address(start, buffer.len() as u64)
+            .ok_or(SubDiskError::OutOfRange)?;
+        self.disk
+            .write(start, buffer)
+            .await
+            .map_err(SubDiskError::Disk)
+    }
+
+    async fn erase(&mut self, start: u64, end: u64) -> Result<(), Self::Error> {
+        let len = end.saturating_sub(start);
+        let disk_start = self
+            .disk_address(start, len)
+            .ok_or(SubDiskError::OutOfRange)?;
+        self.disk
+            .erase(disk_start, disk_start + len)
+            .await
+            .map_err(SubDiskError::Disk)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.disk.flush().await.map_err(SubDiskError::Disk)
+    }
+}
@@ -0,0 +1,99 @@
+use core::ops::{Add, Mul, Sub};
+
+/// A span of time, with microsecond resolution
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Duration {
+    micros: u64,
+}
+
+impl Duration {
+    pub const fn from_micros(micros: u64) -> Self {
+        Self { micros }
+    }
+
+    pub const fn from_millis(millis: u64) -> Self {
+        Self {
+            micros: millis * 1_000,
+        }
+    }
+
+    pub const fn from_secs(secs: u64) -> Self {
+        Self {
+            micros: secs * 1_000_000,
+        }
+    }
+
+    pub const fn as_micros(&self) -> u64 {
+        self.micros
+    }
+
+    pub const fn as_millis(&self) -> u64 {
+        self.micros / 1_000
+    }
+}
+
+impl Add for Duration {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self::from_micros(self.micros + rhs.micros)
+    }
+}
+
+impl Mul<u32> for Duration {
+    type Output = Self;
+
+    fn mul(self, rhs: u32) -> Self {
+        Self::from_micros(self.micros * u64::from(rhs))
+    }
+}
+
+/// A point in time from a monotonic [`Clock`], with microsecond resolution
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Instant {
+    micros: u64,
+}
+
+impl Instant {
+    pub const fn from_micros(micros: u64) -> Self {
+        Self { micros }
+    }
+
+    pub const fn as_micros(&self) -> u64 {
+        self.micros
+    }
+
+    /// The time since this instant, according to `clock`
+    pub fn elapsed(&self, clock: Clock) -> Duration {
+        clock() - *self
+    }
+}
+
+impl Sub for Instant {
+    type Output = Duration;
+
+    /// Saturates to 0 if `rhs` is later than `self`
+    fn sub(self, rhs: Self) -> Duration {
+        Duration::from_micros(self.micros.saturating_sub(rhs.micros))
+    }
+}
+
+/// Returns the current time from a monotonic clock, used for timeouts.
+/// This lets the driver run without an embassy time driver, for example under RTIC or a bare executor.
+pub type Clock = fn() -> Instant;
+
+/// A [`Clock`] that uses embassy-time's time driver
+#[cfg(feature = "embassy-time")]
+pub fn embassy_clock() -> Instant {
+    Instant::from_micros(embassy_time::Instant::now().as_micros())
+}
+
+/// A [`Clock`] that uses the host's monotonic clock, counting from the first time it's called
+#[cfg(feature = "std")]
+pub fn std_clock() -> Instant {
+    static START: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+    let start = *START.get_or_init(std::time::Instant::now);
+    Instant::from_micros(start.elapsed().as_micros() as u64)
+}
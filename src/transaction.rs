@@ -0,0 +1,132 @@
+use core::{fmt::Debug, ops::DerefMut};
+
+use embassy_embedded_hal::SetConfig;
+use embedded_hal_async::{delay::DelayNs, spi::SpiBus};
+
+use crate::{
+    CardDetect, ChipSelect, Error, ErrorContext, OpenTransfer, SdCardDisk, SharedSpiBus,
+    report_error,
+};
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect> SdCardDisk<'_, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// Runs `f` with the card selected and the bus locked for the whole time.
+    ///
+    /// Normally every operation selects the card before it and deselects it after, which is 2 [`ChipSelect`] transactions.
+    /// If CS is driven through something slow like an I2C GPIO expander, that can take longer than the operation itself.
+    /// Inside of a transaction, the card is only selected before the first operation and deselected after `f` returns,
+    /// so any number of operations only cost 2 [`ChipSelect`] transactions.
+    ///
+    /// Other devices on the bus can't be used until `f` returns, because the card is selected and would see their data as commands.
+    /// Using another device on the same bus inside of `f` waits for the bus forever.
+    ///
+    /// Every operation of the disk passed to `f` reuses the locked bus, so they can't deadlock.
+    /// A transaction inside of `f` becomes part of this one, instead of deselecting the card when it ends.
+    ///
+    /// If the returned future is dropped before `f` finishes, the card stays selected and the bus stays locked until the next operation of this disk,
+    /// which deselects the card and unlocks the bus when it ends.
+    pub async fn transaction<R>(
+        &mut self,
+        f: impl AsyncFnOnce(&mut Self) -> R,
+    ) -> Result<R, Error<Spi::Bus, Cs::Error>> {
+        if self.in_transaction {
+            return Ok(f(self).await);
+        }
+        self.in_transaction = true;
+        let output = {
+            let transaction = Transaction { disk: self };
+            f(transaction.disk).await
+        };
+        let result = self.end_transaction().await;
+        report_error(self.sd_card.on_error, ErrorContext::Transaction, result)?;
+        Ok(output)
+    }
+
+    async fn end_transaction(&mut self) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        if let Some(spi) = self.transaction_bus.take() {
+            self.end_operation(spi).await?;
+        } else if self.sd_card.cs_selected {
+            // An operation failed in the middle, so the bus was unlocked with the card still selected
+            let spi = self.begin_operation().await?;
+            self.end_operation(spi).await?;
+        }
+        Ok(())
+    }
+
+    /// Uses `spi`, which is already locked at the transfer speed with the card selected, for the next operation
+    pub(crate) fn continue_selected(&mut self, spi: Spi::Guard) {
+        self.transaction_bus = Some(spi);
+        self.sd_card.cs_selected = true;
+    }
+
+    /// Locks the bus and selects the card, unless they already are because of a transaction
+    pub(crate) async fn begin_operation(
+        &mut self,
+    ) -> Result<Spi::Guard, Error<Spi::Bus, Cs::Error>> {
+        let mut spi = match self.transaction_bus.take() {
+            Some(spi) => spi,
+            None => {
+                let mut spi = self.sd_card.spi.lock_with_priority(self.bus_priority).await;
+                spi.set_config(self.sd_card.transfer_config())
+                    .map_err(Error::SpiSetConfig)?;
+                spi
+            }
+        };
+        if !self.sd_card.cs_selected {
+            self.sd_card.cs.select().await.map_err(Error::CsPin)?;
+            self.sd_card.cs_selected = true;
+        }
+        // The transfer can't be stopped when the stream is dropped, so it's stopped here
+        match self.sd_card.stream_left_open.take() {
+            Some(OpenTransfer::Read) => self.recover_read(spi.deref_mut(), true).await,
+            Some(OpenTransfer::Write) => self.recover_write(spi.deref_mut()).await,
+            None => {}
+        }
+        Ok(spi)
+    }
+
+    /// Deselects the card and unlocks the bus, or keeps both for the next operation of a transaction
+    pub(crate) async fn end_operation(
+        &mut self,
+        mut spi: Spi::Guard,
+    ) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        spi.flush().await.map_err(Error::SpiBus)?;
+        if self.in_transaction {
+            self.transaction_bus = Some(spi);
+            return Ok(());
+        }
+        self.sd_card.cs.deselect().await.map_err(Error::CsPin)?;
+        self.sd_card.cs_selected = false;
+        spi.write(&[0xFF]).await.map_err(Error::SpiBus)?;
+        spi.flush().await.map_err(Error::SpiBus)?;
+        Ok(())
+    }
+}
+
+/// Ends the transaction when dropped, even if the future of [`SdCardDisk::transaction`] is dropped before `f` finishes
+struct Transaction<'a, 'b, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+    Cs: ChipSelect,
+{
+    disk: &'a mut SdCardDisk<'b, Spi, Cs, Delayer, Cd>,
+}
+
+impl<Spi, Cs, Delayer, Cd> Drop for Transaction<'_, '_, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+    Cs: ChipSelect,
+{
+    fn drop(&mut self) {
+        // The bus stays in `transaction_bus` with the card selected, so the next operation ends it
+        self.disk.in_transaction = false;
+    }
+}
@@ -0,0 +1,287 @@
+use core::{fmt::Debug, ops::DerefMut};
+
+use embassy_embedded_hal::SetConfig;
+use embedded_hal_async::{delay::DelayNs, spi::SpiBus};
+
+use crate::{
+    BLOCK_SIZE, BusyOperation, CardCommand3Error, CardCommandOperation, CardDetect, ChipSelect,
+    EXPECTED_BYTES_UNTIL_RESPONSE, Error, MAX_RESPONSE_LEN, ReadOperation, START_BLOCK_TOKEN,
+    START_BLOCK_TOKEN_MULTIPLE_WRITE, SdCommand, SharedSpiBus, SpiSdCard, WriteOperation,
+    card_command, stop_multiple_block_write,
+};
+
+/// The clock speed of a [`SdTransport`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TransportSpeed {
+    /// 400 kHz, which every card supports before it's initialized
+    Init,
+    /// The speed for reading and writing data after init
+    Transfer,
+}
+
+/// An error from [`SdTransport::command`]
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TransportError<E> {
+    /// From the bus or host
+    Bus(E),
+    /// The card didn't respond to the command
+    ResponseTimeout,
+    /// Expected the start of the data, but got something else, such as a [`crate::DataErrorToken`]
+    UnexpectedData(u8),
+    InvalidCrc,
+    /// The card didn't send the data
+    DataTimeout,
+    /// The card was still busy after the timeout
+    BusyTimeout,
+    /// The card didn't say if it accepted the written data
+    DataResponseTimeout,
+    /// The card didn't accept the written data. Contains the status from the data response token.
+    DataRejected(u8),
+    /// The transfer didn't fit the command, such as a write without any data, or a multiple block transfer that isn't whole blocks
+    NothingToTransfer,
+    /// The card sent this byte after the response, and [`crate::SdCardConfig::trailing_bytes`] is [`crate::TrailingBytes::Error`]
+    UnexpectedTrailingData(u8),
+}
+
+/// What happens after the response to a command
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DataTransfer<'a> {
+    None,
+    /// Waits until the card is done being busy, for commands with an R1b response
+    Busy,
+    /// Receives data into the buffer. For [`SdCommand::ReadMultipleBlock`] it's received in blocks, otherwise as a single block of its length, like a register.
+    /// The buffer can't be empty, and for [`SdCommand::ReadMultipleBlock`] its length must be a multiple of [`BLOCK_SIZE`].
+    Read(&'a mut [u8]),
+    /// Sends the data. For [`SdCommand::WriteMultipleBlock`] it's sent in blocks, and the transfer is stopped afterwards.
+    /// Like [`DataTransfer::Read`], the data can't be empty, and must be whole blocks for [`SdCommand::WriteMultipleBlock`].
+    Write(&'a [u8]),
+}
+
+/// The ways to talk to a card, without the details of the bus, so that code that sends commands can work with other kinds of hosts, like a native SD host controller.
+///
+/// The card must be [selected](Self::select) while sending commands, and [deselected](Self::deselect) so that other devices can use the bus.
+/// [`SdCommand::ReadMultipleBlock`] keeps sending data until it's stopped with [`SdCommand::StopTransmission`], which is up to the caller.
+/// Like [`SharedSpiBus`], this uses `async fn`, so it's used through generics and not as a `dyn` trait object.
+pub trait SdTransport {
+    type Error;
+
+    /// Used from the next time the card is selected, or right away if it already is
+    async fn set_speed(&mut self, speed: TransportSpeed) -> Result<(), Self::Error>;
+
+    async fn select(&mut self) -> Result<(), Self::Error>;
+
+    async fn deselect(&mut self) -> Result<(), Self::Error>;
+
+    /// Sends the command, receives the response, and transfers the data of the command.
+    /// Returns the response. Only the first [`crate::ResponseType::size`] bytes of it are valid.
+    async fn command(
+        &mut self,
+        command: SdCommand,
+        data: DataTransfer<'_>,
+    ) -> Result<[u8; MAX_RESPONSE_LEN], TransportError<Self::Error>>;
+}
+
+/// The SPI engine of [`SpiSdCard`] as a [`SdTransport`]. Get one from [`SpiSdCard::transport`].
+pub struct SpiTransport<'a, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+    Cs: ChipSelect,
+{
+    sd_card: &'a mut SpiSdCard<Spi, Cs, Delayer, Cd>,
+    speed: TransportSpeed,
+    /// Kept locked while the card is selected
+    bus: Option<Spi::Guard>,
+}
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect> SpiSdCard<Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// Sends commands through [`SdTransport`], without the checks and retries of [`crate::SdCardDisk`]
+    pub fn transport(&mut self) -> SpiTransport<'_, Spi, Cs, Delayer, Cd> {
+        SpiTransport {
+            sd_card: self,
+            speed: TransportSpeed::Init,
+            bus: None,
+        }
+    }
+}
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect> SpiTransport<'_, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    fn apply_speed(&mut self) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        let Some(bus) = &mut self.bus else {
+            return Ok(());
+        };
+        let config = match self.speed {
+            TransportSpeed::Init => &self.sd_card._400_khz_config,
+            TransportSpeed::Transfer => self.sd_card.transfer_config(),
+        };
+        bus.set_config(config).map_err(Error::SpiSetConfig)
+    }
+}
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect> SdTransport
+    for SpiTransport<'_, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    type Error = Error<Spi::Bus, Cs::Error>;
+
+    async fn set_speed(&mut self, speed: TransportSpeed) -> Result<(), Self::Error> {
+        self.speed = speed;
+        self.apply_speed()
+    }
+
+    async fn select(&mut self) -> Result<(), Self::Error> {
+        if self.bus.is_none() {
+            self.bus = Some(self.sd_card.spi.lock().await);
+            self.apply_speed()?;
+        }
+        self.sd_card.cs.select().await.map_err(Error::CsPin)
+    }
+
+    async fn deselect(&mut self) -> Result<(), Self::Error> {
+        self.sd_card.cs.deselect().await.map_err(Error::CsPin)?;
+        if let Some(mut bus) = self.bus.take() {
+            bus.write(&[0xFF]).await.map_err(Error::SpiBus)?;
+            bus.flush().await.map_err(Error::SpiBus)?;
+        }
+        Ok(())
+    }
+
+    async fn command(
+        &mut self,
+        command: SdCommand,
+        data: DataTransfer<'_>,
+    ) -> Result<[u8; MAX_RESPONSE_LEN], TransportError<Self::Error>> {
+        let multiple_blocks = matches!(
+            command,
+            SdCommand::ReadMultipleBlock { .. } | SdCommand::WriteMultipleBlock { .. }
+        );
+        let data_len = match &data {
+            DataTransfer::Read(buffer) => Some(buffer.len()),
+            DataTransfer::Write(buffer) => Some(buffer.len()),
+            DataTransfer::None | DataTransfer::Busy => None,
+        };
+        // Otherwise the data would be split into parts of 0 bytes, or the last partial block would be left out
+        if let Some(len) = data_len
+            && (len == 0 || multiple_blocks && len % BLOCK_SIZE != 0)
+        {
+            return Err(TransportError::NothingToTransfer);
+        }
+        if self.bus.is_none() {
+            self.select().await.map_err(TransportError::Bus)?;
+        }
+        let config = &self.sd_card.config;
+        let timeouts = &config.timeouts;
+        let busy = BusyOperation {
+            expected_bytes_until_not_busy: config.gaps.not_busy,
+            timeout: timeouts.busy,
+        };
+        let operation = match data {
+            DataTransfer::None => None,
+            DataTransfer::Busy => Some(CardCommandOperation::BusySignal(busy.clone())),
+            DataTransfer::Read(buffer) => {
+                let (expected_bytes_until_data, timeout) = match command {
+                    SdCommand::SendCsd | SdCommand::SendCid => {
+                        (config.gaps.read_data, timeouts.register)
+                    }
+                    SdCommand::SdStatus | SdCommand::SendScr => {
+                        (config.gaps.app_data, timeouts.app_data)
+                    }
+                    _ => (config.gaps.read_data, timeouts.read),
+                };
+                let part_size = if multiple_blocks {
+                    BLOCK_SIZE
+                } else {
+                    buffer.len()
+                };
+                Some(CardCommandOperation::Read(ReadOperation {
+                    expected_bytes_until_data,
+                    timeout,
+                    parts: buffer.len() / part_size,
+                    part_size,
+                    buffer,
+                    crc_enabled: self.sd_card.crc_enabled,
+                    skip_bytes: 0,
+                    bytes_until_data: None,
+                }))
+            }
+            DataTransfer::Write(buffer) => Some(CardCommandOperation::Write(WriteOperation {
+                buffer,
+                part_size: if multiple_blocks {
+                    BLOCK_SIZE
+                } else {
+                    buffer.len()
+                },
+                start_token: if multiple_blocks {
+                    START_BLOCK_TOKEN_MULTIPLE_WRITE
+                } else {
+                    START_BLOCK_TOKEN
+                },
+                busy: busy.clone(),
+            })),
+        };
+        let write_multiple =
+            multiple_blocks && matches!(operation, Some(CardCommandOperation::Write(_)));
+
+        let bus = self.bus.as_mut().unwrap().deref_mut();
+        let spi_buffer = &mut self.sd_card.scratch;
+        let result = match card_command(
+            bus,
+            spi_buffer,
+            config,
+            command,
+            EXPECTED_BYTES_UNTIL_RESPONSE,
+            timeouts.command,
+            operation,
+        )
+        .await
+        {
+            Ok(response) if write_multiple && response[0] == 0 => {
+                stop_multiple_block_write(bus, spi_buffer, config, &busy)
+                    .await
+                    .map(|()| response)
+            }
+            result => result,
+        };
+        self.sd_card.zeroize_scratch();
+        result.map_err(map_spi_error)
+    }
+}
+
+fn map_spi_error<Bus: SpiBus + SetConfig, CsError>(
+    error: CardCommand3Error<Bus::Error>,
+) -> TransportError<Error<Bus, CsError>>
+where
+    <Bus as SetConfig>::ConfigError: Debug,
+{
+    match error {
+        CardCommand3Error::Spi(e) => TransportError::Bus(Error::SpiBus(e)),
+        CardCommand3Error::ReceiveResponseTimeout(_) => TransportError::ResponseTimeout,
+        CardCommand3Error::ExpectedStartBlockToken(byte) => TransportError::UnexpectedData(byte),
+        CardCommand3Error::InvalidCrc => TransportError::InvalidCrc,
+        CardCommand3Error::ReceiveDataTimeout(_) => TransportError::DataTimeout,
+        CardCommand3Error::BusyTimeout => TransportError::BusyTimeout,
+        CardCommand3Error::DataResponseTimeout => TransportError::DataResponseTimeout,
+        CardCommand3Error::DataRejected(status) => TransportError::DataRejected(status),
+        CardCommand3Error::NothingToTransfer => TransportError::NothingToTransfer,
+        CardCommand3Error::UnexpectedTrailingData(byte) => {
+            TransportError::UnexpectedTrailingData(byte)
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use core::fmt::Debug;
+
+use embassy_embedded_hal::SetConfig;
+use embedded_hal_async::delay::DelayNs;
+
+use crate::{
+    BLOCK_SIZE, CardDetect, ChipSelect, Error, ErrorContext, ScatterBuffer, SdCardDisk,
+    SharedSpiBus, report_error,
+};
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect> SdCardDisk<'_, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// Reads from `start` into `buffers` one after another, as if they were a single buffer.
+    /// The data is received straight into the buffers, even where a block is split between them,
+    /// so it can land in non-contiguous segments (such as the two halves of a ring buffer) without an extra copy.
+    ///
+    /// `start` and the lengths of the buffers can be anything. The blocks are read with a single `CMD18`.
+    pub async fn read_vectored(
+        &mut self,
+        start: u64,
+        buffers: &mut [&mut [u8]],
+    ) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        let context = ErrorContext::Read {
+            start,
+            len: buffers.iter().map(|buffer| buffer.len()).sum(),
+        };
+        let result = self.read_vectored_inner(start, buffers).await;
+        report_error(self.sd_card.on_error, context, result)
+    }
+
+    async fn read_vectored_inner(
+        &mut self,
+        start: u64,
+        buffers: &mut [&mut [u8]],
+    ) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        let len = buffers
+            .iter()
+            .map(|buffer| buffer.len() as u64)
+            .sum::<u64>();
+        if len == 0 {
+            return Ok(());
+        }
+        let block_size = BLOCK_SIZE as u64;
+        let (Ok(start_block), Ok(end_block)) = (
+            u32::try_from(start / block_size),
+            u32::try_from((start + len).div_ceil(block_size)),
+        ) else {
+            return Err(Error::ReadAddressError);
+        };
+
+        let mut data = ScatterBuffer::new(buffers, (start % block_size) as usize);
+        let mut stream = self
+            .open_stream(start_block, self.sd_card.config.timeouts.read)
+            .await?;
+        let mut result = Ok(());
+        for _ in start_block..end_block {
+            result = stream.next_scattered(&mut data).await;
+            if result.is_err() {
+                break;
+            }
+        }
+        let close_result = stream.close().await;
+        result
+            .map_err(|e| match e {
+                Error::StreamDeadlineMissed => Error::ReadReceiveDataTimeout,
+                e => e,
+            })
+            .and(close_result)
+    }
+}
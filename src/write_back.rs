@@ -0,0 +1,64 @@
+use crate::{BlockCache, Disk};
+
+/// Keeps written blocks in RAM, so that writes of parts of a block, and repeated writes to the same block, cost one write to the disk.
+/// This suits FAT table updates, which write a few bytes of the same blocks over and over.
+///
+/// A block is written to the disk when its slot is needed for another block (the least recently written one is evicted),
+/// or on [`WriteBackDisk::flush`]. Writing part of a block that isn't buffered reads the rest of it first.
+/// Writes that touch more than `BLOCKS` blocks go straight to the disk.
+/// Reads see the buffered writes, but aren't cached. To cache reads too, use [`BlockCache`].
+///
+/// Call [`WriteBackDisk::flush`] before dropping this or removing the card, or the buffered writes are lost.
+pub struct WriteBackDisk<D, const BLOCKS: usize> {
+    cache: BlockCache<D, BLOCKS>,
+}
+
+impl<D: Disk<Address = u64>, const BLOCKS: usize> WriteBackDisk<D, BLOCKS> {
+    pub const fn new(disk: D) -> Self {
+        Self {
+            cache: BlockCache::write_only(disk),
+        }
+    }
+
+    /// How many blocks are buffered and not written to the disk yet
+    pub fn dirty_blocks(&self) -> usize {
+        self.cache.dirty_blocks()
+    }
+
+    /// Only call this after [`WriteBackDisk::flush`] succeeded, or the buffered writes are lost
+    pub fn into_inner(self) -> D {
+        self.cache.into_inner()
+    }
+}
+
+impl<D: Disk<Address = u64>, const BLOCKS: usize> Disk for WriteBackDisk<D, BLOCKS> {
+    type Address = u64;
+    type Error = D::Error;
+    const BLOCK_SIZE: usize = D::BLOCK_SIZE;
+
+    fn block_size(&self) -> usize {
+        self.cache.block_size()
+    }
+
+    fn erase_unit_size(&self) -> Option<usize> {
+        self.cache.erase_unit_size()
+    }
+
+    async fn read(&mut self, start: u64, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.cache.read(start, buffer).await
+    }
+
+    async fn write(&mut self, start: u64, buffer: &[u8]) -> Result<(), Self::Error> {
+        self.cache.write(start, buffer).await
+    }
+
+    async fn erase(&mut self, start: u64, end: u64) -> Result<(), Self::Error> {
+        self.cache.erase(start, end).await
+    }
+
+    /// Writes every buffered block to the disk, and then flushes the disk.
+    /// If a write fails, that block and the ones that weren't written yet stay buffered.
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.cache.flush().await
+    }
+}
@@ -0,0 +1,44 @@
+use core::fmt::Debug;
+
+use embassy_embedded_hal::SetConfig;
+use embedded_hal_async::delay::DelayNs;
+
+use crate::{CardDetect, ChipSelect, Error, ErrorContext, SdCardDisk, SharedSpiBus, report_error};
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect> SdCardDisk<'_, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// Returns `true` if the card can't be written to, because of the socket's write protect switch or the write protect bits in the CSD.
+    /// The CSD is only read the first time.
+    pub async fn is_write_protected(&mut self) -> Result<bool, Error<Spi::Bus, Cs::Error>> {
+        let result = self.is_write_protected_inner().await;
+        report_error(self.sd_card.on_error, ErrorContext::Csd, result)
+    }
+
+    async fn is_write_protected_inner(&mut self) -> Result<bool, Error<Spi::Bus, Cs::Error>> {
+        if self.sd_card.card_detect.is_write_protected() {
+            return Ok(true);
+        }
+        let csd_write_protected = match self.csd_write_protected {
+            Some(csd_write_protected) => csd_write_protected,
+            None => {
+                let csd_write_protected = self.csd_inner().await?.is_write_protected();
+                self.csd_write_protected = Some(csd_write_protected);
+                csd_write_protected
+            }
+        };
+        Ok(csd_write_protected)
+    }
+
+    /// Fails with [`Error::WriteProtected`] if the card is read-only
+    pub(crate) async fn check_write_protect(&mut self) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        if self.is_write_protected_inner().await? {
+            Err(Error::WriteProtected)
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use core::{fmt::Debug, ops::DerefMut};
+
+use embassy_embedded_hal::SetConfig;
+use embedded_hal_async::{
+    delay::DelayNs,
+    spi::{ErrorType, SpiBus},
+};
+
+use crate::{
+    BLOCK_SIZE, BusyOperation, CardCommand3Error, CardDetect, ChipSelect,
+    EXPECTED_BYTES_UNTIL_RESPONSE, Error, OpenTransfer, R1, START_BLOCK_TOKEN_MULTIPLE_WRITE,
+    SdCardDisk, SdCommand, SharedSpiBus, card_command, send_data_block, stop_multiple_block_write,
+    write_response_error,
+};
+
+/// A `CMD25` transfer that is kept open, so that blocks can be written one at a time without sending a command for each one.
+/// Like [`crate::BlockStream`], it holds the bus and keeps the card selected,
+/// so a data logger can write each block as soon as it's full at a steady rate.
+///
+/// Call [`WriteStream::close`] when you are done, which waits until the card has programmed every block.
+/// If the stream is dropped without closing it, the bus stays locked until the next operation of the disk stops the transfer.
+/// Dropping it in the middle of [`WriteStream::write_block`] can leave that block partly sent, and the card may write it with whatever it gets next.
+#[must_use]
+pub struct WriteStream<'s, 'a, Spi, Cs, Delayer, Cd = ()>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+    Cs: ChipSelect,
+{
+    disk: &'s mut SdCardDisk<'a, Spi, Cs, Delayer, Cd>,
+    /// Only `None` after closing
+    spi: Option<Spi::Guard>,
+}
+
+/// The error for a failed data block, or for the busy signal after it
+fn write_error<Bus, CsError>(e: CardCommand3Error<<Bus as ErrorType>::Error>) -> Error<Bus, CsError>
+where
+    Bus: SpiBus + SetConfig,
+    <Bus as SetConfig>::ConfigError: Debug,
+{
+    match e {
+        CardCommand3Error::Spi(e) => Error::SpiBus(e),
+        CardCommand3Error::ReceiveResponseTimeout(_) => Error::WriteResponseTimeout,
+        CardCommand3Error::DataResponseTimeout => Error::WriteDataResponseTimeout,
+        CardCommand3Error::DataRejected(0b101) => Error::WriteInvalidCrc,
+        CardCommand3Error::DataRejected(_) => Error::WriteDataRejected,
+        CardCommand3Error::BusyTimeout => Error::WriteBusyTimeout,
+        CardCommand3Error::NothingToTransfer => Error::NothingToTransfer,
+        CardCommand3Error::UnexpectedTrailingData(byte) => Error::UnexpectedTrailingData(byte),
+        _ => unreachable!(),
+    }
+}
+
+impl<'a, Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect> SdCardDisk<'a, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// Starts writing consecutive blocks from `start_block`
+    pub async fn open_write_stream(
+        &mut self,
+        start_block: u32,
+    ) -> Result<WriteStream<'_, 'a, Spi, Cs, Delayer, Cd>, Error<Spi::Bus, Cs::Error>> {
+        self.check_write_protect().await?;
+        let address = self
+            .block_argument(start_block)
+            .ok_or(Error::WriteAddressError)?;
+        let mut spi = self.begin_transfer().await?;
+        let response = card_command(
+            spi.deref_mut(),
+            &mut self.sd_card.scratch,
+            &self.sd_card.config,
+            SdCommand::WriteMultipleBlock { address },
+            EXPECTED_BYTES_UNTIL_RESPONSE,
+            self.sd_card.config.timeouts.command,
+            None,
+        )
+        .await
+        .map_err(write_error)?;
+        let r1 = R1::from_bits_retain(response[0]);
+        if !r1.is_empty() {
+            return Err(write_response_error(r1));
+        }
+
+        Ok(WriteStream {
+            disk: self,
+            spi: Some(spi),
+        })
+    }
+
+    /// Stops a `CMD25` transfer that a dropped [`WriteStream`] left open
+    pub(crate) async fn recover_write(&mut self, spi: &mut Spi::Bus) {
+        let busy = self.write_busy();
+        if stop_multiple_block_write(spi, &mut self.sd_card.scratch, &self.sd_card.config, &busy)
+            .await
+            .is_err()
+        {
+            warn!("failed to stop the multiple block write of a dropped stream");
+        }
+    }
+
+    fn write_busy(&self) -> BusyOperation {
+        BusyOperation {
+            expected_bytes_until_not_busy: self.sd_card.config.gaps.not_busy,
+            timeout: self.sd_card.config.timeouts.busy,
+        }
+    }
+}
+
+impl<Spi, Cs: ChipSelect, Delayer: DelayNs, Cd: CardDetect>
+    WriteStream<'_, '_, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+{
+    /// Writes the next block of the stream, and waits until the card is done programming it.
+    /// If the card rejects the block, the stream should still be closed with [`WriteStream::close`].
+    pub async fn write_block(
+        &mut self,
+        block: &[u8; BLOCK_SIZE],
+    ) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        let busy = self.disk.write_busy();
+        let result = send_data_block(
+            self.spi.as_deref_mut().unwrap(),
+            &mut self.disk.sd_card.scratch,
+            &self.disk.sd_card.config,
+            block,
+            START_BLOCK_TOKEN_MULTIPLE_WRITE,
+            self.disk.sd_card.config.timeouts.command,
+            &busy,
+        )
+        .await
+        .map_err(write_error);
+        self.disk.sd_card.zeroize_scratch();
+        result
+    }
+
+    /// Sends the stop transmission token, waits until the card is done programming, and releases the bus
+    pub async fn close(mut self) -> Result<(), Error<Spi::Bus, Cs::Error>> {
+        let mut spi = self.spi.take().unwrap();
+        let busy = self.disk.write_busy();
+        stop_multiple_block_write(
+            spi.deref_mut(),
+            &mut self.disk.sd_card.scratch,
+            &self.disk.sd_card.config,
+            &busy,
+        )
+        .await
+        .map_err(write_error)?;
+
+        self.disk.end_operation(spi).await
+    }
+}
+
+impl<Spi, Cs, Delayer, Cd> Drop for WriteStream<'_, '_, Spi, Cs, Delayer, Cd>
+where
+    Spi: SharedSpiBus<u8>,
+    Spi::Bus: SetConfig,
+    <Spi::Bus as SetConfig>::ConfigError: Debug,
+    Cs: ChipSelect,
+{
+    fn drop(&mut self) {
+        if let Some(spi) = self.spi.take() {
+            self.disk.sd_card.stream_left_open = Some(OpenTransfer::Write);
+            // The card is still selected, so the bus stays locked until the next operation stops the transfer.
+            // Otherwise, other devices could clock data into the card.
+            self.disk.transaction_bus = Some(spi);
+        }
+    }
+}
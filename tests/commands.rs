@@ -0,0 +1,18 @@
+//! Commands checked against the bytes that cards expect, including the CRC7
+
+use spi_sd_card::{SdCommand, format_command};
+
+#[test]
+fn crc7() {
+    // The two commands that always need a valid CRC, with the CRCs given in the spec
+    assert_eq!(
+        SdCommand::GoIdleState.format(),
+        [0x40, 0x00, 0x00, 0x00, 0x00, 0x95]
+    );
+    assert_eq!(
+        format_command(8, 0x1AA),
+        [0x48, 0x00, 0x00, 0x01, 0xAA, 0x87]
+    );
+    // CMD17 for block 0
+    assert_eq!(format_command(17, 0), [0x51, 0x00, 0x00, 0x00, 0x00, 0x55]);
+}
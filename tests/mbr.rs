@@ -0,0 +1,51 @@
+//! A partition table as written by `fdisk`, with a FAT32 partition and an exFAT partition
+
+use spi_sd_card::{Mbr, PartitionEntry};
+
+fn sample_mbr() -> [u8; 512] {
+    let mut sector = [0; 512];
+    // Bootable, FAT32 (LBA), starting at 8192 for 1 GiB
+    sector[446..462].copy_from_slice(&[
+        0x80, 0x20, 0x21, 0x00, 0x0C, 0xFE, 0xFF, 0xFF, 0x00, 0x20, 0x00, 0x00, 0x00, 0x00, 0x20,
+        0x00,
+    ]);
+    // exFAT, right after the first partition for 2 GiB
+    sector[462..478].copy_from_slice(&[
+        0x00, 0xFE, 0xFF, 0xFF, 0x07, 0xFE, 0xFF, 0xFF, 0x00, 0x20, 0x20, 0x00, 0x00, 0x00, 0x40,
+        0x00,
+    ]);
+    sector[510] = 0x55;
+    sector[511] = 0xAA;
+    sector
+}
+
+#[test]
+fn primary_partitions() {
+    let mbr = Mbr::parse(&sample_mbr()).unwrap();
+    assert_eq!(
+        mbr.partitions,
+        [
+            Some(PartitionEntry {
+                bootable: true,
+                partition_type: 0x0C,
+                start_lba: 8192,
+                sectors: 2 * 1024 * 1024,
+            }),
+            Some(PartitionEntry {
+                bootable: false,
+                partition_type: 0x07,
+                start_lba: 8192 + 2 * 1024 * 1024,
+                sectors: 4 * 1024 * 1024,
+            }),
+            None,
+            None,
+        ]
+    );
+}
+
+#[test]
+fn missing_signature() {
+    let mut sector = sample_mbr();
+    sector[511] = 0;
+    assert_eq!(Mbr::parse(&sector), None);
+}
@@ -0,0 +1,616 @@
+//! Disk adapters tested against a `RamDisk`
+
+use core::{
+    pin::pin,
+    task::{Context, Poll, Waker},
+};
+
+use spi_sd_card::{
+    AppendLog, AppendLogError, BLOCK_SIZE, BlockCache, CacheStats, CachedDisk, CircularLog,
+    CircularLogError, Disk, LogCursor, RamDisk, RamDiskError, SubDisk, SubDiskError, WriteBackDisk,
+};
+// The checksum errors are logged, and the driver's defmt logs need a global logger, which the host doesn't have
+#[cfg(not(feature = "defmt"))]
+use spi_sd_card::{ChecksumDisk, ChecksumError};
+
+/// `RamDisk` never waits, so its futures are ready the first time they are polled
+fn block_on<F: Future>(future: F) -> F::Output {
+    match pin!(future).poll(&mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(output) => output,
+        Poll::Pending => panic!("RamDisk future was pending"),
+    }
+}
+
+#[test]
+fn read_write_erase() {
+    let mut disk = RamDisk::<2048>::new();
+    block_on(disk.write(510, &[1, 2, 3, 4])).unwrap();
+    let mut buffer = [0; 4];
+    block_on(disk.read(510, &mut buffer)).unwrap();
+    assert_eq!(buffer, [1, 2, 3, 4]);
+
+    block_on(disk.erase(512, 1024)).unwrap();
+    assert_eq!(disk.as_bytes()[510..514], [1, 2, 0xFF, 0xFF]);
+
+    assert_eq!(
+        block_on(disk.read(2046, &mut buffer)),
+        Err(RamDiskError::OutOfRange)
+    );
+}
+
+#[test]
+fn sub_disk_translates_addresses() {
+    let mut disk = RamDisk::<2048>::new();
+    let mut sub_disk = SubDisk::new(&mut disk, 512, 1024);
+    block_on(sub_disk.write(0, &[7; 512])).unwrap();
+    assert!(matches!(
+        block_on(sub_disk.write(1000, &[7; 512])),
+        Err(SubDiskError::OutOfRange)
+    ));
+
+    assert!(disk.as_bytes()[..512].iter().all(|&byte| byte == 0));
+    assert!(disk.as_bytes()[512..1024].iter().all(|&byte| byte == 7));
+    assert!(disk.as_bytes()[1024..].iter().all(|&byte| byte == 0));
+}
+
+#[cfg(feature = "embassy-sync")]
+#[test]
+fn cancelled_lease_frees_its_slots() {
+    use core::cell::Cell;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use spi_sd_card::ReadLeaseCache;
+
+    /// A disk whose reads never finish while `stalled` is set, like a read that gets cancelled
+    struct StallingDisk<'a, D> {
+        disk: D,
+        stalled: &'a Cell<bool>,
+    }
+
+    impl<D: Disk<Address = u64>> Disk for StallingDisk<'_, D> {
+        type Address = u64;
+        type Error = D::Error;
+        const BLOCK_SIZE: usize = D::BLOCK_SIZE;
+
+        async fn read(&mut self, start: u64, buffer: &mut [u8]) -> Result<(), Self::Error> {
+            if self.stalled.get() {
+                core::future::pending::<()>().await;
+            }
+            self.disk.read(start, buffer).await
+        }
+
+        async fn write(&mut self, start: u64, buffer: &[u8]) -> Result<(), Self::Error> {
+            self.disk.write(start, buffer).await
+        }
+    }
+
+    let mut ram_disk = RamDisk::<{ 8 * BLOCK_SIZE }>::new();
+    block_on(ram_disk.write(0, &[3; 8 * BLOCK_SIZE])).unwrap();
+    let stalled = Cell::new(true);
+    let cache = ReadLeaseCache::<NoopRawMutex, _, 2>::new(StallingDisk {
+        disk: ram_disk,
+        stalled: &stalled,
+    });
+    {
+        let mut lease = pin!(cache.lease(0, 2));
+        assert!(
+            lease
+                .as_mut()
+                .poll(&mut Context::from_waker(Waker::noop()))
+                .is_pending()
+        );
+    }
+
+    // The slots that the cancelled lease was reading into can be used again
+    stalled.set(false);
+    let lease = block_on(cache.lease(4, 2)).unwrap();
+    assert_eq!(lease[..], [3; 2 * BLOCK_SIZE]);
+}
+
+/// Counts the reads and writes that reach the disk
+struct CountingDisk<D> {
+    disk: D,
+    reads: usize,
+    writes: usize,
+    flushes: usize,
+    /// Returned by `erase_unit_size`, to check that it reaches the layers above
+    erase_unit: Option<usize>,
+}
+
+impl<D> CountingDisk<D> {
+    fn new(disk: D) -> Self {
+        Self {
+            disk,
+            reads: 0,
+            writes: 0,
+            flushes: 0,
+            erase_unit: None,
+        }
+    }
+}
+
+impl<D: Disk<Address = u64>> Disk for CountingDisk<D> {
+    type Address = u64;
+    type Error = D::Error;
+    const BLOCK_SIZE: usize = D::BLOCK_SIZE;
+
+    fn erase_unit_size(&self) -> Option<usize> {
+        self.erase_unit
+    }
+
+    async fn read(&mut self, start: u64, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.reads += 1;
+        self.disk.read(start, buffer).await
+    }
+
+    async fn write(&mut self, start: u64, buffer: &[u8]) -> Result<(), Self::Error> {
+        self.writes += 1;
+        self.disk.write(start, buffer).await
+    }
+
+    async fn erase(&mut self, start: u64, end: u64) -> Result<(), Self::Error> {
+        self.disk.erase(start, end).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.flushes += 1;
+        self.disk.flush().await
+    }
+}
+
+#[test]
+fn layers_report_the_disk_geometry() {
+    let mut disk = CountingDisk::new(RamDisk::<{ 8 * BLOCK_SIZE }>::new());
+    assert_eq!(disk.block_size(), BLOCK_SIZE);
+    assert_eq!(disk.erase_unit_size(), None);
+    disk.erase_unit = Some(4 * BLOCK_SIZE);
+
+    let cache = BlockCache::<_, 2>::new(WriteBackDisk::<_, 2>::new(CachedDisk::<_, 2>::new(
+        &mut disk,
+    )));
+    let sub_disk = SubDisk::new(cache, 0, 8 * BLOCK_SIZE as u64);
+    assert_eq!(sub_disk.block_size(), BLOCK_SIZE);
+    assert_eq!(sub_disk.erase_unit_size(), Some(4 * BLOCK_SIZE));
+}
+
+#[test]
+fn flush_reaches_every_layer() {
+    let mut disk = CountingDisk::new(RamDisk::<{ 8 * BLOCK_SIZE }>::new());
+    let mut sub_disk = SubDisk::new(
+        BlockCache::<_, 2>::new(WriteBackDisk::<_, 2>::new(&mut disk)),
+        BLOCK_SIZE as u64,
+        4 * BLOCK_SIZE as u64,
+    );
+    block_on(sub_disk.write(10, &[3; 20])).unwrap();
+    // Generic code can make sure everything is written without knowing which layers there are
+    async fn flush_all<D: Disk>(disk: &mut D) -> Result<(), D::Error> {
+        disk.flush().await
+    }
+    block_on(flush_all(&mut sub_disk)).unwrap();
+    assert_eq!((disk.writes, disk.flushes), (1, 1));
+    assert_eq!(
+        disk.disk.as_bytes()[BLOCK_SIZE + 10..BLOCK_SIZE + 30],
+        [3; 20]
+    );
+}
+
+#[test]
+fn cached_disk_reads_ahead() {
+    let mut disk = RamDisk::<{ 10 * BLOCK_SIZE }>::new();
+    for (i, byte) in disk.as_bytes_mut().iter_mut().enumerate() {
+        *byte = (i / 7) as u8;
+    }
+    let expected = disk.as_bytes().to_vec();
+    let mut cached = CachedDisk::<_, 4>::new(CountingDisk::new(disk));
+    let mut buffer = [0; 100];
+
+    // The first read isn't sequential, so only its block is read
+    block_on(cached.read(3 * 512 + 20, &mut buffer)).unwrap();
+    assert_eq!(buffer, expected[3 * 512 + 20..3 * 512 + 120]);
+    // Reads through blocks 4 to 7 in pieces, which only reads ahead once
+    for start in (4 * 512..8 * 512).step_by(100) {
+        let len = buffer.len().min(8 * 512 - start);
+        block_on(cached.read(start as u64, &mut buffer[..len])).unwrap();
+        assert_eq!(buffer[..len], expected[start..start + len]);
+    }
+    assert_eq!(cached.into_inner().reads, 2);
+
+    let mut cached = CachedDisk::<_, 4>::new(CountingDisk::new(
+        RamDisk::<{ 10 * BLOCK_SIZE }>::from_bytes(expected.clone().try_into().unwrap()),
+    ));
+    block_on(cached.read(7 * 512, &mut buffer)).unwrap();
+    // Reading ahead would go past the end of the disk
+    block_on(cached.read(8 * 512, &mut buffer)).unwrap();
+    assert_eq!(buffer, expected[8 * 512..8 * 512 + 100]);
+    // Writes update the cache
+    block_on(cached.write(8 * 512 + 50, &[0xEE; 10])).unwrap();
+    block_on(cached.read(8 * 512, &mut buffer)).unwrap();
+    assert_eq!(buffer[50..60], [0xEE; 10]);
+    assert_eq!(buffer[60..], expected[8 * 512 + 60..8 * 512 + 100]);
+}
+
+#[test]
+fn write_back_coalesces_writes() {
+    let mut disk =
+        WriteBackDisk::<_, 2>::new(CountingDisk::new(RamDisk::<{ 8 * BLOCK_SIZE }>::new()));
+    // Many small writes to the same 2 blocks
+    for i in 0..100u64 {
+        block_on(disk.write(BLOCK_SIZE as u64 + i * 7, &[i as u8; 7])).unwrap();
+    }
+    assert_eq!(disk.dirty_blocks(), 2);
+    let mut buffer = [0; 7];
+    block_on(disk.read(BLOCK_SIZE as u64 + 99 * 7, &mut buffer)).unwrap();
+    assert_eq!(buffer, [99; 7]);
+
+    // A third block evicts the block that was written least recently
+    block_on(disk.write(5 * BLOCK_SIZE as u64, &[0xAB; BLOCK_SIZE])).unwrap();
+    assert_eq!(disk.dirty_blocks(), 2);
+    block_on(disk.flush()).unwrap();
+    assert_eq!(disk.dirty_blocks(), 0);
+    let disk = disk.into_inner();
+    assert_eq!(disk.writes, 3);
+    let bytes = disk.disk.as_bytes();
+    for i in 0..100 {
+        let start = BLOCK_SIZE + i * 7;
+        assert_eq!(bytes[start..start + 7], [i as u8; 7]);
+    }
+    assert!(
+        bytes[5 * BLOCK_SIZE..6 * BLOCK_SIZE]
+            .iter()
+            .all(|&byte| byte == 0xAB)
+    );
+}
+
+#[test]
+fn block_cache_evicts_least_recently_used() {
+    let mut cache =
+        BlockCache::<_, 3>::new(CountingDisk::new(RamDisk::<{ 8 * BLOCK_SIZE }>::new()));
+    let mut buffer = [0; 16];
+    for block in [0, 1, 2, 0, 3] {
+        block_on(cache.read(block * BLOCK_SIZE as u64, &mut buffer)).unwrap();
+    }
+    // Block 1 was used least recently, so it was evicted for block 3
+    assert_eq!(
+        cache.stats(),
+        CacheStats {
+            hits: 1,
+            misses: 4,
+            evictions: 1,
+            write_backs: 0,
+        }
+    );
+    block_on(cache.read(2 * BLOCK_SIZE as u64, &mut buffer)).unwrap();
+    assert_eq!(cache.stats().hits, 2);
+
+    // Writes are only in the cache until they're flushed or evicted
+    block_on(cache.write(10, &[0x5A; 4])).unwrap();
+    block_on(cache.write(12, &[0x3C; 4])).unwrap();
+    block_on(cache.read(8, &mut buffer)).unwrap();
+    assert_eq!(
+        buffer[..10],
+        [0, 0, 0x5A, 0x5A, 0x3C, 0x3C, 0x3C, 0x3C, 0, 0]
+    );
+    assert_eq!(cache.dirty_blocks(), 1);
+    block_on(cache.write(7 * BLOCK_SIZE as u64, &[0x77; BLOCK_SIZE])).unwrap();
+    assert_eq!(cache.stats().evictions, 2);
+    block_on(cache.flush()).unwrap();
+    assert_eq!(cache.dirty_blocks(), 0);
+    assert_eq!(cache.stats().write_backs, 2);
+
+    let disk = cache.into_inner();
+    assert_eq!(disk.writes, 2);
+    assert_eq!(
+        disk.disk.as_bytes()[10..16],
+        [0x5A, 0x5A, 0x3C, 0x3C, 0x3C, 0x3C]
+    );
+    assert!(
+        disk.disk.as_bytes()[7 * BLOCK_SIZE..]
+            .iter()
+            .all(|&byte| byte == 0x77)
+    );
+}
+
+/// A disk with 128 byte blocks, which remembers the address and length of every access
+struct SmallBlockDisk {
+    disk: RamDisk<{ 8 * 128 }>,
+    reads: Vec<(u64, usize)>,
+    writes: Vec<(u64, usize)>,
+}
+
+impl Disk for SmallBlockDisk {
+    type Address = u64;
+    type Error = RamDiskError;
+    const BLOCK_SIZE: usize = 128;
+
+    async fn read(&mut self, start: u64, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.reads.push((start, buffer.len()));
+        self.disk.read(start, buffer).await
+    }
+
+    async fn write(&mut self, start: u64, buffer: &[u8]) -> Result<(), Self::Error> {
+        self.writes.push((start, buffer.len()));
+        self.disk.write(start, buffer).await
+    }
+}
+
+#[test]
+fn caches_use_the_disk_block_size() {
+    let disk = SmallBlockDisk {
+        disk: RamDisk::new(),
+        reads: Vec::new(),
+        writes: Vec::new(),
+    };
+    let mut cache = BlockCache::<_, 2>::new(disk);
+    block_on(cache.write(130, &[1; 10])).unwrap();
+    block_on(cache.write(300, &[2; 10])).unwrap();
+    block_on(cache.flush()).unwrap();
+    let disk = cache.into_inner();
+    assert_eq!(disk.reads, [(128, 128), (256, 128)]);
+    assert_eq!(disk.writes, [(128, 128), (256, 128)]);
+
+    let mut write_back = WriteBackDisk::<_, 2>::new(SmallBlockDisk {
+        reads: Vec::new(),
+        writes: Vec::new(),
+        ..disk
+    });
+    block_on(write_back.write(0, &[3; 128])).unwrap();
+    block_on(write_back.write(600, &[4; 4])).unwrap();
+    block_on(write_back.flush()).unwrap();
+    let disk = write_back.into_inner();
+    assert_eq!(disk.reads, [(512, 128)]);
+    assert_eq!(disk.writes, [(0, 128), (512, 128)]);
+    let bytes = disk.disk.as_bytes();
+    assert_eq!(bytes[..128], [3; 128]);
+    assert_eq!(bytes[130..140], [1; 10]);
+    assert_eq!(bytes[300..310], [2; 10]);
+    assert_eq!(bytes[600..604], [4; 4]);
+}
+
+#[test]
+fn append_log_finds_tail() {
+    let mut disk = RamDisk::<{ 16 * BLOCK_SIZE }>::new();
+    // Garbage from before the log was formatted
+    disk.as_bytes_mut().fill(0xA5);
+    let mut buffer = [0; 4 * BLOCK_SIZE];
+    let mut log = block_on(AppendLog::format(&mut disk, 2, 10)).unwrap();
+    assert_eq!(block_on(log.append(b"boot", &mut buffer)).unwrap(), 0);
+    let long = [7; 600];
+    assert_eq!(block_on(log.append(&long, &mut buffer)).unwrap(), 1);
+    assert_eq!(log.next_block(), 5);
+
+    let mut log = block_on(AppendLog::mount(&mut disk, 2, 10)).unwrap();
+    assert_eq!((log.next_seq(), log.next_block()), (2, 5));
+    let record = block_on(log.read(log.first_block(), &mut buffer))
+        .unwrap()
+        .unwrap();
+    assert_eq!(&buffer[..record.len], b"boot");
+    let record = block_on(log.read(record.next_block, &mut buffer))
+        .unwrap()
+        .unwrap();
+    assert_eq!((record.seq, &buffer[..record.len]), (1, &long[..]));
+    assert_eq!(
+        block_on(log.read(record.next_block, &mut buffer)).unwrap(),
+        None
+    );
+
+    // A record that was cut off by losing power
+    assert_eq!(block_on(log.append(b"crash", &mut buffer)).unwrap(), 2);
+    disk.as_bytes_mut()[5 * BLOCK_SIZE + 20] ^= 1;
+    let mut log = block_on(AppendLog::mount(&mut disk, 2, 10)).unwrap();
+    assert_eq!((log.next_seq(), log.next_block()), (2, 5));
+
+    // 7 blocks are left
+    let too_long = [0; 7 * BLOCK_SIZE];
+    assert!(matches!(
+        block_on(log.append(&too_long, &mut [0; 9 * BLOCK_SIZE])),
+        Err(AppendLogError::Full)
+    ));
+    // The end of the region ends the log, so this doesn't need a block after it
+    let last = [1; 7 * BLOCK_SIZE - 16];
+    block_on(log.append(&last, &mut [0; 7 * BLOCK_SIZE])).unwrap();
+    assert_eq!(log.next_block(), 12);
+    let log = block_on(AppendLog::mount(&mut disk, 2, 10)).unwrap();
+    assert_eq!((log.next_seq(), log.next_block()), (3, 12));
+}
+
+#[test]
+fn circular_log_wraps_around() {
+    let mut disk = RamDisk::<{ 10 * BLOCK_SIZE }>::new();
+    disk.as_bytes_mut().fill(0xA5);
+    let mut buffer = [0; 3 * BLOCK_SIZE];
+    // 2 superblocks and 6 blocks for records
+    let mut log = block_on(CircularLog::format(&mut disk, 1, 8)).unwrap();
+    for i in 0..8 {
+        let seq = block_on(log.append(10 * u64::from(i), &[i as u8; 100], &mut buffer)).unwrap();
+        assert_eq!(seq, i);
+    }
+    /// Reads the records from `cursor`, or from the oldest one
+    fn seqs(
+        log: &mut CircularLog<&mut RamDisk<{ 10 * BLOCK_SIZE }>>,
+        cursor: Option<LogCursor>,
+    ) -> Vec<u32> {
+        let mut cursor = cursor.unwrap_or(log.oldest());
+        let mut buffer = [0; 3 * BLOCK_SIZE];
+        let mut seqs = Vec::new();
+        while let Some(record) = block_on(log.read(&mut cursor, &mut buffer)).unwrap() {
+            assert_eq!(record.time, 10 * u64::from(record.seq));
+            seqs.push(record.seq);
+        }
+        seqs
+    }
+    assert_eq!(seqs(&mut log, None), [2, 3, 4, 5, 6, 7]);
+
+    // The records after the superblock are found again
+    let mut log = block_on(CircularLog::mount(&mut disk, 1, 8)).unwrap();
+    assert_eq!(seqs(&mut log, None), [2, 3, 4, 5, 6, 7]);
+    let cursor = block_on(log.seek_time(45)).unwrap();
+    assert_eq!(seqs(&mut log, Some(cursor)), [5, 6, 7]);
+    assert_eq!(block_on(log.seek_time(1000)).unwrap(), log.end());
+
+    // A cursor to an overwritten record skips to the oldest one
+    let stale = log.oldest();
+    block_on(log.append(80, &[8; 600], &mut buffer)).unwrap();
+    assert_eq!(seqs(&mut log, Some(stale)), [4, 5, 6, 7, 8]);
+    // This doesn't fit before the end of the region, so it overwrites everything else
+    block_on(log.append(90, &[9; 1200], &mut buffer)).unwrap();
+    assert_eq!(seqs(&mut log, None), [9]);
+    assert!(matches!(
+        block_on(log.append(100, &[0; 6 * BLOCK_SIZE], &mut [0; 7 * BLOCK_SIZE])),
+        Err(CircularLogError::TooLong)
+    ));
+
+    // A record that was cut off by losing power
+    block_on(log.append(100, b"crash", &mut buffer)).unwrap();
+    let crash_block = log.end().block - 1;
+    disk.as_bytes_mut()[crash_block as usize * BLOCK_SIZE + 40] ^= 1;
+    let mut log = block_on(CircularLog::mount(&mut disk, 1, 8)).unwrap();
+    assert_eq!(seqs(&mut log, None), [9]);
+
+    // Formatting again forgets the old records, even though they are still there
+    let mut log = block_on(CircularLog::format(&mut disk, 1, 8)).unwrap();
+    assert_eq!(log.oldest(), log.end());
+    block_on(log.append(0, b"new", &mut buffer)).unwrap();
+    let mut log = block_on(CircularLog::mount(&mut disk, 1, 8)).unwrap();
+    assert_eq!(seqs(&mut log, None), [0]);
+}
+
+#[cfg(not(feature = "defmt"))]
+#[test]
+fn checksum_disk_round_trip() {
+    let mut disk = CountingDisk::new(RamDisk::<{ 10 * BLOCK_SIZE }>::new());
+    disk.erase_unit = Some(2 * BLOCK_SIZE);
+    assert_eq!(ChecksumDisk::<RamDisk<0>>::sidecar_blocks(8), 1);
+    let mut checksums = ChecksumDisk::new(&mut disk, 8);
+    assert_eq!(checksums.block_size(), BLOCK_SIZE);
+    assert_eq!(checksums.erase_unit_size(), Some(2 * BLOCK_SIZE));
+    block_on(checksums.rebuild()).unwrap();
+
+    let data = (0..2 * BLOCK_SIZE).map(|i| i as u8).collect::<Vec<_>>();
+    block_on(checksums.write(3 * BLOCK_SIZE as u64, &data)).unwrap();
+    let mut buffer = [0; 4 * BLOCK_SIZE];
+    block_on(checksums.read(2 * BLOCK_SIZE as u64, &mut buffer)).unwrap();
+    assert_eq!(buffer[..BLOCK_SIZE], [0; BLOCK_SIZE]);
+    assert_eq!(buffer[BLOCK_SIZE..3 * BLOCK_SIZE], data);
+    assert!(matches!(
+        block_on(checksums.read(10, &mut buffer[..BLOCK_SIZE])),
+        Err(ChecksumError::Unaligned)
+    ));
+    // The sidecar block isn't part of the data region
+    assert!(matches!(
+        block_on(checksums.read(7 * BLOCK_SIZE as u64, &mut buffer[..2 * BLOCK_SIZE])),
+        Err(ChecksumError::OutOfRange)
+    ));
+    assert!(matches!(
+        block_on(checksums.erase(0, 9 * BLOCK_SIZE as u64)),
+        Err(ChecksumError::OutOfRange)
+    ));
+
+    // Erased blocks fail their checksum until they are written again
+    block_on(checksums.erase(3 * BLOCK_SIZE as u64, 4 * BLOCK_SIZE as u64)).unwrap();
+    assert!(matches!(
+        block_on(checksums.read(3 * BLOCK_SIZE as u64, &mut buffer[..BLOCK_SIZE])),
+        Err(ChecksumError::Mismatch { block: 3 })
+    ));
+    block_on(checksums.write(3 * BLOCK_SIZE as u64, &data[..BLOCK_SIZE])).unwrap();
+    block_on(checksums.read(3 * BLOCK_SIZE as u64, &mut buffer[..BLOCK_SIZE])).unwrap();
+    assert_eq!(buffer[..BLOCK_SIZE], data[..BLOCK_SIZE]);
+}
+
+#[cfg(not(feature = "defmt"))]
+#[test]
+fn checksum_disk_detects_corruption() {
+    let mut disk = RamDisk::<{ 10 * BLOCK_SIZE }>::new();
+    let mut checksums = ChecksumDisk::new(&mut disk, 8);
+    block_on(checksums.rebuild()).unwrap();
+    block_on(checksums.write(5 * BLOCK_SIZE as u64, &[0x3C; BLOCK_SIZE])).unwrap();
+
+    // Something other than this disk writes over the block
+    disk.as_bytes_mut()[5 * BLOCK_SIZE + 100] ^= 0x10;
+    let mut checksums = ChecksumDisk::new(&mut disk, 8);
+    let mut buffer = [0; 3 * BLOCK_SIZE];
+    assert!(matches!(
+        block_on(checksums.read(4 * BLOCK_SIZE as u64, &mut buffer)),
+        Err(ChecksumError::Mismatch { block: 5 })
+    ));
+    block_on(checksums.read(4 * BLOCK_SIZE as u64, &mut buffer[..BLOCK_SIZE])).unwrap();
+}
+
+#[cfg(not(feature = "defmt"))]
+#[test]
+fn checksum_disk_write_spans_sidecar_blocks() {
+    // 128 checksums fit in each sidecar block, so blocks 126 to 129 have theirs in 2 sidecar blocks
+    const DATA_BLOCKS: u64 = 130;
+    let sidecar_blocks = ChecksumDisk::<RamDisk<0>>::sidecar_blocks(DATA_BLOCKS);
+    assert_eq!(sidecar_blocks, 2);
+    let mut disk = RamDisk::<{ 132 * BLOCK_SIZE }>::new();
+    let data = (0..4 * BLOCK_SIZE)
+        .map(|i| (i / 3) as u8)
+        .collect::<Vec<_>>();
+    // Without a rebuild, only blocks whose checksums were written can be read
+    let mut checksums = ChecksumDisk::new(&mut disk, DATA_BLOCKS);
+    block_on(checksums.write(126 * BLOCK_SIZE as u64, &data)).unwrap();
+    let mut buffer = vec![0; 4 * BLOCK_SIZE];
+    block_on(checksums.read(126 * BLOCK_SIZE as u64, &mut buffer)).unwrap();
+    assert_eq!(buffer, data);
+    assert!(matches!(
+        block_on(checksums.read(125 * BLOCK_SIZE as u64, &mut buffer[..BLOCK_SIZE])),
+        Err(ChecksumError::Mismatch { block: 125 })
+    ));
+
+    disk.as_bytes_mut()[129 * BLOCK_SIZE] ^= 1;
+    let mut checksums = ChecksumDisk::new(&mut disk, DATA_BLOCKS);
+    assert!(matches!(
+        block_on(checksums.read(126 * BLOCK_SIZE as u64, &mut buffer)),
+        Err(ChecksumError::Mismatch { block: 129 })
+    ));
+}
+
+#[cfg(feature = "embedded-io-async")]
+#[test]
+fn read_image_of_unaligned_range() {
+    use spi_sd_card::read_image;
+
+    let mut disk = RamDisk::<{ 8 * BLOCK_SIZE }>::new();
+    for (i, byte) in disk.as_bytes_mut().iter_mut().enumerate() {
+        *byte = (i / 5) as u8;
+    }
+    let expected = disk.as_bytes()[100..1700].to_vec();
+    let mut disk = CountingDisk::new(disk);
+    let mut image = [0; 1600];
+    block_on(read_image(&mut disk, 100..1700, &mut &mut image[..])).unwrap();
+    assert_eq!(image[..], expected);
+    // The chunks are aligned to blocks, so the first one is short
+    assert_eq!(disk.reads, 2);
+}
+
+#[cfg(feature = "embedded-io-async")]
+#[test]
+fn write_image_pads_last_block() {
+    use spi_sd_card::{ImageError, write_image};
+
+    let mut disk = RamDisk::<{ 8 * BLOCK_SIZE }>::new();
+    disk.as_bytes_mut().fill(0xA5);
+    // 2 whole chunks of 2 blocks, and then 1 block and 188 bytes
+    let image = (0..2748).map(|i| (i / 3) as u8).collect::<Vec<_>>();
+    let mut progress = Vec::new();
+    let written = block_on(write_image(&mut disk, &mut &image[..], 1, |bytes| {
+        progress.push(bytes)
+    }))
+    .unwrap();
+    assert_eq!(written, 2748);
+    assert_eq!(progress, [1024, 2048, 2748]);
+    let bytes = disk.as_bytes();
+    assert!(bytes[..BLOCK_SIZE].iter().all(|&byte| byte == 0xA5));
+    assert_eq!(bytes[BLOCK_SIZE..BLOCK_SIZE + 2748], image);
+    // The rest of the last block is zeroed, and the blocks after it aren't touched
+    assert!(
+        bytes[BLOCK_SIZE + 2748..7 * BLOCK_SIZE]
+            .iter()
+            .all(|&byte| byte == 0)
+    );
+    assert!(bytes[7 * BLOCK_SIZE..].iter().all(|&byte| byte == 0xA5));
+
+    assert!(matches!(
+        block_on(write_image(&mut disk, &mut &image[..], u64::MAX, |_| {})),
+        Err(ImageError::OutOfRange)
+    ));
+}
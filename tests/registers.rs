@@ -0,0 +1,115 @@
+//! Register samples in the format cards send them, checked against values decoded by hand from the spec
+
+use spi_sd_card::{Cid, Csd, Scr};
+
+/// 2 GB standard capacity card (CSD version 1.0)
+const CSD_SDSC_2GB: [u8; 16] = [
+    0x00, 0x26, 0x00, 0x32, 0x5F, 0x5A, 0x83, 0xAE, 0xFE, 0xFB, 0xCF, 0xFF, 0x92, 0x80, 0x40, 0xDF,
+];
+
+/// 16 GB high capacity card (CSD version 2.0)
+const CSD_SDHC_16GB: [u8; 16] = [
+    0x40, 0x0E, 0x00, 0x32, 0x5B, 0x59, 0x00, 0x00, 0x76, 0xB2, 0x7F, 0x80, 0x0A, 0x40, 0x40, 0x01,
+];
+
+/// 64 GB extended capacity card with a 50 MHz `TRAN_SPEED` (CSD version 2.0)
+const CSD_SDXC_64GB: [u8; 16] = [
+    0x40, 0x0E, 0x00, 0x5A, 0x5B, 0x59, 0x00, 0x01, 0xD8, 0xFF, 0x7F, 0x80, 0x0A, 0x40, 0x00, 0x01,
+];
+
+const CID_SANDISK: [u8; 16] = [
+    0x03, 0x53, 0x44, 0x53, 0x44, 0x31, 0x36, 0x47, 0x80, 0x12, 0x34, 0x56, 0x78, 0x01, 0x4A, 0xAD,
+];
+
+const CID_SAMSUNG: [u8; 16] = [
+    0x1B, 0x53, 0x4D, 0x45, 0x42, 0x31, 0x51, 0x54, 0x30, 0xDE, 0xAD, 0xBE, 0xEF, 0x01, 0x23, 0x03,
+];
+
+#[test]
+fn csd_v1_capacity_and_speed() {
+    let csd = Csd::from_bytes(CSD_SDSC_2GB).unwrap();
+    assert!(matches!(csd, Csd::V1(_)));
+    // (C_SIZE + 1) * 2^(C_SIZE_MULT + 2) * 2^READ_BL_LEN = 3772 * 512 * 1024
+    assert_eq!(csd.card_capacity_bytes(), 1_977_614_336);
+    assert_eq!(csd.max_transfer_rate_hz(), Some(25_000_000));
+}
+
+#[test]
+fn csd_v2_capacity_and_speed() {
+    let csd = Csd::from_bytes(CSD_SDHC_16GB).unwrap();
+    assert!(matches!(csd, Csd::V2(_)));
+    // (C_SIZE + 1) * 512 KiB = 30387 * 512 * 1024
+    assert_eq!(csd.card_capacity_bytes(), 15_931_539_456);
+    assert_eq!(csd.max_transfer_rate_hz(), Some(25_000_000));
+
+    let csd = Csd::from_bytes(CSD_SDXC_64GB).unwrap();
+    // 121088 * 512 * 1024
+    assert_eq!(csd.card_capacity_bytes(), 63_484_985_344);
+    assert_eq!(csd.max_transfer_rate_hz(), Some(50_000_000));
+}
+
+#[test]
+fn csd_fields() {
+    let csd = Csd::from_bytes(CSD_SDHC_16GB).unwrap();
+    assert_eq!(csd.get_taac(), 0x0E);
+    assert_eq!(csd.get_nsac(), 0);
+    assert_eq!(csd.get_ccc(), 0x5B5);
+    assert_eq!(csd.get_read_bl_len(), 9);
+    assert_eq!(csd.get_write_bl_len(), 9);
+    assert!(csd.get_erase_blk_en());
+    assert_eq!(csd.get_sector_size(), 0x7F);
+    assert_eq!(csd.erase_sector_size_bytes(), 64 * 1024);
+    assert_eq!(csd.get_wp_grp_size(), 0);
+    assert!(!csd.get_wp_grp_enable());
+    assert_eq!(csd.get_r2w_factor(), 2);
+    assert!(csd.get_copy());
+    assert!(!csd.is_write_protected());
+
+    let csd = Csd::from_bytes(CSD_SDSC_2GB).unwrap();
+    assert_eq!(csd.get_taac(), 0x26);
+    assert_eq!(csd.get_ccc(), 0x5F5);
+    assert_eq!(csd.get_read_bl_len(), 10);
+}
+
+#[test]
+fn csd_reserved_structure() {
+    let mut bytes = CSD_SDHC_16GB;
+    bytes[0] = 0xC0;
+    assert_eq!(Csd::from_bytes(bytes), None);
+}
+
+#[test]
+fn cid_fields() {
+    let cid = Cid::from_bytes(CID_SANDISK);
+    assert_eq!(cid.manufacturer_id(), 0x03);
+    assert_eq!(&cid.oem_id(), b"SD");
+    assert_eq!(&cid.product_name(), b"SD16G");
+    assert_eq!(cid.product_revision(), (8, 0));
+    assert_eq!(cid.serial_number(), 0x1234_5678);
+    assert_eq!(cid.manufacturing_date().get_year(), 20);
+    assert_eq!(cid.manufacturing_date().get_month(), 10);
+    assert!(cid.get_bit_0());
+
+    let cid = Cid::from_bytes(CID_SAMSUNG);
+    assert_eq!(cid.manufacturer_id(), 0x1B);
+    assert_eq!(&cid.oem_id(), b"SM");
+    assert_eq!(&cid.product_name(), b"EB1QT");
+    assert_eq!(cid.product_revision(), (3, 0));
+    assert_eq!(cid.serial_number(), 0xDEAD_BEEF);
+    assert_eq!(cid.manufacturing_date().get_year(), 18);
+    assert_eq!(cid.manufacturing_date().get_month(), 3);
+}
+
+#[test]
+fn scr_fields() {
+    let scr = Scr::from_bytes([0x02, 0x35, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    assert_eq!(scr.sd_spec_version(), Some((3, 0)));
+    assert!(scr.supports_1_bit_bus());
+    assert!(scr.supports_4_bit_bus());
+    assert!(!scr.supports_cmd23());
+
+    let scr = Scr::from_bytes([0x02, 0x45, 0x84, 0x87, 0x00, 0x00, 0x00, 0x00]);
+    assert_eq!(scr.sd_spec_version(), Some((6, 0)));
+    assert!(scr.supports_cmd23());
+    assert!(scr.supports_cmd20());
+}
@@ -0,0 +1,1876 @@
+//! The driver against a simulated card, to test the command state machine and init sequence on the host
+// The driver's defmt logs need a global logger, which the host doesn't have
+#![cfg(not(feature = "defmt"))]
+
+use std::{
+    cell::{Cell, RefCell, RefMut},
+    convert::Infallible,
+    fmt::{self, Debug, Formatter},
+    pin::pin,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    task::{Context, Poll, Waker},
+};
+
+use embassy_embedded_hal::SetConfig;
+use embedded_hal::{
+    digital::{ErrorType as PinErrorType, OutputPin},
+    spi::SpiBus as BlockingSpiBus,
+};
+use embedded_hal_async::spi::{ErrorType, Operation, SpiBus, SpiDevice};
+use spi_sd_card::{
+    BLOCK_SIZE, CancelToken, CardDetect, CardType, ClockChoice, CrashLogHeader, CrashLogRegion,
+    Csd, DataTransfer, Disk, Duration, Environment, Error, ExclusiveSpiBus, ExpectedGaps, Extent,
+    InitStep, LocalSharedSpiBus, NoDelay, QualificationRequirements, RamDisk, SdCardConfig,
+    SdCardPool, SdCommand, SdTransport, SharedSpiBus, SimBus, SimCard, SimCardOptions, SimCs,
+    SpiDeviceCell, SpiSdCard, SpiSdCardBlocking, StdSharedSpiBus, TrailingBytes, TransportError,
+    TransportSpeed, sim_sd_card, std_clock,
+};
+
+const DISK_SIZE: usize = 64 * 512;
+
+/// The simulated card never waits, so the driver's futures are ready the first time they are polled
+fn block_on<F: Future>(future: F) -> F::Output {
+    match pin!(future).poll(&mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(output) => output,
+        Poll::Pending => panic!("future was pending"),
+    }
+}
+
+fn pattern(len: usize, seed: u8) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i as u8).wrapping_mul(31) ^ seed)
+        .collect()
+}
+
+#[test]
+fn init_sdhc() {
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::new(),
+        SimCardOptions::default(),
+    ));
+    let mut sd_card = sim_sd_card(&card);
+    let disk = block_on(sd_card.init_card()).unwrap();
+    assert_eq!(disk.card_type(), CardType::SdV2Hc);
+    drop(disk);
+    // CMD0, CMD59, CMD8, CMD58, 3 tries of ACMD41, CMD58
+    assert_eq!(
+        card.borrow().commands,
+        [0, 59, 8, 58, 55, 41, 55, 41, 55, 41, 58]
+    );
+}
+
+#[test]
+fn set_crc() {
+    let data = pattern(DISK_SIZE, 0x61);
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::from_bytes(data.clone().try_into().unwrap()),
+        SimCardOptions::default(),
+    ));
+    let mut sd_card = sim_sd_card(&card);
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+    assert!(disk.crc_enabled());
+
+    let mut buffer = vec![0; 2 * 512];
+    for enabled in [false, true] {
+        block_on(disk.set_crc(enabled)).unwrap();
+        assert_eq!(disk.crc_enabled(), enabled);
+        assert_eq!(card.borrow().commands.last(), Some(&59));
+        block_on(disk.read(4 * 512, &mut buffer)).unwrap();
+        assert_eq!(buffer, data[4 * 512..6 * 512]);
+    }
+}
+
+#[test]
+fn trailing_bytes() {
+    let options = SimCardOptions {
+        trailing_byte: Some(0x3F),
+        ..Default::default()
+    };
+    for (policy, ok) in [
+        (TrailingBytes::Ignore, true),
+        (TrailingBytes::Warn, true),
+        (TrailingBytes::Error, false),
+    ] {
+        let card = RefCell::new(SimCard::new(RamDisk::<DISK_SIZE>::new(), options));
+        let cs = card.borrow().cs();
+        let config = SdCardConfig::new(std_clock).with_trailing_bytes(policy);
+        let mut sd_card = SpiSdCard::new_with_config(SimBus(&card), cs, NoDelay, (), (), config);
+        let result = block_on(sd_card.init_card()).map(|disk| disk.card_type());
+        if ok {
+            assert_eq!(result.unwrap(), CardType::SdV2Hc);
+        } else {
+            assert!(matches!(result, Err(Error::UnexpectedTrailingData(0x3F))));
+        }
+    }
+}
+
+#[test]
+fn init_timeline() {
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::new(),
+        SimCardOptions::default(),
+    ));
+    let mut sd_card = sim_sd_card(&card);
+    block_on(sd_card.init_card()).unwrap();
+    let timeline = sd_card.init_timeline();
+    assert_eq!(timeline.failed_step(), None);
+    assert!(timeline.duration(InitStep::Initialize).is_some());
+    // Only standard capacity cards need CMD16
+    assert_eq!(timeline.duration(InitStep::SetBlockLength), None);
+    // `NoDelay` doesn't wait at all
+    assert!(timeline.delayer_too_fast());
+
+    // A card that never finishes initializing
+    card.borrow_mut().options.init_polls = u32::MAX;
+    sd_card.config.timeouts.init = Duration::from_micros(0);
+    assert!(matches!(
+        block_on(sd_card.init_card()),
+        Err(Error::ReadyTimeout)
+    ));
+    let timeline = sd_card.init_timeline();
+    assert_eq!(timeline.failed_step(), Some(InitStep::Initialize));
+    assert_eq!(timeline.duration(InitStep::ReadCapacity), None);
+}
+
+#[test]
+fn init_sdsc_sets_block_length() {
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::new(),
+        SimCardOptions {
+            high_capacity: false,
+            ..Default::default()
+        },
+    ));
+    let mut sd_card = sim_sd_card(&card);
+    let disk = block_on(sd_card.init_card()).unwrap();
+    assert_eq!(disk.card_type(), CardType::SdV2Sc);
+    drop(disk);
+    assert_eq!(card.borrow().commands.last(), Some(&16));
+}
+
+#[test]
+fn set_block_length() {
+    for high_capacity in [false, true] {
+        let data = pattern(DISK_SIZE, 0x5A);
+        let card = RefCell::new(SimCard::new(
+            RamDisk::<DISK_SIZE>::from_bytes(data.clone().try_into().unwrap()),
+            SimCardOptions {
+                high_capacity,
+                ..Default::default()
+            },
+        ));
+        // Every disk of the pool is a new handle, like with `SdCardManager`
+        let mut pool = SdCardPool::new([sim_sd_card(&card)]);
+        block_on(pool.init_all()).unwrap();
+        let mut disk = pool.disk(0).unwrap();
+        assert_eq!(disk.block_length(), 512);
+        assert!(matches!(
+            block_on(disk.set_block_length(1024)),
+            Err(Error::SetBlockLengthFailed)
+        ));
+        assert_eq!(disk.block_length(), 512);
+        block_on(disk.set_block_length(16)).unwrap();
+        assert_eq!(disk.block_length(), 16);
+        card.borrow_mut().commands.clear();
+
+        // The card still has the block length after the handle that set it is gone
+        let mut disk = pool.disk(0).unwrap();
+        let mut buffer = [0; 512];
+        block_on(disk.read(2 * 512, &mut buffer)).unwrap();
+        assert_eq!(buffer, data[2 * 512..3 * 512]);
+        if high_capacity {
+            // The block length doesn't apply to reads and writes of high capacity cards
+            assert_eq!(disk.block_length(), 16);
+            assert_eq!(card.borrow().commands, [17]);
+        } else {
+            assert_eq!(disk.block_length(), 512);
+            assert_eq!(card.borrow().commands, [16, 17]);
+        }
+    }
+}
+
+#[test]
+fn init_mmc() {
+    for (high_capacity, card_type) in [(true, CardType::MmcHc), (false, CardType::Mmc)] {
+        let options = SimCardOptions {
+            high_capacity,
+            mmc: true,
+            ..Default::default()
+        };
+        let card = RefCell::new(SimCard::new(RamDisk::<DISK_SIZE>::new(), options));
+        let mut sd_card = sim_sd_card(&card);
+        let mut disk = block_on(sd_card.init_card()).unwrap();
+        assert_eq!(disk.card_type(), card_type);
+        let csd = block_on(disk.csd()).unwrap();
+        assert!(matches!(csd, Csd::Mmc(_)));
+        assert_eq!(csd.card_capacity_bytes(), DISK_SIZE as u64);
+        assert_eq!(csd.erase_sector_size_bytes(), 16 * 512);
+        let data = pattern(2 * BLOCK_SIZE, 3);
+        block_on(disk.write(BLOCK_SIZE as u64, &data)).unwrap();
+        let mut buffer = vec![0; data.len()];
+        block_on(disk.read(BLOCK_SIZE as u64, &mut buffer)).unwrap();
+        assert_eq!(buffer, data);
+        drop(disk);
+        // CMD55 is rejected, so the card is initialized with 3 tries of CMD1
+        assert_eq!(card.borrow().commands[..9], [0, 59, 8, 58, 55, 1, 1, 1, 58]);
+
+        let card = RefCell::new(SimCard::new(RamDisk::<DISK_SIZE>::new(), options));
+        let cs = card.borrow().cs();
+        let mut sd_card =
+            SpiSdCardBlocking::new(BlockingSim(&card), cs, SdCardConfig::new(std_clock));
+        assert_eq!(sd_card.init_card().unwrap(), card_type);
+    }
+}
+
+#[test]
+fn qualify_card_writes_sequentially() {
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::new(),
+        SimCardOptions {
+            mmc: true,
+            ..Default::default()
+        },
+    ));
+    let mut sd_card = sim_sd_card(&card);
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+    card.borrow_mut().commands.clear();
+    let report = block_on(disk.qualify_card(&QualificationRequirements {
+        min_capacity: DISK_SIZE as u64,
+        min_speed_class: 0,
+        min_write_speed: 0,
+        max_crc_errors: 0,
+        scratch_start_block: 8,
+        scratch_blocks: 4,
+    }))
+    .unwrap();
+    assert!(report.passed);
+    assert_eq!(report.mismatched_blocks, 0);
+    // The scratch region is written with one `CMD25`
+    assert_eq!(
+        card.borrow()
+            .commands
+            .iter()
+            .filter(|&&command| command == 24 || command == 25)
+            .collect::<Vec<_>>(),
+        [&25]
+    );
+}
+
+#[test]
+fn erase_mmc() {
+    let data = pattern(DISK_SIZE, 0x3A);
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::from_bytes(data.clone().try_into().unwrap()),
+        SimCardOptions {
+            mmc: true,
+            ..Default::default()
+        },
+    ));
+    let mut sd_card = sim_sd_card(&card);
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+    card.borrow_mut().commands.clear();
+    block_on(disk.erase_blocks(18, 20)).unwrap();
+    // MMC cards don't have an SD Status, so the erase timeout comes from the default
+    assert_eq!(card.borrow().commands, [9, 35, 36, 38]);
+    // The card erases the whole erase group of 16 blocks
+    let bytes = card.borrow().disk.as_bytes().to_vec();
+    assert_eq!(bytes[..16 * BLOCK_SIZE], data[..16 * BLOCK_SIZE]);
+    assert!(
+        bytes[16 * BLOCK_SIZE..32 * BLOCK_SIZE]
+            .iter()
+            .all(|&b| b == 0xFF)
+    );
+    assert_eq!(bytes[32 * BLOCK_SIZE..], data[32 * BLOCK_SIZE..]);
+}
+
+#[test]
+fn erase_keeps_partly_covered_erase_groups() {
+    let data = pattern(DISK_SIZE, 0x4C);
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::from_bytes(data.clone().try_into().unwrap()),
+        SimCardOptions {
+            mmc: true,
+            ..Default::default()
+        },
+    ));
+    let mut sd_card = sim_sd_card(&card);
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+    // Erase groups are 16 blocks, so only blocks 16 to 32 are completely inside of the range
+    block_on(Disk::erase(&mut disk, 3 * 512 + 100, 40 * 512)).unwrap();
+    let bytes = card.borrow().disk.as_bytes().to_vec();
+    assert_eq!(bytes[..16 * BLOCK_SIZE], data[..16 * BLOCK_SIZE]);
+    assert!(
+        bytes[16 * BLOCK_SIZE..32 * BLOCK_SIZE]
+            .iter()
+            .all(|&b| b == 0xFF)
+    );
+    assert_eq!(bytes[32 * BLOCK_SIZE..], data[32 * BLOCK_SIZE..]);
+
+    // A range without a whole erase group in it doesn't erase anything
+    card.borrow_mut().commands.clear();
+    block_on(Disk::erase(&mut disk, 33 * 512, 47 * 512)).unwrap();
+    assert!(!card.borrow().commands.contains(&38));
+    assert_eq!(
+        card.borrow().disk.as_bytes()[32 * BLOCK_SIZE..],
+        data[32 * BLOCK_SIZE..]
+    );
+}
+
+#[test]
+fn init_waits_for_power_up() {
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::new(),
+        SimCardOptions {
+            power_up_polls: 2,
+            ..Default::default()
+        },
+    ));
+    let mut sd_card = sim_sd_card(&card);
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+    // The capacity status is only read once the card is powered up
+    assert_eq!(disk.card_type(), CardType::SdV2Hc);
+    let ocr = block_on(disk.wait_powered_up()).unwrap();
+    assert_eq!(ocr.supports_sdhc_or_sdxc(), Some(true));
+    drop(disk);
+    assert_eq!(
+        card.borrow().commands,
+        [0, 59, 8, 58, 55, 41, 55, 41, 55, 41, 58, 58, 58, 58]
+    );
+
+    // A card that never sets the power up status bit
+    card.borrow_mut().options.power_up_polls = u32::MAX;
+    sd_card.config.acmd41_retry.max_attempts = 5;
+    assert!(matches!(
+        block_on(sd_card.init_card()),
+        Err(Error::PowerUpTimeout)
+    ));
+    assert_eq!(
+        sd_card.init_timeline().failed_step(),
+        Some(InitStep::ReadCapacity)
+    );
+}
+
+#[test]
+fn read_blocks() {
+    let data = pattern(DISK_SIZE, 0x5A);
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::from_bytes(data.clone().try_into().unwrap()),
+        SimCardOptions::default(),
+    ));
+    let mut sd_card = sim_sd_card(&card);
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+
+    // CMD17
+    let mut buffer = [0; 512];
+    block_on(disk.read(3 * 512, &mut buffer)).unwrap();
+    assert_eq!(buffer, data[3 * 512..4 * 512]);
+
+    // CMD18, not starting at a block boundary
+    let mut buffer = vec![0; 5 * 512];
+    block_on(disk.read(7 * 512 + 100, &mut buffer)).unwrap();
+    assert_eq!(buffer, data[7 * 512 + 100..12 * 512 + 100]);
+
+    // Less than a block, inside one block and across 2 blocks
+    for (start, len) in [(20 * 512 + 3, 37), (1000, 37)] {
+        let mut buffer = vec![0; len];
+        block_on(disk.read(start as u64, &mut buffer)).unwrap();
+        assert_eq!(buffer, data[start..start + len]);
+    }
+}
+
+#[test]
+fn read_vectored() {
+    let data = pattern(DISK_SIZE, 0x3C);
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::from_bytes(data.clone().try_into().unwrap()),
+        SimCardOptions::default(),
+    ));
+    let mut sd_card = sim_sd_card(&card);
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+
+    // Segments that split blocks anywhere, including an empty one, starting and ending in the middle of a block
+    let start = 4 * 512 + 100;
+    let (mut a, mut b, mut c, mut d) = ([0; 3], [0; 600], [0; 0], [0; 700]);
+    block_on(disk.read_vectored(start as u64, &mut [&mut a, &mut b, &mut c, &mut d])).unwrap();
+    assert_eq!(a, data[start..start + 3]);
+    assert_eq!(b, data[start + 3..start + 603]);
+    assert_eq!(d, data[start + 603..start + 1303]);
+    assert_eq!(
+        card.borrow().commands.iter().filter(|&&i| i == 18).count(),
+        1
+    );
+
+    // Past the last block that a 32-bit block number can address
+    let start = (u64::from(u32::MAX) + 1) * BLOCK_SIZE as u64;
+    assert!(matches!(
+        block_on(disk.read_vectored(start, &mut [&mut a, &mut b])),
+        Err(Error::ReadAddressError)
+    ));
+}
+
+#[test]
+fn read_extents() {
+    let data = pattern(DISK_SIZE, 0x3D);
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::from_bytes(data.clone().try_into().unwrap()),
+        SimCardOptions::default(),
+    ));
+    let mut sd_card = sim_sd_card(&card);
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+
+    // Out of order, with gaps between them, except for blocks 20 to 23 and 23 to 25
+    let ranges = [(40, 3), (20, 3), (5, 1), (23, 2), (60, 2)];
+    let mut buffers = ranges.map(|(_, len)| vec![0; len * BLOCK_SIZE]);
+    let mut extents = ranges
+        .iter()
+        .zip(&mut buffers)
+        .map(|(&(start_block, _), buffer)| Extent {
+            start_block,
+            buffer,
+        })
+        .collect::<Vec<_>>();
+    card.borrow_mut().commands.clear();
+    block_on(disk.read_extents(&mut extents)).unwrap();
+    drop(extents);
+    // 1 command for each run of extents that continue each other
+    let commands = card.borrow().commands.clone();
+    assert_eq!(commands.iter().filter(|&&i| i == 17).count(), 1);
+    assert_eq!(commands.iter().filter(|&&i| i == 18).count(), 3);
+
+    for ((start_block, len), buffer) in ranges.into_iter().zip(&buffers) {
+        let mut expected = vec![0; len * BLOCK_SIZE];
+        block_on(disk.read(u64::from(start_block) * BLOCK_SIZE as u64, &mut expected)).unwrap();
+        assert_eq!(*buffer, expected);
+    }
+}
+
+#[test]
+fn write_blocks() {
+    for high_capacity in [true, false] {
+        let card = RefCell::new(SimCard::new(
+            RamDisk::<DISK_SIZE>::new(),
+            SimCardOptions {
+                high_capacity,
+                ..Default::default()
+            },
+        ));
+        let mut sd_card = sim_sd_card(&card);
+        let mut disk = block_on(sd_card.init_card()).unwrap();
+
+        // CMD24
+        let single = pattern(512, 1);
+        block_on(disk.write(2 * 512, &single)).unwrap();
+        // CMD25
+        let multiple = pattern(4 * 512, 2);
+        block_on(disk.write(10 * 512, &multiple)).unwrap();
+
+        let mut buffer = vec![0; 4 * 512];
+        block_on(disk.read(10 * 512, &mut buffer)).unwrap();
+        assert_eq!(buffer, multiple);
+        drop(disk);
+
+        let card = card.borrow();
+        assert_eq!(card.disk.as_bytes()[2 * 512..3 * 512], single);
+        assert_eq!(card.disk.as_bytes()[10 * 512..14 * 512], multiple);
+        assert!(
+            card.disk.as_bytes()[14 * 512..]
+                .iter()
+                .all(|&byte| byte == 0)
+        );
+    }
+}
+
+#[test]
+fn write_partial_blocks() {
+    let mut data = pattern(DISK_SIZE, 0x4C);
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::from_bytes(data.clone().try_into().unwrap()),
+        SimCardOptions::default(),
+    ));
+    let mut sd_card = sim_sd_card(&card);
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+    let mut scratch = [0; 512];
+
+    // Inside one block, and then across a partial block, 2 whole blocks, and another partial block
+    for (start, len) in [(3 * 512 + 10, 100), (5 * 512 + 300, 3 * 512)] {
+        let written = pattern(len, start as u8);
+        block_on(disk.write_partial(start as u64, &written, &mut scratch)).unwrap();
+        data[start..start + len].copy_from_slice(&written);
+    }
+    // `Disk::write` does the same
+    let written = pattern(37, 0x91);
+    block_on(disk.write(7 * 512 + 1000, &written)).unwrap();
+    data[7 * 512 + 1000..7 * 512 + 1037].copy_from_slice(&written);
+    let mut buffer = vec![0; 10 * 512];
+    block_on(disk.read(0, &mut buffer)).unwrap();
+    assert_eq!(buffer, data[..10 * 512]);
+}
+
+#[test]
+fn transfers_use_the_cards_buffer() {
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::from_bytes(pattern(DISK_SIZE, 0x21).try_into().unwrap()),
+        SimCardOptions::default(),
+    ));
+    let mut sd_card = sim_sd_card(&card);
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+    let mut buffer = [0; 2 * BLOCK_SIZE];
+    // The futures don't hold buffers of their own for the transfers, so they're small enough for a small stack
+    assert!(size_of_val(&disk.read(0, &mut buffer)) < 3 * BLOCK_SIZE);
+    assert!(size_of_val(&disk.write(0, &buffer)) < 3 * BLOCK_SIZE);
+    block_on(disk.read(0, &mut buffer)).unwrap();
+    block_on(disk.write(BLOCK_SIZE as u64, &buffer)).unwrap();
+    assert_eq!(
+        card.borrow().disk.as_bytes()[BLOCK_SIZE..3 * BLOCK_SIZE],
+        buffer
+    );
+}
+
+#[test]
+fn gaps_shorter_than_the_card() {
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::new(),
+        SimCardOptions {
+            read_gap: 20,
+            busy_bytes: 10,
+            ..Default::default()
+        },
+    ));
+    let cs = card.borrow().cs();
+    let config = SdCardConfig::new(std_clock)
+        .with_gaps(ExpectedGaps {
+            read_data: 1,
+            not_busy: 0,
+            ..Default::default()
+        })
+        .with_max_transfer_len(64);
+    let mut sd_card = SpiSdCard::new_with_config(SimBus(&card), cs, NoDelay, (), (), config);
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+
+    let data = pattern(3 * 512, 9);
+    block_on(disk.write(2 * 512, &data)).unwrap();
+    let mut buffer = vec![0; 3 * 512];
+    block_on(disk.read(2 * 512, &mut buffer)).unwrap();
+    assert_eq!(buffer, data);
+}
+
+#[test]
+fn reads_straight_into_the_buffer() {
+    let data = pattern(DISK_SIZE, 0x33);
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::from_bytes(data.clone().try_into().unwrap()),
+        SimCardOptions::default(),
+    ));
+    let cs = card.borrow().cs();
+    // About the same gap as the card, so the transfers stop at about the start block tokens
+    let config = SdCardConfig::new(std_clock).with_gaps(ExpectedGaps {
+        read_data: SimCardOptions::default().read_gap,
+        ..Default::default()
+    });
+    let mut sd_card = SpiSdCard::new_with_config(SimBus(&card), cs, NoDelay, (), (), config);
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+
+    card.borrow_mut().bytes_read = 0;
+    let mut buffer = vec![0; 4 * BLOCK_SIZE];
+    block_on(disk.read(8 * 512, &mut buffer)).unwrap();
+    assert_eq!(buffer, data[8 * 512..12 * 512]);
+    // The data is received with transfers that send `0xFF`, since `read` might send anything
+    assert_eq!(card.borrow().bytes_read, 0);
+
+    // The partial blocks at the ends still go through the transfer buffer
+    block_on(disk.read(8 * 512 + 100, &mut buffer)).unwrap();
+    assert_eq!(buffer, data[8 * 512 + 100..12 * 512 + 100]);
+    assert_eq!(card.borrow().bytes_read, 0);
+}
+
+#[test]
+fn split_transfers() {
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::new(),
+        SimCardOptions::default(),
+    ));
+    let cs = card.borrow().cs();
+    let config = SdCardConfig::new(std_clock).with_split_transfers(true);
+    let mut sd_card = SpiSdCard::new_with_config(SimBus(&card), cs, NoDelay, (), (), config);
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+    assert_eq!(block_on(disk.capacity()).unwrap(), 512 * 1024);
+
+    let data = pattern(3 * 512, 0x19);
+    block_on(disk.write(4 * 512, &data)).unwrap();
+    block_on(disk.write(9 * 512, &data[..512])).unwrap();
+    card.borrow_mut().bytes_read = 0;
+    let mut buffer = [0; 512];
+    block_on(disk.read(9 * 512, &mut buffer)).unwrap();
+    assert_eq!(buffer[..], data[..512]);
+    // Everything after the command was received with `read`
+    assert!(card.borrow().bytes_read > 512);
+    let mut buffer = vec![0; 3 * 512];
+    block_on(disk.read(4 * 512, &mut buffer)).unwrap();
+    assert_eq!(buffer, data);
+}
+
+#[test]
+fn learns_read_gap() {
+    let data = pattern(DISK_SIZE, 0x2D);
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::from_bytes(data.clone().try_into().unwrap()),
+        SimCardOptions {
+            read_gap: 10,
+            ..Default::default()
+        },
+    ));
+    let mut sd_card = sim_sd_card(&card);
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+    let mut buffer = [0; 512];
+    for block in 0..8 {
+        assert_eq!(disk.learned_read_gap(), None);
+        block_on(disk.read(block * 512, &mut buffer)).unwrap();
+    }
+    // The sim sends the token right after the gap, so the most measured is 10
+    assert_eq!(disk.learned_read_gap(), Some(17));
+
+    // Reads still work after the card gets slower
+    card.borrow_mut().options.read_gap = 40;
+    block_on(disk.read(20 * 512, &mut buffer)).unwrap();
+    assert_eq!(buffer, data[20 * 512..21 * 512]);
+    assert_eq!(disk.learned_read_gap(), Some(62));
+}
+
+#[test]
+fn dropped_stream_is_stopped() {
+    let data = pattern(DISK_SIZE, 0x2B);
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::from_bytes(data.clone().try_into().unwrap()),
+        SimCardOptions::default(),
+    ));
+    let mut pool = SdCardPool::new([sim_sd_card(&card)]);
+    block_on(pool.init_all()).unwrap();
+    let mut disk = pool.disk(0).unwrap();
+    let mut block = [0; BLOCK_SIZE];
+    let mut stream = block_on(disk.open_stream(2, Duration::from_millis(100))).unwrap();
+    block_on(stream.next_block(&mut block)).unwrap();
+    assert_eq!(block[..], data[2 * BLOCK_SIZE..3 * BLOCK_SIZE]);
+    drop(stream);
+    // The bus stays locked, so nothing else is sent to the card in the middle of its transfer
+    assert!(card.try_borrow().is_err());
+
+    // The card remembers the open transfer, even for a new disk
+    drop(disk);
+    let mut disk = pool.disk(0).unwrap();
+    block_on(disk.read(7 * 512, &mut block)).unwrap();
+    assert_eq!(block[..], data[7 * BLOCK_SIZE..8 * BLOCK_SIZE]);
+    assert!(card.borrow().commands.ends_with(&[18, 12, 13, 17]));
+}
+
+#[test]
+fn write_stream() {
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::new(),
+        SimCardOptions::default(),
+    ));
+    let mut sd_card = sim_sd_card(&card);
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+    let data = pattern(4 * BLOCK_SIZE, 0x19);
+    let mut stream = block_on(disk.open_write_stream(3)).unwrap();
+    for block in data[..3 * BLOCK_SIZE].chunks(BLOCK_SIZE) {
+        block_on(stream.write_block(block.try_into().unwrap())).unwrap();
+    }
+    block_on(stream.close()).unwrap();
+    assert_eq!(card.borrow().commands.last(), Some(&25));
+    assert_eq!(
+        card.borrow().disk.as_bytes()[3 * BLOCK_SIZE..6 * BLOCK_SIZE],
+        data[..3 * BLOCK_SIZE]
+    );
+
+    // A dropped stream is stopped before the next operation
+    let mut stream = block_on(disk.open_write_stream(10)).unwrap();
+    block_on(stream.write_block(data[3 * BLOCK_SIZE..].try_into().unwrap())).unwrap();
+    drop(stream);
+    let mut block = [0; BLOCK_SIZE];
+    block_on(disk.read(10 * 512, &mut block)).unwrap();
+    assert_eq!(block[..], data[3 * BLOCK_SIZE..]);
+    assert!(card.borrow().commands.ends_with(&[25, 17]));
+}
+
+#[test]
+fn read_past_end() {
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::new(),
+        SimCardOptions::default(),
+    ));
+    let mut sd_card = sim_sd_card(&card);
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+    let mut buffer = [0; 512];
+    assert!(block_on(disk.read(DISK_SIZE as u64, &mut buffer)).is_err());
+    // The card still works afterwards
+    block_on(disk.read(0, &mut buffer)).unwrap();
+}
+
+#[test]
+fn erase_all() {
+    let data = pattern(DISK_SIZE, 0x6E);
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::from_bytes(data.try_into().unwrap()),
+        SimCardOptions {
+            mmc: true,
+            ..Default::default()
+        },
+    ));
+    let mut sd_card = sim_sd_card(&card);
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+    let mut progress = Vec::new();
+    let erased =
+        block_on(disk.erase_all(|done, total| progress.push((done, total)), None)).unwrap();
+    // MMC cards are erased in chunks of their erase group
+    assert_eq!(erased, 64);
+    assert_eq!(progress, [(0, 64), (16, 64), (32, 64), (48, 64), (64, 64)]);
+    assert!(card.borrow().disk.as_bytes().iter().all(|&b| b == 0xFF));
+}
+
+#[test]
+fn erase_all_cancelled() {
+    let data = pattern(DISK_SIZE, 0x6F);
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::from_bytes(data.clone().try_into().unwrap()),
+        SimCardOptions {
+            mmc: true,
+            ..Default::default()
+        },
+    ));
+    let mut sd_card = sim_sd_card(&card);
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+    let cancel = CancelToken::new();
+    let erased = block_on(disk.erase_all(
+        |done, _| {
+            if done == 16 {
+                cancel.cancel();
+            }
+        },
+        Some(&cancel),
+    ))
+    .unwrap();
+    // It stops after the chunk that was being erased
+    assert_eq!(erased, 16);
+    let bytes = card.borrow().disk.as_bytes().to_vec();
+    assert!(bytes[..16 * BLOCK_SIZE].iter().all(|&b| b == 0xFF));
+    assert_eq!(bytes[16 * BLOCK_SIZE..], data[16 * BLOCK_SIZE..]);
+}
+
+/// A bus that cancels `cancel` once the card has received `command` `times` times, to cancel in the middle of a long operation
+struct CancellingBus<'a> {
+    card: &'a RefCell<SimCard<DISK_SIZE>>,
+    cancel: &'a CancelToken,
+    command: u8,
+    times: usize,
+}
+
+impl<'a> SharedSpiBus<u8> for CancellingBus<'a> {
+    type Bus = SimCard<DISK_SIZE>;
+    type Guard = RefMut<'a, SimCard<DISK_SIZE>>;
+
+    async fn lock(&self) -> Self::Guard {
+        let card = self.card.borrow_mut();
+        let received = card.commands.iter().filter(|&&c| c == self.command).count();
+        if received >= self.times {
+            self.cancel.cancel();
+        }
+        card
+    }
+}
+
+#[test]
+fn read_cancellable() {
+    let data = pattern(DISK_SIZE, 0x71);
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::from_bytes(data.clone().try_into().unwrap()),
+        SimCardOptions::default(),
+    ));
+    let cancel = CancelToken::new();
+    let bus = CancellingBus {
+        card: &card,
+        cancel: &cancel,
+        command: 18,
+        times: 2,
+    };
+    let cs = card.borrow().cs();
+    let mut sd_card =
+        SpiSdCard::new_with_config(bus, cs, NoDelay, (), (), SdCardConfig::new(std_clock));
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+
+    // Chunks of blocks 4 to 16, 16 to 32, 32 to 48 and 48 to 52
+    let mut buffer = vec![0; 48 * BLOCK_SIZE];
+    let read =
+        block_on(disk.read_cancellable(4 * BLOCK_SIZE as u64, &mut buffer, &cancel)).unwrap();
+    // It is cancelled while the third chunk is being read, which is finished first
+    assert_eq!(read, 44 * BLOCK_SIZE);
+    assert_eq!(buffer[..read], data[4 * BLOCK_SIZE..48 * BLOCK_SIZE]);
+    assert_eq!(buffer[read..], [0; 4 * BLOCK_SIZE]);
+    {
+        let card = card.borrow();
+        assert!(card.waiting_for_command());
+        assert_eq!(card.commands.iter().filter(|&&c| c == 18).count(), 3);
+        assert_eq!(card.commands.last(), Some(&12));
+    }
+
+    // The card is ready for the next read
+    cancel.reset();
+    let mut buffer = vec![0; 2 * BLOCK_SIZE];
+    block_on(disk.read(60 * BLOCK_SIZE as u64, &mut buffer)).unwrap();
+    assert_eq!(buffer, data[60 * BLOCK_SIZE..62 * BLOCK_SIZE]);
+}
+
+#[test]
+fn write_cancellable() {
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::new(),
+        SimCardOptions::default(),
+    ));
+    let cancel = CancelToken::new();
+    let bus = CancellingBus {
+        card: &card,
+        cancel: &cancel,
+        command: 25,
+        times: 1,
+    };
+    let cs = card.borrow().cs();
+    let mut sd_card =
+        SpiSdCard::new_with_config(bus, cs, NoDelay, (), (), SdCardConfig::new(std_clock));
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+
+    let data = pattern(40 * BLOCK_SIZE, 0x72);
+    let written = block_on(disk.write_cancellable(8 * BLOCK_SIZE as u64, &data, &cancel)).unwrap();
+    // It is cancelled while the second chunk is being written, which is finished first
+    assert_eq!(written, 24 * BLOCK_SIZE);
+    {
+        let card = card.borrow();
+        assert!(card.waiting_for_command());
+        assert_eq!(card.commands.iter().filter(|&&c| c == 25).count(), 2);
+        let bytes = card.disk.as_bytes();
+        assert_eq!(bytes[8 * BLOCK_SIZE..32 * BLOCK_SIZE], data[..written]);
+        assert!(bytes[32 * BLOCK_SIZE..].iter().all(|&b| b == 0));
+    }
+
+    // The card is ready for the next read
+    let mut buffer = vec![0; 2 * BLOCK_SIZE];
+    block_on(disk.read(30 * BLOCK_SIZE as u64, &mut buffer)).unwrap();
+    assert_eq!(buffer, data[22 * BLOCK_SIZE..24 * BLOCK_SIZE]);
+}
+
+#[cfg(feature = "block-device-driver")]
+#[test]
+fn block_device() {
+    use aligned::{A1, Aligned};
+    use block_device_driver::BlockDevice;
+
+    let data = pattern(DISK_SIZE, 0x70);
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::from_bytes(data.clone().try_into().unwrap()),
+        SimCardOptions {
+            mmc: true,
+            ..Default::default()
+        },
+    ));
+    let mut sd_card = sim_sd_card(&card);
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+    assert_eq!(
+        block_on(BlockDevice::size(&mut disk)).unwrap(),
+        DISK_SIZE as u64
+    );
+
+    let mut blocks = [Aligned::<A1, _>([0; BLOCK_SIZE]); 2];
+    block_on(BlockDevice::read(&mut disk, 5, &mut blocks)).unwrap();
+    assert_eq!(blocks[0][..], data[5 * BLOCK_SIZE..6 * BLOCK_SIZE]);
+    assert_eq!(blocks[1][..], data[6 * BLOCK_SIZE..7 * BLOCK_SIZE]);
+
+    let written = [
+        Aligned::<A1, _>([0x11; BLOCK_SIZE]),
+        Aligned([0x22; BLOCK_SIZE]),
+    ];
+    block_on(BlockDevice::write(&mut disk, 9, &written)).unwrap();
+    let bytes = card.borrow().disk.as_bytes().to_vec();
+    assert!(
+        bytes[9 * BLOCK_SIZE..10 * BLOCK_SIZE]
+            .iter()
+            .all(|&b| b == 0x11)
+    );
+    assert!(
+        bytes[10 * BLOCK_SIZE..11 * BLOCK_SIZE]
+            .iter()
+            .all(|&b| b == 0x22)
+    );
+    assert_eq!(bytes[11 * BLOCK_SIZE..], data[11 * BLOCK_SIZE..]);
+}
+
+#[test]
+fn addresses_past_32_bits() {
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::new(),
+        SimCardOptions {
+            high_capacity: false,
+            ..Default::default()
+        },
+    ));
+    let mut sd_card = sim_sd_card(&card);
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+    card.borrow_mut().commands.clear();
+    let mut buffer = [0; 2 * BLOCK_SIZE];
+    // A byte addressed card can't address past 4 GiB
+    let start = 1 << 32;
+    assert!(matches!(
+        block_on(disk.write(start, &buffer)),
+        Err(Error::WriteAddressError)
+    ));
+    assert!(matches!(
+        block_on(disk.read(start, &mut buffer)),
+        Err(Error::ReadAddressError)
+    ));
+    // And no card can address past 2^32 blocks
+    let start = (u64::from(u32::MAX) + 1) * BLOCK_SIZE as u64;
+    assert!(matches!(
+        block_on(disk.write(start, &buffer)),
+        Err(Error::WriteAddressError)
+    ));
+    assert!(matches!(
+        block_on(disk.read(start, &mut buffer)),
+        Err(Error::ReadAddressError)
+    ));
+    assert!(
+        !card
+            .borrow()
+            .commands
+            .iter()
+            .any(|command| matches!(command, 17 | 18 | 24 | 25))
+    );
+}
+
+#[test]
+fn erase_past_block_addresses() {
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::new(),
+        SimCardOptions::default(),
+    ));
+    let mut sd_card = sim_sd_card(&card);
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+    let end = (u64::from(u32::MAX) + 2) * BLOCK_SIZE as u64;
+    assert!(matches!(
+        block_on(Disk::erase(&mut disk, 0, end)),
+        Err(Error::EraseAddressError)
+    ));
+}
+
+#[test]
+fn auto_reinit() {
+    let data = pattern(DISK_SIZE, 0x7A);
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::from_bytes(data.clone().try_into().unwrap()),
+        SimCardOptions::default(),
+    ));
+    let mut sd_card = sim_sd_card(&card);
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+    block_on(disk.enable_auto_reinit()).unwrap();
+    let mut buffer = [0; 512];
+
+    // The card is pulled out, and is in the idle state once it's put back in
+    card.borrow_mut().remove();
+    assert!(matches!(
+        block_on(disk.read(0, &mut buffer)),
+        Err(Error::ReadReceiveResponseTimeout)
+    ));
+    assert!(disk.is_card_lost());
+    card.borrow_mut().insert();
+    card.borrow_mut().commands.clear();
+    block_on(disk.read(3 * 512, &mut buffer)).unwrap();
+    assert_eq!(buffer, data[3 * 512..4 * 512]);
+    assert!(!disk.is_card_lost());
+    // It was initialized again, and its CID checked, before the read
+    let commands = card.borrow().commands.clone();
+    assert_eq!(commands.first(), Some(&0));
+    assert!(commands.ends_with(&[10, 17]));
+
+    // A different card is inserted
+    card.borrow_mut().remove();
+    assert!(block_on(disk.read(0, &mut buffer)).is_err());
+    card.borrow_mut().cid ^= 1 << 24;
+    card.borrow_mut().insert();
+    assert!(matches!(
+        block_on(disk.read(0, &mut buffer)),
+        Err(Error::CardChanged)
+    ));
+    block_on(disk.read(5 * 512, &mut buffer)).unwrap();
+    assert_eq!(buffer, data[5 * 512..6 * 512]);
+}
+
+/// A socket without a card detect switch, but with a write protect switch
+struct WriteProtectSwitch(bool);
+
+impl CardDetect for WriteProtectSwitch {
+    fn is_card_present(&mut self) -> bool {
+        true
+    }
+
+    async fn wait_for_card(&mut self) {}
+
+    fn is_write_protected(&mut self) -> bool {
+        self.0
+    }
+}
+
+#[test]
+fn write_protected() {
+    // The socket's switch, and then the CSD's write protect bit
+    for (switch, csd) in [(true, false), (false, true)] {
+        let card = RefCell::new(SimCard::new(
+            RamDisk::<DISK_SIZE>::new(),
+            SimCardOptions {
+                write_protected: csd,
+                ..Default::default()
+            },
+        ));
+        let cs = card.borrow().cs();
+        let mut sd_card = SpiSdCard::new_with_config(
+            SimBus(&card),
+            cs,
+            NoDelay,
+            (),
+            (),
+            SdCardConfig::new(std_clock),
+        )
+        .with_card_detect(WriteProtectSwitch(switch));
+        let mut disk = block_on(sd_card.init_card()).unwrap();
+        assert!(block_on(disk.is_write_protected()).unwrap());
+
+        assert!(matches!(
+            block_on(disk.write(2 * 512, &[1; 512])),
+            Err(Error::WriteProtected)
+        ));
+        assert!(matches!(
+            block_on(disk.write(4 * 512, &[2; 3 * 512])),
+            Err(Error::WriteProtected)
+        ));
+        // Reading still works
+        let mut buffer = [0xAA; 512];
+        block_on(disk.read(2 * 512, &mut buffer)).unwrap();
+        assert_eq!(buffer, [0; 512]);
+        drop(disk);
+
+        let card = card.borrow();
+        assert!(!card.commands.iter().any(|&i| i == 24 || i == 25));
+        assert!(card.disk.as_bytes().iter().all(|&byte| byte == 0));
+    }
+}
+
+#[cfg(feature = "embassy-sync")]
+#[test]
+fn manager_hot_swap() {
+    use std::future::poll_fn;
+
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use embedded_hal_async::delay::DelayNs;
+    use spi_sd_card::{CardEvent, SdCardManager};
+
+    /// Returns to the test between the checks of `SdCardManager::run`
+    struct YieldDelay;
+
+    impl DelayNs for YieldDelay {
+        async fn delay_ns(&mut self, _ns: u32) {
+            let mut yielded = false;
+            poll_fn(|_| {
+                if yielded {
+                    Poll::Ready(())
+                } else {
+                    yielded = true;
+                    Poll::Pending
+                }
+            })
+            .await
+        }
+    }
+
+    let data = pattern(DISK_SIZE, 0x7B);
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::from_bytes(data.clone().try_into().unwrap()),
+        SimCardOptions::default(),
+    ));
+    let manager = SdCardManager::<NoopRawMutex, _, _, _, _, 4>::new(
+        sim_sd_card(&card),
+        Duration::from_millis(100),
+    );
+    let events = manager.events();
+    let mut run = pin!(manager.run(YieldDelay));
+    // Checks the card once
+    let mut check = || {
+        assert!(
+            run.as_mut()
+                .poll(&mut Context::from_waker(Waker::noop()))
+                .is_pending()
+        );
+    };
+
+    check();
+    assert_eq!(
+        events.try_receive(),
+        Ok(CardEvent::Inserted(CardType::SdV2Hc))
+    );
+    let mut old_disk = block_on(manager.disk()).unwrap();
+    let mut buffer = [0; 512];
+    block_on(old_disk.read(0, &mut buffer)).unwrap();
+
+    // Without a card detect switch, the card is removed once it stops answering `CMD13`
+    card.borrow_mut().remove();
+    check();
+    assert_eq!(events.try_receive(), Ok(CardEvent::Removed));
+    assert!(block_on(manager.disk()).is_none());
+    assert!(matches!(
+        block_on(old_disk.read(0, &mut buffer)),
+        Err(Error::NoCard)
+    ));
+
+    // The card is found again once it's put back in
+    card.borrow_mut().insert();
+    check();
+    assert_eq!(
+        events.try_receive(),
+        Ok(CardEvent::Inserted(CardType::SdV2Hc))
+    );
+    assert!(events.try_receive().is_err());
+    let mut disk = block_on(manager.disk()).unwrap();
+    block_on(disk.read(3 * 512, &mut buffer)).unwrap();
+    assert_eq!(buffer, data[3 * 512..4 * 512]);
+    // Handles to the card from before it was removed don't use the new one
+    assert!(matches!(
+        block_on(old_disk.read(0, &mut buffer)),
+        Err(Error::CardChanged)
+    ));
+}
+
+#[test]
+fn cancelled_transaction() {
+    let data = pattern(DISK_SIZE, 0x5D);
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::from_bytes(data.clone().try_into().unwrap()),
+        SimCardOptions::default(),
+    ));
+    let mut sd_card = sim_sd_card(&card);
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+    let mut buffer = [0; 512];
+    {
+        let mut transaction = pin!(disk.transaction(async |disk| {
+            disk.read(0, &mut buffer).await.unwrap();
+            core::future::pending::<()>().await;
+        }));
+        assert!(
+            transaction
+                .as_mut()
+                .poll(&mut Context::from_waker(Waker::noop()))
+                .is_pending()
+        );
+    }
+    // The card is still selected, so the bus stays locked
+    assert!(card.try_borrow().is_err());
+
+    // The next operation ends the transaction
+    block_on(disk.read(512, &mut buffer)).unwrap();
+    assert_eq!(buffer[..], data[512..1024]);
+    assert!(card.try_borrow().is_ok());
+}
+
+#[test]
+fn first_bad_block() {
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::new(),
+        SimCardOptions::default(),
+    ));
+    card.borrow_mut().bad_blocks = vec![37, 50];
+    let mut sd_card = sim_sd_card(&card);
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+    let mut buffer = vec![0; 8 * 512];
+    assert_eq!(
+        block_on(disk.first_bad_block(0, 64, &mut buffer)).unwrap(),
+        Some(37)
+    );
+    assert_eq!(
+        block_on(disk.first_bad_block(38, 64, &mut buffer)).unwrap(),
+        Some(50)
+    );
+    assert_eq!(
+        block_on(disk.first_bad_block(0, 37, &mut buffer)).unwrap(),
+        None
+    );
+}
+
+#[test]
+fn read_crc_only() {
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::from_bytes(pattern(DISK_SIZE, 0x0F).try_into().unwrap()),
+        SimCardOptions::default(),
+    ));
+    let mut sd_card = sim_sd_card(&card);
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+    block_on(disk.read_crc_only(0, 20)).unwrap();
+
+    card.borrow_mut().bad_blocks.push(13);
+    assert!(matches!(
+        block_on(disk.read_crc_only(10, 5)),
+        Err(Error::ReadDataError(_))
+    ));
+    // The card is ready for the next command after the failed scan
+    block_on(disk.read_crc_only(14, 6)).unwrap();
+}
+
+#[test]
+fn address_error_rechecks_addressing() {
+    let data = pattern(DISK_SIZE, 0x3C);
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::from_bytes(data.clone().try_into().unwrap()),
+        SimCardOptions::default(),
+    ));
+    let mut sd_card = sim_sd_card(&card);
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+    assert_eq!(disk.card_type(), CardType::SdV2Hc);
+    // The card now says that it's standard capacity, like an SDSC card that was detected wrong
+    card.borrow_mut().options.high_capacity = false;
+    card.borrow_mut().commands.clear();
+
+    let mut buffer = [0; 512];
+    block_on(disk.read(3 * 512, &mut buffer)).unwrap();
+    assert_eq!(buffer, data[3 * 512..4 * 512]);
+    assert_eq!(disk.card_type(), CardType::SdV2Sc);
+    // CMD17 with a block address, CMD58, CMD16, and CMD17 with a byte address
+    assert_eq!(card.borrow().commands, [17, 58, 16, 17]);
+}
+
+/// Counts how many times the card was selected
+struct CountingCs<'a> {
+    cs: SimCs,
+    selects: &'a Cell<u32>,
+}
+
+impl PinErrorType for CountingCs<'_> {
+    type Error = Infallible;
+}
+
+impl OutputPin for CountingCs<'_> {
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        self.selects.set(self.selects.get() + 1);
+        self.cs.set_low()
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        self.cs.set_high()
+    }
+}
+
+#[test]
+fn nested_transaction() {
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::new(),
+        SimCardOptions::default(),
+    ));
+    let selects = Cell::new(0);
+    let cs = CountingCs {
+        cs: card.borrow().cs(),
+        selects: &selects,
+    };
+    let mut sd_card = SpiSdCard::new_with_config(
+        SimBus(&card),
+        cs,
+        NoDelay,
+        (),
+        (),
+        SdCardConfig::new(std_clock),
+    );
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+    selects.set(0);
+
+    let data = pattern(512, 0x71);
+    let result = block_on(disk.transaction(async |disk| {
+        disk.write(512, &data).await?;
+        // The bus is still locked by the outer transaction, so this would panic if it was locked again
+        disk.transaction(async |disk| disk.capacity().await)
+            .await??;
+        let mut buffer = [0; 512];
+        disk.read(512, &mut buffer).await?;
+        Ok::<_, Error<_, _>>(buffer)
+    }));
+    assert_eq!(result.unwrap().unwrap()[..], data);
+    assert_eq!(selects.get(), 1);
+}
+
+#[test]
+fn init_card_in_transaction() {
+    let data = pattern(DISK_SIZE, 0x4C);
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::from_bytes(data.clone().try_into().unwrap()),
+        SimCardOptions::default(),
+    ));
+    let selects = Cell::new(0);
+    let cs = CountingCs {
+        cs: card.borrow().cs(),
+        selects: &selects,
+    };
+    let mut sd_card = SpiSdCard::new_with_config(
+        SimBus(&card),
+        cs,
+        NoDelay,
+        (),
+        (),
+        SdCardConfig::new(std_clock),
+    );
+
+    let (mut disk, result) = block_on(sd_card.init_card_in_transaction(async |disk| {
+        let capacity = disk.capacity().await?;
+        let mut boot_sector = [0; 512];
+        disk.read(0, &mut boot_sector).await?;
+        Ok::<_, Error<_, _>>((capacity, boot_sector))
+    }))
+    .unwrap();
+    let (capacity, boot_sector) = result.unwrap();
+    assert_eq!(capacity, 512 * 1024);
+    assert_eq!(boot_sector[..], data[..512]);
+    // Only selected once, for init and everything in the transaction
+    assert_eq!(selects.get(), 1);
+
+    // The disk keeps working after the transaction
+    let mut buffer = [0; 512];
+    block_on(disk.read(512, &mut buffer)).unwrap();
+    assert_eq!(buffer[..], data[512..1024]);
+    assert_eq!(selects.get(), 2);
+}
+
+#[test]
+fn exclusive_bus() {
+    let data = pattern(DISK_SIZE, 0x11);
+    let card = SimCard::new(
+        RamDisk::<DISK_SIZE>::from_bytes(data.clone().try_into().unwrap()),
+        SimCardOptions::default(),
+    );
+    let cs = card.cs();
+    let bus = ExclusiveSpiBus::new(card);
+    let mut sd_card =
+        SpiSdCard::new_with_config(&bus, cs, NoDelay, (), (), SdCardConfig::new(std_clock));
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+    let mut buffer = vec![0; 2 * 512];
+    block_on(disk.read(512, &mut buffer)).unwrap();
+    assert_eq!(buffer, data[512..3 * 512]);
+}
+
+#[test]
+fn fixed_speed_bus() {
+    let data = pattern(DISK_SIZE, 0x22);
+    let card = SimCard::new(
+        RamDisk::<DISK_SIZE>::from_bytes(data.clone().try_into().unwrap()),
+        SimCardOptions::default(),
+    );
+    let cs = card.cs();
+    let bus = ExclusiveSpiBus::new(card);
+    let mut sd_card = SpiSdCard::new_fixed_speed(&bus, cs, NoDelay, SdCardConfig::new(std_clock));
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+    let written = pattern(512, 0x33);
+    block_on(disk.write(4 * 512, &written)).unwrap();
+    let mut buffer = vec![0; 2 * 512];
+    block_on(disk.read(3 * 512, &mut buffer)).unwrap();
+    assert_eq!(buffer[..512], data[3 * 512..4 * 512]);
+    assert_eq!(buffer[512..], written);
+}
+
+#[test]
+fn std_mutex_bus() {
+    let data = pattern(DISK_SIZE, 0x44);
+    let card = SimCard::new(
+        RamDisk::<DISK_SIZE>::from_bytes(data.clone().try_into().unwrap()),
+        SimCardOptions::default(),
+    );
+    let cs = card.cs();
+    let bus = StdSharedSpiBus::new(card);
+    let mut sd_card =
+        SpiSdCard::new_with_config(&bus, cs, NoDelay, (), (), SdCardConfig::new(std_clock));
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+    let mut buffer = vec![0; 512];
+    block_on(disk.read(3 * 512, &mut buffer)).unwrap();
+    assert_eq!(buffer, data[3 * 512..4 * 512]);
+
+    // Another task has the bus, so the read waits to be woken when it's unlocked
+    let other_task = block_on(SharedSpiBus::<u8>::lock(&&bus));
+    let wakes = Arc::new(CountingWaker::default());
+    let waker = Waker::from(wakes.clone());
+    let mut cx = Context::from_waker(&waker);
+    {
+        let mut read = pin!(disk.read(512, &mut buffer));
+        assert!(read.as_mut().poll(&mut cx).is_pending());
+        assert!(read.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(wakes.0.load(Ordering::Relaxed), 0);
+        drop(other_task);
+        assert_eq!(wakes.0.load(Ordering::Relaxed), 1);
+        assert!(matches!(read.as_mut().poll(&mut cx), Poll::Ready(Ok(()))));
+    }
+    assert_eq!(buffer, data[512..2 * 512]);
+}
+
+/// Counts how many times it was woken
+#[derive(Default)]
+struct CountingWaker(AtomicUsize);
+
+impl std::task::Wake for CountingWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn local_bus_waits_while_locked() {
+    let data = pattern(DISK_SIZE, 0x22);
+    let card = SimCard::new(
+        RamDisk::<DISK_SIZE>::from_bytes(data.clone().try_into().unwrap()),
+        SimCardOptions::default(),
+    );
+    let cs = card.cs();
+    let bus = LocalSharedSpiBus::new(card);
+    let mut sd_card =
+        SpiSdCard::new_with_config(&bus, cs, NoDelay, (), (), SdCardConfig::new(std_clock));
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+
+    // Another task has the bus, so the read waits for it instead of panicking
+    let other_task = block_on(SharedSpiBus::<u8>::lock(&&bus));
+    let mut buffer = vec![0; 512];
+    let wakes = Arc::new(CountingWaker::default());
+    let waker = Waker::from(wakes.clone());
+    let mut cx = Context::from_waker(&waker);
+    {
+        let mut read = pin!(disk.read(512, &mut buffer));
+        assert!(read.as_mut().poll(&mut cx).is_pending());
+        // It isn't polled again until the bus is unlocked
+        assert_eq!(wakes.0.load(Ordering::Relaxed), 0);
+        drop(other_task);
+        assert_eq!(wakes.0.load(Ordering::Relaxed), 1);
+        assert!(matches!(read.as_mut().poll(&mut cx), Poll::Ready(Ok(()))));
+    }
+    assert_eq!(buffer, data[512..2 * 512]);
+}
+
+#[test]
+fn clock_policy_derates() {
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::new(),
+        SimCardOptions::default(),
+    ));
+    let mut sd_card = sim_sd_card(&card).with_derated_config(());
+    sd_card.config.clock_policy = Some(|environment| match environment.vdd_mv {
+        Some(vdd_mv) if vdd_mv < 3000 => ClockChoice::Derated,
+        _ => ClockChoice::Full,
+    });
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+    let mut buffer = [0; 512];
+    block_on(disk.read(0, &mut buffer)).unwrap();
+    assert!(!disk.is_derated());
+
+    disk.set_environment(Environment {
+        vdd_mv: Some(2800),
+        temperature_c: None,
+    });
+    block_on(disk.read(0, &mut buffer)).unwrap();
+    assert!(disk.is_derated());
+}
+
+/// A card whose wiring only works up to 4 MHz. The config is the clock in MHz, and above 4 MHz the lowest bit of MISO is stuck high.
+struct SlowWiring(SimCard<DISK_SIZE>, u32);
+
+impl Debug for SlowWiring {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("SlowWiring")
+    }
+}
+
+impl ErrorType for SlowWiring {
+    type Error = Infallible;
+}
+
+impl SetConfig for SlowWiring {
+    type Config = u32;
+    type ConfigError = Infallible;
+
+    fn set_config(&mut self, mhz: &u32) -> Result<(), Infallible> {
+        self.1 = *mhz;
+        Ok(())
+    }
+}
+
+impl SpiBus for SlowWiring {
+    async fn read(&mut self, words: &mut [u8]) -> Result<(), Infallible> {
+        words.fill(0xFF);
+        self.transfer_in_place(words).await
+    }
+
+    async fn write(&mut self, words: &[u8]) -> Result<(), Infallible> {
+        self.transfer_in_place(&mut words.to_vec()).await
+    }
+
+    async fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Infallible> {
+        let mut words = write.to_vec();
+        words.resize(read.len().max(write.len()), 0xFF);
+        self.transfer_in_place(&mut words).await?;
+        read.copy_from_slice(&words[..read.len()]);
+        Ok(())
+    }
+
+    async fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Infallible> {
+        self.0.transfer_in_place(words).await?;
+        if self.1 > 4 {
+            for word in words {
+                *word |= 1;
+            }
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+#[test]
+fn verify_speed_switch() {
+    let data = pattern(DISK_SIZE, 0x4D);
+    let wiring = || {
+        let card = SimCard::new(
+            RamDisk::<DISK_SIZE>::from_bytes(data.clone().try_into().unwrap()),
+            SimCardOptions::default(),
+        );
+        let cs = card.cs();
+        (ExclusiveSpiBus::new(SlowWiring(card, 0)), cs)
+    };
+    let config = || SdCardConfig::new(std_clock).with_verify_speed_switch(true);
+
+    // Falls back to 2 MHz
+    let (bus, cs) = wiring();
+    let mut sd_card =
+        SpiSdCard::new_with_config(&bus, cs, NoDelay, 0, 25, config()).with_derated_config(2);
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+    assert!(disk.speed_fell_back());
+    let mut buffer = vec![0; 2 * 512];
+    block_on(disk.read(3 * 512, &mut buffer)).unwrap();
+    assert!(disk.is_derated());
+    assert_eq!(buffer, data[3 * 512..5 * 512]);
+    drop(disk);
+    assert!(
+        sd_card
+            .init_timeline()
+            .duration(InitStep::VerifySpeed)
+            .is_some()
+    );
+
+    // 8 MHz is still too fast
+    let (bus, cs) = wiring();
+    let mut sd_card =
+        SpiSdCard::new_with_config(&bus, cs, NoDelay, 0, 25, config()).with_derated_config(8);
+    assert!(matches!(
+        block_on(sd_card.init_card()),
+        Err(Error::SpeedSwitchFailed)
+    ));
+    assert_eq!(
+        sd_card.init_timeline().failed_step(),
+        Some(InitStep::VerifySpeed)
+    );
+}
+
+#[test]
+fn spi_transport() {
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::new(),
+        SimCardOptions::default(),
+    ));
+    let mut sd_card = sim_sd_card(&card);
+    block_on(sd_card.init_card()).unwrap();
+    let mut transport = sd_card.transport();
+    block_on(transport.set_speed(TransportSpeed::Transfer)).unwrap();
+    block_on(transport.select()).unwrap();
+    let data = pattern(2 * BLOCK_SIZE, 0x33);
+    let response = block_on(transport.command(
+        SdCommand::WriteMultipleBlock { address: 4 },
+        DataTransfer::Write(&data),
+    ))
+    .unwrap();
+    assert_eq!(response[0], 0);
+    let mut buffer = [0; BLOCK_SIZE];
+    let response = block_on(transport.command(
+        SdCommand::ReadSingleBlock { address: 5 },
+        DataTransfer::Read(&mut buffer),
+    ))
+    .unwrap();
+    assert_eq!(response[0], 0);
+    block_on(transport.deselect()).unwrap();
+    assert_eq!(buffer[..], data[BLOCK_SIZE..]);
+    assert_eq!(
+        card.borrow().disk.as_bytes()[4 * BLOCK_SIZE..6 * BLOCK_SIZE],
+        data[..]
+    );
+}
+
+#[test]
+fn spi_transport_rejects_partial_blocks() {
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::new(),
+        SimCardOptions::default(),
+    ));
+    let mut sd_card = sim_sd_card(&card);
+    block_on(sd_card.init_card()).unwrap();
+    let mut transport = sd_card.transport();
+    block_on(transport.set_speed(TransportSpeed::Transfer)).unwrap();
+    card.borrow_mut().commands.clear();
+    assert!(matches!(
+        block_on(transport.command(
+            SdCommand::ReadSingleBlock { address: 0 },
+            DataTransfer::Read(&mut []),
+        )),
+        Err(TransportError::NothingToTransfer)
+    ));
+    let mut buffer = [0; BLOCK_SIZE + 100];
+    assert!(matches!(
+        block_on(transport.command(
+            SdCommand::ReadMultipleBlock { address: 0 },
+            DataTransfer::Read(&mut buffer),
+        )),
+        Err(TransportError::NothingToTransfer)
+    ));
+    assert!(matches!(
+        block_on(transport.command(
+            SdCommand::WriteMultipleBlock { address: 0 },
+            DataTransfer::Write(&buffer),
+        )),
+        Err(TransportError::NothingToTransfer)
+    ));
+    // The commands weren't sent
+    assert!(card.borrow().commands.is_empty());
+}
+
+/// 2 simulated cards on the same bus. A deselected card sends `0xFF`, so MISO is the AND of both.
+struct TwoCards([SimCard<DISK_SIZE>; 2]);
+
+impl Debug for TwoCards {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("TwoCards")
+    }
+}
+
+impl ErrorType for TwoCards {
+    type Error = Infallible;
+}
+
+impl SetConfig for TwoCards {
+    type Config = ();
+    type ConfigError = Infallible;
+
+    fn set_config(&mut self, _config: &()) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+impl SpiBus for TwoCards {
+    async fn read(&mut self, words: &mut [u8]) -> Result<(), Infallible> {
+        words.fill(0xFF);
+        self.transfer_in_place(words).await
+    }
+
+    async fn write(&mut self, words: &[u8]) -> Result<(), Infallible> {
+        self.transfer_in_place(&mut words.to_vec()).await
+    }
+
+    async fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Infallible> {
+        let mut words = write.to_vec();
+        words.resize(read.len().max(write.len()), 0xFF);
+        self.transfer_in_place(&mut words).await?;
+        read.copy_from_slice(&words[..read.len()]);
+        Ok(())
+    }
+
+    async fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Infallible> {
+        let mut other = words.to_vec();
+        self.0[0].transfer_in_place(words).await?;
+        self.0[1].transfer_in_place(&mut other).await?;
+        for (word, other) in words.iter_mut().zip(other) {
+            *word &= other;
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+#[test]
+fn pool_of_two_cards() {
+    let data = [pattern(DISK_SIZE, 0x55), pattern(DISK_SIZE, 0x66)];
+    let cards = data.clone().map(|data| {
+        SimCard::new(
+            RamDisk::<DISK_SIZE>::from_bytes(data.try_into().unwrap()),
+            SimCardOptions::default(),
+        )
+    });
+    let cs = [cards[0].cs(), cards[1].cs()];
+    let bus = ExclusiveSpiBus::new(TwoCards(cards));
+    let mut pool = SdCardPool::new(cs.map(|cs| {
+        SpiSdCard::new_with_config(&bus, cs, NoDelay, (), (), SdCardConfig::new(std_clock))
+    }));
+    assert_eq!(
+        block_on(pool.init_all()).unwrap(),
+        [Some(CardType::SdV2Hc); 2]
+    );
+
+    let [Some(mut disk_0), Some(mut disk_1)] = pool.disks() else {
+        panic!("both cards should be initialized");
+    };
+    let mut buffer = vec![0; 512];
+    block_on(disk_0.read(512, &mut buffer)).unwrap();
+    assert_eq!(buffer, data[0][512..2 * 512]);
+    block_on(disk_1.read(512, &mut buffer)).unwrap();
+    assert_eq!(buffer, data[1][512..2 * 512]);
+}
+
+#[cfg(feature = "embassy-sync")]
+#[test]
+fn server_serves_clients() {
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use spi_sd_card::SdCardServer;
+
+    let data = pattern(DISK_SIZE, 0x77);
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::from_bytes(data.clone().try_into().unwrap()),
+        SimCardOptions::default(),
+    ));
+    let mut sd_card = sim_sd_card(&card);
+    let disk = block_on(sd_card.init_card()).unwrap();
+    let server = SdCardServer::<NoopRawMutex, _, _, 2>::new();
+    let [mut reader, mut writer] = server.clients().unwrap();
+    assert!(server.clients().is_none());
+
+    let mut run = pin!(server.run(disk));
+    let mut clients = pin!(async {
+        writer.write_block(2, &[9; BLOCK_SIZE]).await.unwrap();
+        let (read, info) = (reader.read_block(2).await, writer.info().await);
+        (read.unwrap(), info.unwrap())
+    });
+    // Polls the clients and the server in turns, like an executor would
+    let mut cx = Context::from_waker(Waker::noop());
+    let (block, info) = loop {
+        if let Poll::Ready(output) = clients.as_mut().poll(&mut cx) {
+            break output;
+        }
+        let _ = run.as_mut().poll(&mut cx);
+    };
+    assert_eq!(block, [9; BLOCK_SIZE]);
+    assert_eq!(info.card_type, CardType::SdV2Hc);
+    assert_eq!(card.borrow().disk.as_bytes()[3 * 512..], data[3 * 512..]);
+}
+
+/// The simulated card as an [`SpiDevice`], which selects it for each transaction
+struct SimDevice<'a> {
+    card: &'a RefCell<SimCard<DISK_SIZE>>,
+    cs: SimCs,
+}
+
+impl ErrorType for SimDevice<'_> {
+    type Error = Infallible;
+}
+
+impl SpiDevice for SimDevice<'_> {
+    // The simulated card never waits, so nothing else can borrow it in the meantime
+    #[allow(clippy::await_holding_refcell_ref)]
+    async fn transaction(
+        &mut self,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), Infallible> {
+        self.cs.set_low()?;
+        let mut card = self.card.borrow_mut();
+        for operation in operations {
+            match operation {
+                Operation::Read(words) => card.read(words).await?,
+                Operation::Write(words) => card.write(words).await?,
+                Operation::Transfer(read, write) => card.transfer(read, write).await?,
+                Operation::TransferInPlace(words) => card.transfer_in_place(words).await?,
+                Operation::DelayNs(_) => {}
+            }
+        }
+        self.cs.set_high()
+    }
+}
+
+#[test]
+fn spi_device() {
+    let card = RefCell::new(SimCard::new(
+        RamDisk::<DISK_SIZE>::new(),
+        SimCardOptions::default(),
+    ));
+    let cs = card.borrow().cs();
+    let device = SpiDeviceCell::new(SimDevice { card: &card, cs });
+    let mut sd_card = SpiSdCard::new_with_device(&device, NoDelay, SdCardConfig::new(std_clock));
+    let mut disk = block_on(sd_card.init_card()).unwrap();
+    assert_eq!(disk.card_type(), CardType::SdV2Hc);
+
+    let data = pattern(3 * 512, 7);
+    block_on(disk.write(5 * 512, &data)).unwrap();
+    let mut buffer = vec![0; 3 * 512];
+    block_on(disk.read(5 * 512, &mut buffer)).unwrap();
+    assert_eq!(buffer, data);
+}
+
+/// The simulated card with a blocking bus, like in a panic handler
+struct BlockingSim<'a>(&'a RefCell<SimCard<DISK_SIZE>>);
+
+impl ErrorType for BlockingSim<'_> {
+    type Error = Infallible;
+}
+
+impl BlockingSpiBus for BlockingSim<'_> {
+    fn read(&mut self, words: &mut [u8]) -> Result<(), Infallible> {
+        block_on(self.0.borrow_mut().read(words))
+    }
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Infallible> {
+        block_on(self.0.borrow_mut().write(words))
+    }
+
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Infallible> {
+        block_on(self.0.borrow_mut().transfer(read, write))
+    }
+
+    fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Infallible> {
+        block_on(self.0.borrow_mut().transfer_in_place(words))
+    }
+
+    fn flush(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+#[test]
+fn blocking_crash_log() {
+    for high_capacity in [true, false] {
+        let card = RefCell::new(SimCard::new(
+            RamDisk::<DISK_SIZE>::new(),
+            SimCardOptions {
+                high_capacity,
+                ..Default::default()
+            },
+        ));
+        let cs = card.borrow().cs();
+        let mut sd_card =
+            SpiSdCardBlocking::new(BlockingSim(&card), cs, SdCardConfig::new(std_clock));
+        let region = CrashLogRegion {
+            start_block: 40,
+            blocks: 4,
+        };
+        let log = pattern(1000, 9);
+        sd_card.write_crash_log(region, &log).unwrap();
+
+        let card = card.borrow();
+        let bytes = card.disk.as_bytes();
+        let header =
+            CrashLogHeader::from_block(bytes[40 * BLOCK_SIZE..41 * BLOCK_SIZE].try_into().unwrap())
+                .unwrap();
+        assert!(header.matches(&bytes[41 * BLOCK_SIZE..41 * BLOCK_SIZE + 1000]));
+        assert_eq!(bytes[41 * BLOCK_SIZE..41 * BLOCK_SIZE + 1000], log);
+    }
+}